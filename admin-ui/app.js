@@ -0,0 +1,166 @@
+// Admin UI: a thin client over the JSON API. Everything is rendered with
+// textContent, so catalog data can never inject markup.
+
+const PAGE_SIZE = 20;
+const state = { page: 1, totalPages: 1, query: "", editing: null };
+
+const $ = (id) => document.getElementById(id);
+
+function credentials() {
+  const token = sessionStorage.getItem("token");
+  if (!token) return {};
+  // API keys start with `lib_`; anything else is a session token.
+  return token.startsWith("lib_") ? { "X-Api-Key": token } : { Authorization: `Bearer ${token}` };
+}
+
+async function api(method, path, body) {
+  const headers = credentials();
+  if (body !== undefined) headers["Content-Type"] = "application/json";
+  const response = await fetch(path, {
+    method,
+    headers,
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+  const text = await response.text();
+  let data = text;
+  try {
+    data = JSON.parse(text);
+  } catch {
+    // Plain-text error message.
+  }
+  if (!response.ok) {
+    const message = typeof data === "string" ? data : data.message || JSON.stringify(data);
+    throw new Error(`${response.status}: ${message}`);
+  }
+  return data;
+}
+
+function showStatus(message, isError = false) {
+  const status = $("status");
+  status.textContent = message;
+  status.className = isError ? "error" : "";
+}
+
+function cell(row, text) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  row.appendChild(td);
+  return td;
+}
+
+async function loadBooks() {
+  const params = new URLSearchParams({ page: state.page, limit: PAGE_SIZE, sort: "title" });
+  if (state.query) params.set("q", state.query);
+  const result = await api("GET", `/books?${params}`);
+  state.totalPages = Math.max(result.pagination.total_pages, 1);
+
+  const rows = $("book-rows");
+  rows.replaceChildren();
+  for (const book of result.data) {
+    const row = document.createElement("tr");
+    cell(row, book.title);
+    cell(row, book.author);
+    cell(row, book.year);
+    cell(row, book.isbn);
+    cell(row, book.available ? "Yes" : "On loan");
+    const edit = document.createElement("a");
+    edit.href = `#edit/${book.id}`;
+    edit.textContent = "Edit";
+    cell(row, "").appendChild(edit);
+    rows.appendChild(row);
+  }
+  $("page-info").textContent = `Page ${state.page} of ${state.totalPages} (${result.pagination.total_items} books)`;
+  $("previous").disabled = state.page <= 1;
+  $("next").disabled = state.page >= state.totalPages;
+}
+
+async function loadLoans() {
+  const loans = await api("GET", "/borrowings/overdue");
+  const rows = $("loan-rows");
+  rows.replaceChildren();
+  for (const loan of loans) {
+    const row = document.createElement("tr");
+    cell(row, `${loan.book_title} (${loan.book_author})`);
+    cell(row, loan.borrower_name);
+    cell(row, loan.borrowed_at.slice(0, 10));
+    cell(row, loan.due_date.slice(0, 10));
+    rows.appendChild(row);
+  }
+  if (loans.length === 0) showStatus("No overdue loans.");
+}
+
+async function openEditor(id) {
+  const form = $("book-form");
+  form.reset();
+  state.editing = id;
+  $("edit-heading").textContent = id ? "Edit book" : "Add book";
+  if (!id) return;
+  const book = await api("GET", `/books/${id}`);
+  for (const name of ["title", "author", "year", "isbn", "format", "description"]) {
+    form.elements[name].value = book[name] ?? "";
+  }
+}
+
+async function saveBook(event) {
+  event.preventDefault();
+  const form = event.target;
+  const book = {
+    title: form.elements.title.value,
+    author: form.elements.author.value,
+    year: Number(form.elements.year.value),
+    isbn: form.elements.isbn.value,
+    format: form.elements.format.value,
+  };
+  const description = form.elements.description.value.trim();
+  if (description) book.description = description;
+  try {
+    const saved = state.editing
+      ? await api("PUT", `/books/${state.editing}`, book)
+      : await api("POST", "/books?allow_duplicates=true", book);
+    showStatus(`Saved "${saved.title}".`);
+    location.hash = `#edit/${saved.id}`;
+  } catch (error) {
+    showStatus(error.message, true);
+  }
+}
+
+async function route() {
+  showStatus("");
+  const [view, id] = location.hash.slice(1).split("/");
+  $("books-view").hidden = view !== "books" && view !== "";
+  $("edit-view").hidden = view !== "new" && view !== "edit";
+  $("loans-view").hidden = view !== "loans";
+  try {
+    if (view === "loans") await loadLoans();
+    else if (view === "new" || view === "edit") await openEditor(id ? Number(id) : null);
+    else await loadBooks();
+  } catch (error) {
+    showStatus(error.message, true);
+  }
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  $("token").value = sessionStorage.getItem("token") || "";
+  $("credentials").addEventListener("submit", (event) => {
+    event.preventDefault();
+    sessionStorage.setItem("token", $("token").value.trim());
+    route();
+  });
+  $("search").addEventListener("submit", (event) => {
+    event.preventDefault();
+    state.query = $("query").value.trim();
+    state.page = 1;
+    loadBooks().catch((error) => showStatus(error.message, true));
+  });
+  $("previous").addEventListener("click", () => {
+    state.page -= 1;
+    loadBooks().catch((error) => showStatus(error.message, true));
+  });
+  $("next").addEventListener("click", () => {
+    state.page += 1;
+    loadBooks().catch((error) => showStatus(error.message, true));
+  });
+  $("book-form").addEventListener("submit", saveBook);
+  window.addEventListener("hashchange", route);
+  route();
+});
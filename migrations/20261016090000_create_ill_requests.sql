@@ -0,0 +1,17 @@
+ALTER TABLE books ADD COLUMN temporary BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS ill_requests (
+    id              BIGSERIAL   PRIMARY KEY,
+    patron_name     TEXT        NOT NULL,
+    title           TEXT        NOT NULL,
+    author          TEXT        NOT NULL,
+    year            BIGINT,
+    isbn            TEXT,
+    status          TEXT        NOT NULL,
+    lending_library TEXT,
+    tracking_number TEXT,
+    due_date        TIMESTAMPTZ,
+    book_id         BIGINT      REFERENCES books(id) ON DELETE SET NULL,
+    requested_at    TIMESTAMPTZ NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,25 @@
+CREATE SEQUENCE IF NOT EXISTS copy_barcode_seq;
+
+CREATE TABLE IF NOT EXISTS copies (
+    id         BIGSERIAL   PRIMARY KEY,
+    book_id    BIGINT      NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    barcode    TEXT        NOT NULL UNIQUE DEFAULT ('3' || lpad(nextval('copy_barcode_seq')::text, 13, '0')),
+    status     TEXT        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS acquisition_requests (
+    id           BIGSERIAL   PRIMARY KEY,
+    title        TEXT        NOT NULL,
+    author       TEXT        NOT NULL,
+    year         BIGINT,
+    isbn         TEXT,
+    requested_by TEXT        NOT NULL,
+    reason       TEXT,
+    status       TEXT        NOT NULL,
+    quantity     INTEGER     NOT NULL DEFAULT 1,
+    staff_notes  TEXT,
+    book_id      BIGINT      REFERENCES books(id) ON DELETE SET NULL,
+    requested_at TIMESTAMPTZ NOT NULL,
+    updated_at   TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS vendors (
+    id            BIGSERIAL   PRIMARY KEY,
+    name          TEXT        NOT NULL,
+    contact_name  TEXT,
+    email         TEXT,
+    phone         TEXT,
+    address       TEXT,
+    terms         TEXT,
+    created_at    TIMESTAMPTZ NOT NULL
+);
+
+ALTER TABLE acquisition_requests
+    ADD COLUMN vendor_id  BIGINT REFERENCES vendors(id),
+    ADD COLUMN ordered_at TIMESTAMPTZ;
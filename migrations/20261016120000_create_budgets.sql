@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS budgets (
+    id              BIGSERIAL   PRIMARY KEY,
+    fiscal_year     INTEGER     NOT NULL,
+    fund_code       TEXT        NOT NULL,
+    allocated_cents BIGINT      NOT NULL,
+    committed_cents BIGINT      NOT NULL DEFAULT 0,
+    spent_cents     BIGINT      NOT NULL DEFAULT 0,
+    created_at      TIMESTAMPTZ NOT NULL,
+    UNIQUE (fiscal_year, fund_code)
+);
+
+ALTER TABLE acquisition_requests
+    ADD COLUMN budget_id        BIGINT REFERENCES budgets(id),
+    ADD COLUMN unit_price_cents BIGINT;
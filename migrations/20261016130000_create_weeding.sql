@@ -0,0 +1,21 @@
+ALTER TABLE copies ADD COLUMN IF NOT EXISTS condition TEXT NOT NULL DEFAULT 'good';
+
+CREATE TABLE IF NOT EXISTS weeding_candidates (
+    id             BIGSERIAL   PRIMARY KEY,
+    copy_id        BIGINT      NOT NULL REFERENCES copies(id) ON DELETE CASCADE,
+    reason         TEXT        NOT NULL,
+    status         TEXT        NOT NULL,
+    last_loaned_at TIMESTAMPTZ,
+    reviewed_by    TEXT,
+    review_notes   TEXT,
+    discard_reason TEXT,
+    flagged_at     TIMESTAMPTZ NOT NULL,
+    reviewed_at    TIMESTAMPTZ,
+    discarded_at   TIMESTAMPTZ
+);
+
+-- A copy can only be under review once at a time; retained or discarded
+-- candidates are history and don't block a later flag.
+CREATE UNIQUE INDEX IF NOT EXISTS weeding_candidates_open_copy
+    ON weeding_candidates (copy_id)
+    WHERE status IN ('flagged', 'approved');
@@ -0,0 +1,15 @@
+CREATE SEQUENCE IF NOT EXISTS member_card_seq;
+
+CREATE TABLE IF NOT EXISTS members (
+    id          BIGSERIAL   PRIMARY KEY,
+    card_number TEXT        NOT NULL UNIQUE DEFAULT ('2' || lpad(nextval('member_card_seq')::text, 13, '0')),
+    name        TEXT        NOT NULL,
+    email       TEXT,
+    created_at  TIMESTAMPTZ NOT NULL
+);
+
+ALTER TABLE borrowings
+    ADD COLUMN IF NOT EXISTS copy_id   BIGINT REFERENCES copies(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS member_id BIGINT REFERENCES members(id);
+
+CREATE INDEX IF NOT EXISTS borrowings_open_copy ON borrowings (copy_id) WHERE returned_at IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE copies ADD COLUMN IF NOT EXISTS call_number TEXT;
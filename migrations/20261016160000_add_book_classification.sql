@@ -0,0 +1,10 @@
+ALTER TABLE books
+    ADD COLUMN IF NOT EXISTS classification_scheme TEXT,
+    ADD COLUMN IF NOT EXISTS classification        TEXT,
+    -- Normalized so that string order is shelf order; maintained by the API.
+    ADD COLUMN IF NOT EXISTS classification_key    TEXT;
+
+ALTER TABLE books ADD CONSTRAINT books_classification_complete
+    CHECK ((classification_scheme IS NULL) = (classification IS NULL));
+
+CREATE INDEX IF NOT EXISTS books_classification_key ON books (classification_scheme, classification_key);
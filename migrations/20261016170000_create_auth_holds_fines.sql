@@ -0,0 +1,35 @@
+ALTER TABLE members ADD COLUMN IF NOT EXISTS password_hash TEXT;
+
+-- Bearer tokens are stored hashed; the plaintext is only ever returned at login.
+CREATE TABLE IF NOT EXISTS sessions (
+    id         BIGSERIAL   PRIMARY KEY,
+    member_id  BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    token_hash TEXT        NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS holds (
+    id           BIGSERIAL   PRIMARY KEY,
+    book_id      BIGINT      NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    member_id    BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    status       TEXT        NOT NULL,
+    placed_at    TIMESTAMPTZ NOT NULL,
+    ready_at     TIMESTAMPTZ,
+    expires_at   TIMESTAMPTZ,
+    cancelled_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS holds_active_member_book
+    ON holds (member_id, book_id)
+    WHERE status IN ('waiting', 'ready');
+
+CREATE TABLE IF NOT EXISTS fines (
+    id           BIGSERIAL   PRIMARY KEY,
+    member_id    BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    borrowing_id BIGINT      REFERENCES borrowings(id) ON DELETE SET NULL,
+    amount_cents BIGINT      NOT NULL,
+    reason       TEXT        NOT NULL,
+    assessed_at  TIMESTAMPTZ NOT NULL,
+    paid_at      TIMESTAMPTZ
+);
@@ -0,0 +1,26 @@
+ALTER TABLE members ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ;
+
+-- Outbox of messages to members. A mailer picks up rows with no sent_at.
+CREATE TABLE IF NOT EXISTS notifications (
+    id         BIGSERIAL   PRIMARY KEY,
+    member_id  BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    channel    TEXT        NOT NULL,
+    recipient  TEXT        NOT NULL,
+    subject    TEXT        NOT NULL,
+    body       TEXT        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    sent_at    TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS notifications_pending ON notifications (created_at) WHERE sent_at IS NULL;
+
+-- Single-use tokens mailed to members, e.g. to confirm an email address.
+CREATE TABLE IF NOT EXISTS member_tokens (
+    id         BIGSERIAL   PRIMARY KEY,
+    member_id  BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    purpose    TEXT        NOT NULL,
+    token_hash TEXT        NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at    TIMESTAMPTZ
+);
@@ -0,0 +1,19 @@
+-- Failed login counters, keyed by account ("member:<id>") or client address
+-- ("ip:<addr>").
+CREATE TABLE IF NOT EXISTS login_throttles (
+    key            TEXT        PRIMARY KEY,
+    failures       INTEGER     NOT NULL,
+    last_failed_at TIMESTAMPTZ NOT NULL,
+    blocked_until  TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id         BIGSERIAL   PRIMARY KEY,
+    event      TEXT        NOT NULL,
+    member_id  BIGINT      REFERENCES members(id) ON DELETE SET NULL,
+    ip         TEXT,
+    detail     TEXT        NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_member ON audit_log (member_id, created_at);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS terms_versions (
+    id           BIGSERIAL   PRIMARY KEY,
+    version      TEXT        NOT NULL UNIQUE,
+    body         TEXT        NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS terms_acceptances (
+    member_id   BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    version     TEXT        NOT NULL REFERENCES terms_versions(version),
+    accepted_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (member_id, version)
+);
@@ -0,0 +1,16 @@
+-- Category notifications (reminders, hold pickups) respect member
+-- preferences; account messages such as verification have no category.
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS category TEXT;
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS borrowing_id BIGINT REFERENCES borrowings(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS notifications_borrowing_category ON notifications (borrowing_id, category);
+
+-- Members without a row get email for every category.
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    member_id   BIGINT      PRIMARY KEY REFERENCES members(id) ON DELETE CASCADE,
+    channel     TEXT        NOT NULL,
+    categories  TEXT[]      NOT NULL,
+    phone       TEXT,
+    webhook_url TEXT,
+    updated_at  TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,18 @@
+ALTER TABLE members ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'patron'
+    CHECK (role IN ('patron', 'staff', 'admin'));
+
+-- Base32 TOTP secret. Enrollment stores it with totp_enabled_at still NULL
+-- until the member proves their authenticator produces matching codes.
+ALTER TABLE members ADD COLUMN IF NOT EXISTS totp_secret TEXT;
+ALTER TABLE members ADD COLUMN IF NOT EXISTS totp_enabled_at TIMESTAMPTZ;
+-- Last accepted time step, so a code can't be replayed within its window.
+ALTER TABLE members ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id        BIGSERIAL   PRIMARY KEY,
+    member_id BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    code_hash TEXT        NOT NULL,
+    used_at   TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_member ON recovery_codes (member_id);
@@ -0,0 +1,10 @@
+ALTER TABLE books ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- Deleting a book changes the book list without leaving a row behind to
+-- carry the time, so it is tracked here.
+CREATE TABLE IF NOT EXISTS catalog_state (
+    id               BOOLEAN     PRIMARY KEY DEFAULT true CHECK (id),
+    books_deleted_at TIMESTAMPTZ
+);
+
+INSERT INTO catalog_state (id) VALUES (true) ON CONFLICT DO NOTHING;
@@ -0,0 +1,15 @@
+ALTER TABLE books ADD COLUMN slug TEXT UNIQUE;
+
+-- Existing books get `title-year`, numbered when several share one. New
+-- books get theirs from the application, which also folds accented letters.
+WITH bases AS (
+    SELECT id,
+           COALESCE(NULLIF(TRIM(BOTH '-' FROM LOWER(REGEXP_REPLACE(title, '[^A-Za-z0-9]+', '-', 'g'))), ''), 'book')
+               || '-' || year AS base
+    FROM books
+),
+numbered AS (
+    SELECT id, base, ROW_NUMBER() OVER (PARTITION BY base ORDER BY id) AS n FROM bases
+)
+UPDATE books SET slug = CASE WHEN n = 1 THEN base ELSE base || '-' || n END
+FROM numbered WHERE books.id = numbered.id;
@@ -0,0 +1,11 @@
+ALTER TABLE books ADD COLUMN description TEXT;
+
+CREATE TABLE IF NOT EXISTS book_translations (
+    book_id     BIGINT      NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    -- Lowercase language tag, e.g. `fr` or `pt-br`.
+    language    TEXT        NOT NULL,
+    title       TEXT        NOT NULL,
+    description TEXT,
+    updated_at  TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (book_id, language)
+);
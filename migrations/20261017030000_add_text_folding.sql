@@ -0,0 +1,8 @@
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+-- Lowercased with diacritics removed, so "Brontë" matches "bronte". Every
+-- text filter compares through this. Naming the dictionary makes the
+-- function safe to declare immutable (and so to index).
+CREATE OR REPLACE FUNCTION fold_text(value TEXT) RETURNS TEXT
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT
+    RETURN lower(public.unaccent('public.unaccent'::regdictionary, value));
@@ -0,0 +1,5 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Candidate lookup for duplicate detection uses the trigram `%` operator
+-- on folded titles.
+CREATE INDEX IF NOT EXISTS books_folded_title_trgm ON books USING GIN (fold_text(title) gin_trgm_ops);
@@ -0,0 +1,5 @@
+-- Translated works record the title and language they were translated from,
+-- and who translated them.
+ALTER TABLE books ADD COLUMN original_title TEXT;
+ALTER TABLE books ADD COLUMN original_language TEXT;
+ALTER TABLE books ADD COLUMN translator TEXT;
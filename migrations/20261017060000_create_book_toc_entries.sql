@@ -0,0 +1,8 @@
+-- A book's table of contents, one row per chapter in reading order.
+CREATE TABLE book_toc_entries (
+    book_id  BIGINT NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    title    TEXT NOT NULL,
+    page     BIGINT,
+    PRIMARY KEY (book_id, position)
+);
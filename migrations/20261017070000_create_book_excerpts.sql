@@ -0,0 +1,6 @@
+-- A short passage from each book for catalog preview panes.
+CREATE TABLE book_excerpts (
+    book_id    BIGINT PRIMARY KEY REFERENCES books(id) ON DELETE CASCADE,
+    text       TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
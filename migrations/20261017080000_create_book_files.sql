@@ -0,0 +1,16 @@
+-- E-book files for a book. The bytes live here rather than on the book so
+-- catalog reads never load them; downloads go through signed links.
+CREATE TABLE book_files (
+    id          BIGSERIAL PRIMARY KEY,
+    book_id     BIGINT NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    format      TEXT NOT NULL,
+    filename    TEXT NOT NULL,
+    access      TEXT NOT NULL,
+    size_bytes  BIGINT NOT NULL,
+    sha256      TEXT NOT NULL,
+    content     BYTEA NOT NULL,
+    uploaded_by BIGINT REFERENCES members(id) ON DELETE SET NULL,
+    uploaded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS book_files_book_id ON book_files (book_id);
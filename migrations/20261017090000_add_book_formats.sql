@@ -0,0 +1,7 @@
+-- What kind of item a catalog record describes, plus the details that only
+-- apply to audiobooks.
+ALTER TABLE books ADD COLUMN format TEXT NOT NULL DEFAULT 'print';
+ALTER TABLE books ADD COLUMN narrator TEXT;
+ALTER TABLE books ADD COLUMN duration_minutes BIGINT;
+ALTER TABLE books ADD COLUMN disc_count BIGINT;
+ALTER TABLE books ADD COLUMN file_count BIGINT;
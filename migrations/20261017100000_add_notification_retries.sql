@@ -0,0 +1,10 @@
+-- Delivery bookkeeping for the outbox. A send that fails is retried with
+-- exponential backoff; after too many attempts the row is marked failed and
+-- waits for staff to requeue it.
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS attempts        INT         NOT NULL DEFAULT 0;
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS last_error      TEXT;
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS failed_at       TIMESTAMPTZ;
+
+DROP INDEX IF EXISTS notifications_pending;
+CREATE INDEX IF NOT EXISTS notifications_due ON notifications (next_attempt_at) WHERE sent_at IS NULL AND failed_at IS NULL;
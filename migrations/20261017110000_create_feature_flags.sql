@@ -0,0 +1,7 @@
+-- Feature flags switched at runtime through the admin API. Flags without a
+-- row use the FEATURES setting or their built-in default.
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name       TEXT        PRIMARY KEY,
+    enabled    BOOLEAN     NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
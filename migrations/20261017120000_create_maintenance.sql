@@ -0,0 +1,7 @@
+-- At most one row: while it exists the API is in maintenance mode.
+CREATE TABLE IF NOT EXISTS maintenance (
+    singleton        BOOLEAN     PRIMARY KEY DEFAULT true CHECK (singleton),
+    message          TEXT        NOT NULL,
+    retry_after_secs INT         NOT NULL,
+    started_at       TIMESTAMPTZ NOT NULL DEFAULT now()
+);
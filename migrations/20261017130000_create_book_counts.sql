@@ -0,0 +1,47 @@
+-- Number of books per (available, year), kept current by triggers, so
+-- counts for the common list filters (none, availability, year, or both)
+-- are a sum over a few small rows instead of a scan of the catalog.
+CREATE TABLE IF NOT EXISTS book_counts (
+    available BOOLEAN NOT NULL,
+    year      BIGINT  NOT NULL,
+    books     BIGINT  NOT NULL,
+    PRIMARY KEY (available, year)
+);
+
+CREATE OR REPLACE FUNCTION count_books_row() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE book_counts SET books = books - 1 WHERE available = OLD.available AND year = OLD.year;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        INSERT INTO book_counts (available, year, books) VALUES (NEW.available, NEW.year, 1)
+        ON CONFLICT (available, year) DO UPDATE SET books = book_counts.books + 1;
+    END IF;
+    RETURN NULL;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION clear_book_counts() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    DELETE FROM book_counts;
+    RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS books_count_insert_delete ON books;
+CREATE TRIGGER books_count_insert_delete AFTER INSERT OR DELETE ON books
+    FOR EACH ROW EXECUTE FUNCTION count_books_row();
+
+DROP TRIGGER IF EXISTS books_count_update ON books;
+CREATE TRIGGER books_count_update AFTER UPDATE OF available, year ON books
+    FOR EACH ROW WHEN (OLD.available IS DISTINCT FROM NEW.available OR OLD.year IS DISTINCT FROM NEW.year)
+    EXECUTE FUNCTION count_books_row();
+
+DROP TRIGGER IF EXISTS books_count_truncate ON books;
+CREATE TRIGGER books_count_truncate AFTER TRUNCATE ON books
+    FOR EACH STATEMENT EXECUTE FUNCTION clear_book_counts();
+
+-- Start from the books already there.
+DELETE FROM book_counts;
+INSERT INTO book_counts (available, year, books)
+SELECT available, year, COUNT(*) FROM books GROUP BY available, year;
@@ -0,0 +1,17 @@
+-- Bumped by every statement that changes books, including loans and
+-- translations (which touch the book), so cached list results can tell
+-- they are stale. The counter is bumped in the writing transaction, so it
+-- becomes visible together with the change.
+ALTER TABLE catalog_state ADD COLUMN IF NOT EXISTS generation BIGINT NOT NULL DEFAULT 0;
+
+CREATE OR REPLACE FUNCTION bump_catalog_generation() RETURNS trigger AS $$
+BEGIN
+    UPDATE catalog_state SET generation = generation + 1;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS books_generation ON books;
+CREATE TRIGGER books_generation
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON books
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_catalog_generation();
@@ -0,0 +1,3 @@
+-- `year`, `year_from`/`year_to`, and `filter=year:...` become index range
+-- scans instead of reading every book.
+CREATE INDEX IF NOT EXISTS books_year_idx ON books (year);
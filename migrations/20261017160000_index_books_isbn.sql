@@ -0,0 +1,3 @@
+-- ISBN lookups (`GET /books/isbn/{isbn}`, receiving acquisitions) compare
+-- ISBNs without hyphens. Not unique: separate records may share an ISBN.
+CREATE INDEX IF NOT EXISTS books_isbn_idx ON books (REPLACE(isbn, '-', ''));
@@ -0,0 +1,3 @@
+-- Members who sign in through LDAP, by their directory login name
+-- (lower-cased). They have no local password.
+ALTER TABLE members ADD COLUMN IF NOT EXISTS directory_username TEXT UNIQUE;
@@ -0,0 +1,9 @@
+-- Members who sign in through a SAML identity provider, by their NameID.
+ALTER TABLE members ADD COLUMN IF NOT EXISTS saml_subject TEXT UNIQUE;
+
+-- Assertions already used to sign in, kept until they expire so a captured
+-- response can't be posted again.
+CREATE TABLE IF NOT EXISTS saml_assertions (
+    id         TEXT        PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,11 @@
+-- One row per delivery attempt reported for a notification, so staff can
+-- see why a message keeps failing, not just the latest error.
+CREATE TABLE IF NOT EXISTS notification_deliveries (
+    id              BIGSERIAL   PRIMARY KEY,
+    notification_id BIGINT      NOT NULL REFERENCES notifications(id) ON DELETE CASCADE,
+    attempted_at    TIMESTAMPTZ NOT NULL,
+    delivered       BOOLEAN     NOT NULL,
+    error           TEXT
+);
+
+CREATE INDEX IF NOT EXISTS notification_deliveries_notification ON notification_deliveries (notification_id);
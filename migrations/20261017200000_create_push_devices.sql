@@ -0,0 +1,11 @@
+-- Mobile app installs registered for push notifications. A token belongs
+-- to one install, so it moves with whoever registers it last.
+CREATE TABLE IF NOT EXISTS push_devices (
+    id         BIGSERIAL   PRIMARY KEY,
+    member_id  BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    platform   TEXT        NOT NULL CHECK (platform IN ('fcm', 'apns')),
+    token      TEXT        NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS push_devices_member ON push_devices (member_id);
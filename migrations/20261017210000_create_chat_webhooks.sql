@@ -0,0 +1,27 @@
+-- Slack and Discord incoming webhooks for staff announcements, each with
+-- the events it wants.
+CREATE TABLE IF NOT EXISTS chat_webhooks (
+    id         BIGSERIAL   PRIMARY KEY,
+    name       TEXT        NOT NULL,
+    service    TEXT        NOT NULL CHECK (service IN ('slack', 'discord')),
+    url        TEXT        NOT NULL,
+    events     TEXT[]      NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+-- The outbox of formatted announcements, retried like notifications.
+CREATE TABLE IF NOT EXISTS chat_messages (
+    id              BIGSERIAL   PRIMARY KEY,
+    webhook_id      BIGINT      NOT NULL REFERENCES chat_webhooks(id) ON DELETE CASCADE,
+    event           TEXT        NOT NULL,
+    payload         TEXT        NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL,
+    sent_at         TIMESTAMPTZ,
+    attempts        INTEGER     NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL,
+    last_error      TEXT,
+    failed_at       TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS chat_messages_due ON chat_messages (next_attempt_at) WHERE sent_at IS NULL AND failed_at IS NULL;
+CREATE INDEX IF NOT EXISTS chat_messages_webhook ON chat_messages (webhook_id);
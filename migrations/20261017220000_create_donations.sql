@@ -0,0 +1,21 @@
+-- Donated items awaiting review, and what became of them.
+CREATE TABLE IF NOT EXISTS donations (
+    id                BIGSERIAL   PRIMARY KEY,
+    donor_name        TEXT        NOT NULL,
+    donor_email       TEXT,
+    title             TEXT        NOT NULL,
+    author            TEXT        NOT NULL,
+    year              BIGINT,
+    isbn              TEXT,
+    quantity          INTEGER     NOT NULL DEFAULT 1,
+    condition         TEXT,
+    notes             TEXT,
+    status            TEXT        NOT NULL,
+    accepted_quantity INTEGER,
+    decline_reason    TEXT,
+    book_id           BIGINT      REFERENCES books(id) ON DELETE SET NULL,
+    received_at       TIMESTAMPTZ NOT NULL,
+    reviewed_at       TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS donations_status ON donations (status, received_at);
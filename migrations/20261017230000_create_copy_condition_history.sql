@@ -0,0 +1,12 @@
+-- Grade changes per copy, and the grade recorded at each return.
+CREATE TABLE IF NOT EXISTS copy_condition_history (
+    id                 BIGSERIAL   PRIMARY KEY,
+    copy_id            BIGINT      NOT NULL REFERENCES copies(id) ON DELETE CASCADE,
+    condition          TEXT        NOT NULL,
+    previous_condition TEXT        NOT NULL,
+    borrowing_id       BIGINT      REFERENCES borrowings(id) ON DELETE SET NULL,
+    note               TEXT,
+    recorded_at        TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS copy_condition_history_copy ON copy_condition_history (copy_id, recorded_at);
@@ -0,0 +1,3 @@
+-- Minimum age for borrowing a title, and members' birthdates to check it against.
+ALTER TABLE books ADD COLUMN IF NOT EXISTS age_rating INTEGER;
+ALTER TABLE members ADD COLUMN IF NOT EXISTS birthdate DATE;
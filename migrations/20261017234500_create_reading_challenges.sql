@@ -0,0 +1,29 @@
+-- Members' yearly reading goals, and library-run reading challenges.
+CREATE TABLE IF NOT EXISTS reading_goals (
+    member_id  BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    year       INTEGER     NOT NULL,
+    target     INTEGER     NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (member_id, year)
+);
+
+CREATE TABLE IF NOT EXISTS reading_challenges (
+    id          BIGSERIAL   PRIMARY KEY,
+    name        TEXT        NOT NULL,
+    description TEXT,
+    target      INTEGER     NOT NULL,
+    format      TEXT,
+    starts_on   DATE        NOT NULL,
+    ends_on     DATE        NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS challenge_participants (
+    challenge_id        BIGINT      NOT NULL REFERENCES reading_challenges(id) ON DELETE CASCADE,
+    member_id           BIGINT      NOT NULL REFERENCES members(id) ON DELETE CASCADE,
+    show_on_leaderboard BOOLEAN     NOT NULL DEFAULT false,
+    joined_at           TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (challenge_id, member_id)
+);
+
+CREATE INDEX IF NOT EXISTS challenge_participants_member ON challenge_participants (member_id);
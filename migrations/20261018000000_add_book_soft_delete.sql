@@ -0,0 +1,27 @@
+-- Deleting a book marks it instead of removing the row, so a mistaken
+-- deletion can be undone and the book's loan history stays attached.
+ALTER TABLE books ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+-- book_counts only counts books still in the catalog.
+CREATE OR REPLACE FUNCTION count_books_row() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.deleted_at IS NULL THEN
+        UPDATE book_counts SET books = books - 1 WHERE available = OLD.available AND year = OLD.year;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.deleted_at IS NULL THEN
+        INSERT INTO book_counts (available, year, books) VALUES (NEW.available, NEW.year, 1)
+        ON CONFLICT (available, year) DO UPDATE SET books = book_counts.books + 1;
+    END IF;
+    RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS books_count_update ON books;
+CREATE TRIGGER books_count_update AFTER UPDATE OF available, year, deleted_at ON books
+    FOR EACH ROW WHEN (OLD.available IS DISTINCT FROM NEW.available OR OLD.year IS DISTINCT FROM NEW.year
+                       OR OLD.deleted_at IS DISTINCT FROM NEW.deleted_at)
+    EXECUTE FUNCTION count_books_row();
+
+DELETE FROM book_counts;
+INSERT INTO book_counts (available, year, books)
+SELECT available, year, COUNT(*) FROM books WHERE deleted_at IS NULL GROUP BY available, year;
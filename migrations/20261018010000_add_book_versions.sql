@@ -0,0 +1,3 @@
+-- Counts edits to each book, so a client can send back the version it
+-- edited (If-Match) and be refused if someone else has changed it since.
+ALTER TABLE books ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
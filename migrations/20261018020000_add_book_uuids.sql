@@ -0,0 +1,19 @@
+-- A UUID for every book, alongside the numeric id, for clients that
+-- shouldn't learn the catalog's size from its ids or that merge records
+-- from several servers. Version 7, so they still sort by creation time.
+CREATE OR REPLACE FUNCTION uuid_generate_v7() RETURNS uuid LANGUAGE sql VOLATILE AS $$
+    -- A random (v4) UUID with the first 48 bits replaced by the Unix time in
+    -- milliseconds and the version nibble changed from 4 to 7.
+    SELECT encode(
+        set_bit(
+            set_bit(
+                overlay(uuid_send(gen_random_uuid())
+                        PLACING substring(int8send(floor(extract(epoch FROM clock_timestamp()) * 1000)::bigint) FROM 3)
+                        FROM 1 FOR 6),
+                52, 1),
+            53, 1),
+        'hex')::uuid;
+$$;
+
+ALTER TABLE books ADD COLUMN IF NOT EXISTS uuid UUID NOT NULL DEFAULT uuid_generate_v7();
+CREATE UNIQUE INDEX IF NOT EXISTS books_uuid ON books (uuid);
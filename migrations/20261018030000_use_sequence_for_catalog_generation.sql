@@ -0,0 +1,19 @@
+-- The catalog generation moves to a sequence. Bumping a counter row made
+-- every transaction that wrote books hold that row's lock until it
+-- committed, so catalog writes ran one at a time; nextval takes no lock.
+--
+-- A sequence is bumped as soon as the statement runs, not at commit. Each
+-- writing transaction therefore also holds a shared advisory lock until it
+-- ends; readers only cache a result when they can briefly take the lock
+-- exclusively, i.e. when no bumped change is still waiting to commit.
+CREATE SEQUENCE IF NOT EXISTS catalog_generation;
+
+CREATE OR REPLACE FUNCTION bump_catalog_generation() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock_shared(hashtext('catalog_generation'));
+    PERFORM nextval('catalog_generation');
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE catalog_state DROP COLUMN IF EXISTS generation;
@@ -0,0 +1,5 @@
+-- One member per email address, compared case-insensitively as logins and
+-- password resets look them up. Without it, a reset for a shared address
+-- went to whichever member the lookup found first. Existing duplicates
+-- must be merged or cleared by hand before this migration can run.
+CREATE UNIQUE INDEX IF NOT EXISTS members_email_lower ON members (LOWER(email));
@@ -0,0 +1,7 @@
+-- Reviews and discards are recorded against the staff account that made
+-- them instead of a name sent with the review. Names typed in before this
+-- are kept as reviewer_name.
+ALTER TABLE weeding_candidates RENAME COLUMN reviewed_by TO reviewer_name;
+ALTER TABLE weeding_candidates
+    ADD COLUMN IF NOT EXISTS reviewed_by  BIGINT REFERENCES members(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS discarded_by BIGINT REFERENCES members(id) ON DELETE SET NULL;
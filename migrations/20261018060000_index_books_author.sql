@@ -0,0 +1,4 @@
+-- `author`, `filter=author:contains:...` and `author:starts_with:...` match
+-- folded authors with LIKE, which this trigram index answers instead of
+-- reading every book.
+CREATE INDEX IF NOT EXISTS books_folded_author_trgm ON books USING GIN (fold_text(author) gin_trgm_ops);
@@ -0,0 +1,5 @@
+-- ILL requests are made by a signed-in member and recorded against their
+-- account, so they can be shown to them, exported, and erased with it.
+-- Requests made before this keep only the patron_name they were sent with.
+ALTER TABLE ill_requests ADD COLUMN IF NOT EXISTS member_id BIGINT REFERENCES members(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS ill_requests_member_id_idx ON ill_requests (member_id);
@@ -0,0 +1,7 @@
+-- Purchase suggestions made while signed in, and donations from members,
+-- are recorded against the member's account so erasing the member can
+-- clear the names and addresses typed in with them.
+ALTER TABLE acquisition_requests ADD COLUMN IF NOT EXISTS member_id BIGINT REFERENCES members(id) ON DELETE SET NULL;
+ALTER TABLE donations ADD COLUMN IF NOT EXISTS member_id BIGINT REFERENCES members(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS acquisition_requests_member_id_idx ON acquisition_requests (member_id);
+CREATE INDEX IF NOT EXISTS donations_member_id_idx ON donations (member_id);
@@ -0,0 +1,449 @@
+use std::{fmt, str::FromStr};
+
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, auth::AuthMember, budgets, chat, copies, query::Query, slug, validation::validate_optional_bibliographic};
+
+const MAX_QUANTITY: i32 = 100;
+
+/// Lifecycle of a purchase suggestion: a patron suggests a title, staff
+/// triage it, place the order, and finally receive the physical items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcquisitionStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Ordered,
+    Received,
+    Cancelled,
+}
+
+impl AcquisitionStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            AcquisitionStatus::Pending => "pending",
+            AcquisitionStatus::Approved => "approved",
+            AcquisitionStatus::Rejected => "rejected",
+            AcquisitionStatus::Ordered => "ordered",
+            AcquisitionStatus::Received => "received",
+            AcquisitionStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn can_become(self, next: AcquisitionStatus) -> bool {
+        use AcquisitionStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Approved)
+                | (Pending, Rejected)
+                | (Approved, Ordered)
+                | (Approved, Cancelled)
+                | (Ordered, Received)
+                | (Ordered, Cancelled)
+        )
+    }
+}
+
+impl fmt::Display for AcquisitionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AcquisitionStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(AcquisitionStatus::Pending),
+            "approved" => Ok(AcquisitionStatus::Approved),
+            "rejected" => Ok(AcquisitionStatus::Rejected),
+            "ordered" => Ok(AcquisitionStatus::Ordered),
+            "received" => Ok(AcquisitionStatus::Received),
+            "cancelled" => Ok(AcquisitionStatus::Cancelled),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcquisitionRequest {
+    pub id: i64,
+    pub title: String,
+    pub author: String,
+    pub year: Option<i64>,
+    pub isbn: Option<String>,
+    pub requested_by: String,
+    pub reason: Option<String>,
+    pub status: AcquisitionStatus,
+    pub quantity: i32,
+    pub staff_notes: Option<String>,
+    /// The catalog record the received copies were attached to.
+    pub book_id: Option<i64>,
+    pub requested_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub vendor_id: Option<i64>,
+    pub ordered_at: Option<DateTime<Utc>>,
+    /// The fund the order is paid from.
+    pub budget_id: Option<i64>,
+    pub unit_price_cents: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuggestPurchase {
+    title: String,
+    author: String,
+    year: Option<i64>,
+    isbn: Option<String>,
+    /// Defaults to the member's name when the suggestion is made signed in.
+    requested_by: Option<String>,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAcquisition {
+    status: Option<AcquisitionStatus>,
+    quantity: Option<i32>,
+    staff_notes: Option<String>,
+    year: Option<i64>,
+    isbn: Option<String>,
+    vendor_id: Option<i64>,
+    budget_id: Option<i64>,
+    unit_price_cents: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcquisitionParams {
+    status: Option<AcquisitionStatus>,
+}
+
+struct AcquisitionRow {
+    id: i64,
+    title: String,
+    author: String,
+    year: Option<i64>,
+    isbn: Option<String>,
+    requested_by: String,
+    reason: Option<String>,
+    status: String,
+    quantity: i32,
+    staff_notes: Option<String>,
+    book_id: Option<i64>,
+    requested_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    vendor_id: Option<i64>,
+    ordered_at: Option<DateTime<Utc>>,
+    budget_id: Option<i64>,
+    unit_price_cents: Option<i64>,
+}
+
+impl From<AcquisitionRow> for AcquisitionRequest {
+    fn from(r: AcquisitionRow) -> Self {
+        AcquisitionRequest {
+            id: r.id,
+            title: r.title,
+            author: r.author,
+            year: r.year,
+            isbn: r.isbn,
+            requested_by: r.requested_by,
+            reason: r.reason,
+            status: r.status.parse().unwrap_or(AcquisitionStatus::Pending),
+            quantity: r.quantity,
+            staff_notes: r.staff_notes,
+            book_id: r.book_id,
+            requested_at: r.requested_at,
+            updated_at: r.updated_at,
+            vendor_id: r.vendor_id,
+            ordered_at: r.ordered_at,
+            budget_id: r.budget_id,
+            unit_price_cents: r.unit_price_cents,
+        }
+    }
+}
+
+/// Open to anyone. A suggestion made signed in is recorded against the
+/// member, so it goes when they are erased.
+pub async fn suggest_purchase(
+    State(pool): State<PgPool>,
+    member: Result<AuthMember, AppError>,
+    Json(input): Json<SuggestPurchase>,
+) -> Result<(StatusCode, Json<AcquisitionRequest>), AppError> {
+    let member = member.ok().map(|AuthMember(m)| m);
+    let requested_by = input
+        .requested_by
+        .or_else(|| member.as_ref().map(|m| m.name.clone()))
+        .unwrap_or_default();
+    if input.title.trim().is_empty() || input.author.trim().is_empty() || requested_by.trim().is_empty() {
+        return Err(AppError::InvalidInput("title, author, and requested_by must not be empty".to_string()));
+    }
+    validate_optional_bibliographic(input.year, input.isbn.as_deref())?;
+
+    let now = Utc::now();
+    let row = sqlx::query_as!(
+        AcquisitionRow,
+        "INSERT INTO acquisition_requests
+            (title, author, year, isbn, requested_by, member_id, reason, status, requested_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)
+         RETURNING id, title, author, year, isbn, requested_by, reason, status, quantity,
+                   staff_notes, book_id, requested_at, updated_at, vendor_id, ordered_at, budget_id, unit_price_cents",
+        input.title,
+        input.author,
+        input.year,
+        input.isbn,
+        requested_by,
+        member.map(|m| m.id),
+        input.reason,
+        AcquisitionStatus::Pending.as_str(),
+        now,
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+pub async fn list_acquisitions(
+    State(pool): State<PgPool>,
+    Query(params): Query<AcquisitionParams>,
+) -> Result<Json<Vec<AcquisitionRequest>>, AppError> {
+    let rows = sqlx::query_as!(
+        AcquisitionRow,
+        "SELECT id, title, author, year, isbn, requested_by, reason, status, quantity,
+                staff_notes, book_id, requested_at, updated_at, vendor_id, ordered_at, budget_id, unit_price_cents
+         FROM acquisition_requests
+         WHERE ($1::text IS NULL OR status = $1)
+         ORDER BY id",
+        params.status.map(AcquisitionStatus::as_str),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(AcquisitionRequest::from).collect()))
+}
+
+pub async fn get_acquisition(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<AcquisitionRequest>, AppError> {
+    let row = sqlx::query_as!(
+        AcquisitionRow,
+        "SELECT id, title, author, year, isbn, requested_by, reason, status, quantity,
+                staff_notes, book_id, requested_at, updated_at, vendor_id, ordered_at, budget_id, unit_price_cents
+         FROM acquisition_requests WHERE id = $1",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Acquisition request", id))?;
+
+    Ok(Json(row.into()))
+}
+
+/// Staff-side triage. Moving to `received` creates (or reuses, matched by
+/// ISBN) the catalog record and adds `quantity` copies to it.
+pub async fn update_acquisition(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateAcquisition>,
+) -> Result<Json<AcquisitionRequest>, AppError> {
+    if !staff.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can triage purchase suggestions".to_string()));
+    }
+    validate_optional_bibliographic(input.year, input.isbn.as_deref())?;
+    if input.quantity.is_some_and(|q| !(1..=MAX_QUANTITY).contains(&q)) {
+        return Err(AppError::InvalidInput(format!("quantity must be between 1 and {}", MAX_QUANTITY)));
+    }
+    if input.unit_price_cents.is_some_and(|p| p < 0) {
+        return Err(AppError::InvalidInput("unit_price_cents must not be negative".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let current: AcquisitionRequest = sqlx::query_as!(
+        AcquisitionRow,
+        "SELECT id, title, author, year, isbn, requested_by, reason, status, quantity,
+                staff_notes, book_id, requested_at, updated_at, vendor_id, ordered_at, budget_id, unit_price_cents
+         FROM acquisition_requests WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Acquisition request", id))?
+    .into();
+
+    let year = input.year.or(current.year);
+    let isbn = input.isbn.or(current.isbn);
+    let quantity = input.quantity.unwrap_or(current.quantity);
+    let vendor_id = input.vendor_id.or(current.vendor_id);
+    let mut book_id = current.book_id;
+    let mut ordered_at = current.ordered_at;
+
+    if let Some(vendor_id) = input.vendor_id {
+        let vendor_exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM vendors WHERE id = $1)", vendor_id)
+            .fetch_one(&mut *tx)
+            .await?
+            .unwrap_or(false);
+        if !vendor_exists {
+            return Err(AppError::InvalidInput(format!("Vendor with ID {} does not exist", vendor_id)));
+        }
+    }
+
+    let budget_id = input.budget_id.or(current.budget_id);
+    let unit_price_cents = input.unit_price_cents.or(current.unit_price_cents);
+
+    if let Some(budget_id) = input.budget_id
+        && !budgets::exists(&mut tx, budget_id).await?
+    {
+        return Err(AppError::InvalidInput(format!("Budget with ID {} does not exist", budget_id)));
+    }
+
+    // Once funds are committed the order's cost is fixed, otherwise the
+    // budget's committed and spent totals would drift.
+    let funds_committed = matches!(current.status, AcquisitionStatus::Ordered | AcquisitionStatus::Received);
+    if funds_committed
+        && (input.quantity.is_some() || input.budget_id.is_some() || input.unit_price_cents.is_some())
+    {
+        return Err(AppError::Conflict(format!(
+            "Acquisition request with ID {} has been ordered; quantity, budget, and price can no longer change",
+            id
+        )));
+    }
+    let order_cost = budget_id.zip(unit_price_cents).map(|(b, price)| (b, price * i64::from(quantity)));
+
+    if let Some(next) = input.status.filter(|s| *s != current.status) {
+        if !current.status.can_become(next) {
+            return Err(AppError::Conflict(format!(
+                "Acquisition request with ID {} cannot move from {} to {}",
+                id, current.status, next
+            )));
+        }
+
+        if next == AcquisitionStatus::Ordered {
+            if budget_id.is_some() && unit_price_cents.is_none() {
+                return Err(AppError::InvalidInput(
+                    "unit_price_cents is required to order against a budget".to_string(),
+                ));
+            }
+            if let Some((budget_id, cost)) = order_cost {
+                budgets::commit(&mut tx, budget_id, cost).await?;
+            }
+            ordered_at = Some(Utc::now());
+        }
+
+        if next == AcquisitionStatus::Cancelled
+            && current.status == AcquisitionStatus::Ordered
+            && let Some((budget_id, cost)) = order_cost
+        {
+            budgets::release(&mut tx, budget_id, cost).await?;
+        }
+
+        if next == AcquisitionStatus::Received {
+            let (Some(year), Some(isbn)) = (year, isbn.as_deref()) else {
+                return Err(AppError::InvalidInput(
+                    "year and isbn are required before an order can be received".to_string(),
+                ));
+            };
+
+            let received_book_id = catalog_record(&mut tx, &current.title, &current.author, year, isbn).await?;
+            copies::insert_copies(&mut tx, received_book_id, quantity.into()).await?;
+            book_id = Some(received_book_id);
+
+            if let Some((budget_id, cost)) = order_cost {
+                budgets::spend(&mut tx, budget_id, cost).await?;
+            }
+        }
+    }
+
+    let row = sqlx::query_as!(
+        AcquisitionRow,
+        "UPDATE acquisition_requests
+         SET status      = $1,
+             quantity    = $2,
+             staff_notes = COALESCE($3, staff_notes),
+             year        = $4,
+             isbn        = $5,
+             book_id     = $6,
+             vendor_id   = $7,
+             ordered_at  = $8,
+             budget_id   = $9,
+             unit_price_cents = $10,
+             updated_at  = $11
+         WHERE id = $12
+         RETURNING id, title, author, year, isbn, requested_by, reason, status, quantity,
+                   staff_notes, book_id, requested_at, updated_at, vendor_id, ordered_at, budget_id, unit_price_cents",
+        input.status.unwrap_or(current.status).as_str(),
+        quantity,
+        input.staff_notes,
+        year,
+        isbn,
+        book_id,
+        vendor_id,
+        ordered_at,
+        budget_id,
+        unit_price_cents,
+        Utc::now(),
+        id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(row.into()))
+}
+
+pub async fn orders_for_vendor(pool: &PgPool, vendor_id: i64) -> Result<Vec<AcquisitionRequest>, AppError> {
+    let rows = sqlx::query_as!(
+        AcquisitionRow,
+        "SELECT id, title, author, year, isbn, requested_by, reason, status, quantity,
+                staff_notes, book_id, requested_at, updated_at, vendor_id, ordered_at, budget_id, unit_price_cents
+         FROM acquisition_requests
+         WHERE vendor_id = $1
+         ORDER BY ordered_at DESC NULLS LAST, id DESC",
+        vendor_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(AcquisitionRequest::from).collect())
+}
+
+/// The catalog record arriving items belong to: the book with the same
+/// ISBN, or a new one (announced to staff chat).
+pub(crate) async fn catalog_record(
+    conn: &mut PgConnection,
+    title: &str,
+    author: &str,
+    year: i64,
+    isbn: &str,
+) -> Result<i64, AppError> {
+    let existing = sqlx::query_scalar!(
+        "SELECT id FROM books WHERE REPLACE(isbn, '-', '') = REPLACE($1, '-', '') AND deleted_at IS NULL ORDER BY id LIMIT 1",
+        isbn
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(existing_id) = existing {
+        return Ok(existing_id);
+    }
+
+    let new_id = sqlx::query_scalar!(
+        "INSERT INTO books (title, author, year, isbn, available)
+         VALUES ($1, $2, $3, $4, true)
+         RETURNING id",
+        title,
+        author,
+        year,
+        isbn,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    slug::assign(&mut *conn, new_id, title, year).await?;
+    chat::announce(conn, chat::Announcement::BookAdded { title, author, year }).await?;
+    Ok(new_id)
+}
@@ -0,0 +1,104 @@
+//! Minimum borrowing ages. A book's `age_rating` is the age a member must
+//! have reached to check it out; `AGE_RESTRICTIONS` decides how strictly
+//! that is applied at the circulation desk and self-check kiosks.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use sqlx::PgConnection;
+
+use crate::{AppError, members::Member};
+
+/// Highest rating a book can carry.
+pub const MAX_AGE_RATING: i32 = 21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AgePolicy {
+    /// Ratings are informational only.
+    Off,
+    /// Members with a birthdate on file must be old enough; members without
+    /// one are let through.
+    #[default]
+    Enforce,
+    /// Rated titles also need a birthdate on file.
+    Strict,
+}
+
+impl AgePolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            AgePolicy::Off => "off",
+            AgePolicy::Enforce => "enforce",
+            AgePolicy::Strict => "strict",
+        }
+    }
+}
+
+impl fmt::Display for AgePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AgePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(AgePolicy::Off),
+            "enforce" => Ok(AgePolicy::Enforce),
+            "strict" => Ok(AgePolicy::Strict),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn validate_rating(rating: Option<i32>) -> Result<(), AppError> {
+    if rating.is_some_and(|r| !(0..=MAX_AGE_RATING).contains(&r)) {
+        return Err(AppError::InvalidInput(format!("age_rating must be between 0 and {}", MAX_AGE_RATING)));
+    }
+    Ok(())
+}
+
+pub fn validate_birthdate(birthdate: Option<NaiveDate>) -> Result<(), AppError> {
+    if birthdate.is_some_and(|b| b > Utc::now().date_naive()) {
+        return Err(AppError::InvalidInput("birthdate must not be in the future".to_string()));
+    }
+    Ok(())
+}
+
+/// Age in whole years on `today`.
+pub fn age_on(birthdate: NaiveDate, today: NaiveDate) -> i32 {
+    let years = today.year() - birthdate.year();
+    if (today.month(), today.day()) < (birthdate.month(), birthdate.day()) { years - 1 } else { years }
+}
+
+/// Refuses a checkout of `book_id` the policy doesn't allow `member` to make.
+pub async fn require_old_enough(
+    conn: &mut PgConnection,
+    policy: AgePolicy,
+    member: &Member,
+    book_id: i64,
+) -> Result<(), AppError> {
+    if policy == AgePolicy::Off {
+        return Ok(());
+    }
+    let rating = sqlx::query_scalar!("SELECT age_rating FROM books WHERE id = $1", book_id)
+        .fetch_one(conn)
+        .await?;
+    let Some(rating) = rating.filter(|r| *r > 0) else {
+        return Ok(());
+    };
+
+    match member.birthdate {
+        Some(birthdate) if age_on(birthdate, Utc::now().date_naive()) < rating => Err(AppError::Forbidden(format!(
+            "This title is rated {}+ and the member is under {}",
+            rating, rating
+        ))),
+        None if policy == AgePolicy::Strict => Err(AppError::Forbidden(format!(
+            "This title is rated {}+; the member needs a birthdate on file to borrow it",
+            rating
+        ))),
+        _ => Ok(()),
+    }
+}
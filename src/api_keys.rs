@@ -0,0 +1,444 @@
+//! API keys for integrations (kiosks, discovery layers, scripts). Admins
+//! create, label, scope, rotate, and revoke them; clients send one as
+//! `X-Api-Key`. Only a hash of each key is stored.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    Json,
+    extract::{FromRef, FromRequestParts, Path, State},
+    http::{HeaderMap, StatusCode, request::Parts},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    auth::{self, AuthMember},
+    config::AuthConfig,
+    members::MemberRole,
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const KEY_PREFIX: &str = "lib_";
+/// Characters of the key kept in the clear for display.
+const DISPLAY_PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Catalog and availability lookups, which are public; for keys that
+    /// only look things up.
+    Read,
+    /// Catalog changes.
+    Write,
+    /// Checkouts, returns, and holds.
+    Circulation,
+    /// Everything under `/admin`, including managing keys.
+    Admin,
+}
+
+impl ApiScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+            ApiScope::Circulation => "circulation",
+            ApiScope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "write" => Ok(ApiScope::Write),
+            "circulation" => Ok(ApiScope::Circulation),
+            "admin" => Ok(ApiScope::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub label: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+struct ApiKeyRow {
+    id: i64,
+    label: String,
+    prefix: String,
+    scopes: Vec<String>,
+    created_by: Option<i64>,
+    created_at: DateTime<Utc>,
+    rotated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(r: ApiKeyRow) -> Self {
+        ApiKey {
+            id: r.id,
+            label: r.label,
+            prefix: r.prefix,
+            scopes: r.scopes.iter().filter_map(|s| s.parse().ok()).collect(),
+            created_by: r.created_by,
+            created_at: r.created_at,
+            rotated_at: r.rotated_at,
+            last_used_at: r.last_used_at,
+            revoked_at: r.revoked_at,
+        }
+    }
+}
+
+/// Returned when a key is created or rotated; the only time the full key
+/// is shown.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IssuedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKey {
+    label: String,
+    scopes: Vec<ApiScope>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateApiKey {
+    label: Option<String>,
+    scopes: Option<Vec<ApiScope>>,
+}
+
+/// Who is calling an admin endpoint: an admin's session, or an API key with
+/// the `admin` scope.
+pub(crate) enum AdminAccess {
+    Member(i64),
+    Key,
+}
+
+impl AdminAccess {
+    pub(crate) fn member_id(&self) -> Option<i64> {
+        match self {
+            AdminAccess::Member(id) => Some(*id),
+            AdminAccess::Key => None,
+        }
+    }
+
+    /// Who acted, for audit details: `member 3` or `an admin API key`.
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            AdminAccess::Member(id) => format!("member {}", id),
+            AdminAccess::Key => "an admin API key".to_string(),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for AdminAccess
+where
+    PgPool: FromRef<S>,
+    AuthConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(API_KEY_HEADER) {
+            let pool = PgPool::from_ref(state);
+            authenticate(&pool, &parts.headers, ApiScope::Admin).await?;
+            return Ok(AdminAccess::Key);
+        }
+
+        let AuthMember(member) = AuthMember::from_request_parts(parts, state).await?;
+        if member.role != MemberRole::Admin {
+            return Err(AppError::Forbidden("This endpoint is for admins".to_string()));
+        }
+        Ok(AdminAccess::Member(member.id))
+    }
+}
+
+/// Who is working the circulation desk: a staff session, or an API key
+/// with the `circulation` scope, such as a self-checkout kiosk's.
+pub(crate) struct CirculationAccess;
+
+impl<S> FromRequestParts<S> for CirculationAccess
+where
+    PgPool: FromRef<S>,
+    AuthConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(API_KEY_HEADER) {
+            let pool = PgPool::from_ref(state);
+            authenticate(&pool, &parts.headers, ApiScope::Circulation).await?;
+            return Ok(CirculationAccess);
+        }
+
+        let AuthMember(member) = AuthMember::from_request_parts(parts, state).await?;
+        if !member.role.is_staff() {
+            return Err(AppError::Forbidden("This endpoint is for staff".to_string()));
+        }
+        Ok(CirculationAccess)
+    }
+}
+
+/// A catalog lookup. Lookups are open to everyone, so a request that sends
+/// `X-Api-Key` only needs the key to be active: a key never leaves its
+/// holder worse off than sending none.
+pub(crate) struct CatalogRead;
+
+/// A catalog change: a staff session, or an API key with the `write` scope.
+pub(crate) struct CatalogWrite;
+
+/// Checks that the key, if the request sent one, is active.
+pub(crate) async fn check_lookup_key(pool: &PgPool, headers: &HeaderMap) -> Result<(), AppError> {
+    if headers.contains_key(API_KEY_HEADER) {
+        resolve(pool, headers).await?;
+    }
+    Ok(())
+}
+
+impl<S> FromRequestParts<S> for CatalogRead
+where
+    PgPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        check_lookup_key(&PgPool::from_ref(state), &parts.headers).await?;
+        Ok(CatalogRead)
+    }
+}
+
+impl<S> FromRequestParts<S> for CatalogWrite
+where
+    PgPool: FromRef<S>,
+    AuthConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(API_KEY_HEADER) {
+            let pool = PgPool::from_ref(state);
+            authenticate(&pool, &parts.headers, ApiScope::Write).await?;
+            return Ok(CatalogWrite);
+        }
+
+        let AuthMember(member) = AuthMember::from_request_parts(parts, state).await?;
+        if !member.role.is_staff() {
+            return Err(AppError::Forbidden("Only staff can change the catalog".to_string()));
+        }
+        Ok(CatalogWrite)
+    }
+}
+
+/// Resolves the `X-Api-Key` header to an active key holding `scope`, and
+/// records the use.
+pub async fn authenticate(pool: &PgPool, headers: &HeaderMap, scope: ApiScope) -> Result<ApiKey, AppError> {
+    let api_key = resolve(pool, headers).await?;
+    if !api_key.scopes.contains(&scope) {
+        return Err(AppError::Forbidden(format!("The API key lacks the {} scope", scope)));
+    }
+    Ok(api_key)
+}
+
+/// Resolves the `X-Api-Key` header to an active key, whatever its scopes,
+/// and records the use.
+async fn resolve(pool: &PgPool, headers: &HeaderMap) -> Result<ApiKey, AppError> {
+    let key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| AppError::Unauthorized("An API key is required".to_string()))?;
+
+    Ok(sqlx::query_as!(
+        ApiKeyRow,
+        "UPDATE api_keys SET last_used_at = $1
+         WHERE key_hash = $2 AND revoked_at IS NULL
+         RETURNING id, label, prefix, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at",
+        Utc::now(),
+        auth::hash_token(key),
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::Unauthorized("The API key is invalid or has been revoked".to_string()))?
+    .into())
+}
+
+fn new_key() -> (String, String) {
+    let key = format!("{}{}", KEY_PREFIX, auth::new_token());
+    let prefix = key[..DISPLAY_PREFIX_LEN].to_string();
+    (key, prefix)
+}
+
+fn validate(label: Option<&str>, scopes: Option<&mut Vec<ApiScope>>) -> Result<(), AppError> {
+    if label.is_some_and(|l| l.trim().is_empty()) {
+        return Err(AppError::InvalidInput("API key label must not be empty".to_string()));
+    }
+    if let Some(scopes) = scopes {
+        if scopes.is_empty() {
+            return Err(AppError::InvalidInput("API keys need at least one scope".to_string()));
+        }
+        scopes.sort();
+        scopes.dedup();
+    }
+    Ok(())
+}
+
+fn scope_names(scopes: &[ApiScope]) -> Vec<String> {
+    scopes.iter().map(|s| s.as_str().to_string()).collect()
+}
+
+pub async fn create_api_key(
+    State(pool): State<PgPool>,
+    access: AdminAccess,
+    Json(mut input): Json<CreateApiKey>,
+) -> Result<(StatusCode, Json<IssuedApiKey>), AppError> {
+    validate(Some(&input.label), Some(&mut input.scopes))?;
+
+    let (key, prefix) = new_key();
+    let api_key = sqlx::query_as!(
+        ApiKeyRow,
+        "INSERT INTO api_keys (label, prefix, key_hash, scopes, created_by, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, label, prefix, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at",
+        input.label.trim(),
+        prefix,
+        auth::hash_token(&key),
+        &scope_names(&input.scopes),
+        access.member_id(),
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?
+    .into();
+
+    Ok((StatusCode::CREATED, Json(IssuedApiKey { key, api_key })))
+}
+
+pub async fn list_api_keys(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    let rows = sqlx::query_as!(
+        ApiKeyRow,
+        "SELECT id, label, prefix, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at
+         FROM api_keys ORDER BY id"
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(ApiKey::from).collect()))
+}
+
+pub async fn update_api_key(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+    Json(mut input): Json<UpdateApiKey>,
+) -> Result<Json<ApiKey>, AppError> {
+    validate(input.label.as_deref(), input.scopes.as_mut())?;
+    let scopes = input.scopes.as_deref().map(scope_names);
+
+    let api_key = sqlx::query_as!(
+        ApiKeyRow,
+        "UPDATE api_keys SET label = COALESCE($1, label), scopes = COALESCE($2, scopes)
+         WHERE id = $3
+         RETURNING id, label, prefix, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at",
+        input.label.as_deref().map(str::trim),
+        scopes.as_deref(),
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("API key", id))?
+    .into();
+
+    Ok(Json(api_key))
+}
+
+/// Replaces the key's secret. The old secret stops working immediately;
+/// label, scopes, and history carry over.
+pub async fn rotate_api_key(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<Json<IssuedApiKey>, AppError> {
+    let (key, prefix) = new_key();
+    let api_key = sqlx::query_as!(
+        ApiKeyRow,
+        "UPDATE api_keys SET prefix = $1, key_hash = $2, rotated_at = $3
+         WHERE id = $4 AND revoked_at IS NULL
+         RETURNING id, label, prefix, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at",
+        prefix,
+        auth::hash_token(&key),
+        Utc::now(),
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?;
+
+    match api_key {
+        Some(row) => Ok(Json(IssuedApiKey { key, api_key: row.into() })),
+        None => Err(revoked_or_missing(&pool, id).await),
+    }
+}
+
+pub async fn revoke_api_key(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<Json<ApiKey>, AppError> {
+    let api_key = sqlx::query_as!(
+        ApiKeyRow,
+        "UPDATE api_keys SET revoked_at = $1
+         WHERE id = $2 AND revoked_at IS NULL
+         RETURNING id, label, prefix, scopes, created_by, created_at, rotated_at, last_used_at, revoked_at",
+        Utc::now(),
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?;
+
+    match api_key {
+        Some(row) => Ok(Json(row.into())),
+        None => Err(revoked_or_missing(&pool, id).await),
+    }
+}
+
+async fn revoked_or_missing(pool: &PgPool, id: i64) -> AppError {
+    match sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM api_keys WHERE id = $1)", id)
+        .fetch_one(pool)
+        .await
+    {
+        Ok(Some(true)) => AppError::Conflict(format!("API key {} has been revoked", id)),
+        Ok(_) => AppError::ResourceNotFound("API key", id),
+        Err(e) => e.into(),
+    }
+}
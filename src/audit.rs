@@ -0,0 +1,170 @@
+//! Append-only trail of security-relevant events, for staff to review.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    Json,
+    extract::State,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, api_keys::AdminAccess, query::Query};
+
+const MAX_ENTRIES: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    LoginSucceeded,
+    LoginFailed,
+    /// A login attempt refused because of earlier failures.
+    LoginThrottled,
+    AccountLocked,
+    PasswordReset,
+    MemberErased,
+    RoleChanged,
+    TwoFactorEnabled,
+    /// The book catalog was replaced from a backup.
+    CatalogRestored,
+}
+
+impl AuditEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuditEvent::LoginSucceeded => "login_succeeded",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::LoginThrottled => "login_throttled",
+            AuditEvent::AccountLocked => "account_locked",
+            AuditEvent::PasswordReset => "password_reset",
+            AuditEvent::MemberErased => "member_erased",
+            AuditEvent::RoleChanged => "role_changed",
+            AuditEvent::TwoFactorEnabled => "two_factor_enabled",
+            AuditEvent::CatalogRestored => "catalog_restored",
+        }
+    }
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "login_succeeded" => Ok(AuditEvent::LoginSucceeded),
+            "login_failed" => Ok(AuditEvent::LoginFailed),
+            "login_throttled" => Ok(AuditEvent::LoginThrottled),
+            "account_locked" => Ok(AuditEvent::AccountLocked),
+            "password_reset" => Ok(AuditEvent::PasswordReset),
+            "member_erased" => Ok(AuditEvent::MemberErased),
+            "role_changed" => Ok(AuditEvent::RoleChanged),
+            "two_factor_enabled" => Ok(AuditEvent::TwoFactorEnabled),
+            "catalog_restored" => Ok(AuditEvent::CatalogRestored),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub event: AuditEvent,
+    pub member_id: Option<i64>,
+    pub ip: Option<String>,
+    pub detail: String,
+    pub created_at: DateTime<Utc>,
+}
+
+struct AuditRow {
+    id: i64,
+    event: String,
+    member_id: Option<i64>,
+    ip: Option<String>,
+    detail: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<AuditRow> for AuditEntry {
+    fn from(r: AuditRow) -> Self {
+        AuditEntry {
+            id: r.id,
+            event: r.event.parse().unwrap_or(AuditEvent::LoginFailed),
+            member_id: r.member_id,
+            ip: r.ip,
+            detail: r.detail,
+            created_at: r.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditParams {
+    event: Option<String>,
+    member_id: Option<i64>,
+}
+
+pub async fn record(
+    conn: &mut PgConnection,
+    event: AuditEvent,
+    member_id: Option<i64>,
+    ip: Option<&str>,
+    detail: &str,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        "INSERT INTO audit_log (event, member_id, ip, detail, created_at) VALUES ($1, $2, $3, $4, $5)",
+        event.as_str(),
+        member_id,
+        ip,
+        detail,
+        Utc::now(),
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn for_member(conn: &mut PgConnection, member_id: i64) -> Result<Vec<AuditEntry>, AppError> {
+    let rows = sqlx::query_as!(
+        AuditRow,
+        "SELECT * FROM audit_log WHERE member_id = $1 ORDER BY created_at, id",
+        member_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(AuditEntry::from).collect())
+}
+
+/// The most recent audit entries, newest first.
+pub async fn list_audit_log(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditEntry>>, AppError> {
+    let event = params
+        .event
+        .as_deref()
+        .map(|s| s.parse::<AuditEvent>().map_err(|_| AppError::InvalidInput(format!("Unknown audit event: {}", s))))
+        .transpose()?;
+
+    let rows = sqlx::query_as!(
+        AuditRow,
+        "SELECT * FROM audit_log
+         WHERE ($1::text IS NULL OR event = $1) AND ($2::bigint IS NULL OR member_id = $2)
+         ORDER BY created_at DESC, id DESC
+         LIMIT $3",
+        event.map(|e| e.as_str()),
+        params.member_id,
+        MAX_ENTRIES,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(AuditEntry::from).collect()))
+}
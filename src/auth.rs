@@ -0,0 +1,624 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, FromRef, FromRequestParts, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    audit::{self, AuditEvent},
+    config::AuthConfig,
+    ldap::{self, LdapError},
+    members::{Member, MemberRole, MemberRow},
+    notifications,
+    templates::Template,
+    throttle,
+    two_factor::{self, LoginFactor},
+};
+
+const SESSION_DAYS: i64 = 30;
+const MIN_PASSWORD_LENGTH: usize = 8;
+const VERIFICATION_HOURS: i64 = 48;
+const PASSWORD_RESET_MINUTES: i64 = 60;
+
+/// What a mailed single-use token may be exchanged for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    EmailVerification,
+    PasswordReset,
+}
+
+impl TokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            TokenPurpose::EmailVerification => "email_verification",
+            TokenPurpose::PasswordReset => "password_reset",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Login {
+    card_number: Option<String>,
+    email: Option<String>,
+    /// A directory login name, when LDAP is configured.
+    username: Option<String>,
+    password: String,
+    /// Authenticator or recovery code, for accounts with two-factor enabled.
+    otp: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmail {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerification {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPassword {
+    token: String,
+    password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    /// Sent back as `Authorization: Bearer <token>`. Only returned here; the
+    /// server keeps a hash.
+    pub token: String,
+    pub member_id: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The member a request is authenticated as, resolved from its bearer token.
+/// When `REQUIRE_ADMIN_2FA` is set, admins without two-factor authentication
+/// are refused until they enroll.
+pub(crate) struct AuthMember(pub Member);
+
+/// Like [`AuthMember`] but without the two-factor requirement, for the
+/// enrollment endpoints themselves.
+pub(crate) struct SessionMember(pub Member);
+
+impl<S> FromRequestParts<S> for SessionMember
+where
+    PgPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers)
+            .ok_or_else(|| AppError::Unauthorized("A bearer token is required".to_string()))?;
+        let pool = PgPool::from_ref(state);
+
+        let member = sqlx::query_as!(
+            MemberRow,
+            "SELECT m.id, m.card_number, m.name, m.email, m.email_verified_at, m.role, m.birthdate, m.created_at
+             FROM sessions s JOIN members m ON m.id = s.member_id
+             WHERE s.token_hash = $1 AND s.expires_at > $2",
+            hash_token(token),
+            Utc::now(),
+        )
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("The session has expired or is invalid".to_string()))?;
+
+        Ok(SessionMember(member.into()))
+    }
+}
+
+impl<S> FromRequestParts<S> for AuthMember
+where
+    PgPool: FromRef<S>,
+    AuthConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let SessionMember(member) = SessionMember::from_request_parts(parts, state).await?;
+
+        if member.role == MemberRole::Admin && AuthConfig::from_ref(state).require_admin_2fa {
+            let pool = PgPool::from_ref(state);
+            if !two_factor::is_enabled(&pool, member.id).await? {
+                return Err(AppError::Forbidden(
+                    "Admin accounts must enable two-factor authentication (POST /me/2fa/enroll)".to_string(),
+                ));
+            }
+        }
+        Ok(AuthMember(member))
+    }
+}
+
+/// The address of the connected client, when the server was started with
+/// connect info. Proxy headers are deliberately not trusted.
+pub struct ClientIp(pub Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        Ok(ClientIp(ip))
+    }
+}
+
+/// Exchanges a card number or email plus password for a session token, or
+/// with LDAP configured, a directory username and password (see `ldap`).
+/// Repeated failures for an account or client address are throttled.
+pub async fn login(
+    State(pool): State<PgPool>,
+    State(auth): State<AuthConfig>,
+    ClientIp(ip): ClientIp,
+    Json(input): Json<Login>,
+) -> Result<Json<Session>, AppError> {
+    let mut conn = pool.acquire().await?;
+    let account = match (&input.card_number, &input.email, &input.username) {
+        (Some(card_number), _, _) => {
+            sqlx::query!("SELECT id, password_hash FROM members WHERE card_number = $1", card_number.trim())
+                .fetch_optional(&mut *conn)
+                .await?
+                .map(|r| (r.id, r.password_hash))
+        }
+        (None, Some(email), _) => {
+            sqlx::query!("SELECT id, password_hash FROM members WHERE LOWER(email) = LOWER($1)", email.trim())
+                .fetch_optional(&mut *conn)
+                .await?
+                .map(|r| (r.id, r.password_hash))
+        }
+        (None, None, Some(username)) => {
+            if auth.ldap.is_none() {
+                return Err(AppError::InvalidInput("username logins need LDAP to be configured".to_string()));
+            }
+            find_external_member(&mut conn, ExternalId::Directory(username)).await?.map(|id| (id, None))
+        }
+        (None, None, None) => {
+            return Err(AppError::InvalidInput("card_number, email, or username is required".to_string()));
+        }
+    };
+    let account_id = account.as_ref().map(|(id, _)| *id);
+
+    let member_key = account_id.map(throttle::member_key);
+    let ip_key = ip.as_deref().map(throttle::ip_key);
+    let keys: Vec<String> = member_key.iter().chain(ip_key.iter()).cloned().collect();
+
+    if let Err(e) = throttle::check(&mut conn, &keys).await {
+        audit::record(&mut conn, AuditEvent::LoginThrottled, account_id, ip.as_deref(), "").await?;
+        return Err(e);
+    }
+
+    let directory = match (&input.card_number, &input.email, &input.username, &auth.ldap) {
+        (None, None, Some(username), Some(config)) => Some((username, config)),
+        _ => None,
+    };
+    let member_id = match (directory, account) {
+        (Some((username, config)), _) => match ldap::authenticate(config, username, &input.password).await {
+            Ok(account) => sync_external_member(&mut conn, ExternalId::Directory(username), account_id, account).await?,
+            Err(LdapError::InvalidCredentials) => {
+                record_failed_login(&mut conn, account_id, ip.as_deref(), "rejected by the directory").await?;
+                return Err(AppError::Unauthorized("Invalid credentials".to_string()));
+            }
+            Err(LdapError::Unavailable(reason)) => {
+                eprintln!("LDAP login failed: {}", reason);
+                return Err(AppError::Unavailable("The directory server could not be reached".to_string(), 30));
+            }
+        },
+        (None, Some((id, Some(hash)))) if verify_password(&input.password, &hash) => id,
+        _ => {
+            record_failed_login(&mut conn, account_id, ip.as_deref(), "").await?;
+            return Err(AppError::Unauthorized("Invalid credentials".to_string()));
+        }
+    };
+
+    match two_factor::check_login(&mut conn, member_id, input.otp.as_deref()).await? {
+        LoginFactor::NotEnrolled | LoginFactor::Passed => {}
+        LoginFactor::Missing => {
+            return Err(AppError::Unauthorized("A two-factor code is required (otp)".to_string()));
+        }
+        LoginFactor::Failed => {
+            record_failed_login(&mut conn, account_id, ip.as_deref(), "invalid two-factor code").await?;
+            return Err(AppError::Unauthorized("Invalid two-factor code".to_string()));
+        }
+    }
+
+    if let Some(key) = &member_key {
+        throttle::clear(&mut conn, key).await?;
+    }
+    audit::record(&mut conn, AuditEvent::LoginSucceeded, Some(member_id), ip.as_deref(), "").await?;
+
+    Ok(Json(start_session(&mut conn, member_id).await?))
+}
+
+pub(crate) async fn start_session(conn: &mut PgConnection, member_id: i64) -> Result<Session, AppError> {
+    let token = new_token();
+    let now = Utc::now();
+    let expires_at = now + Duration::days(SESSION_DAYS);
+    sqlx::query!(
+        "INSERT INTO sessions (member_id, token_hash, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+        member_id,
+        hash_token(&token),
+        now,
+        expires_at,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(Session { token, member_id, expires_at })
+}
+
+/// A member as an outside identity provider (an LDAP directory, a SAML
+/// identity provider) describes them.
+#[derive(Debug, Clone)]
+pub struct ExternalAccount {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub role: MemberRole,
+}
+
+/// The name an outside provider knows a member by.
+#[derive(Debug, Clone, Copy)]
+pub enum ExternalId<'a> {
+    /// A directory login name; matched without regard to case.
+    Directory(&'a str),
+    /// A SAML `NameID`.
+    Saml(&'a str),
+}
+
+impl ExternalId<'_> {
+    fn columns(self) -> (Option<String>, Option<String>) {
+        match self {
+            ExternalId::Directory(username) => (Some(username.trim().to_lowercase()), None),
+            ExternalId::Saml(subject) => (None, Some(subject.to_string())),
+        }
+    }
+}
+
+pub(crate) async fn find_external_member(conn: &mut PgConnection, id: ExternalId<'_>) -> Result<Option<i64>, AppError> {
+    let (directory_username, saml_subject) = id.columns();
+    let member_id = sqlx::query_scalar!(
+        "SELECT id FROM members WHERE directory_username = $1 OR saml_subject = $2",
+        directory_username,
+        saml_subject,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(member_id)
+}
+
+/// Creates or refreshes the member for an outside login. The provider is
+/// the source of truth: name, email and role are copied on every login, and
+/// its email addresses count as verified.
+pub(crate) async fn sync_external_member(
+    conn: &mut PgConnection,
+    id: ExternalId<'_>,
+    member_id: Option<i64>,
+    account: ExternalAccount,
+) -> Result<i64, AppError> {
+    let (directory_username, saml_subject) = id.columns();
+    let name = account.name.unwrap_or_else(|| match id {
+        ExternalId::Directory(username) | ExternalId::Saml(username) => username.trim().to_string(),
+    });
+    let now = Utc::now();
+    let synced = match member_id {
+        Some(id) => sqlx::query_scalar!(
+            "UPDATE members SET name = $1, email = $2, role = $3,
+                 email_verified_at = CASE WHEN email IS NOT DISTINCT FROM $2 THEN email_verified_at
+                                          WHEN $2::text IS NULL THEN NULL ELSE $4 END
+             WHERE id = $5 RETURNING id",
+            name,
+            account.email,
+            account.role.as_str(),
+            now,
+            id,
+        )
+        .fetch_one(&mut *conn)
+        .await,
+        None => sqlx::query_scalar!(
+            "INSERT INTO members (name, email, email_verified_at, role, directory_username, saml_subject, created_at)
+             VALUES ($1, $2, CASE WHEN $2::text IS NULL THEN NULL ELSE $3::timestamptz END, $4, $5, $6, $3)
+             RETURNING id",
+            name,
+            account.email,
+            now,
+            account.role.as_str(),
+            directory_username,
+            saml_subject,
+        )
+        .fetch_one(&mut *conn)
+        .await,
+    };
+    synced.map_err(|e| match e {
+        sqlx::Error::Database(db) if db.constraint() == Some("members_email_lower") => {
+            AppError::Conflict("Another member already uses this account's email address".to_string())
+        }
+        e => e.into(),
+    })
+}
+
+/// Audits a failed attempt and counts it against the account and address.
+async fn record_failed_login(
+    conn: &mut PgConnection,
+    member_id: Option<i64>,
+    ip: Option<&str>,
+    detail: &str,
+) -> Result<(), AppError> {
+    audit::record(&mut *conn, AuditEvent::LoginFailed, member_id, ip, detail).await?;
+    if let Some(ip) = ip {
+        throttle::record_failure(&mut *conn, &throttle::ip_key(ip)).await?;
+    }
+    if let Some(member_id) = member_id {
+        let failures = throttle::record_failure(&mut *conn, &throttle::member_key(member_id)).await?;
+        if failures == throttle::LOCKOUT_THRESHOLD {
+            let detail = format!("{} consecutive failed logins", failures);
+            audit::record(conn, AuditEvent::AccountLocked, Some(member_id), ip, &detail).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn logout(State(pool): State<PgPool>, headers: HeaderMap) -> Result<StatusCode, AppError> {
+    if let Some(token) = bearer_token(&headers) {
+        sqlx::query!("DELETE FROM sessions WHERE token_hash = $1", hash_token(token))
+            .execute(&pool)
+            .await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Confirms a member's email address with the token from their
+/// verification message.
+pub async fn verify_email(
+    State(pool): State<PgPool>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<Json<Member>, AppError> {
+    let mut tx = pool.begin().await?;
+    let member_id = consume_member_token(&mut tx, TokenPurpose::EmailVerification, input.token.trim())
+        .await?
+        .ok_or_else(|| AppError::InvalidInput("Verification token is invalid or has expired".to_string()))?;
+
+    let member = sqlx::query_as!(
+        MemberRow,
+        "UPDATE members SET email_verified_at = COALESCE(email_verified_at, $1) WHERE id = $2
+         RETURNING id, card_number, name, email, email_verified_at, role, birthdate, created_at",
+        Utc::now(),
+        member_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(member.into()))
+}
+
+/// Sends a fresh verification token, replacing any outstanding one. Always
+/// answers 202 so the endpoint can't be used to discover registered emails.
+pub async fn resend_verification(
+    State(pool): State<PgPool>,
+    Json(input): Json<ResendVerification>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = pool.begin().await?;
+    let member = sqlx::query_as!(
+        MemberRow,
+        "SELECT id, card_number, name, email, email_verified_at, role, birthdate, created_at
+         FROM members WHERE LOWER(email) = LOWER($1) AND email_verified_at IS NULL",
+        input.email.trim(),
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(Member::from);
+
+    if let Some(member) = member {
+        send_verification(&mut tx, &member).await?;
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Issues a verification token and queues the email carrying it. Earlier
+/// tokens for the member stop working.
+pub async fn send_verification(conn: &mut PgConnection, member: &Member) -> Result<(), AppError> {
+    let Some(email) = member.email.as_deref() else {
+        return Ok(());
+    };
+    let token = issue_member_token(
+        &mut *conn,
+        member.id,
+        TokenPurpose::EmailVerification,
+        Duration::hours(VERIFICATION_HOURS),
+    )
+    .await?;
+
+    let message = Template::EmailVerification { name: &member.name, token: &token, hours: VERIFICATION_HOURS };
+    notifications::enqueue_email(conn, member.id, email, message.subject(), &message.body()).await
+}
+
+/// Mails a password reset token to the member with this email address.
+/// Like resending verification, the response doesn't reveal whether the
+/// address is registered.
+pub async fn forgot_password(
+    State(pool): State<PgPool>,
+    Json(input): Json<ForgotPassword>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = pool.begin().await?;
+    let member = sqlx::query_as!(
+        MemberRow,
+        "SELECT id, card_number, name, email, email_verified_at, role, birthdate, created_at
+         FROM members WHERE LOWER(email) = LOWER($1)",
+        input.email.trim(),
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(Member::from);
+
+    if let Some(member) = member
+        && let Some(email) = member.email.as_deref()
+    {
+        let token = issue_member_token(
+            &mut tx,
+            member.id,
+            TokenPurpose::PasswordReset,
+            Duration::minutes(PASSWORD_RESET_MINUTES),
+        )
+        .await?;
+        let message = Template::PasswordReset { name: &member.name, token: &token, minutes: PASSWORD_RESET_MINUTES };
+        notifications::enqueue_email(&mut tx, member.id, email, message.subject(), &message.body()).await?;
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Sets a new password using a reset token and signs the member out of
+/// every existing session.
+pub async fn reset_password(
+    State(pool): State<PgPool>,
+    ClientIp(ip): ClientIp,
+    Json(input): Json<ResetPassword>,
+) -> Result<StatusCode, AppError> {
+    let password_hash = hash_password(&input.password)?;
+
+    let mut tx = pool.begin().await?;
+    let member_id = consume_member_token(&mut tx, TokenPurpose::PasswordReset, input.token.trim())
+        .await?
+        .ok_or_else(|| AppError::InvalidInput("Reset token is invalid or has expired".to_string()))?;
+
+    sqlx::query!("UPDATE members SET password_hash = $1 WHERE id = $2", password_hash, member_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM sessions WHERE member_id = $1", member_id)
+        .execute(&mut *tx)
+        .await?;
+    throttle::clear(&mut tx, &throttle::member_key(member_id)).await?;
+    audit::record(&mut tx, AuditEvent::PasswordReset, Some(member_id), ip.as_deref(), "").await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Members who gave an email address must confirm it before placing holds or
+/// borrowing. Members registered at the desk without one are not affected.
+pub fn require_verified_email(member: &Member) -> Result<(), AppError> {
+    if member.email.is_some() && member.email_verified_at.is_none() {
+        return Err(AppError::Forbidden("Confirm your email address before borrowing or placing holds".to_string()));
+    }
+    Ok(())
+}
+
+/// Creates a single-use token for `purpose`, revoking any unused ones the
+/// member already has for it, and returns the plaintext.
+pub async fn issue_member_token(
+    conn: &mut PgConnection,
+    member_id: i64,
+    purpose: TokenPurpose,
+    valid_for: Duration,
+) -> Result<String, AppError> {
+    let now = Utc::now();
+    sqlx::query!(
+        "UPDATE member_tokens SET used_at = $1 WHERE member_id = $2 AND purpose = $3 AND used_at IS NULL",
+        now,
+        member_id,
+        purpose.as_str(),
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let token = new_token();
+    sqlx::query!(
+        "INSERT INTO member_tokens (member_id, purpose, token_hash, created_at, expires_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        member_id,
+        purpose.as_str(),
+        hash_token(&token),
+        now,
+        now + valid_for,
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+/// Marks a token used and returns its member, or `None` if the token is
+/// unknown, meant for something else, expired, or already used.
+pub async fn consume_member_token(
+    conn: &mut PgConnection,
+    purpose: TokenPurpose,
+    token: &str,
+) -> Result<Option<i64>, AppError> {
+    let now = Utc::now();
+    let member_id = sqlx::query_scalar!(
+        "UPDATE member_tokens SET used_at = $1
+         WHERE token_hash = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > $1
+         RETURNING member_id",
+        now,
+        hash_token(token),
+        purpose.as_str(),
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(member_id)
+}
+
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::InvalidInput(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::InvalidInput(format!("Password could not be hashed: {}", e)))
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+/// A random 256-bit token, hex encoded.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
@@ -0,0 +1,321 @@
+//! Catalog backups: the bibliographic records (books with their
+//! translations, tables of contents, and excerpts) as one JSON document
+//! that can be downloaded and later restored. Copies, members, and loans
+//! are not part of it.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    Json,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    api_keys::AdminAccess,
+    audit::{self, AuditEvent},
+    auth::ClientIp,
+    books::{self, Book, BookRow},
+    classification::ClassificationScheme,
+    conditional,
+    toc::TocEntry,
+    validation::classification_key,
+};
+
+/// Identifies a backup document, so a restore isn't fed some other JSON.
+const FORMAT: &str = "book-library-catalog";
+const VERSION: u32 = 1;
+
+/// Largest restore body accepted.
+pub const MAX_BACKUP_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CatalogBackup {
+    pub format: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub books: Vec<BackupBook>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupBook {
+    #[serde(flatten)]
+    pub book: Book,
+    #[serde(default)]
+    pub translations: Vec<BackupTranslation>,
+    #[serde(default)]
+    pub contents: Vec<TocEntry>,
+    #[serde(default)]
+    pub excerpt: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupTranslation {
+    pub language: String,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreSummary {
+    pub books_restored: usize,
+    /// Books added since the backup was made, removed with their copies.
+    pub books_deleted: u64,
+}
+
+/// Downloads the whole catalog. Taken in one repeatable-read transaction so
+/// the books and what hangs off them agree even while staff keep editing.
+/// Books always carry their numeric id here, even with `UUID_BOOK_IDS` on,
+/// since that is what a restore matches them on.
+pub async fn backup_catalog(State(pool): State<PgPool>, _access: AdminAccess) -> Result<Response, AppError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut *tx)
+        .await?;
+
+    let books = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books ORDER BY id"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut translations: HashMap<i64, Vec<BackupTranslation>> = HashMap::new();
+    for row in sqlx::query!("SELECT book_id, language, title, description FROM book_translations ORDER BY book_id, language")
+        .fetch_all(&mut *tx)
+        .await?
+    {
+        translations.entry(row.book_id).or_default().push(BackupTranslation {
+            language: row.language,
+            title: row.title,
+            description: row.description,
+        });
+    }
+
+    let mut contents: HashMap<i64, Vec<TocEntry>> = HashMap::new();
+    for row in sqlx::query!("SELECT book_id, title, page FROM book_toc_entries ORDER BY book_id, position")
+        .fetch_all(&mut *tx)
+        .await?
+    {
+        contents.entry(row.book_id).or_default().push(TocEntry { title: row.title, page: row.page });
+    }
+
+    let mut excerpts: HashMap<i64, String> = sqlx::query!("SELECT book_id, text FROM book_excerpts")
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|r| (r.book_id, r.text))
+        .collect();
+    tx.commit().await?;
+
+    let created_at = Utc::now();
+    let backup = CatalogBackup {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        created_at,
+        books: books
+            .into_iter()
+            .map(|row| {
+                let id = row.id;
+                BackupBook {
+                    book: row.into(),
+                    translations: translations.remove(&id).unwrap_or_default(),
+                    contents: contents.remove(&id).unwrap_or_default(),
+                    excerpt: excerpts.remove(&id),
+                }
+            })
+            .collect(),
+    };
+    let disposition = format!("attachment; filename=\"catalog-{}.json\"", created_at.format("%Y%m%dT%H%M%SZ"));
+
+    // Serialized here rather than on the way out, where the id is hidden.
+    let body = books::with_numeric_ids(|| Json(backup).into_response());
+    Ok(([(header::CONTENT_DISPOSITION, disposition)], body).into_response())
+}
+
+/// Replaces the catalog with a backup, all or nothing. Books in the backup
+/// are put back as they were, under their original ids; books added since
+/// are deleted, which is refused if any of them has been borrowed. Whether
+/// a book is available is left as it is now, since that follows its copies.
+pub async fn restore_catalog(
+    State(pool): State<PgPool>,
+    access: AdminAccess,
+    ClientIp(ip): ClientIp,
+    Json(backup): Json<CatalogBackup>,
+) -> Result<Json<RestoreSummary>, AppError> {
+    if backup.format != FORMAT || backup.version != VERSION {
+        return Err(AppError::InvalidInput(format!(
+            "Not a catalog backup this server can read (expected format {:?}, version {})",
+            FORMAT, VERSION
+        )));
+    }
+    let mut seen = HashSet::new();
+    let mut keys = Vec::with_capacity(backup.books.len());
+    for entry in &backup.books {
+        let book = &entry.book;
+        // Without an id the book would be deleted rather than restored.
+        if book.id < 1 {
+            return Err(AppError::InvalidInput(format!(
+                "Book {:?} has no numeric id; restore needs a backup taken by POST /admin/backup",
+                book.title
+            )));
+        }
+        if !seen.insert(book.id) {
+            return Err(AppError::InvalidInput(format!("Book {} appears more than once in the backup", book.id)));
+        }
+        keys.push(classification_key(book.classification_scheme, book.classification.as_deref())?);
+    }
+    let ids: Vec<i64> = backup.books.iter().map(|b| b.book.id).collect();
+
+    let mut tx = pool.begin().await?;
+    // Readers carry on; other writers wait until the restore is done.
+    sqlx::query!("LOCK TABLE books IN EXCLUSIVE MODE").execute(&mut *tx).await?;
+
+    let borrowed = sqlx::query_scalar!(
+        r#"SELECT DISTINCT book_id AS "book_id!" FROM borrowings WHERE NOT (book_id = ANY($1)) ORDER BY 1"#,
+        &ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if !borrowed.is_empty() {
+        let list: Vec<String> = borrowed.iter().map(i64::to_string).collect();
+        return Err(AppError::Conflict(format!(
+            "Books {} are not in the backup but have loans on record; delete or merge them another way",
+            list.join(", ")
+        )));
+    }
+
+    let books_deleted = sqlx::query!("DELETE FROM books WHERE NOT (id = ANY($1))", &ids)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if books_deleted > 0 {
+        conditional::record_book_deletion(&mut tx).await?;
+    }
+    // Slugs move between rows as the backup is written back; clearing them
+    // first keeps the unique constraint from tripping halfway through.
+    sqlx::query!("UPDATE books SET slug = NULL WHERE id = ANY($1)", &ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM book_translations WHERE book_id = ANY($1)", &ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM book_toc_entries WHERE book_id = ANY($1)", &ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM book_excerpts WHERE book_id = ANY($1)", &ids)
+        .execute(&mut *tx)
+        .await?;
+
+    let now = Utc::now();
+    for (entry, key) in backup.books.iter().zip(keys) {
+        let book = &entry.book;
+        let audiobook = book.audiobook.clone().unwrap_or_default();
+        sqlx::query!(
+            "INSERT INTO books (id, title, author, year, isbn, available, temporary, classification_scheme, classification,
+                                classification_key, slug, description, original_title, original_language, translator, age_rating,
+                                format, narrator, duration_minutes, disc_count, file_count, deleted_at, updated_at, uuid)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23,
+                     COALESCE($24, uuid_generate_v7()))
+             ON CONFLICT (id) DO UPDATE SET
+                 title = EXCLUDED.title, author = EXCLUDED.author, year = EXCLUDED.year, isbn = EXCLUDED.isbn,
+                 temporary = EXCLUDED.temporary, classification_scheme = EXCLUDED.classification_scheme,
+                 classification = EXCLUDED.classification, classification_key = EXCLUDED.classification_key,
+                 slug = EXCLUDED.slug, description = EXCLUDED.description, original_title = EXCLUDED.original_title,
+                 original_language = EXCLUDED.original_language, translator = EXCLUDED.translator,
+                 age_rating = EXCLUDED.age_rating, format = EXCLUDED.format, narrator = EXCLUDED.narrator,
+                 duration_minutes = EXCLUDED.duration_minutes, disc_count = EXCLUDED.disc_count,
+                 file_count = EXCLUDED.file_count, deleted_at = EXCLUDED.deleted_at, updated_at = EXCLUDED.updated_at,
+                 uuid = EXCLUDED.uuid, version = books.version + 1",
+            book.id,
+            book.title,
+            book.author,
+            book.year,
+            book.isbn,
+            book.available,
+            book.temporary,
+            book.classification_scheme.map(ClassificationScheme::as_str),
+            book.classification,
+            key,
+            book.slug,
+            book.description,
+            book.original_title,
+            book.original_language,
+            book.translator,
+            book.age_rating,
+            book.format.as_str(),
+            audiobook.narrator,
+            audiobook.duration_minutes,
+            audiobook.discs,
+            audiobook.files,
+            book.deleted_at,
+            now,
+            book.uuid,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for translation in &entry.translations {
+            sqlx::query!(
+                "INSERT INTO book_translations (book_id, language, title, description, updated_at) VALUES ($1, $2, $3, $4, $5)",
+                book.id,
+                translation.language,
+                translation.title,
+                translation.description,
+                now,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        let positions: Vec<i32> = (0..entry.contents.len() as i32).collect();
+        let titles: Vec<String> = entry.contents.iter().map(|e| e.title.clone()).collect();
+        let pages: Vec<Option<i64>> = entry.contents.iter().map(|e| e.page).collect();
+        sqlx::query!(
+            "INSERT INTO book_toc_entries (book_id, position, title, page)
+             SELECT $1, * FROM UNNEST($2::int[], $3::text[], $4::bigint[])",
+            book.id,
+            &positions,
+            &titles,
+            &pages as &[Option<i64>],
+        )
+        .execute(&mut *tx)
+        .await?;
+        if let Some(text) = &entry.excerpt {
+            sqlx::query!(
+                "INSERT INTO book_excerpts (book_id, text, updated_at) VALUES ($1, $2, $3)",
+                book.id,
+                text,
+                now,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    // Restored ids may be past where the sequence is; never move it back,
+    // so an id handed out since the backup isn't given to a different book.
+    sqlx::query_scalar!(
+        "SELECT setval('books_id_seq', GREATEST((SELECT MAX(id) FROM books), (SELECT last_value FROM books_id_seq)))"
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let detail = format!(
+        "catalog restored by {} from backup of {}: {} book(s) restored, {} deleted",
+        access.describe(),
+        backup.created_at.to_rfc3339(),
+        ids.len(),
+        books_deleted
+    );
+    audit::record(&mut tx, AuditEvent::CatalogRestored, access.member_id(), ip.as_deref(), &detail).await?;
+    tx.commit().await?;
+
+    Ok(Json(RestoreSummary { books_restored: ids.len(), books_deleted }))
+}
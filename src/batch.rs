@@ -0,0 +1,251 @@
+//! `POST /batch` runs several API calls in one round trip, for sync clients
+//! that would otherwise issue them one by one.
+
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    Json,
+    body::{self, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{Method, StatusCode, header, request::Parts},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{
+    PgPool, TransactionManager,
+    postgres::{PgPoolOptions, PgTransactionManager},
+};
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+
+use crate::{
+    AppError, AppState,
+    api_keys::{self, API_KEY_HEADER, ApiScope},
+    auth::AuthMember,
+    legacy,
+    query::Query,
+    query_cache::QueryCache,
+};
+
+const MAX_OPERATIONS: usize = 100;
+/// Largest sub-response body read back into the batch response.
+const MAX_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+/// How long an atomic batch's operation waits for the batch's connection.
+/// Only an operation that needs two connections at once waits at all.
+const ATOMIC_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Atomic batches running at once. Each holds a database connection of its
+/// own, outside the shared pool, for as long as it runs.
+const MAX_ATOMIC_BATCHES: usize = 4;
+/// Seconds a caller turned away by the atomic batch limit should wait.
+const ATOMIC_RETRY_AFTER: i64 = 5;
+
+static ATOMIC_BATCHES: Semaphore = Semaphore::const_new(MAX_ATOMIC_BATCHES);
+
+#[derive(Debug, Deserialize)]
+pub struct BatchParams {
+    /// Run the batch in one transaction: it stops at the first failing
+    /// operation and nothing before it is kept.
+    #[serde(default)]
+    atomic: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchOperation {
+    pub method: String,
+    pub path: String,
+    pub body: Option<Value>,
+}
+
+/// Outcome of one operation. `body` is the JSON the endpoint returned, or
+/// the raw text for non-JSON responses.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub status: u16,
+    pub body: Option<Value>,
+}
+
+/// Runs each operation in order against the API as if it had been sent on
+/// its own, with the caller's credentials. Operations are independent: a
+/// failing one is reported in its result and the rest still run, unless
+/// the batch is atomic. Atomic batches are for signed-in members and API
+/// keys with the `write` scope, and only a few run at once.
+pub async fn run_batch(
+    State(state): State<AppState>,
+    Query(params): Query<BatchParams>,
+    member: Result<AuthMember, AppError>,
+    parts: Parts,
+    Json(operations): Json<Vec<BatchOperation>>,
+) -> Result<Json<Vec<BatchResult>>, AppError> {
+    if params.atomic {
+        require_caller(&state, &parts, member).await?;
+    }
+    if operations.is_empty() || operations.len() > MAX_OPERATIONS {
+        return Err(AppError::InvalidInput(format!(
+            "A batch must contain between 1 and {} entries",
+            MAX_OPERATIONS
+        )));
+    }
+    let mut requests = Vec::with_capacity(operations.len());
+    for (i, operation) in operations.iter().enumerate() {
+        requests.push(build_request(&parts, operation, params.atomic).map_err(|e| {
+            AppError::InvalidInput(format!("Operation {}: {}", i, e))
+        })?);
+    }
+
+    if !params.atomic {
+        let mut results = Vec::with_capacity(requests.len());
+        for request in requests {
+            results.push(run(&state, request).await);
+        }
+        return Ok(Json(results));
+    }
+
+    let _permit = ATOMIC_BATCHES.try_acquire().map_err(|_| {
+        AppError::Unavailable("Too many atomic batches are running; try again shortly".to_string(), ATOMIC_RETRY_AFTER)
+    })?;
+    let pool = transaction_pool(&state.pool);
+    // Reads here can see writes the batch later rolls back, so they must
+    // not reach the shared query cache.
+    let state = AppState { pool: pool.clone(), cache: QueryCache::disabled(), ..state };
+    let mut results = Vec::with_capacity(requests.len());
+    let mut failed = None;
+    for (i, request) in requests.into_iter().enumerate() {
+        let result = run(&state, request).await;
+        let ok = (200..300).contains(&result.status);
+        results.push(result);
+        if !ok {
+            failed = Some(i);
+            break;
+        }
+    }
+
+    let finished = async {
+        let mut conn = pool.acquire().await?;
+        match failed {
+            None => PgTransactionManager::commit(&mut conn).await,
+            Some(_) => PgTransactionManager::rollback(&mut conn).await,
+        }
+    }
+    .await;
+    pool.close().await;
+    finished?;
+
+    if let Some(failed) = failed {
+        for result in &mut results[..failed] {
+            *result = not_applied(format!("Rolled back because operation {} failed", failed));
+        }
+        results.extend((failed + 1..operations.len()).map(|_| {
+            not_applied(format!("Not run because operation {} failed", failed))
+        }));
+    }
+    Ok(Json(results))
+}
+
+async fn run(state: &AppState, request: Request) -> BatchResult {
+    let response = crate::build_router(state.clone()).oneshot(request).await.unwrap_or_else(|e| match e {});
+    let status = response.status().as_u16();
+    let bytes = body::to_bytes(response.into_body(), MAX_RESPONSE_BYTES).await.unwrap_or_default();
+    let body = if bytes.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+            Value::String(String::from_utf8_lossy(&bytes).into_owned())
+        }))
+    };
+    BatchResult { status, body }
+}
+
+/// Who may run an atomic batch: the holder of an API key with the `write`
+/// scope, when the request sends one, or else a signed-in member.
+async fn require_caller(state: &AppState, parts: &Parts, member: Result<AuthMember, AppError>) -> Result<(), AppError> {
+    if parts.headers.contains_key(API_KEY_HEADER) {
+        api_keys::authenticate(&state.pool, &parts.headers, ApiScope::Write).await?;
+        return Ok(());
+    }
+    member.map(|_| ())
+}
+
+/// Result for an operation an atomic batch didn't keep.
+fn not_applied(message: String) -> BatchResult {
+    BatchResult {
+        status: StatusCode::FAILED_DEPENDENCY.as_u16(),
+        body: Some(serde_json::json!({ "message": message })),
+    }
+}
+
+/// A one-connection pool whose connection is inside a transaction, for the
+/// operations of an atomic batch. Handlers that begin their own transaction
+/// get a savepoint in it instead, and their commits only release the
+/// savepoint; the batch commits or rolls back the whole thing at the end.
+/// Everything else handlers change goes through the database too (the
+/// notification and chat outboxes included), and the only state held in
+/// memory, the query cache, is disabled for the batch. The connection is
+/// opened apart from `pool`, since handlers need a pool of their own to
+/// run against; [`MAX_ATOMIC_BATCHES`] bounds how many are open at once.
+fn transaction_pool(pool: &PgPool) -> PgPool {
+    PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(ATOMIC_ACQUIRE_TIMEOUT)
+        .after_connect(|conn, _| Box::pin(PgTransactionManager::begin(conn, None)))
+        .connect_lazy_with((*pool.connect_options()).clone())
+}
+
+/// The route `path` reaches, without its query and the version prefix, so
+/// `/v1/batch/?atomic=true` and `/batch` compare equal.
+fn route(path: &str) -> &str {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let path = match path.strip_prefix(legacy::PREFIX) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => path,
+    };
+    path.trim_end_matches('/')
+}
+
+/// Whether `path` reaches this handler, on either the versioned or the
+/// unversioned routes.
+fn routes_to_batch(path: &str) -> bool {
+    route(path) == "/batch"
+}
+
+/// Sign-in and account recovery record failed attempts, throttles, and used
+/// tokens that must stay recorded whatever happens to the rest of a batch;
+/// a rollback would hand out unlimited password guesses.
+fn must_outlive_rollback(path: &str) -> bool {
+    route(path).starts_with("/auth/")
+}
+
+fn build_request(parts: &Parts, operation: &BatchOperation, atomic: bool) -> Result<Request, String> {
+    let method: Method = operation
+        .method
+        .to_uppercase()
+        .parse()
+        .map_err(|_| format!("'{}' is not an HTTP method", operation.method))?;
+    if !operation.path.starts_with('/') {
+        return Err("path must start with '/'".to_string());
+    }
+    if routes_to_batch(&operation.path) {
+        return Err("batches can't be nested".to_string());
+    }
+    if atomic && must_outlive_rollback(&operation.path) {
+        return Err(format!("{} can't be part of an atomic batch", operation.path));
+    }
+
+    let mut builder = Request::builder().method(method).uri(&operation.path);
+    for name in [header::AUTHORIZATION.as_str(), API_KEY_HEADER] {
+        if let Some(value) = parts.headers.get(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    if let Some(connect_info) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        builder = builder.extension(*connect_info);
+    }
+
+    let body = match &operation.body {
+        Some(value) => {
+            builder = builder.header(header::CONTENT_TYPE, "application/json");
+            Body::from(value.to_string())
+        }
+        None => Body::empty(),
+    };
+    builder.body(body).map_err(|_| format!("'{}' is not a valid path", operation.path))
+}
@@ -0,0 +1,1006 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{FromRef, FromRequestParts, Path, RawQuery, Request, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    middleware::Next,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::{
+    AppError, age_rating, chat, conditional, duplicates, filter, formats, include, query_cache, search, slug, sort, translations,
+    api_keys::{self, AdminAccess, CatalogRead, CatalogWrite},
+    classification::ClassificationScheme,
+    config::{AuthConfig, CatalogConfig},
+    formats::{AudiobookDetails, BookFormat},
+    include::Relation,
+    pagination::{PaginatedResponse, PaginationMeta, page_bounds},
+    query::Query,
+    query_cache::QueryCache,
+    strict_json::StrictJson,
+    validation::{check_field_lengths, classification_key, is_valid_isbn, validate_book, validate_original_work},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Book {
+    /// Left out of responses when `UUID_BOOK_IDS` is on.
+    #[serde(default, skip_serializing_if = "numeric_ids_hidden")]
+    pub id: i64,
+    /// Another name for the book, usable wherever `id` is in `/books/{id}`.
+    #[serde(default)]
+    pub uuid: Option<Uuid>,
+    pub title: String,
+    pub author: String,
+    pub year: i64,
+    pub isbn: String,
+    pub available: bool,
+    pub temporary: bool,
+    pub classification_scheme: Option<ClassificationScheme>,
+    pub classification: Option<String>,
+    /// URL-safe name for links, e.g. `the-rust-programming-language-2018`.
+    pub slug: Option<String>,
+    pub description: Option<String>,
+    /// For translated works: the title in the original language, that
+    /// language's tag (e.g. `ja`), and the translator.
+    pub original_title: Option<String>,
+    pub original_language: Option<String>,
+    pub translator: Option<String>,
+    /// Minimum age to borrow the book; see `age_rating`.
+    #[serde(default)]
+    pub age_rating: Option<i32>,
+    #[serde(default)]
+    pub format: BookFormat,
+    /// Narrator, running time, and discs or files; only for audiobooks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audiobook: Option<AudiobookDetails>,
+    /// Set when `title` and `description` come from a translation picked by
+    /// `Accept-Language`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// When the book was deleted; only shown to admins listing deleted books.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Goes up by one with every edit; sent back in `If-Match` to make sure
+    /// an update doesn't overwrite changes the client hasn't seen.
+    #[serde(default)]
+    pub version: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct BookRow {
+    pub id: i64,
+    pub title: String,
+    pub author: String,
+    pub year: i64,
+    pub isbn: String,
+    pub available: bool,
+    pub temporary: bool,
+    pub classification_scheme: Option<String>,
+    pub classification: Option<String>,
+    pub slug: Option<String>,
+    pub description: Option<String>,
+    pub original_title: Option<String>,
+    pub original_language: Option<String>,
+    pub translator: Option<String>,
+    pub age_rating: Option<i32>,
+    pub format: String,
+    pub narrator: Option<String>,
+    pub duration_minutes: Option<i64>,
+    pub disc_count: Option<i64>,
+    pub file_count: Option<i64>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub version: i64,
+    pub uuid: Uuid,
+}
+
+impl From<BookRow> for Book {
+    fn from(r: BookRow) -> Self {
+        Book {
+            id: r.id,
+            uuid: Some(r.uuid),
+            title: r.title,
+            author: r.author,
+            year: r.year,
+            isbn: r.isbn,
+            available: r.available,
+            temporary: r.temporary,
+            classification_scheme: r.classification_scheme.and_then(|s| s.parse().ok()),
+            classification: r.classification,
+            slug: r.slug,
+            description: r.description,
+            original_title: r.original_title,
+            original_language: r.original_language,
+            translator: r.translator,
+            age_rating: r.age_rating,
+            format: r.format.parse().unwrap_or_default(),
+            audiobook: AudiobookDetails::from_columns(r.narrator, r.duration_minutes, r.disc_count, r.file_count),
+            language: None,
+            deleted_at: r.deleted_at,
+            version: r.version,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddBook {
+    pub title: String,
+    pub author: String,
+    pub year: i64,
+    pub isbn: String,
+    pub classification_scheme: Option<ClassificationScheme>,
+    pub classification: Option<String>,
+    pub description: Option<String>,
+    pub original_title: Option<String>,
+    pub original_language: Option<String>,
+    pub translator: Option<String>,
+    pub age_rating: Option<i32>,
+    pub format: Option<BookFormat>,
+    pub audiobook: Option<AudiobookDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddBookParams {
+    /// Create the book even if strict duplicate checking finds matches.
+    #[serde(default)]
+    pub allow_duplicates: bool,
+}
+
+/// The created book, plus any existing records it probably duplicates.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedBook {
+    #[serde(flatten)]
+    pub book: Book,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub possible_duplicates: Vec<duplicates::DuplicateCandidate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateBook {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub year: Option<i64>,
+    pub isbn: Option<String>,
+    pub available: Option<bool>,
+    pub classification_scheme: Option<ClassificationScheme>,
+    pub classification: Option<String>,
+    pub description: Option<String>,
+    pub original_title: Option<String>,
+    pub original_language: Option<String>,
+    pub translator: Option<String>,
+    pub age_rating: Option<i32>,
+    /// Switching away from `audiobook` clears the audiobook details.
+    pub format: Option<BookFormat>,
+    /// Replaces the audiobook details given; omitted ones are kept.
+    pub audiobook: Option<AudiobookDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookParams {
+    pub available: Option<bool>,
+    pub author: Option<String>,
+    /// Exact language tag of the original, e.g. `original_language=ja`.
+    pub original_language: Option<String>,
+    /// Substring of the translator's name, like `author`.
+    pub translator: Option<String>,
+    pub format: Option<BookFormat>,
+    pub year: Option<i64>,
+    /// Inclusive publication year range, e.g. `year_from=1950&year_to=1959`.
+    pub year_from: Option<i64>,
+    pub year_to: Option<i64>,
+    pub classification_scheme: Option<ClassificationScheme>,
+    /// Inclusive shelf-order range, e.g. `class_from=510&class_to=519`.
+    pub class_from: Option<String>,
+    pub class_to: Option<String>,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+    /// Related records to embed, e.g. `include=copies,holds`.
+    pub include: Option<String>,
+    /// Conditions like `year:gte:1950,author:contains:orwell`; see `filter`.
+    pub filter: Option<String>,
+    /// Full-text search terms; see `search`.
+    pub q: Option<String>,
+    /// Comma-separated fields, `-` for descending, e.g. `sort=author,-year`.
+    pub sort: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookQuery {
+    pub include: Option<String>,
+}
+
+tokio::task_local! {
+    /// Set for the length of a request while `UUID_BOOK_IDS` is on.
+    static HIDE_NUMERIC_IDS: bool;
+}
+
+pub(crate) fn numeric_ids_hidden<T>(_: &T) -> bool {
+    HIDE_NUMERIC_IDS.try_with(|hidden| *hidden).unwrap_or(false)
+}
+
+/// With `UUID_BOOK_IDS` on, books are serialized without their numeric id
+/// for the rest of the request.
+pub async fn hide_numeric_ids(State(catalog): State<CatalogConfig>, request: Request, next: Next) -> Response {
+    HIDE_NUMERIC_IDS.scope(catalog.uuid_book_ids, next.run(request)).await
+}
+
+/// Runs `f` with books serialized with their numeric id whatever
+/// `UUID_BOOK_IDS` says, for output that must round-trip, like a backup.
+pub(crate) fn with_numeric_ids<R>(f: impl FnOnce() -> R) -> R {
+    HIDE_NUMERIC_IDS.sync_scope(false, f)
+}
+
+/// The `{id}` in `/books/{id}/...`: the book's numeric id or its UUID,
+/// resolved to the numeric id. With `UUID_BOOK_IDS` on, only the UUID.
+pub(crate) struct BookId(pub i64);
+
+impl<S> FromRequestParts<S> for BookId
+where
+    PgPool: FromRef<S>,
+    CatalogConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::InvalidInput(e.body_text()))?;
+        let raw = params.get("id").cloned().unwrap_or_default();
+        if let Ok(id) = raw.parse() {
+            if CatalogConfig::from_ref(state).uuid_book_ids {
+                return Err(AppError::InvalidInput("Books are addressed by their UUID".to_string()));
+            }
+            return Ok(BookId(id));
+        }
+        let uuid = Uuid::parse_str(&raw)
+            .map_err(|_| AppError::InvalidInput(format!("{} is neither a book id nor a UUID", raw)))?;
+        sqlx::query_scalar!("SELECT id FROM books WHERE uuid = $1", uuid)
+            .fetch_optional(&PgPool::from_ref(state))
+            .await?
+            .map(BookId)
+            .ok_or(AppError::ResourceNotFoundBy("Book", "UUID", raw))
+    }
+}
+
+/// The `include_deleted=true` query parameter, which shows soft-deleted
+/// books too. Only admins may ask for it.
+pub(crate) struct IncludeDeleted(pub bool);
+
+#[derive(Deserialize)]
+struct IncludeDeletedParam {
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+impl<S> FromRequestParts<S> for IncludeDeleted
+where
+    PgPool: FromRef<S>,
+    AuthConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(param) = Query::<IncludeDeletedParam>::from_request_parts(parts, state).await?;
+        if param.include_deleted {
+            AdminAccess::from_request_parts(parts, state).await?;
+        }
+        Ok(IncludeDeleted(param.include_deleted))
+    }
+}
+
+pub async fn list_books(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    State(cache): State<QueryCache>,
+    headers: HeaderMap,
+    RawQuery(raw_query): RawQuery,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    Query(params): Query<BookParams>
+) -> Result<Response, AppError> {
+    // The same check as `CatalogRead`.
+    api_keys::check_lookup_key(&pool, &headers).await?;
+    let relations = Relation::parse_list(params.include.as_deref())?;
+    let filters = BookFilters { include_deleted, ..BookFilters::from_params(&params)? };
+    let sort_keys = sort::parse(params.sort.as_deref())?;
+    let (page, limit) = page_bounds(params.page, params.limit, catalog.max_page_limit)?;
+    // Any change to the catalog counts, since it can move books in or out
+    // of the filtered page. Embedded loans and holds change without touching
+    // the books, so responses that include them are never conditional, and
+    // never cached.
+    let (last_modified, generation) = if relations.is_empty() {
+        let mut conn = pool.acquire().await?;
+        let last_modified = conditional::books_last_modified(&mut conn).await?;
+        let generation = if cache.is_enabled() { Some(query_cache::generation(&mut conn).await?) } else { None };
+        (last_modified, generation)
+    } else {
+        (None, None)
+    };
+    if last_modified.is_some_and(|t| conditional::not_modified_since(&headers, t)) {
+        return Ok((StatusCode::NOT_MODIFIED, VARY_LANGUAGE).into_response());
+    }
+    let respond = |body: Bytes| {
+        let content_type = [(header::CONTENT_TYPE, "application/json")];
+        match last_modified {
+            Some(t) => (VARY_LANGUAGE, conditional::last_modified_header(t), content_type, body).into_response(),
+            None => (VARY_LANGUAGE, content_type, body).into_response(),
+        }
+    };
+
+    let languages = translations::preferred_languages(&headers);
+    let cache_key = format!("{}\n{}", raw_query.unwrap_or_default(), languages.join(","));
+    if let Some(generation) = &generation
+        && let Some(body) = cache.get(generation.number, &cache_key)
+    {
+        return Ok(respond(body));
+    }
+
+    let offset = (page - 1) * limit;
+
+    let total_items = count_books(&pool, &filters).await? as usize;
+
+    let total_pages = total_items.div_ceil(limit);
+
+    let mut select = QueryBuilder::new(
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid FROM books",
+    );
+    filters.push_where(&mut select);
+    sort::push_order_by(&mut select, &sort_keys, &catalog.collation());
+    select.push(" LIMIT ").push_bind(limit as i64).push(" OFFSET ").push_bind(offset as i64);
+    let rows: Vec<BookRow> = select.build_query_as().fetch_all(&pool).await?;
+
+    let mut books: Vec<Book> = rows.into_iter().map(Book::from).collect();
+    let mut conn = pool.acquire().await?;
+    translations::localize(&mut conn, &mut books, &languages, &catalog.locale).await?;
+    let paginated_data = include::expand(&mut conn, books, &relations).await?;
+
+    let body = serde_json::to_vec(&PaginatedResponse {
+        data: paginated_data,
+        pagination: PaginationMeta {
+            page,
+            limit,
+            total_items,
+            total_pages,
+        },
+    })
+    .expect("book pages serialize");
+    let body = Bytes::from(body);
+    if let Some(generation) = generation
+        && generation.cacheable
+    {
+        cache.insert(generation.number, cache_key, body.clone());
+    }
+    Ok(respond(body))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookCount {
+    pub count: i64,
+}
+
+/// The number of books matching the list filters, without fetching any.
+pub async fn books_count(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    Query(params): Query<BookParams>,
+) -> Result<Json<BookCount>, AppError> {
+    let filters = BookFilters { include_deleted, ..BookFilters::from_params(&params)? };
+    Ok(Json(BookCount { count: count_books(&pool, &filters).await? }))
+}
+
+pub async fn count_books(pool: &PgPool, filters: &BookFilters) -> Result<i64, AppError> {
+    // Availability and year alone are answered from the running totals.
+    if let Some((available, year_from, year_to)) = filters.counted_dimensions() {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COALESCE(SUM(books), 0)::BIGINT AS "count!" FROM book_counts
+               WHERE ($1::BOOLEAN IS NULL OR available = $1)
+                 AND ($2::BIGINT IS NULL OR year >= $2) AND ($3::BIGINT IS NULL OR year <= $3)"#,
+            available,
+            year_from,
+            year_to,
+        )
+        .fetch_one(pool)
+        .await?;
+        return Ok(count);
+    }
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM books");
+    filters.push_where(&mut count);
+    Ok(count.build_query_scalar().fetch_one(pool).await?)
+}
+
+/// One book picked at random from those matching the list filters, for
+/// "surprise me" features. Pagination and sorting parameters are ignored.
+pub async fn random_book(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Query(params): Query<BookParams>,
+) -> Result<Json<Book>, AppError> {
+    let filters = BookFilters::from_params(&params)?;
+
+    let mut select = QueryBuilder::new(
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid FROM books",
+    );
+    filters.push_where(&mut select);
+    select.push(" ORDER BY random() LIMIT 1");
+    let row: Option<BookRow> = select.build_query_as().fetch_optional(&pool).await?;
+
+    row.map(|r| Json(r.into())).ok_or(AppError::NoMatches("books"))
+}
+
+/// Rows the stream reads ahead of a slow client.
+const STREAM_BUFFER: usize = 64;
+
+/// Every book matching the list filters as newline-delimited JSON, in
+/// `sort` order. Rows are sent as the database returns them, so memory use
+/// doesn't grow with the catalog. `page`, `limit`, and translations don't
+/// apply. A database error part-way through cuts the response short, since
+/// the status has already been sent.
+pub async fn stream_books(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogRead,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    Query(params): Query<BookParams>,
+) -> Result<Response, AppError> {
+    if params.include.is_some() {
+        return Err(AppError::InvalidInput("include is not supported when streaming books".to_string()));
+    }
+    let filters = BookFilters { include_deleted, ..BookFilters::from_params(&params)? };
+    let sort_keys = sort::parse(params.sort.as_deref())?;
+    let mut conn = pool.acquire().await?;
+
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let hidden = numeric_ids_hidden(&());
+    tokio::spawn(HIDE_NUMERIC_IDS.scope(hidden, async move {
+        let mut select = QueryBuilder::new(
+            "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                    format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid FROM books",
+        );
+        filters.push_where(&mut select);
+        sort::push_order_by(&mut select, &sort_keys, &catalog.collation());
+        let mut rows = select.build_query_as::<BookRow>().fetch(&mut *conn);
+        loop {
+            let line = match rows.try_next().await {
+                Ok(Some(row)) => {
+                    let mut line = serde_json::to_vec(&Book::from(row)).expect("books serialize");
+                    line.push(b'\n');
+                    Ok(Bytes::from(line))
+                }
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let failed = line.is_err();
+            // A closed channel means the client went away.
+            if sender.send(line).await.is_err() || failed {
+                break;
+            }
+        }
+    }));
+
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(ReceiverStream::new(receiver))).into_response())
+}
+
+pub async fn add_book(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogWrite,
+    Query(params): Query<AddBookParams>,
+    StrictJson(input): StrictJson<AddBook>
+) -> Result<(StatusCode, Json<CreatedBook>), AppError> {
+    check_field_lengths(
+        &catalog.field_limits,
+        Some(&input.title),
+        Some(&input.author),
+        Some(&input.isbn),
+        input.description.as_deref(),
+    )?;
+    if !validate_book(&input) {
+        return Err(AppError::BadRequest)
+    }
+    let classification_key = classification_key(input.classification_scheme, input.classification.as_deref())?;
+    let original_language = validate_original_work(&input.original_title, &input.original_language, &input.translator)?;
+    age_rating::validate_rating(input.age_rating)?;
+    let format = input.format.unwrap_or_default();
+    formats::validate(format, input.audiobook.as_ref())?;
+    let audiobook = input.audiobook.unwrap_or_default();
+
+    let mut tx = pool.begin().await?;
+    let possible_duplicates = duplicates::find(&mut tx, &input.title, &input.author, &input.isbn).await?;
+    if catalog.strict_duplicates && !params.allow_duplicates && !possible_duplicates.is_empty() {
+        return Err(AppError::PossibleDuplicates(possible_duplicates));
+    }
+
+    let row = sqlx::query!(
+        "INSERT INTO books (title, author, year, isbn, available, classification_scheme, classification, classification_key, description,
+                            original_title, original_language, translator, age_rating, format, narrator, duration_minutes, disc_count, file_count)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+         RETURNING id, uuid",
+        input.title,
+        input.author,
+        input.year,
+        input.isbn,
+        true,
+        input.classification_scheme.map(ClassificationScheme::as_str),
+        input.classification,
+        classification_key,
+        input.description,
+        input.original_title,
+        original_language,
+        input.translator,
+        input.age_rating,
+        format.as_str(),
+        audiobook.narrator,
+        audiobook.duration_minutes,
+        audiobook.discs,
+        audiobook.files,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let slug = slug::assign(&mut tx, row.id, &input.title, input.year).await?;
+    chat::announce(&mut tx, chat::Announcement::BookAdded { title: &input.title, author: &input.author, year: input.year }).await?;
+    tx.commit().await?;
+
+    let book = Book {
+        id: row.id,
+        uuid: Some(row.uuid),
+        title: input.title,
+        author: input.author,
+        year: input.year,
+        isbn: input.isbn,
+        available: true,
+        temporary: false,
+        classification_scheme: input.classification_scheme,
+        classification: input.classification,
+        slug: Some(slug),
+        description: input.description,
+        original_title: input.original_title,
+        original_language,
+        translator: input.translator,
+        age_rating: input.age_rating,
+        format,
+        audiobook: AudiobookDetails::from_columns(audiobook.narrator, audiobook.duration_minutes, audiobook.discs, audiobook.files),
+        language: None,
+        deleted_at: None,
+        version: 1,
+    };
+
+    Ok((StatusCode::CREATED, Json(CreatedBook { book, possible_duplicates })))
+}
+
+/// The book list's filters, validated: the flat query parameters plus any
+/// `filter` expression. Shared by every query over the filtered list.
+#[derive(Default)]
+pub struct BookFilters {
+    pub available: Option<bool>,
+    /// Inclusive bounds; `year` sets both.
+    pub year_from: Option<i64>,
+    pub year_to: Option<i64>,
+    pub classification_scheme: Option<ClassificationScheme>,
+    pub class_from: Option<String>,
+    pub class_to: Option<String>,
+    pub conditions: Vec<filter::Condition>,
+    pub search: Option<String>,
+    /// Exact ISBN, compared without hyphens. Only set by SRU searches.
+    pub isbn: Option<String>,
+    /// Deleted books are left out unless an admin asks for them.
+    pub include_deleted: bool,
+}
+
+impl BookFilters {
+    pub fn from_params(params: &BookParams) -> Result<Self, AppError> {
+        let (class_from, class_to) = classification_range(params)?;
+        if let (Some(from), Some(to)) = (params.year_from, params.year_to)
+            && from > to
+        {
+            return Err(AppError::InvalidInput(format!("year_from {} is after year_to {}", from, to)));
+        }
+        let mut conditions = params.filter.as_deref().map(filter::parse).transpose()?.unwrap_or_default();
+        if let Some(author) = &params.author {
+            conditions.push(filter::Condition::contains(filter::Field::Author, author));
+        }
+        if let Some(language) = &params.original_language {
+            conditions.push(filter::Condition::equals(filter::Field::OriginalLanguage, language));
+        }
+        if let Some(translator) = &params.translator {
+            conditions.push(filter::Condition::contains(filter::Field::Translator, translator));
+        }
+        if let Some(format) = params.format {
+            conditions.push(filter::Condition::equals(filter::Field::Format, format.as_str()));
+        }
+        Ok(BookFilters {
+            available: params.available,
+            year_from: params.year.into_iter().chain(params.year_from).max(),
+            year_to: params.year.into_iter().chain(params.year_to).min(),
+            classification_scheme: params.classification_scheme,
+            class_from,
+            class_to,
+            conditions,
+            search: params.q.as_deref().map(str::trim).filter(|q| !q.is_empty()).map(str::to_string),
+            isbn: None,
+            include_deleted: false,
+        })
+    }
+
+    /// `available` and the year range, when they are the only filters, so
+    /// the count can come from `book_counts`.
+    fn counted_dimensions(&self) -> Option<(Option<bool>, Option<i64>, Option<i64>)> {
+        let only_counted = self.classification_scheme.is_none()
+            && self.class_from.is_none()
+            && self.class_to.is_none()
+            && self.conditions.is_empty()
+            && self.search.is_none()
+            && self.isbn.is_none()
+            && !self.include_deleted;
+        only_counted.then_some((self.available, self.year_from, self.year_to))
+    }
+
+    /// Appends ` WHERE ...` (or nothing, with no filters and deleted books
+    /// included) to `query`.
+    pub fn push_where(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        let mut clause = " WHERE ";
+        let mut next = |query: &mut QueryBuilder<'_, Postgres>| {
+            query.push(clause);
+            clause = " AND ";
+        };
+        if !self.include_deleted {
+            next(query);
+            query.push("deleted_at IS NULL");
+        }
+        if let Some(available) = self.available {
+            next(query);
+            query.push("available = ").push_bind(available);
+        }
+        match (self.year_from, self.year_to) {
+            (Some(from), Some(to)) if from == to => {
+                next(query);
+                query.push("year = ").push_bind(from);
+            }
+            (from, to) => {
+                if let Some(from) = from {
+                    next(query);
+                    query.push("year >= ").push_bind(from);
+                }
+                if let Some(to) = to {
+                    next(query);
+                    query.push("year <= ").push_bind(to);
+                }
+            }
+        }
+        if let Some(scheme) = self.classification_scheme {
+            next(query);
+            query.push("classification_scheme = ").push_bind(scheme.as_str());
+        }
+        if let Some(from) = &self.class_from {
+            next(query);
+            query.push("classification_key >= ").push_bind(from.clone());
+        }
+        if let Some(to) = &self.class_to {
+            next(query);
+            query
+                .push("(classification_key <= ")
+                .push_bind(to.clone())
+                .push(" OR starts_with(classification_key, ")
+                .push_bind(to.clone())
+                .push("))");
+        }
+        for condition in &self.conditions {
+            next(query);
+            condition.push_sql(query);
+        }
+        if let Some(text) = &self.search {
+            next(query);
+            search::push_match(query, text);
+        }
+        if let Some(isbn) = &self.isbn {
+            next(query);
+            query.push("REPLACE(isbn, '-', '') = ").push_bind(isbn.replace('-', ""));
+        }
+    }
+}
+
+/// Turns the `class_from`/`class_to` query parameters into shelf-order keys.
+pub fn classification_range(params: &BookParams) -> Result<(Option<String>, Option<String>), AppError> {
+    if params.class_from.is_none() && params.class_to.is_none() {
+        return Ok((None, None));
+    }
+    let scheme = params.classification_scheme.ok_or_else(|| {
+        AppError::InvalidInput("classification_scheme is required with class_from or class_to".to_string())
+    })?;
+    let key = |bound: &Option<String>| -> Result<Option<String>, AppError> {
+        bound
+            .as_deref()
+            .map(|value| {
+                scheme.sort_key(value).ok_or_else(|| {
+                    AppError::InvalidInput(format!("{} is not a valid {} class number", value, scheme))
+                })
+            })
+            .transpose()
+    };
+    Ok((key(&params.class_from)?, key(&params.class_to)?))
+}
+
+pub async fn get_book(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogRead,
+    headers: HeaderMap,
+    BookId(id): BookId,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    Query(query): Query<BookQuery>,
+) -> Result<Response, AppError> {
+    let relations = Relation::parse_list(query.include.as_deref())?;
+    let current = sqlx::query!(
+        "SELECT updated_at, version FROM books WHERE id = $1 AND ($2 OR deleted_at IS NULL)",
+        id,
+        include_deleted
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::NotFound(id))?;
+    let (last_modified, etag) = (current.updated_at, conditional::etag_header(current.version));
+    if relations.is_empty() && conditional::not_modified_since(&headers, last_modified) {
+        return Ok((StatusCode::NOT_MODIFIED, VARY_LANGUAGE, conditional::last_modified_header(last_modified), etag).into_response());
+    }
+
+    let row = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books WHERE id = $1",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?;
+
+    let book = row.map(Book::from).ok_or(AppError::NotFound(id))?;
+    let mut conn = pool.acquire().await?;
+    let mut books = [book];
+    translations::localize(&mut conn, &mut books, &translations::preferred_languages(&headers), &catalog.locale).await?;
+    let [book] = books;
+    let content_language = content_language(&book);
+
+    if !relations.is_empty() {
+        let mut expanded = include::expand(&mut conn, vec![book], &relations).await?;
+        return Ok((VARY_LANGUAGE, content_language, Json(expanded.remove(0))).into_response());
+    }
+    Ok((VARY_LANGUAGE, content_language, conditional::last_modified_header(last_modified), etag, Json(book)).into_response())
+}
+
+/// Book responses depend on `Accept-Language` once translations exist.
+pub const VARY_LANGUAGE: [(header::HeaderName, &str); 1] = [(header::VARY, "accept-language")];
+
+/// `Content-Language` for a book served from a translation.
+pub fn content_language(book: &Book) -> AppendHeaders<Option<(header::HeaderName, String)>> {
+    AppendHeaders(book.language.clone().map(|language| (header::CONTENT_LANGUAGE, language)))
+}
+
+pub async fn get_book_by_slug(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogRead,
+    headers: HeaderMap,
+    Path(slug): Path<String>,
+) -> Result<Response, AppError> {
+    let book: Book = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books WHERE slug = $1 AND deleted_at IS NULL",
+        slug
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFoundBy("Book", "slug", slug))?
+    .into();
+
+    let mut books = [book];
+    let languages = translations::preferred_languages(&headers);
+    translations::localize(&mut *pool.acquire().await?, &mut books, &languages, &catalog.locale).await?;
+    let [book] = books;
+    Ok((VARY_LANGUAGE, content_language(&book), Json(book)).into_response())
+}
+
+/// The catalog record with this ISBN, hyphenated or not. Copies usually
+/// share one record, but the catalog doesn't require it; when several
+/// records carry the ISBN the lookup is ambiguous and answers `409`.
+pub async fn get_book_by_isbn(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogRead,
+    headers: HeaderMap,
+    Path(isbn): Path<String>,
+) -> Result<Response, AppError> {
+    if !is_valid_isbn(&isbn) {
+        return Err(AppError::InvalidInput(format!("{} is not a valid ISBN-13", isbn)));
+    }
+    let mut rows = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books WHERE REPLACE(isbn, '-', '') = $1 AND deleted_at IS NULL ORDER BY id LIMIT 2",
+        isbn.replace('-', "")
+    )
+    .fetch_all(&pool)
+    .await?;
+    if rows.len() > 1 {
+        return Err(AppError::Conflict(format!("More than one book has ISBN {}", isbn)));
+    }
+    let book: Book = rows.pop().ok_or(AppError::ResourceNotFoundBy("Book", "isbn", isbn))?.into();
+
+    let mut books = [book];
+    let languages = translations::preferred_languages(&headers);
+    translations::localize(&mut *pool.acquire().await?, &mut books, &languages, &catalog.locale).await?;
+    let [book] = books;
+    Ok((VARY_LANGUAGE, content_language(&book), Json(book)).into_response())
+}
+
+pub async fn update_book(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogWrite,
+    headers: HeaderMap,
+    BookId(id): BookId,
+    StrictJson(input): StrictJson<UpdateBook>
+) -> Result<Response, AppError> {
+    check_field_lengths(
+        &catalog.field_limits,
+        input.title.as_deref(),
+        input.author.as_deref(),
+        input.isbn.as_deref(),
+        input.description.as_deref(),
+    )?;
+    // A new class number on its own is checked against the book's existing scheme.
+    let scheme = match (input.classification_scheme, &input.classification) {
+        (None, Some(_)) => sqlx::query_scalar!("SELECT classification_scheme FROM books WHERE id = $1", id)
+            .fetch_optional(&pool)
+            .await?
+            .ok_or(AppError::NotFound(id))?
+            .and_then(|s| s.parse().ok()),
+        (scheme, _) => scheme,
+    };
+    let classification_key = classification_key(scheme, input.classification.as_deref())?;
+    let original_language = validate_original_work(&input.original_title, &input.original_language, &input.translator)?;
+    age_rating::validate_rating(input.age_rating)?;
+    // Audiobook details on their own are checked against the book's existing format.
+    let format = match (input.format, &input.audiobook) {
+        (None, Some(_)) => sqlx::query_scalar!("SELECT format FROM books WHERE id = $1", id)
+            .fetch_optional(&pool)
+            .await?
+            .ok_or(AppError::NotFound(id))?
+            .parse()
+            .ok(),
+        (format, _) => format,
+    };
+    formats::validate(format.unwrap_or_default(), input.audiobook.as_ref())?;
+    let audiobook = input.audiobook.unwrap_or_default();
+
+    let mut tx = pool.begin().await?;
+    conditional::lock_book_version(&mut tx, &headers, id).await?;
+    let result = sqlx::query!(
+        "UPDATE books
+         SET title     = COALESCE($1, title),
+             author    = COALESCE($2, author),
+             year      = COALESCE($3, year),
+             isbn      = COALESCE($4, isbn),
+             available = COALESCE($5, available),
+             classification_scheme = COALESCE($6, classification_scheme),
+             classification        = COALESCE($7, classification),
+             classification_key    = COALESCE($8, classification_key),
+             description           = COALESCE($9, description),
+             original_title        = COALESCE($10, original_title),
+             original_language     = COALESCE($11, original_language),
+             translator            = COALESCE($12, translator),
+             age_rating            = COALESCE($13, age_rating),
+             format                = COALESCE($14, format),
+             narrator         = CASE WHEN COALESCE($14, format) = 'audiobook' THEN COALESCE($15, narrator) END,
+             duration_minutes = CASE WHEN COALESCE($14, format) = 'audiobook' THEN COALESCE($16, duration_minutes) END,
+             disc_count       = CASE WHEN COALESCE($14, format) = 'audiobook' THEN COALESCE($17, disc_count) END,
+             file_count       = CASE WHEN COALESCE($14, format) = 'audiobook' THEN COALESCE($18, file_count) END,
+             updated_at = $19,
+             version    = version + 1
+         WHERE id = $20 AND deleted_at IS NULL",
+        input.title,
+        input.author,
+        input.year,
+        input.isbn,
+        input.available,
+        scheme.map(ClassificationScheme::as_str),
+        input.classification,
+        classification_key,
+        input.description,
+        input.original_title,
+        original_language,
+        input.translator,
+        input.age_rating,
+        format.map(BookFormat::as_str),
+        audiobook.narrator,
+        audiobook.duration_minutes,
+        audiobook.discs,
+        audiobook.files,
+        Utc::now(),
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound(id))
+    }
+
+    let row = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books WHERE id = $1",
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((conditional::etag_header(row.version), Json(Book::from(row))).into_response())
+}
+
+/// Marks the book deleted. Its copies, loans, and translations are kept,
+/// so `restore_book` can bring it back as it was.
+pub async fn delete_book(
+    State(pool): State<PgPool>,
+    _scope: CatalogWrite,
+    headers: HeaderMap,
+    BookId(id): BookId,
+) -> Result<StatusCode, AppError> {
+    let mut tx = pool.begin().await?;
+    conditional::lock_book_version(&mut tx, &headers, id).await?;
+    let now = Utc::now();
+    // Moving `updated_at` on is what changes the lists' Last-Modified.
+    sqlx::query!(
+        "UPDATE books SET deleted_at = $1, updated_at = $1, version = version + 1 WHERE id = $2",
+        now,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Undoes a deletion.
+pub async fn restore_book(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    BookId(id): BookId,
+) -> Result<Json<Book>, AppError> {
+    let row = sqlx::query_as!(
+        BookRow,
+        "UPDATE books SET deleted_at = NULL, updated_at = $1, version = version + 1 WHERE id = $2 AND deleted_at IS NOT NULL
+         RETURNING id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                   format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid",
+        Utc::now(),
+        id
+    )
+    .fetch_optional(&pool)
+    .await?;
+
+    match row {
+        Some(row) => Ok(Json(row.into())),
+        None => {
+            let exists = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM books WHERE id = $1) AS "exists!""#, id)
+                .fetch_one(&pool)
+                .await?;
+            Err(if exists { AppError::Conflict(format!("Book {} is not deleted", id)) } else { AppError::NotFound(id) })
+        }
+    }
+}
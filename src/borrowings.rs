@@ -0,0 +1,229 @@
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError, CatalogConfig, api_keys::CirculationAccess, books::BookId, circulation, copies::CopyStatus, holds, members,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Borrowing {
+    pub id: i64,
+    pub book_id: i64,
+    /// Set when the loan was made by scanning a specific copy.
+    pub copy_id: Option<i64>,
+    pub member_id: Option<i64>,
+    pub borrower_name: String,
+    pub borrowed_at: DateTime<Utc>,
+    pub due_date: DateTime<Utc>,
+    pub returned_at: Option<DateTime<Utc>>,
+}
+
+/// Loan period used when a borrow request doesn't specify one.
+pub const DEFAULT_LOAN_DAYS: i64 = 14;
+
+/// Longest loan period a borrow request may ask for.
+pub const MAX_LOAN_DAYS: i64 = 365;
+
+#[derive(Debug, Deserialize)]
+pub struct BorrowBook {
+    pub card_number: String,
+    pub days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OverdueBorrowing {
+    pub borrowing_id: i64,
+    pub book_id: i64,
+    pub book_title: String,
+    pub book_author: String,
+    pub borrower_name: String,
+    pub borrowed_at: DateTime<Utc>,
+    pub due_date: DateTime<Utc>,
+}
+
+/// Books with copies on record circulate copy by copy, through
+/// `circulation::scan`. This endpoint keeps serving titles without copies,
+/// lent against a member's card with the same eligibility checks.
+async fn has_copies(conn: &mut PgConnection, book_id: i64) -> Result<bool, AppError> {
+    Ok(sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM copies WHERE book_id = $1 AND status <> $2)",
+        book_id,
+        CopyStatus::Discarded.as_str(),
+    )
+    .fetch_one(conn)
+    .await?
+    .unwrap_or(false))
+}
+
+fn copies_circulate(book_id: i64) -> AppError {
+    AppError::Conflict(format!(
+        "Book {} has copies; check them out and in by barcode at /circulation/scan",
+        book_id
+    ))
+}
+
+/// The loan period a request asked for, or the default; outside
+/// 1..=`MAX_LOAN_DAYS` days is refused.
+pub fn loan_days(days: Option<i64>) -> Result<i64, AppError> {
+    let days = days.unwrap_or(DEFAULT_LOAN_DAYS);
+    if !(1..=MAX_LOAN_DAYS).contains(&days) {
+        return Err(AppError::InvalidInput(format!("days must be between 1 and {}", MAX_LOAN_DAYS)));
+    }
+    Ok(days)
+}
+
+/// Lends a copyless title to the member holding `card_number`. For staff,
+/// or a key with the `circulation` scope.
+pub async fn borrow_book(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _access: CirculationAccess,
+    BookId(id): BookId,
+    Json(input): Json<BorrowBook>,
+) -> Result<(StatusCode, Json<Borrowing>), AppError> {
+    let days = loan_days(input.days)?;
+    // The row lock makes a second borrower wait and then see the book as
+    // taken, rather than both loans going through.
+    let mut tx = pool.begin().await?;
+    let book = sqlx::query!(
+        "SELECT id, available FROM books WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let book = match book {
+        Some(b) => b,
+        None => return Err(AppError::NotFound(id)),
+    };
+
+    if has_copies(&mut tx, id).await? {
+        return Err(copies_circulate(id));
+    }
+    if !book.available {
+        return Err(AppError::BookUnavailable(id));
+    }
+    let member = members::find_by_card(&mut tx, input.card_number.trim()).await?;
+    circulation::require_eligible(&mut tx, &member, id, catalog.age_policy).await?;
+    holds::require_unreserved(&mut tx, id, member.id).await?;
+
+    let now = chrono::Utc::now();
+    let borrowed_at: DateTime<Utc> = now;
+    let due_date: DateTime<Utc> = now + chrono::Duration::days(days);
+
+    let row = sqlx::query!(
+        "INSERT INTO borrowings (book_id, member_id, borrower_name, borrowed_at, due_date) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        id,
+        member.id,
+        member.name,
+        borrowed_at,
+        due_date,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    holds::fulfil(&mut tx, member.id, id).await?;
+
+    sqlx::query!(
+        "UPDATE books SET available = false, updated_at = $1, version = version + 1 WHERE id = $2",
+        Utc::now(),
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(Borrowing {
+        id: row.id,
+        book_id: id,
+        copy_id: None,
+        member_id: Some(member.id),
+        borrower_name: member.name,
+        borrowed_at,
+        due_date,
+        returned_at: None,
+    })))
+}
+
+pub async fn return_book(
+    State(pool): State<PgPool>,
+    _access: CirculationAccess,
+    BookId(id): BookId,
+) -> Result<StatusCode, AppError> {
+    // Copy loans are closed by scanning the copy, which also puts it back
+    // on the shelf; only loans made here are returned here.
+    let mut tx = pool.begin().await?;
+    let borrowing = sqlx::query!(
+        "SELECT id, copy_id FROM borrowings WHERE book_id = $1 AND returned_at IS NULL
+         ORDER BY copy_id NULLS FIRST LIMIT 1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    match borrowing {
+        None => return Err(AppError::NotBorrowed(id)),
+        Some(b) if b.copy_id.is_some() => return Err(copies_circulate(id)),
+        Some(_) => {}
+    }
+
+    let returned_at: DateTime<Utc> = chrono::Utc::now();
+
+    sqlx::query!(
+        "UPDATE borrowings SET returned_at = $1 WHERE book_id = $2 AND copy_id IS NULL AND returned_at IS NULL",
+        returned_at,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Copies added since the loan was made decide availability from now on.
+    sqlx::query!(
+        "UPDATE books
+         SET available = NOT EXISTS(SELECT 1 FROM copies WHERE book_id = $1 AND status <> $2)
+                         OR EXISTS(SELECT 1 FROM copies WHERE book_id = $1 AND status = $3),
+             updated_at = $4,
+             version = version + 1
+         WHERE id = $1",
+        id,
+        CopyStatus::Discarded.as_str(),
+        CopyStatus::Available.as_str(),
+        Utc::now(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    holds::promote_next(&mut tx, id).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn list_overdue(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<OverdueBorrowing>>, AppError> {
+    let now: DateTime<Utc> = chrono::Utc::now();
+
+    let rows = sqlx::query!(
+        "SELECT b.id as borrowing_id, b.book_id, bk.title as book_title,
+                bk.author as book_author, b.borrower_name, b.borrowed_at, b.due_date
+         FROM borrowings b
+         JOIN books bk ON b.book_id = bk.id
+         WHERE b.due_date < $1 AND b.returned_at IS NULL",
+         now
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let overdue = rows.into_iter().map(|r| OverdueBorrowing {
+        borrowing_id: r.borrowing_id,
+        book_id: r.book_id,
+        book_title: r.book_title,
+        book_author: r.book_author,
+        borrower_name: r.borrower_name,
+        borrowed_at: r.borrowed_at,
+        due_date: r.due_date,
+    }).collect();
+
+    Ok(Json(overdue))
+}
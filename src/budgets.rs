@@ -0,0 +1,217 @@
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, auth::AuthMember, members::Member, query::Query};
+
+/// Money is tracked in integer cents. `committed` is earmarked by orders that
+/// have been placed but not yet received; `spent` is what received orders
+/// actually cost.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Budget {
+    pub id: i64,
+    pub fiscal_year: i32,
+    pub fund_code: String,
+    pub allocated_cents: i64,
+    pub committed_cents: i64,
+    pub spent_cents: i64,
+    pub remaining_cents: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+struct BudgetRow {
+    id: i64,
+    fiscal_year: i32,
+    fund_code: String,
+    allocated_cents: i64,
+    committed_cents: i64,
+    spent_cents: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl From<BudgetRow> for Budget {
+    fn from(r: BudgetRow) -> Self {
+        Budget {
+            id: r.id,
+            fiscal_year: r.fiscal_year,
+            fund_code: r.fund_code,
+            allocated_cents: r.allocated_cents,
+            committed_cents: r.committed_cents,
+            spent_cents: r.spent_cents,
+            remaining_cents: r.allocated_cents - r.committed_cents - r.spent_cents,
+            created_at: r.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddBudget {
+    fiscal_year: i32,
+    fund_code: String,
+    allocated_cents: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateBudget {
+    allocated_cents: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetParams {
+    fiscal_year: Option<i32>,
+}
+
+fn require_staff(member: &Member) -> Result<(), AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can manage budgets".to_string()));
+    }
+    Ok(())
+}
+
+pub async fn add_budget(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Json(input): Json<AddBudget>,
+) -> Result<(StatusCode, Json<Budget>), AppError> {
+    require_staff(&staff)?;
+    if input.fund_code.trim().is_empty() || input.allocated_cents < 0 {
+        return Err(AppError::InvalidInput(
+            "fund_code must not be empty and allocated_cents must not be negative".to_string(),
+        ));
+    }
+
+    let row = sqlx::query_as!(
+        BudgetRow,
+        "INSERT INTO budgets (fiscal_year, fund_code, allocated_cents, created_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (fiscal_year, fund_code) DO NOTHING
+         RETURNING id, fiscal_year, fund_code, allocated_cents, committed_cents, spent_cents, created_at",
+        input.fiscal_year,
+        input.fund_code,
+        input.allocated_cents,
+        Utc::now(),
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| AppError::Conflict(format!(
+        "A budget for fund {} in fiscal year {} already exists",
+        input.fund_code, input.fiscal_year
+    )))?;
+
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+pub async fn list_budgets(
+    State(pool): State<PgPool>,
+    Query(params): Query<BudgetParams>,
+) -> Result<Json<Vec<Budget>>, AppError> {
+    let rows = sqlx::query_as!(
+        BudgetRow,
+        "SELECT id, fiscal_year, fund_code, allocated_cents, committed_cents, spent_cents, created_at
+         FROM budgets
+         WHERE ($1::integer IS NULL OR fiscal_year = $1)
+         ORDER BY fiscal_year DESC, fund_code",
+        params.fiscal_year,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(Budget::from).collect()))
+}
+
+pub async fn get_budget(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<Budget>, AppError> {
+    let row = sqlx::query_as!(
+        BudgetRow,
+        "SELECT id, fiscal_year, fund_code, allocated_cents, committed_cents, spent_cents, created_at
+         FROM budgets WHERE id = $1",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Budget", id))?;
+
+    Ok(Json(row.into()))
+}
+
+pub async fn update_budget(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateBudget>,
+) -> Result<Json<Budget>, AppError> {
+    require_staff(&staff)?;
+    if input.allocated_cents < 0 {
+        return Err(AppError::InvalidInput("allocated_cents must not be negative".to_string()));
+    }
+
+    let row = sqlx::query_as!(
+        BudgetRow,
+        "UPDATE budgets SET allocated_cents = $1 WHERE id = $2
+         RETURNING id, fiscal_year, fund_code, allocated_cents, committed_cents, spent_cents, created_at",
+        input.allocated_cents,
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Budget", id))?;
+
+    Ok(Json(row.into()))
+}
+
+pub async fn exists(conn: &mut PgConnection, id: i64) -> Result<bool, AppError> {
+    Ok(sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM budgets WHERE id = $1)", id)
+        .fetch_one(conn)
+        .await?
+        .unwrap_or(false))
+}
+
+/// Earmarks `amount_cents` for a placed order, refusing to overdraw the budget.
+pub async fn commit(conn: &mut PgConnection, id: i64, amount_cents: i64) -> Result<(), AppError> {
+    let updated = sqlx::query!(
+        "UPDATE budgets SET committed_cents = committed_cents + $2
+         WHERE id = $1 AND allocated_cents - committed_cents - spent_cents >= $2",
+        id,
+        amount_cents,
+    )
+    .execute(conn)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(AppError::Conflict(format!(
+            "Budget with ID {} does not have {} cents remaining",
+            id, amount_cents
+        )));
+    }
+    Ok(())
+}
+
+/// Moves a previously committed amount to spent when the order arrives.
+pub async fn spend(conn: &mut PgConnection, id: i64, amount_cents: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        "UPDATE budgets
+         SET committed_cents = committed_cents - $2,
+             spent_cents     = spent_cents + $2
+         WHERE id = $1",
+        id,
+        amount_cents,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Returns a committed amount to the budget when an order is cancelled.
+pub async fn release(conn: &mut PgConnection, id: i64, amount_cents: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        "UPDATE budgets SET committed_cents = committed_cents - $2 WHERE id = $1",
+        id,
+        amount_cents,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use axum::{extract::State, response::Html};
+use sqlx::PgPool;
+
+use crate::{AppError, api_keys::CatalogRead, books::{Book, BookId, BookRow}, copies::CopyStatus};
+
+/// Standard 3 × 5 inch catalog card, typed in a monospace face.
+const CARD_STYLE: &str = "\
+body { margin: 2em; background: #eee; }
+.card { width: 5in; min-height: 3in; box-sizing: border-box; padding: 0.3in 0.35in;
+        background: #fffdf5; border: 1px solid #bbb; position: relative;
+        font: 11pt/1.35 \"Courier New\", Courier, monospace; color: #222; }
+.card::after { content: \"\"; position: absolute; left: 50%; bottom: 0.15in; width: 0.25in; height: 0.25in;
+               margin-left: -0.125in; border-radius: 50%; border: 1px solid #bbb; }
+.call-number { position: absolute; left: 0.35in; top: 0.3in; width: 0.8in; white-space: pre-line; }
+.entry { margin-left: 1in; }
+.heading { margin: 0; font-weight: normal; font-size: inherit; }
+.indent { margin: 0 0 0 2ch; text-indent: 0; }
+.tracings { margin-top: 1em; }
+.note { font-style: italic; }
+@media print { body { margin: 0; background: none; } .card { border: none; } }
+";
+
+struct Holdings {
+    copies: i64,
+    call_number: Option<String>,
+}
+
+/// Renders a book as a classic catalog card for kiosks and printouts: the
+/// call number in the corner, the author as main entry, the title and
+/// imprint indented beneath it, then holdings and tracings.
+pub async fn book_card(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    BookId(id): BookId,
+) -> Result<Html<String>, AppError> {
+    let book: Book = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::NotFound(id))?
+    .into();
+
+    let holdings = sqlx::query_as!(
+        Holdings,
+        r#"SELECT COUNT(*) AS "copies!",
+                  (ARRAY_AGG(call_number ORDER BY id) FILTER (WHERE call_number IS NOT NULL))[1] AS call_number
+           FROM copies WHERE book_id = $1 AND status <> $2"#,
+        id,
+        CopyStatus::Discarded.as_str(),
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Html(render_card(&book, &holdings)))
+}
+
+fn render_card(book: &Book, holdings: &Holdings) -> String {
+    let call_number = holdings
+        .call_number
+        .as_deref()
+        .or(book.classification.as_deref())
+        .map(|c| c.split_whitespace().collect::<Vec<_>>().join("\n"))
+        .unwrap_or_default();
+
+    let holdings_line = match holdings.copies {
+        0 => "No copies held.".to_string(),
+        1 => "1 copy.".to_string(),
+        n => format!("{} copies.", n),
+    };
+    let status_line = if book.temporary {
+        "<p class=\"indent note\">Inter-library loan &mdash; temporary record.</p>\n"
+    } else if !book.available {
+        "<p class=\"indent note\">All copies currently on loan.</p>\n"
+    } else {
+        ""
+    };
+    let isbn = if book.isbn.is_empty() {
+        String::new()
+    } else {
+        format!("<p class=\"indent\">ISBN {}</p>\n", escape_html(&book.isbn))
+    };
+
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>{title} &mdash; catalog card</title>
+<style>
+{style}</style>
+</head>
+<body>
+<article class=\"card\">
+<div class=\"call-number\">{call_number}</div>
+<div class=\"entry\">
+<h1 class=\"heading\">{author}.</h1>
+<p class=\"indent\">{title} / {author}. &mdash; {year}.</p>
+<p class=\"indent\">{holdings}</p>
+{isbn}{status}<p class=\"indent tracings\">I. Title.</p>
+</div>
+</article>
+</body>
+</html>
+",
+        style = CARD_STYLE,
+        call_number = escape_html(&call_number),
+        author = escape_html(&book.author),
+        title = escape_html(&book.title),
+        year = book.year,
+        holdings = holdings_line,
+        isbn = isbn,
+        status = status_line,
+    )
+}
+
+/// Also used for XML, which needs the same five characters escaped.
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
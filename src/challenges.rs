@@ -0,0 +1,460 @@
+//! Reading goals and challenges. Members set a yearly goal for themselves
+//! or join challenges the library runs; progress in both is the number of
+//! different books they have returned in the period, so nothing has to be
+//! logged by hand.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, auth::AuthMember, formats::BookFormat, query::Query};
+
+const MAX_TARGET: i32 = 1000;
+const DEFAULT_LEADERBOARD_SIZE: i64 = 10;
+const MAX_LEADERBOARD_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadingGoal {
+    pub year: i32,
+    /// Books the member means to read in the year.
+    pub target: i32,
+    /// Different books returned so far in the year.
+    pub books_read: i64,
+    pub completed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    /// Books to read between `starts_on` and `ends_on`, inclusive.
+    pub target: i32,
+    /// Only books in this format count, when set.
+    pub format: Option<BookFormat>,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub participants: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+struct ChallengeRow {
+    id: i64,
+    name: String,
+    description: Option<String>,
+    target: i32,
+    format: Option<String>,
+    starts_on: NaiveDate,
+    ends_on: NaiveDate,
+    participants: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl From<ChallengeRow> for Challenge {
+    fn from(r: ChallengeRow) -> Self {
+        Challenge {
+            id: r.id,
+            name: r.name,
+            description: r.description,
+            target: r.target,
+            format: r.format.and_then(|f| f.parse().ok()),
+            starts_on: r.starts_on,
+            ends_on: r.ends_on,
+            participants: r.participants,
+            created_at: r.created_at,
+        }
+    }
+}
+
+/// A member's standing in a challenge they joined.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeProgress {
+    pub challenge_id: i64,
+    pub name: String,
+    pub target: i32,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub books_read: i64,
+    pub completed: bool,
+    /// Whether the member appears, by first name and initial, on the
+    /// challenge's leaderboard.
+    pub show_on_leaderboard: bool,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub challenge_id: i64,
+    /// Everyone taking part, including those who keep off the board.
+    pub participants: i64,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub rank: i64,
+    pub display_name: String,
+    pub books_read: i64,
+    pub completed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoalParams {
+    year: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetGoal {
+    /// Defaults to the current year.
+    year: Option<i32>,
+    target: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateChallenge {
+    name: String,
+    description: Option<String>,
+    target: i32,
+    format: Option<BookFormat>,
+    starts_on: NaiveDate,
+    ends_on: NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChallengeParams {
+    /// Only challenges running today.
+    active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JoinChallenge {
+    /// Off unless the member asks to be listed.
+    #[serde(default)]
+    show_on_leaderboard: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardParams {
+    limit: Option<i64>,
+}
+
+fn validate_target(target: i32) -> Result<(), AppError> {
+    if !(1..=MAX_TARGET).contains(&target) {
+        return Err(AppError::InvalidInput(format!("target must be between 1 and {}", MAX_TARGET)));
+    }
+    Ok(())
+}
+
+/// "Alice Baker" becomes "Alice B.", so the board doesn't publish full names.
+pub fn display_name(name: &str) -> String {
+    let mut parts = name.split_whitespace();
+    let first = parts.next().unwrap_or("Reader");
+    match parts.last().and_then(|last| last.chars().next()) {
+        Some(initial) => format!("{} {}.", first, initial),
+        None => first.to_string(),
+    }
+}
+
+async fn goal(conn: &mut PgConnection, member_id: i64, year: i32) -> Result<Option<ReadingGoal>, AppError> {
+    Ok(sqlx::query_as!(
+        ReadingGoal,
+        r#"SELECT g.year, g.target,
+                  COUNT(DISTINCT br.book_id) AS "books_read!",
+                  COUNT(DISTINCT br.book_id) >= g.target AS "completed!"
+           FROM reading_goals g
+           LEFT JOIN borrowings br
+             ON br.member_id = g.member_id
+            AND br.returned_at >= make_date(g.year, 1, 1)::timestamptz
+            AND br.returned_at < make_date(g.year + 1, 1, 1)::timestamptz
+           WHERE g.member_id = $1 AND g.year = $2
+           GROUP BY g.year, g.target"#,
+        member_id,
+        year,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+pub async fn goals_for_member(conn: &mut PgConnection, member_id: i64) -> Result<Vec<ReadingGoal>, AppError> {
+    let years = sqlx::query_scalar!("SELECT year FROM reading_goals WHERE member_id = $1 ORDER BY year", member_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut goals = Vec::with_capacity(years.len());
+    for year in years {
+        goals.extend(goal(&mut *conn, member_id, year).await?);
+    }
+    Ok(goals)
+}
+
+pub async fn for_member(conn: &mut PgConnection, member_id: i64) -> Result<Vec<ChallengeProgress>, AppError> {
+    Ok(sqlx::query_as!(
+        ChallengeProgress,
+        r#"SELECT c.id AS challenge_id, c.name, c.target, c.starts_on, c.ends_on,
+                  progress.books_read AS "books_read!",
+                  progress.books_read >= c.target AS "completed!",
+                  p.show_on_leaderboard, p.joined_at
+           FROM challenge_participants p
+           JOIN reading_challenges c ON c.id = p.challenge_id
+           CROSS JOIN LATERAL (
+               SELECT COUNT(DISTINCT br.book_id) AS books_read
+               FROM borrowings br JOIN books b ON b.id = br.book_id
+               WHERE br.member_id = p.member_id
+                 AND br.returned_at >= c.starts_on::timestamptz
+                 AND br.returned_at < (c.ends_on + 1)::timestamptz
+                 AND (c.format IS NULL OR b.format = c.format)
+           ) progress
+           WHERE p.member_id = $1
+           ORDER BY c.starts_on, c.id"#,
+        member_id,
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+pub async fn my_reading_goal(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Query(params): Query<GoalParams>,
+) -> Result<Json<ReadingGoal>, AppError> {
+    let year = params.year.unwrap_or_else(|| Utc::now().year());
+    let mut conn = pool.acquire().await?;
+    goal(&mut conn, member.id, year)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::ResourceNotFoundBy("Reading goal", "year", year.to_string()))
+}
+
+/// Sets or changes the member's goal for a year.
+pub async fn set_my_reading_goal(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Json(input): Json<SetGoal>,
+) -> Result<Json<ReadingGoal>, AppError> {
+    validate_target(input.target)?;
+    let year = input.year.unwrap_or_else(|| Utc::now().year());
+    if !(2000..=9999).contains(&year) {
+        return Err(AppError::InvalidInput("year must be between 2000 and 9999".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO reading_goals (member_id, year, target, updated_at) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (member_id, year) DO UPDATE SET target = EXCLUDED.target, updated_at = EXCLUDED.updated_at",
+        member.id,
+        year,
+        input.target,
+        Utc::now(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    let goal = goal(&mut tx, member.id, year)
+        .await?
+        .ok_or_else(|| AppError::ResourceNotFoundBy("Reading goal", "year", year.to_string()))?;
+    tx.commit().await?;
+
+    Ok(Json(goal))
+}
+
+pub async fn create_challenge(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Json(input): Json<CreateChallenge>,
+) -> Result<(StatusCode, Json<Challenge>), AppError> {
+    if !staff.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can start challenges".to_string()));
+    }
+    if input.name.trim().is_empty() {
+        return Err(AppError::InvalidInput("name must not be empty".to_string()));
+    }
+    validate_target(input.target)?;
+    if input.ends_on < input.starts_on {
+        return Err(AppError::InvalidInput("ends_on must not be before starts_on".to_string()));
+    }
+
+    let row = sqlx::query_as!(
+        ChallengeRow,
+        r#"INSERT INTO reading_challenges (name, description, target, format, starts_on, ends_on, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7)
+           RETURNING id, name, description, target, format, starts_on, ends_on, 0::BIGINT AS "participants!", created_at"#,
+        input.name.trim(),
+        input.description,
+        input.target,
+        input.format.map(BookFormat::as_str),
+        input.starts_on,
+        input.ends_on,
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+pub async fn list_challenges(
+    State(pool): State<PgPool>,
+    Query(params): Query<ChallengeParams>,
+) -> Result<Json<Vec<Challenge>>, AppError> {
+    let rows = sqlx::query_as!(
+        ChallengeRow,
+        r#"SELECT c.id, c.name, c.description, c.target, c.format, c.starts_on, c.ends_on,
+                  (SELECT COUNT(*) FROM challenge_participants p WHERE p.challenge_id = c.id) AS "participants!",
+                  c.created_at
+           FROM reading_challenges c
+           WHERE NOT $1 OR $2 BETWEEN c.starts_on AND c.ends_on
+           ORDER BY c.starts_on, c.id"#,
+        params.active.unwrap_or(false),
+        Utc::now().date_naive(),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(Challenge::from).collect()))
+}
+
+async fn fetch_challenge(conn: &mut PgConnection, id: i64) -> Result<Challenge, AppError> {
+    Ok(sqlx::query_as!(
+        ChallengeRow,
+        r#"SELECT c.id, c.name, c.description, c.target, c.format, c.starts_on, c.ends_on,
+                  (SELECT COUNT(*) FROM challenge_participants p WHERE p.challenge_id = c.id) AS "participants!",
+                  c.created_at
+           FROM reading_challenges c WHERE c.id = $1"#,
+        id,
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Challenge", id))?
+    .into())
+}
+
+pub async fn get_challenge(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<Challenge>, AppError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(fetch_challenge(&mut conn, id).await?))
+}
+
+pub async fn my_challenges(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+) -> Result<Json<Vec<ChallengeProgress>>, AppError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(for_member(&mut conn, member.id).await?))
+}
+
+/// Joins a challenge, or changes whether the member is on its leaderboard.
+/// Challenges that have ended can't be joined.
+pub async fn join_challenge(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<JoinChallenge>,
+) -> Result<Json<ChallengeProgress>, AppError> {
+    let mut tx = pool.begin().await?;
+    let challenge = fetch_challenge(&mut tx, id).await?;
+    let joined = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM challenge_participants WHERE challenge_id = $1 AND member_id = $2)",
+        id,
+        member.id,
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .unwrap_or(false);
+    if !joined && challenge.ends_on < Utc::now().date_naive() {
+        return Err(AppError::Conflict(format!("Challenge {} ended on {}", challenge.name, challenge.ends_on)));
+    }
+
+    sqlx::query!(
+        "INSERT INTO challenge_participants (challenge_id, member_id, show_on_leaderboard, joined_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (challenge_id, member_id) DO UPDATE SET show_on_leaderboard = EXCLUDED.show_on_leaderboard",
+        id,
+        member.id,
+        input.show_on_leaderboard,
+        Utc::now(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    let progress = for_member(&mut tx, member.id)
+        .await?
+        .into_iter()
+        .find(|p| p.challenge_id == id)
+        .ok_or(AppError::ResourceNotFound("Challenge", id))?;
+    tx.commit().await?;
+
+    Ok(Json(progress))
+}
+
+pub async fn leave_challenge(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let left = sqlx::query!(
+        "DELETE FROM challenge_participants WHERE challenge_id = $1 AND member_id = $2",
+        id,
+        member.id,
+    )
+    .execute(&pool)
+    .await?
+    .rows_affected();
+    if left == 0 {
+        return Err(AppError::ResourceNotFound("Challenge", id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The participants who chose to be listed, most books first; ties go to
+/// whoever joined earlier. Names are shortened to a first name and initial.
+pub async fn challenge_leaderboard(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+    Query(params): Query<LeaderboardParams>,
+) -> Result<Json<Leaderboard>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE);
+    if !(1..=MAX_LEADERBOARD_SIZE).contains(&limit) {
+        return Err(AppError::InvalidInput(format!("limit must be between 1 and {}", MAX_LEADERBOARD_SIZE)));
+    }
+
+    let mut conn = pool.acquire().await?;
+    let challenge = fetch_challenge(&mut conn, id).await?;
+    let rows = sqlx::query!(
+        r#"SELECT m.name, progress.books_read AS "books_read!"
+           FROM challenge_participants p
+           JOIN members m ON m.id = p.member_id
+           JOIN reading_challenges c ON c.id = p.challenge_id
+           CROSS JOIN LATERAL (
+               SELECT COUNT(DISTINCT br.book_id) AS books_read
+               FROM borrowings br JOIN books b ON b.id = br.book_id
+               WHERE br.member_id = p.member_id
+                 AND br.returned_at >= c.starts_on::timestamptz
+                 AND br.returned_at < (c.ends_on + 1)::timestamptz
+                 AND (c.format IS NULL OR b.format = c.format)
+           ) progress
+           WHERE p.challenge_id = $1 AND p.show_on_leaderboard
+           ORDER BY progress.books_read DESC, p.joined_at, p.member_id
+           LIMIT $2"#,
+        id,
+        limit,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let entries = rows
+        .into_iter()
+        .zip(1..)
+        .map(|(row, rank)| LeaderboardEntry {
+            rank,
+            display_name: display_name(&row.name),
+            books_read: row.books_read,
+            completed: row.books_read >= i64::from(challenge.target),
+        })
+        .collect();
+
+    Ok(Json(Leaderboard { challenge_id: id, participants: challenge.participants, entries }))
+}
@@ -0,0 +1,444 @@
+//! Announcements to staff chat. Admins register Slack or Discord incoming
+//! webhooks, each with the events it wants (new books, finished reports).
+//! Announcements are written to an outbox in the same transaction as the
+//! change they describe, already formatted for each webhook's service, and
+//! the chat worker posts them, retrying failures on the same schedule as
+//! member notifications.
+
+use std::{fmt, str::FromStr, sync::Arc, time::Duration as StdDuration};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    api_keys::AdminAccess,
+    http_client::{HttpClient, HttpRequest},
+    queue,
+};
+
+const POLL_INTERVAL: StdDuration = StdDuration::from_secs(30);
+const BATCH: i64 = 20;
+const CLAIM_LEASE_MINUTES: i64 = 5;
+/// Messages shown per webhook in its delivery history.
+const HISTORY_LIMIT: i64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatService {
+    Slack,
+    Discord,
+}
+
+impl ChatService {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChatService::Slack => "slack",
+            ChatService::Discord => "discord",
+        }
+    }
+
+    /// Bold text in the service's markup. Text is escaped as far as the
+    /// service allows; in particular a title can't ping a channel.
+    fn bold(self, text: &str) -> String {
+        match self {
+            ChatService::Slack => format!("*{}*", self.escape(text)),
+            ChatService::Discord => format!("**{}**", self.escape(text)),
+        }
+    }
+
+    fn escape(self, text: &str) -> String {
+        match self {
+            ChatService::Slack => text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"),
+            ChatService::Discord => text.chars().fold(String::with_capacity(text.len()), |mut out, c| {
+                if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']') {
+                    out.push('\\');
+                }
+                out.push(c);
+                out
+            }),
+        }
+    }
+
+    fn payload(self, text: &str) -> serde_json::Value {
+        match self {
+            ChatService::Slack => json!({ "text": text }),
+            // Mentions in text still render, but never notify.
+            ChatService::Discord => json!({ "content": text, "allowed_mentions": { "parse": [] } }),
+        }
+    }
+}
+
+impl FromStr for ChatService {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slack" => Ok(ChatService::Slack),
+            "discord" => Ok(ChatService::Discord),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatEvent {
+    /// A book entered the catalog, by hand or by receiving an order.
+    BookAdded,
+    /// A report run finished, such as a weeding scan.
+    ReportCompleted,
+}
+
+impl ChatEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChatEvent::BookAdded => "book_added",
+            ChatEvent::ReportCompleted => "report_completed",
+        }
+    }
+}
+
+impl fmt::Display for ChatEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ChatEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "book_added" => Ok(ChatEvent::BookAdded),
+            "report_completed" => Ok(ChatEvent::ReportCompleted),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Something worth telling staff about.
+pub enum Announcement<'a> {
+    BookAdded { title: &'a str, author: &'a str, year: i64 },
+    ReportCompleted { report: &'a str, summary: String },
+}
+
+impl Announcement<'_> {
+    fn event(&self) -> ChatEvent {
+        match self {
+            Announcement::BookAdded { .. } => ChatEvent::BookAdded,
+            Announcement::ReportCompleted { .. } => ChatEvent::ReportCompleted,
+        }
+    }
+
+    fn text(&self, service: ChatService) -> String {
+        match self {
+            Announcement::BookAdded { title, author, year } => {
+                format!("New in the catalog: {} by {} ({})", service.bold(title), service.escape(author), year)
+            }
+            Announcement::ReportCompleted { report, summary } => {
+                format!("{} finished: {}", service.bold(report), service.escape(summary))
+            }
+        }
+    }
+}
+
+/// Queues the announcement for every webhook that wants its event.
+pub async fn announce(conn: &mut PgConnection, announcement: Announcement<'_>) -> Result<(), AppError> {
+    let event = announcement.event();
+    let webhooks = sqlx::query!(
+        "SELECT id, service FROM chat_webhooks WHERE $1 = ANY(events) ORDER BY id",
+        event.as_str()
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let now = Utc::now();
+    for webhook in webhooks {
+        let Ok(service) = webhook.service.parse::<ChatService>() else {
+            continue;
+        };
+        sqlx::query!(
+            "INSERT INTO chat_messages (webhook_id, event, payload, created_at, next_attempt_at)
+             VALUES ($1, $2, $3, $4, $4)",
+            webhook.id,
+            event.as_str(),
+            service.payload(&announcement.text(service)).to_string(),
+            now,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatWebhook {
+    pub id: i64,
+    pub name: String,
+    pub service: ChatService,
+    pub url: String,
+    pub events: Vec<ChatEvent>,
+    pub created_at: DateTime<Utc>,
+}
+
+struct ChatWebhookRow {
+    id: i64,
+    name: String,
+    service: String,
+    url: String,
+    events: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<ChatWebhookRow> for ChatWebhook {
+    fn from(r: ChatWebhookRow) -> Self {
+        ChatWebhook {
+            id: r.id,
+            name: r.name,
+            service: r.service.parse().unwrap_or(ChatService::Slack),
+            url: r.url,
+            events: r.events.iter().filter_map(|e| e.parse().ok()).collect(),
+            created_at: r.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub event: String,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub failed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateChatWebhook {
+    name: String,
+    service: ChatService,
+    url: String,
+    events: Vec<ChatEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateChatWebhook {
+    name: Option<String>,
+    url: Option<String>,
+    events: Option<Vec<ChatEvent>>,
+}
+
+fn validate(name: Option<&str>, url: Option<&str>, events: Option<&mut Vec<ChatEvent>>) -> Result<(), AppError> {
+    if name.is_some_and(|n| n.trim().is_empty()) {
+        return Err(AppError::InvalidInput("Webhook name must not be empty".to_string()));
+    }
+    if url.is_some_and(|u| !u.starts_with("https://")) {
+        return Err(AppError::InvalidInput("url must be the https:// webhook URL from Slack or Discord".to_string()));
+    }
+    if let Some(events) = events {
+        if events.is_empty() {
+            return Err(AppError::InvalidInput("Webhooks need at least one event".to_string()));
+        }
+        events.sort();
+        events.dedup();
+    }
+    Ok(())
+}
+
+fn event_names(events: &[ChatEvent]) -> Vec<String> {
+    events.iter().map(|e| e.as_str().to_string()).collect()
+}
+
+pub async fn list_chat_webhooks(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+) -> Result<Json<Vec<ChatWebhook>>, AppError> {
+    let rows = sqlx::query_as!(ChatWebhookRow, "SELECT * FROM chat_webhooks ORDER BY id")
+        .fetch_all(&pool)
+        .await?;
+
+    Ok(Json(rows.into_iter().map(ChatWebhook::from).collect()))
+}
+
+pub async fn create_chat_webhook(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Json(mut input): Json<CreateChatWebhook>,
+) -> Result<(StatusCode, Json<ChatWebhook>), AppError> {
+    validate(Some(&input.name), Some(&input.url), Some(&mut input.events))?;
+
+    let webhook = sqlx::query_as!(
+        ChatWebhookRow,
+        "INSERT INTO chat_webhooks (name, service, url, events, created_at)
+         VALUES ($1, $2, $3, $4, $5)
+         RETURNING *",
+        input.name.trim(),
+        input.service.as_str(),
+        input.url,
+        &event_names(&input.events),
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?
+    .into();
+
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub async fn update_chat_webhook(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+    Json(mut input): Json<UpdateChatWebhook>,
+) -> Result<Json<ChatWebhook>, AppError> {
+    validate(input.name.as_deref(), input.url.as_deref(), input.events.as_mut())?;
+    let events = input.events.as_deref().map(event_names);
+
+    let webhook = sqlx::query_as!(
+        ChatWebhookRow,
+        "UPDATE chat_webhooks
+         SET name   = COALESCE($2, name),
+             url    = COALESCE($3, url),
+             events = COALESCE($4, events)
+         WHERE id = $1
+         RETURNING *",
+        id,
+        input.name.as_deref().map(str::trim),
+        input.url,
+        events.as_deref(),
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Webhook", id))?
+    .into();
+
+    Ok(Json(webhook))
+}
+
+/// Removes a webhook along with anything still waiting to be posted to it.
+pub async fn delete_chat_webhook(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query!("DELETE FROM chat_webhooks WHERE id = $1", id).execute(&pool).await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::ResourceNotFound("Webhook", id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The webhook's latest messages, newest first, with how their delivery went.
+pub async fn list_chat_messages(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<ChatMessage>>, AppError> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query_scalar!("SELECT id FROM chat_webhooks WHERE id = $1", id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Webhook", id))?;
+    let messages = sqlx::query_as!(
+        ChatMessage,
+        "SELECT id, event, created_at, sent_at, attempts, last_error, failed_at
+         FROM chat_messages WHERE webhook_id = $1
+         ORDER BY id DESC LIMIT $2",
+        id,
+        HISTORY_LIMIT,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Json(messages))
+}
+
+/// Posts due announcements every `POLL_INTERVAL`, forever.
+pub async fn run(pool: PgPool, client: Arc<dyn HttpClient>) {
+    loop {
+        match deliver_due(&pool, client.as_ref()).await {
+            Ok(_) => {}
+            Err(AppError::Database(e)) => eprintln!("Chat: {}", e),
+            Err(_) => eprintln!("Chat: could not record a delivery"),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Claims the announcements that are due, posts them, and records each
+/// outcome. Returns how many were posted.
+pub async fn deliver_due(pool: &PgPool, client: &dyn HttpClient) -> Result<usize, AppError> {
+    let mut delivered = 0;
+    loop {
+        let now = Utc::now();
+        let batch = sqlx::query!(
+            "WITH due AS (
+                 SELECT id FROM chat_messages
+                 WHERE sent_at IS NULL AND failed_at IS NULL AND next_attempt_at <= $1
+                 ORDER BY next_attempt_at, id
+                 LIMIT $3
+                 FOR UPDATE SKIP LOCKED
+             ), claimed AS (
+                 UPDATE chat_messages m SET next_attempt_at = $2 FROM due WHERE m.id = due.id
+                 RETURNING m.id, m.webhook_id, m.payload, m.attempts
+             )
+             SELECT c.id, c.payload, c.attempts, w.url FROM claimed c JOIN chat_webhooks w ON w.id = c.webhook_id
+             ORDER BY c.id",
+            now,
+            now + Duration::minutes(CLAIM_LEASE_MINUTES),
+            BATCH,
+        )
+        .fetch_all(pool)
+        .await?;
+        if batch.is_empty() {
+            return Ok(delivered);
+        }
+
+        for message in batch {
+            let payload: serde_json::Value = serde_json::from_str(&message.payload).unwrap_or_default();
+            let outcome = match client.send(HttpRequest::post_json(&message.url, &payload)).await {
+                Ok(response) if response.is_success() => Ok(()),
+                // A removed webhook or a malformed message won't get better.
+                Ok(response) => Err((
+                    format!("HTTP {}: {}", response.status, response.body.chars().take(200).collect::<String>()),
+                    (400..500).contains(&response.status) && response.status != 429,
+                )),
+                Err(e) => Err((e.to_string(), false)),
+            };
+
+            let now = Utc::now();
+            match outcome {
+                Ok(()) => {
+                    sqlx::query!("UPDATE chat_messages SET sent_at = $2 WHERE id = $1", message.id, now)
+                        .execute(pool)
+                        .await?;
+                    delivered += 1;
+                }
+                Err((error, permanent)) => {
+                    let attempts = message.attempts + 1;
+                    let failed_at = (permanent || attempts >= queue::MAX_ATTEMPTS).then_some(now);
+                    sqlx::query!(
+                        "UPDATE chat_messages SET attempts = $2, last_error = $3, next_attempt_at = $4, failed_at = $5
+                         WHERE id = $1",
+                        message.id,
+                        attempts,
+                        error,
+                        now + queue::retry_delay(attempts),
+                        failed_at,
+                    )
+                    .execute(pool)
+                    .await?;
+                }
+            }
+        }
+    }
+}
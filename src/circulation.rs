@@ -0,0 +1,221 @@
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError, CatalogConfig, age_rating,
+    age_rating::AgePolicy,
+    api_keys::CirculationAccess,
+    auth,
+    borrowings::{self, Borrowing},
+    copies::{self, BookCopy, CopyCondition, CopyRow, CopyStatus},
+    fines, holds,
+    members::{self, Member},
+    terms,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanAction {
+    Checkout,
+    Return,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScanRequest {
+    barcode: String,
+    card_number: Option<String>,
+    days: Option<i64>,
+    /// Grade assessed at check-in; the copy keeps its grade when omitted.
+    condition: Option<CopyCondition>,
+    condition_note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanResult {
+    pub action: ScanAction,
+    pub borrowing: Borrowing,
+    pub copy: BookCopy,
+}
+
+/// Circulation desk entry point. Scanning a copy that is on loan checks it
+/// back in; scanning an available copy checks it out to the member whose
+/// card was scanned alongside it. For staff, or a key with the
+/// `circulation` scope.
+pub async fn scan(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _access: CirculationAccess,
+    Json(input): Json<ScanRequest>,
+) -> Result<(StatusCode, Json<ScanResult>), AppError> {
+    let mut tx = pool.begin().await?;
+    let copy = lock_copy(&mut tx, &input.barcode).await?;
+
+    let (status, result) = match copy.status {
+        CopyStatus::OnLoan => {
+            let condition = input.condition.unwrap_or(copy.condition);
+            (StatusCode::OK, check_in(&mut tx, copy, condition, input.condition_note.as_deref()).await?)
+        }
+        CopyStatus::Available => {
+            if input.condition.is_some() {
+                return Err(AppError::InvalidInput("condition is recorded when a copy is returned".to_string()));
+            }
+            let card_number = input.card_number.as_deref().ok_or_else(|| {
+                AppError::InvalidInput("card_number is required to check out a copy".to_string())
+            })?;
+            let days = borrowings::loan_days(input.days)?;
+            (StatusCode::CREATED, check_out(&mut tx, copy, card_number, days, catalog.age_policy).await?)
+        }
+        CopyStatus::Discarded => return Err(discarded(&copy)),
+    };
+
+    tx.commit().await?;
+
+    Ok((status, Json(result)))
+}
+
+/// The copy with this barcode, locked until the transaction ends. Copies
+/// of deleted books are still found so they can be checked in; `check_out`
+/// turns them away.
+pub async fn lock_copy(conn: &mut PgConnection, barcode: &str) -> Result<BookCopy, AppError> {
+    Ok(sqlx::query_as!(
+        CopyRow,
+        "SELECT id, book_id, barcode, status, condition, call_number, created_at
+         FROM copies WHERE barcode = $1 FOR UPDATE",
+        barcode.trim()
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| AppError::ResourceNotFoundBy("Copy", "barcode", barcode.to_string()))?
+    .into())
+}
+
+pub fn discarded(copy: &BookCopy) -> AppError {
+    AppError::Conflict(format!("Copy {} has been discarded and cannot circulate", copy.barcode))
+}
+
+/// Refuses a loan of `book_id` to `member` unless their email is verified,
+/// they have accepted the current terms and the age policy allows it. Every
+/// lending path goes through here.
+pub async fn require_eligible(
+    conn: &mut PgConnection,
+    member: &Member,
+    book_id: i64,
+    age_policy: AgePolicy,
+) -> Result<(), AppError> {
+    auth::require_verified_email(member)?;
+    terms::require_current_accepted(&mut *conn, member.id).await?;
+    age_rating::require_old_enough(conn, age_policy, member, book_id).await
+}
+
+pub async fn check_out(
+    conn: &mut PgConnection,
+    copy: BookCopy,
+    card_number: &str,
+    days: i64,
+    age_policy: AgePolicy,
+) -> Result<ScanResult, AppError> {
+    let listed = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)",
+        copy.book_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .unwrap_or(false);
+    if !listed {
+        return Err(AppError::Conflict(format!("Copy {} belongs to a deleted book and cannot circulate", copy.barcode)));
+    }
+    let member = members::find_by_card(&mut *conn, card_number.trim()).await?;
+    require_eligible(&mut *conn, &member, copy.book_id, age_policy).await?;
+    holds::require_unreserved(&mut *conn, copy.book_id, member.id).await?;
+
+    let borrowed_at = Utc::now();
+    let due_date = borrowed_at + Duration::days(days);
+    let id = sqlx::query_scalar!(
+        "INSERT INTO borrowings (book_id, copy_id, member_id, borrower_name, borrowed_at, due_date)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id",
+        copy.book_id,
+        copy.id,
+        member.id,
+        member.name,
+        borrowed_at,
+        due_date,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    holds::fulfil(&mut *conn, member.id, copy.book_id).await?;
+    let copy = set_copy_status(conn, copy, CopyStatus::OnLoan).await?;
+
+    Ok(ScanResult {
+        action: ScanAction::Checkout,
+        borrowing: Borrowing {
+            id,
+            book_id: copy.book_id,
+            copy_id: Some(copy.id),
+            member_id: Some(member.id),
+            borrower_name: member.name,
+            borrowed_at,
+            due_date,
+            returned_at: None,
+        },
+        copy,
+    })
+}
+
+/// Closes the copy's open loan and records its condition on return.
+pub async fn check_in(
+    conn: &mut PgConnection,
+    mut copy: BookCopy,
+    condition: CopyCondition,
+    condition_note: Option<&str>,
+) -> Result<ScanResult, AppError> {
+    let borrowing = sqlx::query_as!(
+        Borrowing,
+        "UPDATE borrowings SET returned_at = $1
+         WHERE copy_id = $2 AND returned_at IS NULL
+         RETURNING id, book_id, copy_id, member_id, borrower_name, borrowed_at, due_date, returned_at",
+        Utc::now(),
+        copy.id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| AppError::Conflict(format!("Copy {} is marked on loan but has no open loan", copy.barcode)))?;
+
+    copies::record_condition(&mut *conn, &mut copy, condition, Some(borrowing.id), condition_note).await?;
+    fines::assess_overdue(&mut *conn, &borrowing).await?;
+    holds::promote_next(&mut *conn, copy.book_id).await?;
+    let copy = set_copy_status(conn, copy, CopyStatus::Available).await?;
+
+    Ok(ScanResult { action: ScanAction::Return, borrowing, copy })
+}
+
+/// Updates a copy's status and keeps the title's `available` flag in step:
+/// a book is available while at least one of its copies is on the shelf.
+async fn set_copy_status(
+    conn: &mut PgConnection,
+    mut copy: BookCopy,
+    status: CopyStatus,
+) -> Result<BookCopy, AppError> {
+    sqlx::query!("UPDATE copies SET status = $1 WHERE id = $2", status.as_str(), copy.id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "UPDATE books
+         SET available = EXISTS(SELECT 1 FROM copies WHERE book_id = $1 AND status = $2),
+             updated_at = $3,
+             version = version + 1
+         WHERE id = $1",
+        copy.book_id,
+        CopyStatus::Available.as_str(),
+        Utc::now(),
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    copy.status = status;
+    Ok(copy)
+}
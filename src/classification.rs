@@ -0,0 +1,144 @@
+//! Dewey Decimal and Library of Congress class numbers: validation, and a
+//! normalized key that sorts in shelf order so ranges like 510–519 or
+//! QA1–QA99 can be queried with plain string comparisons.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassificationScheme {
+    Dewey,
+    Lcc,
+}
+
+impl ClassificationScheme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClassificationScheme::Dewey => "dewey",
+            ClassificationScheme::Lcc => "lcc",
+        }
+    }
+
+    /// Checks a complete class number, e.g. `512.7` or `QA76.73.R87 2020`.
+    pub fn is_valid(self, value: &str) -> bool {
+        match self {
+            ClassificationScheme::Dewey => parse_dewey(value).is_some_and(|(integer, _)| integer.len() == 3),
+            ClassificationScheme::Lcc => {
+                parse_lcc(value).is_some_and(|(_, integer, _, rest)| !integer.is_empty() && is_valid_lcc_rest(rest))
+            }
+        }
+    }
+
+    /// The shelf-order key for a class number. Also accepts the partial
+    /// values used as range bounds, such as `51` or `QA`; a bound matches
+    /// every class number its key is a prefix of.
+    pub fn sort_key(self, value: &str) -> Option<String> {
+        match self {
+            ClassificationScheme::Dewey => {
+                let (integer, fraction) = parse_dewey(value)?;
+                Some(format!("{}{}", integer, fraction))
+            }
+            ClassificationScheme::Lcc => {
+                let (letters, integer, fraction, rest) = parse_lcc(value)?;
+                if integer.is_empty() {
+                    return Some(format!("{:<3}", letters));
+                }
+                let mut key = format!("{:<3}{:0>4}{}", letters, integer, fraction);
+                if !rest.is_empty() {
+                    // A space sorts before '.', so QA76 .R87 shelves ahead of QA76.5.
+                    key.push(' ');
+                    key.push_str(rest);
+                }
+                Some(key)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ClassificationScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ClassificationScheme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dewey" => Ok(ClassificationScheme::Dewey),
+            "lcc" => Ok(ClassificationScheme::Lcc),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Splits a Dewey number into its integer digits and `.fraction` (possibly
+/// empty). Up to three integer digits are allowed so partial bounds parse.
+fn parse_dewey(value: &str) -> Option<(&str, &str)> {
+    let value = value.trim();
+    let (integer, fraction) = match value.split_once('.') {
+        Some((integer, digits)) => {
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            (integer, &value[integer.len()..])
+        }
+        None => (value, ""),
+    };
+    if integer.is_empty() || integer.len() > 3 || !integer.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if !fraction.is_empty() && integer.len() != 3 {
+        return None;
+    }
+    Some((integer, fraction))
+}
+
+/// Splits an LCC number into class letters, integer class number, `.fraction`,
+/// and the remaining cutters/date. Everything after the letters may be empty.
+fn parse_lcc(value: &str) -> Option<(&str, &str, &str, &str)> {
+    let value = value.trim();
+    let letters_end = value.bytes().take_while(|b| b.is_ascii_uppercase()).count();
+    if !(1..=3).contains(&letters_end) {
+        return None;
+    }
+    let (letters, rest) = value.split_at(letters_end);
+
+    let integer_end = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if integer_end > 4 {
+        return None;
+    }
+    let (integer, rest) = rest.split_at(integer_end);
+    if integer.is_empty() {
+        return rest.is_empty().then_some((letters, integer, "", ""));
+    }
+
+    let fraction_end = match rest.strip_prefix('.') {
+        Some(after) if after.starts_with(|c: char| c.is_ascii_digit()) => {
+            1 + after.bytes().take_while(|b| b.is_ascii_digit()).count()
+        }
+        _ => 0,
+    };
+    let (fraction, rest) = rest.split_at(fraction_end);
+    Some((letters, integer, fraction, rest.trim()))
+}
+
+/// Cutter numbers (`.R87`, optionally a second one) and an optional year.
+fn is_valid_lcc_rest(rest: &str) -> bool {
+    let mut parts: Vec<&str> = rest.split_whitespace().collect();
+    if parts.last().is_some_and(|p| p.len() == 4 && p.bytes().all(|b| b.is_ascii_digit())) {
+        parts.pop();
+    }
+    let cutters: Vec<&str> = parts.iter().flat_map(|p| p.trim_start_matches('.').split('.')).collect();
+    cutters.len() <= 2 && cutters.iter().all(|c| is_cutter(c))
+}
+
+fn is_cutter(value: &str) -> bool {
+    let mut chars = value.chars();
+    chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && !chars.as_str().is_empty()
+        && chars.all(|c| c.is_ascii_digit())
+}
@@ -0,0 +1,103 @@
+//! `Last-Modified` / `If-Modified-Since` handling for catalog reads. HTTP
+//! dates only have whole seconds, so times are compared at that precision.
+//! Book edits are guarded the other way round, by `ETag` / `If-Match` on the
+//! book's version.
+
+use axum::http::{HeaderMap, HeaderValue, header};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::PgConnection;
+
+use crate::AppError;
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format(HTTP_DATE).to_string()
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.trim(), HTTP_DATE).ok().map(|t| t.and_utc())
+}
+
+/// Whether the client's copy, as of its `If-Modified-Since`, is still
+/// current. Unparseable dates are ignored, as RFC 9110 requires.
+pub fn not_modified_since(headers: &HeaderMap, last_modified: DateTime<Utc>) -> bool {
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date)
+        .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+}
+
+pub fn last_modified_header(last_modified: DateTime<Utc>) -> [(header::HeaderName, HeaderValue); 1] {
+    let value = HeaderValue::from_str(&http_date(last_modified)).expect("HTTP dates are valid header values");
+    [(header::LAST_MODIFIED, value)]
+}
+
+/// The last time any book was added, changed, or deleted. `None` for a
+/// catalog that has never had a book.
+pub async fn books_last_modified(conn: &mut PgConnection) -> Result<Option<DateTime<Utc>>, AppError> {
+    let last_modified = sqlx::query_scalar!(
+        "SELECT GREATEST((SELECT MAX(updated_at) FROM books), books_deleted_at) FROM catalog_state"
+    )
+    .fetch_optional(conn)
+    .await?
+    .flatten();
+
+    Ok(last_modified)
+}
+
+/// Records a book deletion so list responses stop being reported unmodified.
+pub async fn record_book_deletion(conn: &mut PgConnection) -> Result<(), AppError> {
+    sqlx::query!("UPDATE catalog_state SET books_deleted_at = $1", Utc::now())
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The `ETag` of a book at `version`.
+pub fn book_etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+pub fn etag_header(version: i64) -> [(header::HeaderName, HeaderValue); 1] {
+    let value = HeaderValue::from_str(&book_etag(version)).expect("ETags are valid header values");
+    [(header::ETAG, value)]
+}
+
+/// Locks a book's row for the rest of the transaction and, if the request
+/// has `If-Match`, checks it names the current version. `*` matches any.
+/// Tags are compared strongly, so weak ones (`W/"3"`) never match.
+pub async fn lock_book_version(conn: &mut PgConnection, headers: &HeaderMap, book_id: i64) -> Result<(), AppError> {
+    let version = sqlx::query_scalar!("SELECT version FROM books WHERE id = $1 AND deleted_at IS NULL FOR UPDATE", book_id)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(AppError::NotFound(book_id))?;
+    let Some(if_match) = headers.get(header::IF_MATCH) else {
+        return Ok(());
+    };
+    let current = book_etag(version);
+    let matches = if_match
+        .to_str()
+        .is_ok_and(|tags| tags.split(',').map(str::trim).any(|tag| tag == "*" || tag == current));
+    if !matches {
+        return Err(AppError::PreconditionFailed(format!(
+            "Book {} has been changed by someone else; it is now at version {}",
+            book_id, version
+        )));
+    }
+    Ok(())
+}
+
+/// Marks a book modified, for `Last-Modified` and `If-Match`, when something
+/// served as part of it, such as a translation, changes without touching
+/// its row.
+pub async fn touch_book(conn: &mut PgConnection, book_id: i64) -> Result<(), AppError> {
+    let updated = sqlx::query!("UPDATE books SET updated_at = $1, version = version + 1 WHERE id = $2 AND deleted_at IS NULL", Utc::now(), book_id)
+        .execute(conn)
+        .await?;
+    if updated.rows_affected() == 0 {
+        return Err(AppError::NotFound(book_id));
+    }
+    Ok(())
+}
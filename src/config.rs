@@ -0,0 +1,605 @@
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::{Arc, OnceLock}, time::Duration};
+
+use sqlx::{PgPool, postgres::{PgConnectOptions, PgPoolOptions}};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub catalog: CatalogConfig,
+    /// The self-check listener, if `SIP2_ADDR` is set.
+    pub sip2: Option<crate::sip2::Sip2Config>,
+    /// The email worker, if `SMTP_HOST` is set.
+    pub smtp: Option<crate::mailer::SmtpConfig>,
+    /// The SMS worker, if `SMS_ACCOUNT_SID` is set.
+    pub sms: Option<crate::sms::SmsConfig>,
+    /// The push worker, if `FCM_SERVICE_ACCOUNT` or `APNS_KEY` is set.
+    pub push: Option<crate::push::PushConfig>,
+    /// Keys the member pseudonyms in warehouse exports; `export` needs it.
+    pub warehouse_key: Option<SigningKey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+    /// `None` keeps idle connections open indefinitely.
+    pub idle_timeout: Option<Duration>,
+    /// Number of prepared statements cached per connection; `0` disables caching.
+    pub statement_cache_capacity: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// Refuse admin sessions until the account has enrolled in two-factor
+    /// authentication.
+    pub require_admin_2fa: bool,
+    /// Signs e-book download links.
+    pub download_key: SigningKey,
+    /// How long a signed download link stays valid.
+    pub download_link_ttl: Duration,
+    /// Directory logins, if `LDAP_URL` is set.
+    pub ldap: Option<crate::ldap::LdapConfig>,
+    /// Single sign-on, if `SAML_IDP_SSO_URL` is set.
+    pub saml: Option<crate::saml::SamlConfig>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            require_admin_2fa: false,
+            download_key: SigningKey::for_process(),
+            download_link_ttl: Duration::from_secs(300),
+            ldap: None,
+            saml: None,
+        }
+    }
+}
+
+/// An HMAC key. Debug output never shows the bytes.
+#[derive(Clone)]
+pub struct SigningKey(Arc<[u8]>);
+
+impl SigningKey {
+    pub fn new(bytes: &[u8]) -> Self {
+        SigningKey(bytes.into())
+    }
+
+    /// A random key generated once per process, used when none is
+    /// configured. Links signed with it stop working on restart.
+    pub fn for_process() -> Self {
+        static KEY: OnceLock<SigningKey> = OnceLock::new();
+        KEY.get_or_init(|| {
+            let mut bytes = [0u8; 32];
+            rand::RngCore::fill_bytes(&mut rand::rng(), &mut bytes);
+            SigningKey::new(&bytes)
+        })
+        .clone()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SigningKey(***)")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CatalogConfig {
+    /// Locale whose ICU collation orders titles and authors, e.g. `fr` or
+    /// `sv`. `und` is the language-neutral default order.
+    pub locale: String,
+    /// Reject new books that look like duplicates of existing records
+    /// instead of creating them with a warning.
+    pub strict_duplicates: bool,
+    /// Longest excerpt, in characters, that can be stored for a book.
+    pub excerpt_max_chars: usize,
+    /// Largest `limit` a paginated list accepts.
+    pub max_page_limit: usize,
+    pub field_limits: FieldLimits,
+    /// Reject book payloads with fields the API doesn't know, such as a
+    /// misspelled `auther`, instead of ignoring them.
+    pub strict_json: bool,
+    /// Address books only by UUID: numeric ids are left out of book
+    /// responses and refused in `/books/{id}` paths.
+    pub uuid_book_ids: bool,
+    /// Public read-only demo: the fixtures are loaded into an empty catalog
+    /// and every change is refused.
+    pub demo_mode: bool,
+    /// Printed at the top of loan receipts.
+    pub branch: BranchInfo,
+    /// How books' age ratings limit checkouts.
+    pub age_policy: crate::age_rating::AgePolicy,
+    /// Feature flags set by `FEATURES`; flags not listed use their default.
+    pub features: BTreeMap<&'static str, bool>,
+    /// Warnings sent on the unversioned routes.
+    pub deprecation: crate::legacy::Deprecation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchInfo {
+    pub name: String,
+    /// One line per `;` in `BRANCH_ADDRESS`.
+    pub address: Vec<String>,
+    pub phone: Option<String>,
+}
+
+impl Default for BranchInfo {
+    fn default() -> Self {
+        BranchInfo { name: "Library".to_string(), address: Vec::new(), phone: None }
+    }
+}
+
+/// Longest accepted value, in characters, for each free-text book field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldLimits {
+    pub title: usize,
+    pub author: usize,
+    pub isbn: usize,
+    pub description: usize,
+}
+
+impl Default for FieldLimits {
+    fn default() -> Self {
+        FieldLimits { title: 500, author: 300, isbn: 17, description: 10_000 }
+    }
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        CatalogConfig {
+            locale: "und".to_string(),
+            strict_duplicates: false,
+            excerpt_max_chars: 2000,
+            max_page_limit: 100,
+            field_limits: FieldLimits::default(),
+            strict_json: false,
+            uuid_book_ids: false,
+            demo_mode: false,
+            branch: BranchInfo::default(),
+            age_policy: crate::age_rating::AgePolicy::default(),
+            features: BTreeMap::new(),
+            deprecation: crate::legacy::Deprecation::default(),
+        }
+    }
+}
+
+impl CatalogConfig {
+    /// The Postgres collation for the locale. Only valid once `check` has
+    /// confirmed it exists.
+    pub fn collation(&self) -> String {
+        format!("{}-x-icu", self.locale)
+    }
+
+    /// Confirms the database server has an ICU collation for the locale.
+    pub async fn check(&self, pool: &PgPool) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM pg_collation WHERE collname = $1 AND collprovider = 'i')",
+            self.collation()
+        )
+        .fetch_one(pool)
+        .await
+        .map(|exists| exists.unwrap_or(false))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Invalid {
+        var: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(var) => write!(f, "{} must be set", var),
+            ConfigError::Invalid { var, value, expected } => {
+                write!(f, "{} has invalid value {:?} (expected {})", var, value, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    /// Builds the config from an arbitrary variable source, so parsing can be
+    /// exercised without touching the process environment.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let url = lookup("DATABASE_URL").ok_or(ConfigError::Missing("DATABASE_URL"))?;
+        // Caught here rather than as a connection failure, which doesn't say
+        // which variable is wrong.
+        let is_postgres = url.starts_with("postgres://") || url.starts_with("postgresql://");
+        if !is_postgres || PgConnectOptions::from_str(&url).is_err() {
+            return Err(ConfigError::Invalid {
+                var: "DATABASE_URL",
+                value: redact_url(&url),
+                expected: "a postgres:// connection URL",
+            });
+        }
+
+        let max_connections: u32 = parse_var(&lookup, "DATABASE_MAX_CONNECTIONS", 10, "a positive integer")?;
+        if max_connections == 0 {
+            return Err(ConfigError::Invalid {
+                var: "DATABASE_MAX_CONNECTIONS",
+                value: max_connections.to_string(),
+                expected: "a positive integer",
+            });
+        }
+
+        let acquire_timeout_secs: u64 = parse_var(&lookup, "DATABASE_ACQUIRE_TIMEOUT_SECS", 30, "a number of seconds")?;
+        if acquire_timeout_secs == 0 {
+            return Err(ConfigError::Invalid {
+                var: "DATABASE_ACQUIRE_TIMEOUT_SECS",
+                value: acquire_timeout_secs.to_string(),
+                expected: "a positive number of seconds",
+            });
+        }
+        let idle_timeout_secs: u64 = parse_var(&lookup, "DATABASE_IDLE_TIMEOUT_SECS", 600, "a number of seconds")?;
+        let statement_cache_capacity: usize = parse_var(&lookup, "DATABASE_STATEMENT_CACHE_CAPACITY", 100, "a non-negative integer")?;
+        let require_admin_2fa: bool = parse_var(&lookup, "REQUIRE_ADMIN_2FA", false, "true or false")?;
+        let download_key = match lookup("DOWNLOAD_SIGNING_KEY") {
+            None => SigningKey::for_process(),
+            Some(key) if key.len() >= 32 => SigningKey::new(key.as_bytes()),
+            Some(_) => {
+                // The value is a secret, so it isn't echoed back.
+                return Err(ConfigError::Invalid {
+                    var: "DOWNLOAD_SIGNING_KEY",
+                    value: "***".to_string(),
+                    expected: "a secret of at least 32 characters",
+                });
+            }
+        };
+        let warehouse_key = match lookup("WAREHOUSE_MEMBER_KEY") {
+            None => None,
+            Some(key) if key.len() >= 32 => Some(SigningKey::new(key.as_bytes())),
+            Some(_) => {
+                return Err(ConfigError::Invalid {
+                    var: "WAREHOUSE_MEMBER_KEY",
+                    value: "***".to_string(),
+                    expected: "a secret of at least 32 characters",
+                });
+            }
+        };
+        let download_link_ttl_secs: u64 = parse_var(&lookup, "DOWNLOAD_LINK_TTL_SECS", 300, "a number of seconds")?;
+        let strict_duplicates: bool = parse_var(&lookup, "STRICT_DUPLICATE_CHECK", false, "true or false")?;
+        let strict_json: bool = parse_var(&lookup, "STRICT_JSON", false, "true or false")?;
+        let uuid_book_ids: bool = parse_var(&lookup, "UUID_BOOK_IDS", false, "true or false")?;
+        let demo_mode: bool = parse_var(&lookup, "DEMO_MODE", false, "true or false")?;
+        let branch = BranchInfo {
+            name: lookup("BRANCH_NAME").map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).unwrap_or_else(|| "Library".to_string()),
+            address: lookup("BRANCH_ADDRESS")
+                .map(|a| a.split(';').map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+            phone: lookup("BRANCH_PHONE").map(|p| p.trim().to_string()).filter(|p| !p.is_empty()),
+        };
+        let age_policy = parse_var(&lookup, "AGE_RESTRICTIONS", crate::age_rating::AgePolicy::default(), "off, enforce, or strict")?;
+        let features = match lookup("FEATURES") {
+            None => BTreeMap::new(),
+            Some(value) => crate::features::parse_settings(&value).map_err(|entry| ConfigError::Invalid {
+                var: "FEATURES",
+                value: entry,
+                expected: "comma-separated name=on or name=off for known features",
+            })?,
+        };
+        let deprecated_on: Option<chrono::NaiveDate> = parse_optional(&lookup, "LEGACY_DEPRECATION_DATE", "a date such as 2027-01-31")?;
+        let sunset_on: Option<chrono::NaiveDate> = parse_optional(&lookup, "LEGACY_SUNSET_DATE", "a date such as 2027-06-30")?;
+        if let (Some(deprecated), Some(sunset)) = (deprecated_on, sunset_on)
+            && sunset < deprecated
+        {
+            return Err(ConfigError::Invalid {
+                var: "LEGACY_SUNSET_DATE",
+                value: sunset.to_string(),
+                expected: "a date no earlier than LEGACY_DEPRECATION_DATE",
+            });
+        }
+        let excerpt_max_chars: usize = parse_positive(&lookup, "EXCERPT_MAX_CHARS", 2000)?;
+        let max_page_limit: usize = parse_positive(&lookup, "MAX_PAGE_LIMIT", 100)?;
+        let defaults = FieldLimits::default();
+        let field_limits = FieldLimits {
+            title: parse_positive(&lookup, "TITLE_MAX_CHARS", defaults.title)?,
+            author: parse_positive(&lookup, "AUTHOR_MAX_CHARS", defaults.author)?,
+            isbn: parse_positive(&lookup, "ISBN_MAX_CHARS", defaults.isbn)?,
+            description: parse_positive(&lookup, "DESCRIPTION_MAX_CHARS", defaults.description)?,
+        };
+
+        let sip2_addr: Option<std::net::SocketAddr> = parse_optional(&lookup, "SIP2_ADDR", "an address such as 0.0.0.0:6001")?;
+        let sip2 = match sip2_addr {
+            None => None,
+            Some(addr) => Some(crate::sip2::Sip2Config {
+                addr,
+                username: lookup("SIP2_USERNAME").ok_or(ConfigError::Missing("SIP2_USERNAME"))?,
+                password: lookup("SIP2_PASSWORD").ok_or(ConfigError::Missing("SIP2_PASSWORD"))?,
+                institution: lookup("SIP2_INSTITUTION").unwrap_or_else(|| "library".to_string()),
+                age_policy,
+            }),
+        };
+
+        let smtp = match lookup("SMTP_HOST").filter(|host| !host.trim().is_empty()) {
+            None => None,
+            Some(host) => {
+                let security = match lookup("SMTP_SECURITY").as_deref().map(str::trim) {
+                    None | Some("starttls") => crate::mailer::SmtpSecurity::StartTls,
+                    Some("tls") => crate::mailer::SmtpSecurity::Tls,
+                    Some("none") => crate::mailer::SmtpSecurity::None,
+                    Some(other) => {
+                        return Err(ConfigError::Invalid {
+                            var: "SMTP_SECURITY",
+                            value: other.to_string(),
+                            expected: "starttls, tls, or none",
+                        });
+                    }
+                };
+                let default_port = match security {
+                    crate::mailer::SmtpSecurity::StartTls => 587,
+                    crate::mailer::SmtpSecurity::Tls => 465,
+                    crate::mailer::SmtpSecurity::None => 25,
+                };
+                let username = lookup("SMTP_USERNAME").filter(|name| !name.is_empty());
+                let password = match username {
+                    Some(_) => Some(lookup("SMTP_PASSWORD").ok_or(ConfigError::Missing("SMTP_PASSWORD"))?),
+                    None => None,
+                };
+                Some(crate::mailer::SmtpConfig {
+                    host,
+                    port: parse_optional(&lookup, "SMTP_PORT", "a port number")?.unwrap_or(default_port),
+                    security,
+                    username,
+                    password,
+                    from: lookup("SMTP_FROM").ok_or(ConfigError::Missing("SMTP_FROM"))?,
+                    from_name: lookup("SMTP_FROM_NAME").unwrap_or_else(|| "Library".to_string()),
+                })
+            }
+        };
+
+        let sms = match lookup("SMS_ACCOUNT_SID").filter(|sid| !sid.trim().is_empty()) {
+            None => None,
+            Some(account_sid) => Some(crate::sms::SmsConfig {
+                api_url: lookup("SMS_API_URL").unwrap_or_else(|| "https://api.twilio.com".to_string()),
+                account_sid,
+                auth_token: lookup("SMS_AUTH_TOKEN").ok_or(ConfigError::Missing("SMS_AUTH_TOKEN"))?,
+                from: lookup("SMS_FROM").ok_or(ConfigError::Missing("SMS_FROM"))?,
+            }),
+        };
+
+        let fcm = match lookup("FCM_SERVICE_ACCOUNT").filter(|json| !json.trim().is_empty()) {
+            None => None,
+            Some(json) => Some(crate::push::FcmConfig::from_service_account(&json).ok_or(ConfigError::Invalid {
+                var: "FCM_SERVICE_ACCOUNT",
+                value: "(service account JSON)".to_string(),
+                expected: "the service account key file's JSON, with project_id, client_email and an RSA private_key",
+            })?),
+        };
+        let apns = match lookup("APNS_KEY").filter(|key| !key.trim().is_empty()) {
+            None => None,
+            Some(key) => Some(crate::push::ApnsConfig {
+                private_key: crate::push::ApnsConfig::parse_key(&key).ok_or(ConfigError::Invalid {
+                    var: "APNS_KEY",
+                    value: "(private key)".to_string(),
+                    expected: "the contents of the .p8 signing key from Apple",
+                })?,
+                key_id: lookup("APNS_KEY_ID").ok_or(ConfigError::Missing("APNS_KEY_ID"))?,
+                team_id: lookup("APNS_TEAM_ID").ok_or(ConfigError::Missing("APNS_TEAM_ID"))?,
+                topic: lookup("APNS_TOPIC").ok_or(ConfigError::Missing("APNS_TOPIC"))?,
+                sandbox: parse_optional(&lookup, "APNS_SANDBOX", "true or false")?.unwrap_or(false),
+            }),
+        };
+        let push = (fcm.is_some() || apns.is_some()).then_some(crate::push::PushConfig { fcm, apns });
+
+        let ldap = match lookup("LDAP_URL").filter(|url| !url.trim().is_empty()) {
+            None => None,
+            Some(url) => {
+                let tls = crate::ldap::LdapConfig::is_tls_url(&url).ok_or(ConfigError::Invalid {
+                    var: "LDAP_URL",
+                    value: url.clone(),
+                    expected: "a URL such as ldaps://ldap.example.org",
+                })?;
+                let starttls = parse_optional(&lookup, "LDAP_STARTTLS", "true or false")?.unwrap_or(false);
+                let allow_plaintext = parse_optional(&lookup, "LDAP_ALLOW_PLAINTEXT", "true or false")?.unwrap_or(false);
+                // Binding sends the member's password, so a plain ldap://
+                // URL needs StartTLS or an explicit opt-in to cleartext.
+                let transport = match (tls, starttls, allow_plaintext) {
+                    (true, true, _) => {
+                        return Err(ConfigError::Invalid {
+                            var: "LDAP_STARTTLS",
+                            value: "true".to_string(),
+                            expected: "false with an ldaps:// URL, which is encrypted already",
+                        });
+                    }
+                    (true, false, _) => crate::ldap::Transport::Tls,
+                    (false, true, _) => crate::ldap::Transport::StartTls,
+                    (false, false, true) => crate::ldap::Transport::Plaintext,
+                    (false, false, false) => {
+                        return Err(ConfigError::Invalid {
+                            var: "LDAP_URL",
+                            value: url,
+                            expected: "an ldaps:// URL, or ldap:// with LDAP_STARTTLS=true \
+                                       (LDAP_ALLOW_PLAINTEXT=true sends passwords in the clear)",
+                        });
+                    }
+                };
+                let user_dn = lookup("LDAP_USER_DN").ok_or(ConfigError::Missing("LDAP_USER_DN"))?;
+                if !user_dn.contains("{username}") {
+                    return Err(ConfigError::Invalid {
+                        var: "LDAP_USER_DN",
+                        value: user_dn,
+                        expected: "a DN containing {username}, e.g. uid={username},ou=people,dc=example,dc=org",
+                    });
+                }
+                let attribute = |var: &str, default: &str| lookup(var).unwrap_or_else(|| default.to_string());
+                Some(crate::ldap::LdapConfig {
+                    url: url.trim().to_string(),
+                    transport,
+                    user_dn,
+                    search_base: lookup("LDAP_SEARCH_BASE").filter(|base| !base.trim().is_empty()),
+                    login_attribute: attribute("LDAP_LOGIN_ATTRIBUTE", "uid"),
+                    name_attribute: attribute("LDAP_NAME_ATTRIBUTE", "cn"),
+                    email_attribute: attribute("LDAP_EMAIL_ATTRIBUTE", "mail"),
+                    group_attribute: attribute("LDAP_GROUP_ATTRIBUTE", "memberOf"),
+                    staff_group: lookup("LDAP_STAFF_GROUP").filter(|group| !group.trim().is_empty()),
+                    admin_group: lookup("LDAP_ADMIN_GROUP").filter(|group| !group.trim().is_empty()),
+                })
+            }
+        };
+
+        let saml = match lookup("SAML_IDP_SSO_URL").filter(|url| !url.trim().is_empty()) {
+            None => None,
+            Some(idp_sso_url) => {
+                let required = |var: &'static str| lookup(var).filter(|v| !v.trim().is_empty()).ok_or(ConfigError::Missing(var));
+                let certificate = required("SAML_IDP_CERT")?;
+                let values = |var: &str| {
+                    lookup(var)
+                        .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+                        .unwrap_or_default()
+                };
+                Some(crate::saml::SamlConfig {
+                    entity_id: required("SAML_ENTITY_ID")?,
+                    acs_url: required("SAML_ACS_URL")?,
+                    idp_entity_id: required("SAML_IDP_ENTITY_ID")?,
+                    idp_certificate: crate::saml::SamlConfig::parse_certificate(&certificate).ok_or(ConfigError::Invalid {
+                        var: "SAML_IDP_CERT",
+                        value: "***".to_string(),
+                        expected: "an X.509 certificate, PEM or base64",
+                    })?,
+                    idp_sso_url,
+                    name_attribute: lookup("SAML_NAME_ATTRIBUTE").unwrap_or_else(|| "displayName".to_string()),
+                    email_attribute: lookup("SAML_EMAIL_ATTRIBUTE").unwrap_or_else(|| "mail".to_string()),
+                    role_attribute: lookup("SAML_ROLE_ATTRIBUTE").unwrap_or_else(|| "groups".to_string()),
+                    staff_values: values("SAML_STAFF_VALUES"),
+                    admin_values: values("SAML_ADMIN_VALUES"),
+                })
+            }
+        };
+
+        let locale = lookup("CATALOG_LOCALE").map(|l| l.trim().to_string()).unwrap_or_else(|| "und".to_string());
+        if !is_language_tag(&locale) {
+            return Err(ConfigError::Invalid {
+                var: "CATALOG_LOCALE",
+                value: locale,
+                expected: "a locale tag such as en, fr, or de-AT",
+            });
+        }
+
+        Ok(Config {
+            database: DatabaseConfig {
+                url,
+                max_connections,
+                acquire_timeout: Duration::from_secs(acquire_timeout_secs),
+                idle_timeout: (idle_timeout_secs > 0).then(|| Duration::from_secs(idle_timeout_secs)),
+                statement_cache_capacity,
+            },
+            auth: AuthConfig {
+                require_admin_2fa,
+                download_key,
+                download_link_ttl: Duration::from_secs(download_link_ttl_secs),
+                ldap,
+                saml,
+            },
+            catalog: CatalogConfig {
+                locale,
+                strict_duplicates,
+                excerpt_max_chars,
+                max_page_limit,
+                field_limits,
+                strict_json,
+                uuid_book_ids,
+                demo_mode,
+                branch,
+                age_policy,
+                features,
+                deprecation: crate::legacy::Deprecation {
+                    deprecated_on,
+                    sunset_on,
+                    docs_url: lookup("LEGACY_DOCS_URL").filter(|url| !url.trim().is_empty()),
+                },
+            },
+            sip2,
+            smtp,
+            sms,
+            push,
+            warehouse_key,
+        })
+    }
+}
+
+/// A BCP 47-shaped tag such as `fr`, `de-AT`, or `und`: alphanumeric
+/// subtags of up to eight characters separated by hyphens.
+pub fn is_language_tag(tag: &str) -> bool {
+    tag.split('-').all(|part| (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn parse_var<T: FromStr>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    var: &'static str,
+    default: T,
+    expected: &'static str,
+) -> Result<T, ConfigError> {
+    match lookup(var) {
+        None => Ok(default),
+        Some(value) => value.trim().parse().map_err(|_| ConfigError::Invalid { var, value, expected }),
+    }
+}
+
+fn parse_optional<T: FromStr>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    var: &'static str,
+    expected: &'static str,
+) -> Result<Option<T>, ConfigError> {
+    match lookup(var) {
+        None => Ok(None),
+        Some(value) => value.trim().parse().map(Some).map_err(|_| ConfigError::Invalid { var, value, expected }),
+    }
+}
+
+fn parse_positive(lookup: &impl Fn(&str) -> Option<String>, var: &'static str, default: usize) -> Result<usize, ConfigError> {
+    let value: usize = parse_var(lookup, var, default, "a positive integer")?;
+    if value == 0 {
+        return Err(ConfigError::Invalid { var, value: value.to_string(), expected: "a positive integer" });
+    }
+    Ok(value)
+}
+
+impl DatabaseConfig {
+    pub async fn connect(&self) -> Result<PgPool, sqlx::Error> {
+        let options = PgConnectOptions::from_str(&self.url)?
+            .statement_cache_capacity(self.statement_cache_capacity);
+
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .connect_with(options)
+            .await
+    }
+
+    /// The connection URL with any password masked, safe to print in logs.
+    pub fn redacted_url(&self) -> String {
+        redact_url(&self.url)
+    }
+}
+
+fn redact_url(url: &str) -> String {
+    let Some(scheme_end) = url.find("://") else {
+        return url.to_string();
+    };
+    let rest = &url[scheme_end + 3..];
+    match (rest.find('@'), rest.find(':')) {
+        (Some(at), Some(colon)) if colon < at => {
+            format!("{}{}:***{}", &url[..scheme_end + 3], &rest[..colon], &rest[at..])
+        }
+        _ => url.to_string(),
+    }
+}
@@ -0,0 +1,39 @@
+//! Request bodies must say what they are. Anything sent with a body to a
+//! JSON endpoint has to be `application/json`; other types get a 415 that
+//! names the types the endpoint takes, instead of axum's plain-text default.
+
+use axum::{
+    body::HttpBody,
+    extract::Request,
+    http::{HeaderMap, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppError;
+
+pub const JSON: &[&str] = &["application/json"];
+
+/// Middleware for routes that take JSON. Requests without a body (a bare
+/// `POST /books/{id}/borrow`, say) pass whatever their headers say.
+pub async fn require_json(request: Request, next: Next) -> Response {
+    let mutating = matches!(*request.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    let has_body = request.body().size_hint().exact() != Some(0);
+    if mutating && has_body && media_type(request.headers()).is_none_or(|t| !JSON.contains(&t.as_str())) {
+        return unsupported(request.headers(), JSON).into_response();
+    }
+    next.run(request).await
+}
+
+/// The request's `Content-Type` without parameters, lowercased:
+/// `application/json; charset=utf-8` gives `application/json`.
+pub fn media_type(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let essence = value.split(';').next().unwrap_or_default().trim();
+    (!essence.is_empty()).then(|| essence.to_ascii_lowercase())
+}
+
+/// The 415 for a request whose `Content-Type` isn't one of `supported`.
+pub fn unsupported(headers: &HeaderMap, supported: &'static [&'static str]) -> AppError {
+    AppError::UnsupportedMediaType(media_type(headers), supported)
+}
@@ -0,0 +1,408 @@
+use std::{fmt, str::FromStr};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    api_keys::{CatalogRead, CatalogWrite},
+    barcode,
+    books::BookId,
+};
+
+const MAX_COPIES_PER_REQUEST: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyStatus {
+    Available,
+    OnLoan,
+    /// Withdrawn from the collection through the weeding workflow.
+    Discarded,
+}
+
+impl CopyStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CopyStatus::Available => "available",
+            CopyStatus::OnLoan => "on_loan",
+            CopyStatus::Discarded => "discarded",
+        }
+    }
+}
+
+impl fmt::Display for CopyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CopyStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "available" => Ok(CopyStatus::Available),
+            "on_loan" => Ok(CopyStatus::OnLoan),
+            "discarded" => Ok(CopyStatus::Discarded),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Physical state of a copy as last assessed by staff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyCondition {
+    New,
+    Good,
+    Fair,
+    Poor,
+    Damaged,
+}
+
+impl CopyCondition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CopyCondition::New => "new",
+            CopyCondition::Good => "good",
+            CopyCondition::Fair => "fair",
+            CopyCondition::Poor => "poor",
+            CopyCondition::Damaged => "damaged",
+        }
+    }
+}
+
+impl FromStr for CopyCondition {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new" => Ok(CopyCondition::New),
+            "good" => Ok(CopyCondition::Good),
+            "fair" => Ok(CopyCondition::Fair),
+            "poor" => Ok(CopyCondition::Poor),
+            "damaged" => Ok(CopyCondition::Damaged),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A physical item on the shelf. A book (the bibliographic record) can have
+/// any number of copies, each with its own barcode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookCopy {
+    pub id: i64,
+    pub book_id: i64,
+    pub barcode: String,
+    pub status: CopyStatus,
+    pub condition: CopyCondition,
+    /// Shelf location printed on the spine label, e.g. "FIC CLA" or "823.92 CLA".
+    pub call_number: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct CopyRow {
+    pub id: i64,
+    pub book_id: i64,
+    pub barcode: String,
+    pub status: String,
+    pub condition: String,
+    pub call_number: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<CopyRow> for BookCopy {
+    fn from(r: CopyRow) -> Self {
+        BookCopy {
+            id: r.id,
+            book_id: r.book_id,
+            barcode: r.barcode,
+            status: r.status.parse().unwrap_or(CopyStatus::Available),
+            condition: r.condition.parse().unwrap_or(CopyCondition::Good),
+            call_number: r.call_number,
+            created_at: r.created_at,
+        }
+    }
+}
+
+/// One entry in a copy's condition history: a grade change by staff, or the
+/// grade assessed when it came back from a loan.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionChange {
+    pub id: i64,
+    pub copy_id: i64,
+    pub condition: CopyCondition,
+    pub previous_condition: CopyCondition,
+    /// The loan that ended, for grades recorded at a return.
+    pub borrowing_id: Option<i64>,
+    pub note: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+struct ConditionChangeRow {
+    id: i64,
+    copy_id: i64,
+    condition: String,
+    previous_condition: String,
+    borrowing_id: Option<i64>,
+    note: Option<String>,
+    recorded_at: DateTime<Utc>,
+}
+
+impl From<ConditionChangeRow> for ConditionChange {
+    fn from(r: ConditionChangeRow) -> Self {
+        ConditionChange {
+            id: r.id,
+            copy_id: r.copy_id,
+            condition: r.condition.parse().unwrap_or(CopyCondition::Good),
+            previous_condition: r.previous_condition.parse().unwrap_or(CopyCondition::Good),
+            borrowing_id: r.borrowing_id,
+            note: r.note,
+            recorded_at: r.recorded_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddCopies {
+    count: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCopy {
+    condition: Option<CopyCondition>,
+    /// Why the grade changed, kept in the condition history.
+    condition_note: Option<String>,
+    call_number: Option<String>,
+}
+
+pub async fn list_book_copies(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    BookId(book_id): BookId,
+) -> Result<Json<Vec<BookCopy>>, AppError> {
+    ensure_book_exists(&pool, book_id).await?;
+
+    let rows = sqlx::query_as!(
+        CopyRow,
+        "SELECT id, book_id, barcode, status, condition, call_number, created_at FROM copies WHERE book_id = $1 ORDER BY id",
+        book_id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(BookCopy::from).collect()))
+}
+
+pub async fn for_books(conn: &mut PgConnection, book_ids: &[i64]) -> Result<Vec<BookCopy>, AppError> {
+    let rows = sqlx::query_as!(
+        CopyRow,
+        "SELECT id, book_id, barcode, status, condition, call_number, created_at FROM copies WHERE book_id = ANY($1) ORDER BY id",
+        book_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(BookCopy::from).collect())
+}
+
+pub async fn add_book_copies(
+    State(pool): State<PgPool>,
+    _scope: CatalogWrite,
+    BookId(book_id): BookId,
+    Json(input): Json<AddCopies>,
+) -> Result<(StatusCode, Json<Vec<BookCopy>>), AppError> {
+    let count = input.count.unwrap_or(1);
+    if !(1..=MAX_COPIES_PER_REQUEST).contains(&count) {
+        return Err(AppError::InvalidInput(format!(
+            "count must be between 1 and {}",
+            MAX_COPIES_PER_REQUEST
+        )));
+    }
+    ensure_book_exists(&pool, book_id).await?;
+
+    let mut conn = pool.acquire().await?;
+    let copies = insert_copies(&mut conn, book_id, count).await?;
+
+    Ok((StatusCode::CREATED, Json(copies)))
+}
+
+pub async fn get_copy(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Path(id): Path<i64>,
+) -> Result<Json<BookCopy>, AppError> {
+    let row = sqlx::query_as!(
+        CopyRow,
+        "SELECT id, book_id, barcode, status, condition, call_number, created_at FROM copies WHERE id = $1",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Copy", id))?;
+
+    Ok(Json(row.into()))
+}
+
+pub async fn update_copy(
+    State(pool): State<PgPool>,
+    _scope: CatalogWrite,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateCopy>,
+) -> Result<Json<BookCopy>, AppError> {
+    let mut tx = pool.begin().await?;
+    let mut copy: BookCopy = sqlx::query_as!(
+        CopyRow,
+        "UPDATE copies
+         SET call_number = COALESCE($1, call_number)
+         WHERE id = $2
+         RETURNING id, book_id, barcode, status, condition, call_number, created_at",
+        input.call_number,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Copy", id))?
+    .into();
+
+    if let Some(condition) = input.condition
+        && condition != copy.condition
+    {
+        record_condition(&mut tx, &mut copy, condition, None, input.condition_note.as_deref()).await?;
+    }
+    tx.commit().await?;
+
+    Ok(Json(copy))
+}
+
+/// Every grade the copy has had, oldest first.
+pub async fn condition_history(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<ConditionChange>>, AppError> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM copies WHERE id = $1)", id)
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(AppError::ResourceNotFound("Copy", id));
+    }
+
+    let rows = sqlx::query_as!(
+        ConditionChangeRow,
+        "SELECT id, copy_id, condition, previous_condition, borrowing_id, note, recorded_at
+         FROM copy_condition_history WHERE copy_id = $1 ORDER BY recorded_at, id",
+        id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(ConditionChange::from).collect()))
+}
+
+/// Grades the copy and adds the entry to its history. Returns are always
+/// recorded, even when the grade is unchanged, so the history shows how the
+/// copy held up loan by loan.
+pub async fn record_condition(
+    conn: &mut PgConnection,
+    copy: &mut BookCopy,
+    condition: CopyCondition,
+    borrowing_id: Option<i64>,
+    note: Option<&str>,
+) -> Result<(), AppError> {
+    let note = note.map(str::trim).filter(|n| !n.is_empty());
+    sqlx::query!("UPDATE copies SET condition = $1 WHERE id = $2", condition.as_str(), copy.id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO copy_condition_history (copy_id, condition, previous_condition, borrowing_id, note, recorded_at)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        copy.id,
+        condition.as_str(),
+        copy.condition.as_str(),
+        borrowing_id,
+        note,
+        Utc::now(),
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    copy.condition = condition;
+    Ok(())
+}
+
+/// The copy's barcode as a printable Code 39 label.
+pub async fn copy_barcode_png(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let code = barcode_for(&pool, id).await?;
+    let png = barcode::code39_png(&code).ok_or_else(|| {
+        AppError::Conflict(format!("Barcode {} cannot be encoded as Code 39", code))
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
+/// The copy's barcode as a QR code, for scanning with a phone.
+pub async fn copy_qr_png(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let code = barcode_for(&pool, id).await?;
+    let png = barcode::qr_png(&code).ok_or_else(|| {
+        AppError::Conflict(format!("Barcode {} cannot be encoded as a QR code", code))
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
+async fn barcode_for(pool: &PgPool, id: i64) -> Result<String, AppError> {
+    sqlx::query_scalar!("SELECT barcode FROM copies WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Copy", id))
+}
+
+/// Creates `count` available copies of a book, each with a freshly allocated
+/// barcode. Takes a connection so callers can run it inside a transaction.
+pub async fn insert_copies(
+    conn: &mut PgConnection,
+    book_id: i64,
+    count: i64,
+) -> Result<Vec<BookCopy>, AppError> {
+    let rows = sqlx::query_as!(
+        CopyRow,
+        "INSERT INTO copies (book_id, status, created_at)
+         SELECT $1, $2, $3 FROM generate_series(1, $4)
+         RETURNING id, book_id, barcode, status, condition, call_number, created_at",
+        book_id,
+        CopyStatus::Available.as_str(),
+        Utc::now(),
+        count as i32,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(rows.into_iter().map(BookCopy::from).collect())
+}
+
+async fn ensure_book_exists(pool: &PgPool, book_id: i64) -> Result<(), AppError> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)", book_id)
+        .fetch_one(pool)
+        .await?
+        .unwrap_or(false);
+
+    if exists { Ok(()) } else { Err(AppError::NotFound(book_id)) }
+}
@@ -0,0 +1,20 @@
+//! Read-only demo mode, for public instances that anyone can poke at.
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppError, CatalogConfig};
+
+/// Refuses anything but reads while `DEMO_MODE` is on. `POST /batch` is let
+/// through because its operations come back through here one by one.
+pub async fn reject_mutations(State(catalog): State<CatalogConfig>, request: Request, next: Next) -> Response {
+    let read = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if catalog.demo_mode && !read && request.uri().path() != "/batch" {
+        return AppError::Forbidden("This is a read-only demo; changes are disabled".to_string()).into_response();
+    }
+    next.run(request).await
+}
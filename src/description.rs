@@ -0,0 +1,52 @@
+//! Book descriptions are written in Markdown. This renders them as HTML that
+//! is safe to embed in catalog pages.
+
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{Html, IntoResponse, Response},
+};
+use pulldown_cmark::{Options, Parser};
+use sqlx::PgPool;
+
+use crate::{AppError, CatalogConfig, api_keys::CatalogRead, translations, books::{Book, BookId, BookRow, VARY_LANGUAGE, content_language}};
+
+/// Renders Markdown to HTML, then strips anything that could run script or
+/// restyle the host page: raw `<script>`, event handler attributes,
+/// `javascript:` links, and so on. Links get `rel="noopener noreferrer"`.
+pub fn render(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH));
+    ammonia::clean(&html)
+}
+
+/// The book's description as an HTML fragment, in the best language
+/// `Accept-Language` allows. Books without a description give an empty
+/// fragment.
+pub async fn description_html(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogRead,
+    headers: HeaderMap,
+    BookId(id): BookId,
+) -> Result<Response, AppError> {
+    let book: Book = sqlx::query_as!(
+        BookRow,
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid
+         FROM books WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::NotFound(id))?
+    .into();
+
+    let mut books = [book];
+    let languages = translations::preferred_languages(&headers);
+    translations::localize(&mut *pool.acquire().await?, &mut books, &languages, &catalog.locale).await?;
+    let [book] = books;
+
+    let html = book.description.as_deref().map(render).unwrap_or_default();
+    Ok((VARY_LANGUAGE, content_language(&book), Html(html)).into_response())
+}
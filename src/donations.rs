@@ -0,0 +1,426 @@
+use std::{fmt, str::FromStr};
+
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    AppError, acquisitions,
+    auth::AuthMember,
+    copies::{self, BookCopy, CopyCondition},
+    members::{self, Member},
+    query::Query,
+    validation::validate_optional_bibliographic,
+};
+
+const MAX_QUANTITY: i32 = 100;
+
+/// A donated item waits in review until staff either add it to the
+/// collection or turn it down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DonationStatus {
+    Pending,
+    Accepted,
+    Declined,
+}
+
+impl DonationStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            DonationStatus::Pending => "pending",
+            DonationStatus::Accepted => "accepted",
+            DonationStatus::Declined => "declined",
+        }
+    }
+}
+
+impl fmt::Display for DonationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DonationStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DonationStatus::Pending),
+            "accepted" => Ok(DonationStatus::Accepted),
+            "declined" => Ok(DonationStatus::Declined),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Donation {
+    pub id: i64,
+    /// The donor, when they are a member.
+    pub member_id: Option<i64>,
+    pub donor_name: String,
+    pub donor_email: Option<String>,
+    pub title: String,
+    pub author: String,
+    pub year: Option<i64>,
+    pub isbn: Option<String>,
+    /// Items handed in.
+    pub quantity: i32,
+    pub condition: Option<CopyCondition>,
+    pub notes: Option<String>,
+    pub status: DonationStatus,
+    /// Copies added to the collection; may be fewer than were donated.
+    pub accepted_quantity: Option<i32>,
+    pub decline_reason: Option<String>,
+    /// The catalog record the accepted copies were attached to.
+    pub book_id: Option<i64>,
+    pub received_at: DateTime<Utc>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+struct DonationRow {
+    id: i64,
+    member_id: Option<i64>,
+    donor_name: String,
+    donor_email: Option<String>,
+    title: String,
+    author: String,
+    year: Option<i64>,
+    isbn: Option<String>,
+    quantity: i32,
+    condition: Option<String>,
+    notes: Option<String>,
+    status: String,
+    accepted_quantity: Option<i32>,
+    decline_reason: Option<String>,
+    book_id: Option<i64>,
+    received_at: DateTime<Utc>,
+    reviewed_at: Option<DateTime<Utc>>,
+}
+
+impl From<DonationRow> for Donation {
+    fn from(r: DonationRow) -> Self {
+        Donation {
+            id: r.id,
+            member_id: r.member_id,
+            donor_name: r.donor_name,
+            donor_email: r.donor_email,
+            title: r.title,
+            author: r.author,
+            year: r.year,
+            isbn: r.isbn,
+            quantity: r.quantity,
+            condition: r.condition.and_then(|c| c.parse().ok()),
+            notes: r.notes,
+            status: r.status.parse().unwrap_or(DonationStatus::Pending),
+            accepted_quantity: r.accepted_quantity,
+            decline_reason: r.decline_reason,
+            book_id: r.book_id,
+            received_at: r.received_at,
+            reviewed_at: r.reviewed_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordDonation {
+    /// The donor's library card, when they are a member; their name and
+    /// email are used unless given here.
+    card_number: Option<String>,
+    donor_name: Option<String>,
+    donor_email: Option<String>,
+    title: String,
+    author: String,
+    year: Option<i64>,
+    isbn: Option<String>,
+    quantity: Option<i32>,
+    condition: Option<CopyCondition>,
+    notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptDonation {
+    /// Needed here if the donation was recorded without them.
+    year: Option<i64>,
+    isbn: Option<String>,
+    /// Copies to keep, when only some are fit for the shelf.
+    quantity: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeclineDonation {
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DonationParams {
+    status: Option<DonationStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AcceptedDonation {
+    pub donation: Donation,
+    pub copies: Vec<BookCopy>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcknowledgmentParams {
+    /// Inclusive dates on which donations were received.
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// One donor's gifts in the period, for thank-you letters.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DonorAcknowledgment {
+    pub donor_name: String,
+    pub donor_email: Option<String>,
+    pub items_donated: i64,
+    pub copies_added: i64,
+    pub items_declined: i64,
+    /// Titles that joined the collection.
+    pub titles_added: Vec<String>,
+    pub first_received_at: DateTime<Utc>,
+    pub last_received_at: DateTime<Utc>,
+}
+
+async fn fetch(conn: &mut sqlx::PgConnection, id: i64, lock: bool) -> Result<Donation, AppError> {
+    let row = if lock {
+        sqlx::query_as!(DonationRow, "SELECT * FROM donations WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(conn)
+            .await?
+    } else {
+        sqlx::query_as!(DonationRow, "SELECT * FROM donations WHERE id = $1", id)
+            .fetch_optional(conn)
+            .await?
+    };
+    Ok(row.ok_or(AppError::ResourceNotFound("Donation", id))?.into())
+}
+
+fn require_staff(member: &Member) -> Result<(), AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Donations are handled by staff".to_string()));
+    }
+    Ok(())
+}
+
+fn require_pending(donation: &Donation) -> Result<(), AppError> {
+    if donation.status != DonationStatus::Pending {
+        return Err(AppError::Conflict(format!("Donation with ID {} has already been {}", donation.id, donation.status)));
+    }
+    Ok(())
+}
+
+/// Logs items handed in at the desk; they wait as `pending` for review.
+pub async fn record_donation(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Json(input): Json<RecordDonation>,
+) -> Result<(StatusCode, Json<Donation>), AppError> {
+    require_staff(&staff)?;
+    let mut conn = pool.acquire().await?;
+    let donor = match input.card_number.as_deref() {
+        Some(card_number) => Some(members::find_by_card(&mut conn, card_number.trim()).await?),
+        None => None,
+    };
+    let donor_name = input.donor_name.or_else(|| donor.as_ref().map(|m| m.name.clone())).unwrap_or_default();
+    let donor_email = input.donor_email.or_else(|| donor.as_ref().and_then(|m| m.email.clone()));
+    if input.title.trim().is_empty() || input.author.trim().is_empty() || donor_name.trim().is_empty() {
+        return Err(AppError::InvalidInput("title, author, and donor_name must not be empty".to_string()));
+    }
+    if donor_email.as_deref().is_some_and(|e| !e.contains('@')) {
+        return Err(AppError::InvalidInput("donor_email must be an email address".to_string()));
+    }
+    validate_optional_bibliographic(input.year, input.isbn.as_deref())?;
+    let quantity = input.quantity.unwrap_or(1);
+    if !(1..=MAX_QUANTITY).contains(&quantity) {
+        return Err(AppError::InvalidInput(format!("quantity must be between 1 and {}", MAX_QUANTITY)));
+    }
+
+    let row = sqlx::query_as!(
+        DonationRow,
+        "INSERT INTO donations (member_id, donor_name, donor_email, title, author, year, isbn, quantity, condition, notes, status, received_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+         RETURNING *",
+        donor.map(|m| m.id),
+        donor_name.trim(),
+        donor_email.as_deref().map(str::trim),
+        input.title,
+        input.author,
+        input.year,
+        input.isbn,
+        quantity,
+        input.condition.map(CopyCondition::as_str),
+        input.notes,
+        DonationStatus::Pending.as_str(),
+        Utc::now(),
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+/// The review queue, oldest first. Staff only, as are the other reads,
+/// since donations carry donors' names and addresses.
+pub async fn list_donations(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Query(params): Query<DonationParams>,
+) -> Result<Json<Vec<Donation>>, AppError> {
+    require_staff(&staff)?;
+    let rows = sqlx::query_as!(
+        DonationRow,
+        "SELECT * FROM donations WHERE ($1::text IS NULL OR status = $1) ORDER BY received_at, id",
+        params.status.map(DonationStatus::as_str),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(Donation::from).collect()))
+}
+
+pub async fn get_donation(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<Json<Donation>, AppError> {
+    require_staff(&staff)?;
+    let mut conn = pool.acquire().await?;
+    Ok(Json(fetch(&mut conn, id, false).await?))
+}
+
+/// Adds the donation to the collection: the catalog record (an existing one
+/// with the same ISBN, or a new one) and a copy per item kept, in the
+/// condition it was recorded in.
+pub async fn accept_donation(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<AcceptDonation>,
+) -> Result<Json<AcceptedDonation>, AppError> {
+    require_staff(&staff)?;
+    validate_optional_bibliographic(input.year, input.isbn.as_deref())?;
+
+    let mut tx = pool.begin().await?;
+    let current = fetch(&mut tx, id, true).await?;
+    require_pending(&current)?;
+
+    let quantity = input.quantity.unwrap_or(current.quantity);
+    if !(1..=current.quantity).contains(&quantity) {
+        return Err(AppError::InvalidInput(format!("quantity must be between 1 and the {} donated", current.quantity)));
+    }
+    let year = input.year.or(current.year);
+    let isbn = input.isbn.or(current.isbn);
+    let (Some(year), Some(isbn)) = (year, isbn) else {
+        return Err(AppError::InvalidInput("year and isbn are required to add a donation to the catalog".to_string()));
+    };
+
+    let book_id = acquisitions::catalog_record(&mut tx, &current.title, &current.author, year, &isbn).await?;
+    let mut copies = copies::insert_copies(&mut tx, book_id, quantity.into()).await?;
+    if let Some(condition) = current.condition {
+        let ids: Vec<i64> = copies.iter().map(|c| c.id).collect();
+        sqlx::query!("UPDATE copies SET condition = $1 WHERE id = ANY($2)", condition.as_str(), &ids)
+            .execute(&mut *tx)
+            .await?;
+        for copy in &mut copies {
+            copy.condition = condition;
+        }
+    }
+
+    let donation = sqlx::query_as!(
+        DonationRow,
+        "UPDATE donations
+         SET status = $2, year = $3, isbn = $4, accepted_quantity = $5, book_id = $6, reviewed_at = $7
+         WHERE id = $1
+         RETURNING *",
+        id,
+        DonationStatus::Accepted.as_str(),
+        year,
+        isbn,
+        quantity,
+        book_id,
+        Utc::now(),
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .into();
+    tx.commit().await?;
+
+    Ok(Json(AcceptedDonation { donation, copies }))
+}
+
+pub async fn decline_donation(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<DeclineDonation>,
+) -> Result<Json<Donation>, AppError> {
+    require_staff(&staff)?;
+    if input.reason.trim().is_empty() {
+        return Err(AppError::InvalidInput("reason must not be empty".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+    let current = fetch(&mut tx, id, true).await?;
+    require_pending(&current)?;
+
+    let donation = sqlx::query_as!(
+        DonationRow,
+        "UPDATE donations SET status = $2, decline_reason = $3, reviewed_at = $4 WHERE id = $1 RETURNING *",
+        id,
+        DonationStatus::Declined.as_str(),
+        input.reason.trim(),
+        Utc::now(),
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .into();
+    tx.commit().await?;
+
+    Ok(Json(donation))
+}
+
+/// Who gave what in the period, one entry per donor (matched by email, or
+/// by name when there is none), for acknowledgment letters and tax
+/// receipts. Items still in review count as donated but neither added
+/// nor declined.
+pub async fn donor_acknowledgments(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Query(params): Query<AcknowledgmentParams>,
+) -> Result<Json<Vec<DonorAcknowledgment>>, AppError> {
+    require_staff(&staff)?;
+    if let (Some(from), Some(to)) = (params.from, params.to)
+        && from > to
+    {
+        return Err(AppError::InvalidInput("from must not be after to".to_string()));
+    }
+    let from = params.from.map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    let before = params.to.and_then(|d| d.succ_opt()).map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc());
+
+    let report = sqlx::query_as!(
+        DonorAcknowledgment,
+        r#"SELECT (array_agg(donor_name ORDER BY received_at DESC, id DESC))[1] AS "donor_name!",
+                  (array_agg(donor_email ORDER BY received_at DESC, id DESC))[1] AS donor_email,
+                  SUM(quantity)::BIGINT AS "items_donated!",
+                  COALESCE(SUM(accepted_quantity), 0)::BIGINT AS "copies_added!",
+                  COALESCE(SUM(quantity) FILTER (WHERE status = 'declined'), 0)::BIGINT AS "items_declined!",
+                  COALESCE(array_agg(title ORDER BY received_at, id) FILTER (WHERE status = 'accepted'), '{}') AS "titles_added!",
+                  MIN(received_at) AS "first_received_at!",
+                  MAX(received_at) AS "last_received_at!"
+           FROM donations
+           WHERE ($1::timestamptz IS NULL OR received_at >= $1)
+             AND ($2::timestamptz IS NULL OR received_at < $2)
+           GROUP BY COALESCE(lower(donor_email), lower(donor_name))
+           ORDER BY 1"#,
+        from,
+        before,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(report))
+}
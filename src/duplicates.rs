@@ -0,0 +1,61 @@
+//! Probable-duplicate detection for new catalog records: an existing book
+//! whose title and author closely match but whose ISBN differs is often the
+//! same work entered twice.
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::AppError;
+
+/// Average of the title and author trigram similarities (0 to 1) at which
+/// a record counts as a probable duplicate.
+const MIN_SIMILARITY: f64 = 0.6;
+const MAX_CANDIDATES: i64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateCandidate {
+    #[serde(default, skip_serializing_if = "crate::books::numeric_ids_hidden")]
+    pub id: i64,
+    pub uuid: Option<Uuid>,
+    pub title: String,
+    pub author: String,
+    pub year: i64,
+    pub isbn: String,
+    pub similarity: f64,
+}
+
+/// Existing books that look like the same work under another ISBN, most
+/// similar first. Titles and authors are compared folded, so case and
+/// accents don't matter.
+pub async fn find(
+    conn: &mut PgConnection,
+    title: &str,
+    author: &str,
+    isbn: &str,
+) -> Result<Vec<DuplicateCandidate>, AppError> {
+    let candidates = sqlx::query_as!(
+        DuplicateCandidate,
+        r#"SELECT id, uuid, title, author, year, isbn, score AS "similarity!"
+           FROM (
+               SELECT id, uuid, title, author, year, isbn,
+                      ((similarity(fold_text(title), fold_text($1)) + similarity(fold_text(author), fold_text($2))) / 2)::float8 AS score
+               FROM books
+               WHERE fold_text(title) % fold_text($1)
+               AND deleted_at IS NULL
+               AND REPLACE(isbn, '-', '') <> REPLACE($3, '-', '')
+           ) scored
+           WHERE score >= $4
+           ORDER BY score DESC, id
+           LIMIT $5"#,
+        title,
+        author,
+        isbn,
+        MIN_SIMILARITY,
+        MAX_CANDIDATES,
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(candidates)
+}
@@ -0,0 +1,374 @@
+//! EPUB and PDF files attached to books. Staff upload them; members get a
+//! short-lived signed link to download one, so the file URL itself carries
+//! the permission and can be handed to an e-reader app.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{AppError, auth::AuthMember, books::BookId, config::{AuthConfig, SigningKey}, content_type, members::Member, query::Query};
+
+/// Largest file accepted for upload.
+pub const MAX_FILE_BYTES: usize = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EbookFormat {
+    Epub,
+    Pdf,
+}
+
+impl EbookFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            EbookFormat::Epub => "epub",
+            EbookFormat::Pdf => "pdf",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            EbookFormat::Epub => "application/epub+zip",
+            EbookFormat::Pdf => "application/pdf",
+        }
+    }
+
+    const CONTENT_TYPES: &[&str] = &["application/epub+zip", "application/pdf"];
+
+    fn from_content_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/epub+zip" => Some(EbookFormat::Epub),
+            "application/pdf" => Some(EbookFormat::Pdf),
+            _ => None,
+        }
+    }
+
+    /// Whether `bytes` start the way files of this format must: a PDF
+    /// header, or a ZIP whose first entry is the EPUB `mimetype` file.
+    fn matches(self, bytes: &[u8]) -> bool {
+        match self {
+            EbookFormat::Pdf => bytes.starts_with(b"%PDF-"),
+            EbookFormat::Epub => {
+                bytes.starts_with(b"PK\x03\x04") && bytes.get(30..58) == Some(&b"mimetypeapplication/epub+zip"[..])
+            }
+        }
+    }
+}
+
+impl fmt::Display for EbookFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EbookFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epub" => Ok(EbookFormat::Epub),
+            "pdf" => Ok(EbookFormat::Pdf),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Who may get a download link for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileAccess {
+    /// Any signed-in member.
+    Members,
+    /// Staff and admins only, e.g. review copies.
+    Staff,
+}
+
+impl FileAccess {
+    fn as_str(self) -> &'static str {
+        match self {
+            FileAccess::Members => "members",
+            FileAccess::Staff => "staff",
+        }
+    }
+
+    fn allows(self, member: &Member) -> bool {
+        match self {
+            FileAccess::Members => true,
+            FileAccess::Staff => member.role.is_staff(),
+        }
+    }
+}
+
+impl fmt::Display for FileAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FileAccess {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "members" => Ok(FileAccess::Members),
+            "staff" => Ok(FileAccess::Staff),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookFile {
+    pub id: i64,
+    pub book_id: i64,
+    pub format: EbookFormat,
+    pub filename: String,
+    pub access: FileAccess,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the file, for clients to verify downloads.
+    pub sha256: String,
+    pub uploaded_by: Option<i64>,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+struct BookFileRow {
+    id: i64,
+    book_id: i64,
+    format: String,
+    filename: String,
+    access: String,
+    size_bytes: i64,
+    sha256: String,
+    uploaded_by: Option<i64>,
+    uploaded_at: DateTime<Utc>,
+}
+
+impl From<BookFileRow> for BookFile {
+    fn from(r: BookFileRow) -> Self {
+        BookFile {
+            id: r.id,
+            book_id: r.book_id,
+            format: r.format.parse().unwrap_or(EbookFormat::Pdf),
+            filename: r.filename,
+            access: r.access.parse().unwrap_or(FileAccess::Staff),
+            size_bytes: r.size_bytes,
+            sha256: r.sha256,
+            uploaded_by: r.uploaded_by,
+            uploaded_at: r.uploaded_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    /// Defaults to `members`.
+    access: Option<FileAccess>,
+    /// Name offered to the downloader; defaults to the book's slug.
+    filename: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadLink {
+    /// Path and query to fetch the file from, valid until `expires_at`.
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    expires: i64,
+    signature: String,
+}
+
+/// Staff upload. The body is the raw file, with `Content-Type` set to
+/// `application/epub+zip` or `application/pdf`.
+pub async fn upload_file(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    BookId(book_id): BookId,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<BookFile>), AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can upload e-book files".to_string()));
+    }
+    let format = content_type::media_type(&headers)
+        .and_then(|t| EbookFormat::from_content_type(&t))
+        .ok_or_else(|| content_type::unsupported(&headers, EbookFormat::CONTENT_TYPES))?;
+    if !format.matches(&body) {
+        return Err(AppError::InvalidInput(format!("The uploaded file is not a valid {}", format.as_str().to_uppercase())));
+    }
+
+    let slug = sqlx::query_scalar!("SELECT slug FROM books WHERE id = $1 AND deleted_at IS NULL", book_id)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or(AppError::NotFound(book_id))?;
+    let stem = params.filename.or(slug).unwrap_or_else(|| format!("book-{}", book_id));
+    let filename = download_filename(&stem, format);
+
+    let row = sqlx::query_as!(
+        BookFileRow,
+        "INSERT INTO book_files (book_id, format, filename, access, size_bytes, sha256, content, uploaded_by, uploaded_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         RETURNING id, book_id, format, filename, access, size_bytes, sha256, uploaded_by, uploaded_at",
+        book_id,
+        format.as_str(),
+        filename,
+        params.access.unwrap_or(FileAccess::Members).as_str(),
+        body.len() as i64,
+        hex::encode(Sha256::digest(&body)),
+        &body[..],
+        member.id,
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+/// Lists the files the caller could get a download link for: `staff` files
+/// are left out for anyone but staff.
+pub async fn list_book_files(
+    State(pool): State<PgPool>,
+    member: Result<AuthMember, AppError>,
+    BookId(book_id): BookId,
+) -> Result<Json<Vec<BookFile>>, AppError> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)", book_id)
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(AppError::NotFound(book_id));
+    }
+
+    let rows = sqlx::query_as!(
+        BookFileRow,
+        "SELECT id, book_id, format, filename, access, size_bytes, sha256, uploaded_by, uploaded_at
+         FROM book_files WHERE book_id = $1 AND ($2 OR access = $3) ORDER BY id",
+        book_id,
+        member.is_ok_and(|AuthMember(member)| member.role.is_staff()),
+        FileAccess::Members.as_str(),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(BookFile::from).collect()))
+}
+
+pub async fn delete_file(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can delete e-book files".to_string()));
+    }
+    let deleted = sqlx::query!("DELETE FROM book_files WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::ResourceNotFound("File", id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Issues a signed download link if the member's role may read the file.
+/// Files of deleted books answer 404.
+pub async fn create_download_link(
+    State(pool): State<PgPool>,
+    State(auth): State<AuthConfig>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<Json<DownloadLink>, AppError> {
+    let access: FileAccess = sqlx::query_scalar!(
+        "SELECT f.access FROM book_files f JOIN books b ON b.id = f.book_id
+         WHERE f.id = $1 AND b.deleted_at IS NULL",
+        id
+    )
+        .fetch_optional(&pool)
+        .await?
+        .ok_or(AppError::ResourceNotFound("File", id))?
+        .parse()
+        .unwrap_or(FileAccess::Staff);
+    if !access.allows(&member) {
+        return Err(AppError::Forbidden("This file is available to staff only".to_string()));
+    }
+
+    let expires_at = Utc::now() + auth.download_link_ttl;
+    Ok(Json(DownloadLink { url: signed_url(&auth.download_key, id, expires_at.timestamp()), expires_at }))
+}
+
+/// Serves the file to anyone holding an unexpired link from
+/// `create_download_link`; no session is needed.
+pub async fn download_file(
+    State(pool): State<PgPool>,
+    State(auth): State<AuthConfig>,
+    Path(id): Path<i64>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Response, AppError> {
+    let valid = hex::decode(&params.signature)
+        .is_ok_and(|signature| mac(&auth.download_key, id, params.expires).verify_slice(&signature).is_ok());
+    if !valid || params.expires < Utc::now().timestamp() {
+        return Err(AppError::Forbidden("The download link is invalid or has expired".to_string()));
+    }
+
+    let file = sqlx::query!(
+        "SELECT f.format, f.filename, f.content FROM book_files f JOIN books b ON b.id = f.book_id
+         WHERE f.id = $1 AND b.deleted_at IS NULL",
+        id
+    )
+        .fetch_optional(&pool)
+        .await?
+        .ok_or(AppError::ResourceNotFound("File", id))?;
+    let format: EbookFormat = file.format.parse().unwrap_or(EbookFormat::Pdf);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file.filename)),
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+        ],
+        file.content,
+    )
+        .into_response())
+}
+
+/// The path and query of a download link for file `id` that expires at
+/// the Unix time `expires`.
+pub fn signed_url(key: &SigningKey, id: i64, expires: i64) -> String {
+    let signature = hex::encode(mac(key, id, expires).finalize().into_bytes());
+    format!("/files/{}/download?expires={}&signature={}", id, expires, signature)
+}
+
+fn mac(key: &SigningKey, id: i64, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", id, expires).as_bytes());
+    mac
+}
+
+/// A filename safe to quote in `Content-Disposition`, with the format's
+/// extension.
+fn download_filename(stem: &str, format: EbookFormat) -> String {
+    let extension = format!(".{}", format.as_str());
+    let stem = stem.strip_suffix(extension.as_str()).unwrap_or(stem);
+    let safe: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '-' })
+        .take(100)
+        .collect();
+    let safe = safe.trim_matches(['-', '.']);
+    format!("{}{}", if safe.is_empty() { "book" } else { safe }, extension)
+}
@@ -0,0 +1,176 @@
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{duplicates, query};
+
+pub enum AppError {
+    Database(sqlx::Error),
+    NotFound(i64),
+    ResourceNotFound(&'static str, i64),
+    ResourceNotFoundBy(&'static str, &'static str, String),
+    /// A filtered lookup (e.g. a random pick) found nothing to return.
+    NoMatches(&'static str),
+    BadRequest,
+    InvalidInput(String),
+    BookUnavailable(i64),
+    NotBorrowed(i64),
+    Conflict(String),
+    /// `If-Match` named a version that is no longer current.
+    PreconditionFailed(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// Carries the number of seconds the client should wait.
+    TooManyRequests(i64),
+    /// Maintenance mode: the message to show and when to try again, in
+    /// seconds.
+    Unavailable(String, i64),
+    /// A new book closely matches existing records and strict duplicate
+    /// checking is on.
+    PossibleDuplicates(Vec<duplicates::DuplicateCandidate>),
+    /// One entry per field that failed validation.
+    InvalidFields(Vec<FieldError>),
+    /// The `Content-Type` sent, if any, and the types the endpoint takes.
+    UnsupportedMediaType(Option<String>, &'static [&'static str]),
+    /// A query parameter whose value didn't parse.
+    InvalidQuery(query::ParameterError),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AppError::Database(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error: {}", e)
+            )
+                .into_response(),
+            AppError::NotFound(id) => (
+                StatusCode::NOT_FOUND,
+                format!("Book with ID {} not found", id)
+            )
+                .into_response(),
+            AppError::ResourceNotFound(kind, id) => (
+                StatusCode::NOT_FOUND,
+                format!("{} with ID {} not found", kind, id)
+            )
+                .into_response(),
+            AppError::ResourceNotFoundBy(kind, field, value) => (
+                StatusCode::NOT_FOUND,
+                format!("{} with {} {} not found", kind, field, value)
+            )
+                .into_response(),
+            AppError::NoMatches(kind) => (
+                StatusCode::NOT_FOUND,
+                format!("No {} match the given filters", kind)
+            )
+                .into_response(),
+            AppError::BadRequest => (
+                StatusCode::BAD_REQUEST,
+                "Invalid book data. Check title, author, year, and ISBN format.".to_string()
+            )
+                .into_response(),
+            AppError::InvalidInput(message) => (
+                StatusCode::BAD_REQUEST,
+                message
+            )
+                .into_response(),
+            AppError::BookUnavailable(id) => (
+                StatusCode::CONFLICT,
+                format!("Book with ID {} is already borrowed", id)
+            )
+                .into_response(),
+            AppError::NotBorrowed(id) => (
+                StatusCode::BAD_REQUEST,
+                format!("Book with ID {} is not borrowed", id)
+            )
+                .into_response(),
+            AppError::Conflict(message) => (
+                StatusCode::CONFLICT,
+                message
+            )
+                .into_response(),
+            AppError::PreconditionFailed(message) => (
+                StatusCode::PRECONDITION_FAILED,
+                message
+            )
+                .into_response(),
+            AppError::Unauthorized(message) => (
+                StatusCode::UNAUTHORIZED,
+                [(axum::http::header::WWW_AUTHENTICATE, "Bearer")],
+                message
+            )
+                .into_response(),
+            AppError::Forbidden(message) => (
+                StatusCode::FORBIDDEN,
+                message
+            )
+                .into_response(),
+            AppError::TooManyRequests(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+                format!("Too many failed attempts; try again in {} seconds", retry_after)
+            )
+                .into_response(),
+            AppError::Unavailable(message, retry_after) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+                message
+            )
+                .into_response(),
+            AppError::PossibleDuplicates(candidates) => (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "message": "Book looks like a duplicate of an existing record; resend with allow_duplicates=true to add it anyway",
+                    "possible_duplicates": candidates,
+                }))
+            )
+                .into_response(),
+            AppError::InvalidFields(errors) => (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "message": "Some fields are invalid",
+                    "errors": errors,
+                }))
+            )
+                .into_response(),
+            AppError::UnsupportedMediaType(received, supported) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(serde_json::json!({
+                    "message": match received {
+                        Some(received) => format!("Content-Type {} is not supported; send {}", received, supported.join(" or ")),
+                        None => format!("Missing Content-Type; send {}", supported.join(" or ")),
+                    },
+                    "supported": supported,
+                }))
+            )
+                .into_response(),
+            AppError::InvalidQuery(error) => {
+                let message = match &error.value {
+                    Some(value) => format!("Invalid value {:?} for query parameter {}; expected {}", value, error.parameter, error.expected),
+                    None => format!("Invalid query parameter {}; expected {}", error.parameter, error.expected),
+                };
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "message": message,
+                        "parameter": error.parameter,
+                        "value": error.value,
+                        "expected": error.expected,
+                    }))
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Database(e)
+    }
+}
@@ -0,0 +1,131 @@
+//! Short passages from books, shown in catalog preview panes.
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{AppError, CatalogConfig, api_keys::{CatalogRead, CatalogWrite}, books::BookId, query::Query};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Excerpt {
+    pub book_id: i64,
+    pub text: String,
+    /// Whether `text` was shortened to the requested `length`.
+    pub truncated: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutExcerpt {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExcerptParams {
+    /// Shorten the excerpt to at most this many characters, for smaller
+    /// preview panes.
+    length: Option<usize>,
+}
+
+pub async fn get_excerpt(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    BookId(book_id): BookId,
+    Query(params): Query<ExcerptParams>,
+) -> Result<Json<Excerpt>, AppError> {
+    if params.length == Some(0) {
+        return Err(AppError::InvalidInput("length must be at least 1".to_string()));
+    }
+
+    let row = sqlx::query!(
+        "SELECT b.id, e.text AS \"text?\", e.updated_at AS \"updated_at?\"
+         FROM books b LEFT JOIN book_excerpts e ON e.book_id = b.id
+         WHERE b.id = $1 AND b.deleted_at IS NULL",
+        book_id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::NotFound(book_id))?;
+    let (Some(text), Some(updated_at)) = (row.text, row.updated_at) else {
+        return Err(AppError::ResourceNotFound("Excerpt for book", book_id));
+    };
+
+    let (text, truncated) = match params.length {
+        Some(length) => truncate(&text, length),
+        None => (text, false),
+    };
+    Ok(Json(Excerpt { book_id, text, truncated, updated_at }))
+}
+
+/// Adds or replaces the book's excerpt. Excerpts longer than
+/// `EXCERPT_MAX_CHARS` are rejected rather than cut, so staff choose where
+/// the passage ends.
+pub async fn put_excerpt(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogWrite,
+    BookId(book_id): BookId,
+    Json(input): Json<PutExcerpt>,
+) -> Result<Json<Excerpt>, AppError> {
+    let text = input.text.trim();
+    if text.is_empty() {
+        return Err(AppError::InvalidInput("Excerpt text must not be empty".to_string()));
+    }
+    let length = text.chars().count();
+    if length > catalog.excerpt_max_chars {
+        return Err(AppError::InvalidInput(format!(
+            "Excerpt is {} characters; the maximum is {}",
+            length, catalog.excerpt_max_chars
+        )));
+    }
+
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)", book_id)
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(AppError::NotFound(book_id));
+    }
+
+    let row = sqlx::query!(
+        "INSERT INTO book_excerpts (book_id, text, updated_at) VALUES ($1, $2, $3)
+         ON CONFLICT (book_id) DO UPDATE SET text = EXCLUDED.text, updated_at = EXCLUDED.updated_at
+         RETURNING text, updated_at",
+        book_id,
+        text,
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(Excerpt { book_id, text: row.text, truncated: false, updated_at: row.updated_at }))
+}
+
+pub async fn delete_excerpt(
+    State(pool): State<PgPool>,
+    _scope: CatalogWrite,
+    BookId(book_id): BookId,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query!("DELETE FROM book_excerpts WHERE book_id = $1", book_id)
+        .execute(&pool)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::ResourceNotFound("Excerpt for book", book_id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Cuts `text` to at most `length` characters, ending with an ellipsis.
+/// Breaks at the last space when there is one, so words stay whole.
+pub fn truncate(text: &str, length: usize) -> (String, bool) {
+    if text.chars().count() <= length {
+        return (text.to_string(), false);
+    }
+    let cut: String = text.chars().take(length - 1).collect();
+    let kept = match cut.rfind(char::is_whitespace) {
+        Some(end) if end > 0 => &cut[..end],
+        _ => cut.as_str(),
+    };
+    (format!("{}…", kept.trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation())), true)
+}
@@ -0,0 +1,96 @@
+//! `GET /books/facets`: distinct values with counts for filter sidebars,
+//! over the books matching the usual list filters.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    Json,
+    extract::State,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{PgPool, QueryBuilder};
+
+use crate::{AppError, api_keys::CatalogRead, books::{BookFilters, BookParams}, query::Query};
+
+/// Most values returned per field, most common first.
+const MAX_VALUES: i64 = 100;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    Number,
+    Boolean,
+}
+
+/// Facetable fields: name (also the column grouped on) and value type.
+const FACETS: &[(&str, Kind)] = &[
+    ("author", Kind::Text),
+    ("year", Kind::Number),
+    ("available", Kind::Boolean),
+    ("classification_scheme", Kind::Text),
+    ("format", Kind::Text),
+];
+
+#[derive(Debug, Deserialize)]
+pub struct FacetParams {
+    /// Comma-separated facet names, e.g. `fields=author,year`.
+    fields: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FacetValue {
+    pub value: Value,
+    pub count: i64,
+}
+
+pub async fn book_facets(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Query(params): Query<BookParams>,
+    Query(facets): Query<FacetParams>,
+) -> Result<Json<BTreeMap<String, Vec<FacetValue>>>, AppError> {
+    let filters = BookFilters::from_params(&params)?;
+
+    let mut columns = Vec::new();
+    for name in facets.fields.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let (name, kind) = FACETS.iter().find(|(facet, _)| *facet == name).ok_or_else(|| {
+            let names: Vec<&str> = FACETS.iter().map(|(facet, _)| *facet).collect();
+            AppError::InvalidInput(format!("No facet '{}'; expected one of {}", name, names.join(", ")))
+        })?;
+        if !columns.iter().any(|(n, _)| n == name) {
+            columns.push((*name, *kind));
+        }
+    }
+    if columns.is_empty() {
+        return Err(AppError::InvalidInput("fields must name at least one facet".to_string()));
+    }
+
+    let mut result = BTreeMap::new();
+    for (column, kind) in columns {
+        // Read back as text so every facet has the same row shape, then
+        // turned into a typed JSON value.
+        let mut query = QueryBuilder::new(format!("SELECT {}::text, COUNT(*) FROM books", column));
+        filters.push_where(&mut query);
+        query
+            .push(format!(" GROUP BY {} ORDER BY COUNT(*) DESC, {} LIMIT ", column, column))
+            .push_bind(MAX_VALUES);
+        let rows: Vec<(Option<String>, i64)> = query.build_query_as().fetch_all(&pool).await?;
+        let values = rows
+            .into_iter()
+            .map(|(value, count)| FacetValue { value: json_value(kind, value), count })
+            .collect();
+        result.insert(column.to_string(), values);
+    }
+
+    Ok(Json(result))
+}
+
+fn json_value(kind: Kind, text: Option<String>) -> Value {
+    match (kind, text) {
+        (_, None) => Value::Null,
+        (Kind::Number, Some(text)) => text.parse::<i64>().map(Value::from).unwrap_or(Value::String(text)),
+        (Kind::Boolean, Some(text)) => Value::Bool(text == "true"),
+        (Kind::Text, Some(text)) => Value::String(text),
+    }
+}
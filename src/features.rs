@@ -0,0 +1,186 @@
+//! Runtime feature flags for endpoints that can be switched off per
+//! deployment without a rebuild. Each flag has a built-in default, which the
+//! `FEATURES` variable can change at startup and `PUT /admin/features/{name}`
+//! can override while the server runs. Overrides live in the database, so
+//! every instance sees them and they survive restarts.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    Json,
+    extract::{Path, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, CatalogConfig, api_keys::AdminAccess};
+
+pub struct Flag {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: bool,
+    /// Requests under these path prefixes get a 404 while the flag is off.
+    paths: &'static [&'static str],
+}
+
+pub const FLAGS: &[Flag] = &[
+    Flag {
+        name: "admin_ui",
+        description: "The browser admin interface at /admin/ui",
+        default: true,
+        paths: &["/admin/ui"],
+    },
+    Flag {
+        name: "batch",
+        description: "Several operations in one request with POST /batch",
+        default: true,
+        paths: &["/batch"],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Flag> {
+    FLAGS.iter().find(|flag| flag.name == name)
+}
+
+fn for_path(path: &str) -> Option<&'static Flag> {
+    FLAGS.iter().find(|flag| {
+        flag.paths.iter().any(|prefix| {
+            path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    })
+}
+
+/// Parses `FEATURES`, e.g. `batch=off,admin_ui=on`.
+pub fn parse_settings(value: &str) -> Result<BTreeMap<&'static str, bool>, String> {
+    let mut settings = BTreeMap::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, state) = entry.split_once('=').ok_or_else(|| entry.to_string())?;
+        let flag = find(name.trim()).ok_or_else(|| entry.to_string())?;
+        let enabled = match state.trim() {
+            "on" | "true" => true,
+            "off" | "false" => false,
+            _ => return Err(entry.to_string()),
+        };
+        settings.insert(flag.name, enabled);
+    }
+    Ok(settings)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeatureState {
+    pub name: String,
+    pub description: String,
+    pub enabled: bool,
+    pub default: bool,
+    /// From `FEATURES`, if set there.
+    pub configured: Option<bool>,
+    /// Set through the admin endpoint, if at all; wins over everything else.
+    pub overridden: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetFeature {
+    pub enabled: bool,
+}
+
+pub async fn is_enabled(conn: &mut PgConnection, catalog: &CatalogConfig, flag: &Flag) -> Result<bool, AppError> {
+    let overridden = sqlx::query_scalar!("SELECT enabled FROM feature_flags WHERE name = $1", flag.name)
+        .fetch_optional(conn)
+        .await?;
+    Ok(overridden.or(catalog.features.get(flag.name).copied()).unwrap_or(flag.default))
+}
+
+/// Answers requests for a switched-off feature as if the route didn't
+/// exist.
+pub async fn gate(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(flag) = for_path(request.uri().path()) {
+        let enabled = match pool.acquire().await {
+            Ok(mut conn) => is_enabled(&mut conn, &catalog, flag).await,
+            Err(e) => Err(e.into()),
+        };
+        match enabled {
+            Ok(true) => {}
+            Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => return e.into_response(),
+        }
+    }
+    next.run(request).await
+}
+
+pub async fn list_features(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _access: AdminAccess,
+) -> Result<Json<Vec<FeatureState>>, AppError> {
+    let overrides: BTreeMap<String, bool> = sqlx::query!("SELECT name, enabled FROM feature_flags")
+        .fetch_all(&pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.name, r.enabled))
+        .collect();
+
+    let features = FLAGS
+        .iter()
+        .map(|flag| {
+            let configured = catalog.features.get(flag.name).copied();
+            let overridden = overrides.get(flag.name).copied();
+            FeatureState {
+                name: flag.name.to_string(),
+                description: flag.description.to_string(),
+                enabled: overridden.or(configured).unwrap_or(flag.default),
+                default: flag.default,
+                configured,
+                overridden,
+            }
+        })
+        .collect();
+    Ok(Json(features))
+}
+
+pub async fn set_feature(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _access: AdminAccess,
+    Path(name): Path<String>,
+    Json(input): Json<SetFeature>,
+) -> Result<Json<FeatureState>, AppError> {
+    let flag = find(&name).ok_or_else(|| AppError::ResourceNotFoundBy("Feature", "name", name.clone()))?;
+    sqlx::query!(
+        "INSERT INTO feature_flags (name, enabled) VALUES ($1, $2)
+         ON CONFLICT (name) DO UPDATE SET enabled = EXCLUDED.enabled, updated_at = now()",
+        flag.name,
+        input.enabled,
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(Json(FeatureState {
+        name: flag.name.to_string(),
+        description: flag.description.to_string(),
+        enabled: input.enabled,
+        default: flag.default,
+        configured: catalog.features.get(flag.name).copied(),
+        overridden: Some(input.enabled),
+    }))
+}
+
+/// Drops the override, going back to the configured or default state.
+pub async fn clear_feature(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let flag = find(&name).ok_or_else(|| AppError::ResourceNotFoundBy("Feature", "name", name.clone()))?;
+    sqlx::query!("DELETE FROM feature_flags WHERE name = $1", flag.name)
+        .execute(&pool)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
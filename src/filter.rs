@@ -0,0 +1,248 @@
+//! The `filter` query parameter on the book list: comma-separated
+//! `field:op:value` conditions that are all required to match, e.g.
+//! `filter=year:gte:1950,author:contains:orwell,available:eq:true`.
+
+use std::{fmt, str::FromStr};
+
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Author,
+    Isbn,
+    Year,
+    Available,
+    OriginalTitle,
+    OriginalLanguage,
+    Translator,
+    Format,
+    Narrator,
+}
+
+impl Field {
+    fn column(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Author => "author",
+            Field::Isbn => "isbn",
+            Field::Year => "year",
+            Field::Available => "available",
+            Field::OriginalTitle => "original_title",
+            Field::OriginalLanguage => "original_language",
+            Field::Translator => "translator",
+            Field::Format => "format",
+            Field::Narrator => "narrator",
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title" => Ok(Field::Title),
+            "author" => Ok(Field::Author),
+            "isbn" => Ok(Field::Isbn),
+            "year" => Ok(Field::Year),
+            "available" => Ok(Field::Available),
+            "original_title" => Ok(Field::OriginalTitle),
+            "original_language" => Ok(Field::OriginalLanguage),
+            "translator" => Ok(Field::Translator),
+            "format" => Ok(Field::Format),
+            "narrator" => Ok(Field::Narrator),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Contains,
+    StartsWith,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "eq",
+            Op::Ne => "ne",
+            Op::Gt => "gt",
+            Op::Gte => "gte",
+            Op::Lt => "lt",
+            Op::Lte => "lte",
+            Op::Contains => "contains",
+            Op::StartsWith => "starts_with",
+        }
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            Op::Eq => " = ",
+            Op::Ne => " <> ",
+            Op::Gt => " > ",
+            Op::Gte => " >= ",
+            Op::Lt => " < ",
+            Op::Lte => " <= ",
+            Op::Contains | Op::StartsWith => unreachable!("text matching is built separately"),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Op {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eq" => Ok(Op::Eq),
+            "ne" => Ok(Op::Ne),
+            "gt" => Ok(Op::Gt),
+            "gte" => Ok(Op::Gte),
+            "lt" => Ok(Op::Lt),
+            "lte" => Ok(Op::Lte),
+            "contains" => Ok(Op::Contains),
+            "starts_with" => Ok(Op::StartsWith),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Int(i64),
+    Bool(bool),
+}
+
+/// One parsed `field:op:value` term. Only built by `parse`, which checks
+/// that the operator suits the field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    field: Field,
+    op: Op,
+    value: Value,
+}
+
+impl Condition {
+    /// A case- and accent-insensitive substring match on `field`, as used by
+    /// the plain `author` parameter.
+    pub fn contains(field: Field, text: &str) -> Self {
+        Condition { field, op: Op::Contains, value: Value::Text(text.to_string()) }
+    }
+
+    /// A case- and accent-insensitive exact match on `field`.
+    pub fn equals(field: Field, text: &str) -> Self {
+        Condition { field, op: Op::Eq, value: Value::Text(text.to_string()) }
+    }
+
+    /// A numeric comparison on `year`, e.g. from an SRU `dc.date>=1950`
+    /// clause. `op` must be a comparison, not a text match.
+    pub fn year(op: Op, year: i64) -> Self {
+        debug_assert!(!matches!(op, Op::Contains | Op::StartsWith));
+        Condition { field: Field::Year, op, value: Value::Int(year) }
+    }
+
+    /// Appends the condition as SQL, binding its value. Text comparisons go
+    /// through `fold_text`, so they ignore case and diacritics.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        let column = self.field.column();
+        match (&self.value, self.op) {
+            // Written as LIKE, with the value's wildcards escaped, so the
+            // trigram indexes on folded titles and authors can answer them.
+            (Value::Text(text), Op::Contains) => {
+                query.push(format!("fold_text({}) LIKE '%' || ", column));
+                push_like_literal(query, text);
+                query.push(" || '%'");
+            }
+            (Value::Text(text), Op::StartsWith) => {
+                query.push(format!("fold_text({}) LIKE ", column));
+                push_like_literal(query, text);
+                query.push(" || '%'");
+            }
+            (Value::Text(text), op) => {
+                query.push(format!("fold_text({})", column)).push(op.sql()).push("fold_text(").push_bind(text.clone()).push(")");
+            }
+            (Value::Int(n), op) => {
+                query.push(column).push(op.sql()).push_bind(*n);
+            }
+            (Value::Bool(b), op) => {
+                query.push(column).push(op.sql()).push_bind(*b);
+            }
+        }
+    }
+}
+
+/// Binds `text`, folded, as the literal part of a LIKE pattern: its `%`, `_`
+/// and `\` are escaped so they match themselves.
+fn push_like_literal(query: &mut QueryBuilder<'_, Postgres>, text: &str) {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    query.push("fold_text(").push_bind(escaped).push(")");
+}
+
+/// Parses a whole `filter` value. Values can't contain commas; everything
+/// after the second colon is the value, so they may contain colons.
+pub fn parse(expression: &str) -> Result<Vec<Condition>, AppError> {
+    expression
+        .split(',')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .map(parse_condition)
+        .collect()
+}
+
+fn parse_condition(term: &str) -> Result<Condition, AppError> {
+    let invalid = |reason: String| AppError::InvalidInput(format!("Invalid filter '{}': {}", term, reason));
+
+    let mut parts = term.splitn(3, ':');
+    let (Some(field), Some(op), Some(value)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid("expected field:op:value".to_string()));
+    };
+    let field: Field = field
+        .parse()
+        .map_err(|_| {
+            invalid(
+                "fields are title, author, isbn, year, available, original_title, original_language, translator, format, and narrator"
+                    .to_string(),
+            )
+        })?;
+    let op: Op = op.parse().map_err(|_| invalid(format!("unknown operator '{}'", op)))?;
+
+    let value = match field {
+        Field::Title | Field::Author | Field::Isbn | Field::OriginalTitle | Field::OriginalLanguage | Field::Translator
+        | Field::Format | Field::Narrator => {
+            if !matches!(op, Op::Eq | Op::Ne | Op::Contains | Op::StartsWith) {
+                return Err(invalid(format!("{} can't be compared with {}", field.column(), op)));
+            }
+            Value::Text(value.to_string())
+        }
+        Field::Year => {
+            if matches!(op, Op::Contains | Op::StartsWith) {
+                return Err(invalid(format!("year can't be compared with {}", op)));
+            }
+            Value::Int(value.parse().map_err(|_| invalid("year must be a whole number".to_string()))?)
+        }
+        Field::Available => {
+            if !matches!(op, Op::Eq | Op::Ne) {
+                return Err(invalid(format!("available can't be compared with {}", op)));
+            }
+            Value::Bool(value.parse().map_err(|_| invalid("available must be true or false".to_string()))?)
+        }
+    };
+
+    Ok(Condition { field, op, value })
+}
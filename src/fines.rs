@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::{AppError, borrowings::Borrowing};
+
+const OVERDUE_CENTS_PER_DAY: i64 = 25;
+/// Overdue fines stop accruing here; beyond this the item is treated as lost.
+const MAX_OVERDUE_CENTS: i64 = 1_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fine {
+    pub id: i64,
+    pub member_id: i64,
+    pub borrowing_id: Option<i64>,
+    pub amount_cents: i64,
+    pub reason: String,
+    pub assessed_at: DateTime<Utc>,
+    pub paid_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FineSummary {
+    pub outstanding_cents: i64,
+    pub fines: Vec<Fine>,
+}
+
+/// Charges the member for a late return: a fixed amount per started day past
+/// the due date, capped. Returns `None` for loans returned on time.
+pub async fn assess_overdue(conn: &mut PgConnection, borrowing: &Borrowing) -> Result<Option<Fine>, AppError> {
+    let (Some(member_id), Some(returned_at)) = (borrowing.member_id, borrowing.returned_at) else {
+        return Ok(None);
+    };
+    let late = returned_at - borrowing.due_date;
+    if late <= chrono::Duration::zero() {
+        return Ok(None);
+    }
+    let days = (late.num_seconds() + 86_399) / 86_400;
+    let amount_cents = (days * OVERDUE_CENTS_PER_DAY).min(MAX_OVERDUE_CENTS);
+
+    let fine = sqlx::query_as!(
+        Fine,
+        "INSERT INTO fines (member_id, borrowing_id, amount_cents, reason, assessed_at)
+         VALUES ($1, $2, $3, $4, $5)
+         RETURNING *",
+        member_id,
+        borrowing.id,
+        amount_cents,
+        format!("Returned {} day{} late", days, if days == 1 { "" } else { "s" }),
+        returned_at,
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(Some(fine))
+}
+
+pub async fn summary_for_member(conn: &mut PgConnection, member_id: i64) -> Result<FineSummary, AppError> {
+    let fines = sqlx::query_as!(
+        Fine,
+        "SELECT * FROM fines WHERE member_id = $1 ORDER BY assessed_at DESC, id DESC",
+        member_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let outstanding_cents = fines.iter().filter(|f| f.paid_at.is_none()).map(|f| f.amount_cents).sum();
+    Ok(FineSummary { outstanding_cents, fines })
+}
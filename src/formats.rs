@@ -0,0 +1,90 @@
+//! The format a catalog record describes, and the extra details recorded
+//! for audiobooks.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookFormat {
+    #[default]
+    Print,
+    Ebook,
+    Audiobook,
+}
+
+impl BookFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BookFormat::Print => "print",
+            BookFormat::Ebook => "ebook",
+            BookFormat::Audiobook => "audiobook",
+        }
+    }
+}
+
+impl fmt::Display for BookFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for BookFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "print" => Ok(BookFormat::Print),
+            "ebook" => Ok(BookFormat::Ebook),
+            "audiobook" => Ok(BookFormat::Audiobook),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Audiobook-only fields. A recording comes on discs or as a set of files,
+/// so usually only one of `discs` and `files` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudiobookDetails {
+    pub narrator: Option<String>,
+    pub duration_minutes: Option<i64>,
+    pub discs: Option<i64>,
+    pub files: Option<i64>,
+}
+
+impl AudiobookDetails {
+    /// `None` when no field is set, so print records don't carry an empty
+    /// object.
+    pub fn from_columns(
+        narrator: Option<String>,
+        duration_minutes: Option<i64>,
+        discs: Option<i64>,
+        files: Option<i64>,
+    ) -> Option<Self> {
+        let details = AudiobookDetails { narrator, duration_minutes, discs, files };
+        (details != AudiobookDetails::default()).then_some(details)
+    }
+}
+
+/// Checks audiobook details against the record's format: they are only
+/// accepted for audiobooks, and counts must be positive.
+pub fn validate(format: BookFormat, details: Option<&AudiobookDetails>) -> Result<(), AppError> {
+    let Some(details) = details else {
+        return Ok(());
+    };
+    if format != BookFormat::Audiobook {
+        return Err(AppError::InvalidInput(format!("audiobook details can't be set on a {} record", format)));
+    }
+    if details.narrator.as_deref().is_some_and(|n| n.trim().is_empty()) {
+        return Err(AppError::InvalidInput("narrator must not be empty".to_string()));
+    }
+    for (field, value) in [("duration_minutes", details.duration_minutes), ("discs", details.discs), ("files", details.files)] {
+        if value.is_some_and(|v| v < 1) {
+            return Err(AppError::InvalidInput(format!("{} must be at least 1", field)));
+        }
+    }
+    Ok(())
+}
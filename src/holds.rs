@@ -0,0 +1,314 @@
+use std::{fmt, str::FromStr, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    copies::CopyStatus,
+    notifications::{self, NotificationCategory},
+    templates::Template,
+};
+
+/// How long a ready hold waits on the hold shelf before it lapses.
+const PICKUP_DAYS: i64 = 7;
+
+/// How often lapsed pickups are swept up.
+const EXPIRY_INTERVAL: StdDuration = StdDuration::from_secs(300);
+
+/// A hold waits in the queue until a copy of the book is returned, then is
+/// ready for pickup until the member checks the book out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldStatus {
+    Waiting,
+    Ready,
+    Fulfilled,
+    Cancelled,
+    Expired,
+}
+
+impl HoldStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            HoldStatus::Waiting => "waiting",
+            HoldStatus::Ready => "ready",
+            HoldStatus::Fulfilled => "fulfilled",
+            HoldStatus::Cancelled => "cancelled",
+            HoldStatus::Expired => "expired",
+        }
+    }
+}
+
+impl fmt::Display for HoldStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HoldStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waiting" => Ok(HoldStatus::Waiting),
+            "ready" => Ok(HoldStatus::Ready),
+            "fulfilled" => Ok(HoldStatus::Fulfilled),
+            "cancelled" => Ok(HoldStatus::Cancelled),
+            "expired" => Ok(HoldStatus::Expired),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hold {
+    pub id: i64,
+    pub book_id: i64,
+    pub member_id: i64,
+    pub status: HoldStatus,
+    pub placed_at: DateTime<Utc>,
+    pub ready_at: Option<DateTime<Utc>>,
+    /// Pickup deadline once the hold is ready.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+}
+
+struct HoldRow {
+    id: i64,
+    book_id: i64,
+    member_id: i64,
+    status: String,
+    placed_at: DateTime<Utc>,
+    ready_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    cancelled_at: Option<DateTime<Utc>>,
+}
+
+impl From<HoldRow> for Hold {
+    fn from(r: HoldRow) -> Self {
+        Hold {
+            id: r.id,
+            book_id: r.book_id,
+            member_id: r.member_id,
+            status: r.status.parse().unwrap_or(HoldStatus::Waiting),
+            placed_at: r.placed_at,
+            ready_at: r.ready_at,
+            expires_at: r.expires_at,
+            cancelled_at: r.cancelled_at,
+        }
+    }
+}
+
+pub async fn place(conn: &mut PgConnection, member_id: i64, book_id: i64) -> Result<Hold, AppError> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)", book_id)
+        .fetch_one(&mut *conn)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(AppError::NotFound(book_id));
+    }
+
+    sqlx::query_as!(
+        HoldRow,
+        "INSERT INTO holds (book_id, member_id, status, placed_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (member_id, book_id) WHERE status IN ('waiting', 'ready') DO NOTHING
+         RETURNING *",
+        book_id,
+        member_id,
+        HoldStatus::Waiting.as_str(),
+        Utc::now(),
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .map(Hold::from)
+    .ok_or_else(|| AppError::Conflict(format!("You already have a hold on book {}", book_id)))
+}
+
+pub async fn for_member(conn: &mut PgConnection, member_id: i64) -> Result<Vec<Hold>, AppError> {
+    let rows = sqlx::query_as!(
+        HoldRow,
+        "SELECT * FROM holds WHERE member_id = $1 ORDER BY placed_at DESC, id DESC",
+        member_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(Hold::from).collect())
+}
+
+/// Waiting and ready holds on any of `book_ids`, in queue order.
+pub async fn active_for_books(conn: &mut PgConnection, book_ids: &[i64]) -> Result<Vec<Hold>, AppError> {
+    let rows = sqlx::query_as!(
+        HoldRow,
+        "SELECT * FROM holds WHERE book_id = ANY($1) AND status IN ('waiting', 'ready')
+         ORDER BY placed_at, id",
+        book_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(Hold::from).collect())
+}
+
+/// Cancels one of the member's holds. Holds belonging to someone else are
+/// reported as missing rather than forbidden so ids can't be probed.
+pub async fn cancel(conn: &mut PgConnection, member_id: i64, id: i64) -> Result<Hold, AppError> {
+    let current: Hold = sqlx::query_as!(
+        HoldRow,
+        "SELECT * FROM holds WHERE id = $1 AND member_id = $2 FOR UPDATE",
+        id,
+        member_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Hold", id))?
+    .into();
+
+    if !matches!(current.status, HoldStatus::Waiting | HoldStatus::Ready) {
+        return Err(AppError::Conflict(format!("Hold with ID {} is already {}", id, current.status)));
+    }
+    // The copy kept for a ready hold goes to the next member in the queue.
+    let promote = current.status == HoldStatus::Ready;
+
+    let row = sqlx::query_as!(
+        HoldRow,
+        "UPDATE holds SET status = $1, cancelled_at = $2 WHERE id = $3 RETURNING *",
+        HoldStatus::Cancelled.as_str(),
+        Utc::now(),
+        id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if promote {
+        promote_next(conn, current.book_id).await?;
+    }
+    Ok(row.into())
+}
+
+/// A copy of the book came back: the longest-waiting hold is ready for
+/// pickup, and its member is told so.
+pub async fn promote_next(conn: &mut PgConnection, book_id: i64) -> Result<Option<Hold>, AppError> {
+    let now = Utc::now();
+    let row = sqlx::query_as!(
+        HoldRow,
+        "UPDATE holds SET status = $1, ready_at = $2, expires_at = $3
+         WHERE id = (
+             SELECT id FROM holds WHERE book_id = $4 AND status = $5
+             ORDER BY placed_at, id LIMIT 1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *",
+        HoldStatus::Ready.as_str(),
+        now,
+        now + Duration::days(PICKUP_DAYS),
+        book_id,
+        HoldStatus::Waiting.as_str(),
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(hold) = row.map(Hold::from) else {
+        return Ok(None);
+    };
+
+    let title = sqlx::query_scalar!("SELECT title FROM books WHERE id = $1", book_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let message = Template::HoldReady { title: &title, pick_up_by: hold.expires_at.unwrap_or(now) };
+    notifications::notify(conn, hold.member_id, NotificationCategory::HoldReady, None, message.subject(), &message.body())
+        .await?;
+
+    Ok(Some(hold))
+}
+
+/// Checking the book out satisfies any hold the member had on it.
+pub async fn fulfil(conn: &mut PgConnection, member_id: i64, book_id: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        "UPDATE holds SET status = $1
+         WHERE member_id = $2 AND book_id = $3 AND status IN ('waiting', 'ready')",
+        HoldStatus::Fulfilled.as_str(),
+        member_id,
+        book_id,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Ready holds keep a copy each on the hold shelf, or the book itself for a
+/// title without copies. Refuses to lend `book_id` to `member_id` when
+/// everything on the shelf is kept for someone else. Lapsed holds on the
+/// book are expired first.
+pub async fn require_unreserved(conn: &mut PgConnection, book_id: i64, member_id: i64) -> Result<(), AppError> {
+    expire_lapsed(&mut *conn, Some(book_id)).await?;
+
+    let counts = sqlx::query!(
+        r#"SELECT
+             (SELECT COUNT(*) FROM holds WHERE book_id = $1 AND status = $3 AND member_id <> $2) AS "reserved!",
+             CASE WHEN EXISTS(SELECT 1 FROM copies WHERE book_id = $1 AND status <> $5)
+                  THEN (SELECT COUNT(*) FROM copies WHERE book_id = $1 AND status = $4)
+                  ELSE (SELECT COUNT(*) FROM books WHERE id = $1 AND available)
+             END AS "on_shelf!""#,
+        book_id,
+        member_id,
+        HoldStatus::Ready.as_str(),
+        CopyStatus::Available.as_str(),
+        CopyStatus::Discarded.as_str(),
+    )
+    .fetch_one(conn)
+    .await?;
+
+    if counts.reserved >= counts.on_shelf {
+        return Err(AppError::Conflict(format!(
+            "Book {} is on the hold shelf for another member",
+            book_id
+        )));
+    }
+    Ok(())
+}
+
+/// Ready holds whose pickup deadline has passed lapse, and each one's copy
+/// goes to the next member waiting. Limited to one book when `book_id` is
+/// given. Returns how many holds lapsed.
+pub async fn expire_lapsed(conn: &mut PgConnection, book_id: Option<i64>) -> Result<usize, AppError> {
+    let lapsed = sqlx::query_scalar!(
+        "UPDATE holds SET status = $1
+         WHERE status = $2 AND expires_at <= $3 AND ($4::bigint IS NULL OR book_id = $4)
+         RETURNING book_id",
+        HoldStatus::Expired.as_str(),
+        HoldStatus::Ready.as_str(),
+        Utc::now(),
+        book_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for book_id in &lapsed {
+        promote_next(&mut *conn, *book_id).await?;
+    }
+    Ok(lapsed.len())
+}
+
+/// Expires lapsed holds every `EXPIRY_INTERVAL`, forever. Checkouts expire
+/// a book's holds themselves; this keeps queues moving for the rest.
+pub async fn run(pool: PgPool) {
+    loop {
+        if let Err(e) = expire_all(&pool).await {
+            match e {
+                AppError::Database(e) => eprintln!("Holds: {}", e),
+                _ => eprintln!("Holds: could not expire lapsed holds"),
+            }
+        }
+        tokio::time::sleep(EXPIRY_INTERVAL).await;
+    }
+}
+
+async fn expire_all(pool: &PgPool) -> Result<usize, AppError> {
+    let mut tx = pool.begin().await?;
+    let lapsed = expire_lapsed(&mut tx, None).await?;
+    tx.commit().await?;
+    Ok(lapsed)
+}
@@ -0,0 +1,249 @@
+//! Outbound HTTP for integrations (book metadata lookups, SMS and push
+//! gateways, chat webhooks). Integrations take an [`HttpClient`] instead of
+//! calling a library directly, so tests can swap the network for a
+//! [`Cassette`]: a JSON file of recorded request/response pairs that is
+//! written once against the real service and replayed offline after that.
+
+use std::{fmt, future::Future, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+impl HttpRequest {
+    pub fn get(url: impl Into<String>) -> Self {
+        HttpRequest { method: "GET".to_string(), url: url.into(), headers: Vec::new(), body: None }
+    }
+
+    pub fn post_json(url: impl Into<String>, body: &serde_json::Value) -> Self {
+        HttpRequest {
+            method: "POST".to_string(),
+            url: url.into(),
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: Some(body.to_string()),
+        }
+    }
+
+    /// A POST with an `application/x-www-form-urlencoded` body.
+    pub fn post_form(url: impl Into<String>, fields: &[(&str, &str)]) -> Self {
+        let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(fields).finish();
+        HttpRequest {
+            method: "POST".to_string(),
+            url: url.into(),
+            headers: vec![("content-type".to_string(), "application/x-www-form-urlencoded".to_string())],
+            body: Some(body),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_ascii_lowercase(), value.into()));
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request never got a response: DNS, TLS, timeout, refused
+    /// connection.
+    Transport(String),
+    /// A replaying cassette has no unused recording matching the request.
+    NotRecorded { method: String, url: String },
+    /// The cassette file couldn't be read or written.
+    Cassette(String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Transport(e) => write!(f, "request failed: {}", e),
+            HttpError::NotRecorded { method, url } => write!(f, "no recorded response for {} {}", method, url),
+            HttpError::Cassette(e) => write!(f, "cassette error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Sends one request. Non-2xx statuses are responses, not errors; only a
+/// failure to get any response is an `Err`.
+pub trait HttpClient: Send + Sync {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, HttpError>>;
+}
+
+/// The real network.
+pub struct LiveClient {
+    client: reqwest::Client,
+}
+
+impl LiveClient {
+    pub fn new(timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!("book-library-api/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("the HTTP client configuration is valid");
+        LiveClient { client }
+    }
+}
+
+impl HttpClient for LiveClient {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, HttpError>> {
+        Box::pin(async move {
+            let method = reqwest::Method::from_bytes(request.method.as_bytes())
+                .map_err(|_| HttpError::Transport(format!("invalid method {:?}", request.method)))?;
+            let mut builder = self.client.request(method, &request.url);
+            for (name, value) in &request.headers {
+                builder = builder.header(name, value);
+            }
+            if let Some(body) = request.body {
+                builder = builder.body(body);
+            }
+            let response = builder.send().await.map_err(|e| HttpError::Transport(e.to_string()))?;
+            let status = response.status().as_u16();
+            let headers = response
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect();
+            let body = response.text().await.map_err(|e| HttpError::Transport(e.to_string()))?;
+            Ok(HttpResponse { status, headers, body })
+        })
+    }
+}
+
+/// Request headers whose values are replaced with `***` before a recording
+/// is saved, so cassettes can be committed.
+const SECRET_HEADERS: &[&str] = &["authorization", "cookie", "x-api-key", "x-goog-api-key"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: HttpRequest,
+    pub response: HttpResponse,
+}
+
+enum Mode {
+    Replay,
+    Record(Arc<dyn HttpClient>),
+}
+
+struct Tape {
+    interactions: Vec<Interaction>,
+    /// Which recordings a replay has already handed out.
+    played: Vec<bool>,
+}
+
+/// Record/replay client. Recording forwards every request to a real client
+/// and rewrites the file after each response; replaying answers from the
+/// file and never touches the network.
+///
+/// A replayed request matches a recording on method, URL, and body; headers
+/// are ignored because they carry credentials. Each recording answers once,
+/// in order, so a request repeated three times gets the three responses
+/// that were recorded for it.
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    tape: Mutex<Tape>,
+}
+
+impl Cassette {
+    pub async fn replay(path: impl Into<PathBuf>) -> Result<Self, HttpError> {
+        let path = path.into();
+        let text = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| HttpError::Cassette(format!("{}: {}", path.display(), e)))?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&text)
+            .map_err(|e| HttpError::Cassette(format!("{}: {}", path.display(), e)))?;
+        let played = vec![false; interactions.len()];
+        Ok(Cassette { path, mode: Mode::Replay, tape: Mutex::new(Tape { interactions, played }) })
+    }
+
+    /// Starts an empty recording, replacing the file on the first response.
+    pub fn record(path: impl Into<PathBuf>, client: Arc<dyn HttpClient>) -> Self {
+        Cassette {
+            path: path.into(),
+            mode: Mode::Record(client),
+            tape: Mutex::new(Tape { interactions: Vec::new(), played: Vec::new() }),
+        }
+    }
+
+    /// The recordings so far, or the ones loaded for replay.
+    pub async fn interactions(&self) -> Vec<Interaction> {
+        self.tape.lock().await.interactions.clone()
+    }
+
+    async fn play(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let mut tape = self.tape.lock().await;
+        let Tape { interactions, played } = &mut *tape;
+        let found = interactions.iter().zip(played.iter_mut()).find(|(recorded, played)| {
+            !**played
+                && recorded.request.method.eq_ignore_ascii_case(&request.method)
+                && recorded.request.url == request.url
+                && recorded.request.body == request.body
+        });
+        match found {
+            Some((recorded, played)) => {
+                *played = true;
+                Ok(recorded.response.clone())
+            }
+            None => Err(HttpError::NotRecorded { method: request.method, url: request.url }),
+        }
+    }
+
+    async fn record_one(&self, client: &dyn HttpClient, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let response = client.send(request.clone()).await?;
+        let mut saved = request;
+        for (name, value) in &mut saved.headers {
+            if SECRET_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                *value = "***".to_string();
+            }
+        }
+
+        // Held across the write so concurrent requests can't save an older
+        // tape over a newer one.
+        let mut tape = self.tape.lock().await;
+        tape.interactions.push(Interaction { request: saved, response: response.clone() });
+        tape.played.push(false);
+        let json = serde_json::to_string_pretty(&tape.interactions).expect("interactions serialize");
+        tokio::fs::write(&self.path, json)
+            .await
+            .map_err(|e| HttpError::Cassette(format!("{}: {}", self.path.display(), e)))?;
+        Ok(response)
+    }
+}
+
+impl HttpClient for Cassette {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, HttpError>> {
+        Box::pin(async move {
+            match &self.mode {
+                Mode::Replay => self.play(request).await,
+                Mode::Record(client) => self.record_one(client.as_ref(), request).await,
+            }
+        })
+    }
+}
@@ -0,0 +1,402 @@
+use std::{fmt, str::FromStr};
+
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, auth::AuthMember, conditional, query::Query, slug, validation::validate_optional_bibliographic};
+
+/// Lifecycle of an inter-library loan, from the patron's request until the
+/// item is back with the lending library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IllStatus {
+    Requested,
+    Sent,
+    InTransit,
+    Received,
+    Returning,
+    Returned,
+    Cancelled,
+}
+
+impl IllStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            IllStatus::Requested => "requested",
+            IllStatus::Sent => "sent",
+            IllStatus::InTransit => "in_transit",
+            IllStatus::Received => "received",
+            IllStatus::Returning => "returning",
+            IllStatus::Returned => "returned",
+            IllStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn can_become(self, next: IllStatus) -> bool {
+        use IllStatus::*;
+        matches!(
+            (self, next),
+            (Requested, Sent)
+                | (Requested, Cancelled)
+                | (Sent, InTransit)
+                | (Sent, Cancelled)
+                | (InTransit, Received)
+                | (Received, Returning)
+                | (Received, Returned)
+                | (Returning, Returned)
+        )
+    }
+}
+
+impl fmt::Display for IllStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for IllStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "requested" => Ok(IllStatus::Requested),
+            "sent" => Ok(IllStatus::Sent),
+            "in_transit" => Ok(IllStatus::InTransit),
+            "received" => Ok(IllStatus::Received),
+            "returning" => Ok(IllStatus::Returning),
+            "returned" => Ok(IllStatus::Returned),
+            "cancelled" => Ok(IllStatus::Cancelled),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IllRequest {
+    pub id: i64,
+    /// The member who asked for the item; unset for requests made before
+    /// requests were tied to accounts.
+    pub member_id: Option<i64>,
+    pub patron_name: String,
+    pub title: String,
+    pub author: String,
+    pub year: Option<i64>,
+    pub isbn: Option<String>,
+    pub status: IllStatus,
+    pub lending_library: Option<String>,
+    pub tracking_number: Option<String>,
+    pub due_date: Option<DateTime<Utc>>,
+    /// The temporary catalog entry created when the item was received.
+    pub book_id: Option<i64>,
+    pub requested_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateIllRequest {
+    title: String,
+    author: String,
+    year: Option<i64>,
+    isbn: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateIllRequest {
+    status: Option<IllStatus>,
+    lending_library: Option<String>,
+    tracking_number: Option<String>,
+    due_date: Option<DateTime<Utc>>,
+    year: Option<i64>,
+    isbn: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IllParams {
+    status: Option<IllStatus>,
+}
+
+struct IllRow {
+    id: i64,
+    member_id: Option<i64>,
+    patron_name: String,
+    title: String,
+    author: String,
+    year: Option<i64>,
+    isbn: Option<String>,
+    status: String,
+    lending_library: Option<String>,
+    tracking_number: Option<String>,
+    due_date: Option<DateTime<Utc>>,
+    book_id: Option<i64>,
+    requested_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<IllRow> for IllRequest {
+    fn from(r: IllRow) -> Self {
+        IllRequest {
+            id: r.id,
+            member_id: r.member_id,
+            patron_name: r.patron_name,
+            title: r.title,
+            author: r.author,
+            year: r.year,
+            isbn: r.isbn,
+            status: r.status.parse().unwrap_or(IllStatus::Requested),
+            lending_library: r.lending_library,
+            tracking_number: r.tracking_number,
+            due_date: r.due_date,
+            book_id: r.book_id,
+            requested_at: r.requested_at,
+            updated_at: r.updated_at,
+        }
+    }
+}
+
+/// Made by a signed-in member, recorded against their account.
+pub async fn create_ill_request(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Json(input): Json<CreateIllRequest>,
+) -> Result<(StatusCode, Json<IllRequest>), AppError> {
+    if input.title.trim().is_empty() || input.author.trim().is_empty() {
+        return Err(AppError::InvalidInput("title and author must not be empty".to_string()));
+    }
+    validate_optional_bibliographic(input.year, input.isbn.as_deref())?;
+
+    let now = Utc::now();
+    let row = sqlx::query_as!(
+        IllRow,
+        "INSERT INTO ill_requests (member_id, patron_name, title, author, year, isbn, status, requested_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+         RETURNING id, member_id, patron_name, title, author, year, isbn, status, lending_library,
+                   tracking_number, due_date, book_id, requested_at, updated_at",
+        member.id,
+        member.name,
+        input.title,
+        input.author,
+        input.year,
+        input.isbn,
+        IllStatus::Requested.as_str(),
+        now,
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+/// Staff see every request; members see their own.
+pub async fn list_ill_requests(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Query(params): Query<IllParams>,
+) -> Result<Json<Vec<IllRequest>>, AppError> {
+    let rows = sqlx::query_as!(
+        IllRow,
+        "SELECT id, member_id, patron_name, title, author, year, isbn, status, lending_library,
+                tracking_number, due_date, book_id, requested_at, updated_at
+         FROM ill_requests
+         WHERE ($1::text IS NULL OR status = $1) AND ($2::bigint IS NULL OR member_id = $2)
+         ORDER BY id",
+        params.status.map(IllStatus::as_str),
+        (!member.role.is_staff()).then_some(member.id),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(IllRequest::from).collect()))
+}
+
+/// For staff, or the member who made the request.
+pub async fn get_ill_request(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<Json<IllRequest>, AppError> {
+    let row = sqlx::query_as!(
+        IllRow,
+        "SELECT id, member_id, patron_name, title, author, year, isbn, status, lending_library,
+                tracking_number, due_date, book_id, requested_at, updated_at
+         FROM ill_requests WHERE id = $1",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("ILL request", id))?;
+
+    if !member.role.is_staff() && row.member_id != Some(member.id) {
+        return Err(AppError::Forbidden("Members can only see their own ILL requests".to_string()));
+    }
+    Ok(Json(row.into()))
+}
+
+/// The ILL requests a member has made, newest first.
+pub async fn for_member(conn: &mut PgConnection, member_id: i64) -> Result<Vec<IllRequest>, AppError> {
+    let rows = sqlx::query_as!(
+        IllRow,
+        "SELECT id, member_id, patron_name, title, author, year, isbn, status, lending_library,
+                tracking_number, due_date, book_id, requested_at, updated_at
+         FROM ill_requests WHERE member_id = $1 ORDER BY requested_at DESC, id DESC",
+        member_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(IllRequest::from).collect())
+}
+
+/// Staff-side update. Moving to `received` adds the item to the catalog as a
+/// temporary book; moving to `returned` withdraws it again.
+pub async fn update_ill_request(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateIllRequest>,
+) -> Result<Json<IllRequest>, AppError> {
+    if !staff.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can update inter-library loans".to_string()));
+    }
+    validate_optional_bibliographic(input.year, input.isbn.as_deref())?;
+
+    let mut tx = pool.begin().await?;
+
+    let current: IllRequest = sqlx::query_as!(
+        IllRow,
+        "SELECT id, member_id, patron_name, title, author, year, isbn, status, lending_library,
+                tracking_number, due_date, book_id, requested_at, updated_at
+         FROM ill_requests WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("ILL request", id))?
+    .into();
+
+    let lending_library = input.lending_library.or(current.lending_library);
+    let year = input.year.or(current.year);
+    let isbn = input.isbn.or(current.isbn);
+    let mut book_id = current.book_id;
+
+    if let Some(next) = input.status.filter(|s| *s != current.status) {
+        if !current.status.can_become(next) {
+            return Err(AppError::Conflict(format!(
+                "ILL request with ID {} cannot move from {} to {}",
+                id, current.status, next
+            )));
+        }
+
+        match next {
+            IllStatus::Sent if lending_library.is_none() => {
+                return Err(AppError::InvalidInput(
+                    "lending_library is required before a request can be sent".to_string(),
+                ));
+            }
+            IllStatus::Received => {
+                let (Some(year), Some(isbn)) = (year, isbn.as_deref()) else {
+                    return Err(AppError::InvalidInput(
+                        "year and isbn are required before the item can be received".to_string(),
+                    ));
+                };
+                let temp_id = sqlx::query_scalar!(
+                    "INSERT INTO books (title, author, year, isbn, available, temporary)
+                     VALUES ($1, $2, $3, $4, true, true)
+                     RETURNING id",
+                    current.title,
+                    current.author,
+                    year,
+                    isbn,
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                slug::assign(&mut tx, temp_id, &current.title, year).await?;
+                book_id = Some(temp_id);
+            }
+            IllStatus::Returning | IllStatus::Returned => {
+                if let Some(temp_id) = book_id
+                    && withdraw_temporary_book(&mut tx, temp_id).await?
+                {
+                    book_id = None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let row = sqlx::query_as!(
+        IllRow,
+        "UPDATE ill_requests
+         SET status          = $1,
+             lending_library = $2,
+             tracking_number = COALESCE($3, tracking_number),
+             due_date        = COALESCE($4, due_date),
+             year            = $5,
+             isbn            = $6,
+             book_id         = $7,
+             updated_at      = $8
+         WHERE id = $9
+         RETURNING id, member_id, patron_name, title, author, year, isbn, status, lending_library,
+                   tracking_number, due_date, book_id, requested_at, updated_at",
+        input.status.unwrap_or(current.status).as_str(),
+        lending_library,
+        input.tracking_number,
+        input.due_date,
+        year,
+        isbn,
+        book_id,
+        Utc::now(),
+        id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(row.into()))
+}
+
+/// Takes a received ILL item out of circulation. The temporary record is
+/// deleted outright unless it has loan history, in which case it is kept but
+/// marked unavailable so the history stays intact. Returns whether the record
+/// was deleted.
+async fn withdraw_temporary_book(
+    conn: &mut PgConnection,
+    book_id: i64,
+) -> Result<bool, AppError> {
+    let on_loan = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM borrowings WHERE book_id = $1 AND returned_at IS NULL)",
+        book_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .unwrap_or(false);
+
+    if on_loan {
+        return Err(AppError::Conflict(format!(
+            "Book with ID {} is still on loan and must be returned first",
+            book_id
+        )));
+    }
+
+    let deleted = sqlx::query!(
+        "DELETE FROM books
+         WHERE id = $1 AND temporary
+         AND NOT EXISTS (SELECT 1 FROM borrowings WHERE book_id = $1)",
+        book_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if deleted.rows_affected() > 0 {
+        conditional::record_book_deletion(conn).await?;
+        return Ok(true);
+    }
+
+    sqlx::query!("UPDATE books SET available = false, updated_at = $1, version = version + 1 WHERE id = $2", Utc::now(), book_id)
+        .execute(conn)
+        .await?;
+
+    Ok(false)
+}
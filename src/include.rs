@@ -0,0 +1,127 @@
+//! `?include=` on the book endpoints: embeds related records in each book
+//! so clients don't have to fetch them one book at a time.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::{AppError, books::Book, borrowings::Borrowing, copies::{self, BookCopy}, holds::{self, Hold}, toc::{self, TocEntry}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    /// Every physical copy of the book.
+    Copies,
+    /// Loans that haven't been returned yet.
+    Loans,
+    /// Waiting and ready holds, in queue order.
+    Holds,
+    /// The table of contents, in reading order.
+    Toc,
+}
+
+impl Relation {
+    /// Parses a comma-separated `include` value, ignoring repeats.
+    pub fn parse_list(value: Option<&str>) -> Result<Vec<Relation>, AppError> {
+        let mut relations = Vec::new();
+        for name in value.unwrap_or_default().split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let relation = match name {
+                "copies" => Relation::Copies,
+                "loans" => Relation::Loans,
+                "holds" => Relation::Holds,
+                "toc" => Relation::Toc,
+                _ => {
+                    return Err(AppError::InvalidInput(format!(
+                        "Cannot include '{}'; expected copies, loans, holds, or toc",
+                        name
+                    )));
+                }
+            };
+            if !relations.contains(&relation) {
+                relations.push(relation);
+            }
+        }
+        Ok(relations)
+    }
+}
+
+/// A book with whichever relations were asked for. Without any it
+/// serializes exactly like `Book`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpandedBook {
+    #[serde(flatten)]
+    pub book: Book,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copies: Option<Vec<BookCopy>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loans: Option<Vec<Borrowing>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub holds: Option<Vec<Hold>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toc: Option<Vec<TocEntry>>,
+}
+
+/// Loads each relation for all `books` with one query apiece.
+pub async fn expand(
+    conn: &mut PgConnection,
+    books: Vec<Book>,
+    relations: &[Relation],
+) -> Result<Vec<ExpandedBook>, AppError> {
+    let ids: Vec<i64> = books.iter().map(|b| b.id).collect();
+
+    let mut copies = if relations.contains(&Relation::Copies) {
+        Some(group_by_book(copies::for_books(&mut *conn, &ids).await?, |c| c.book_id))
+    } else {
+        None
+    };
+    let mut loans = if relations.contains(&Relation::Loans) {
+        let rows = sqlx::query_as!(
+            Borrowing,
+            "SELECT id, book_id, copy_id, member_id, borrower_name, borrowed_at, due_date, returned_at
+             FROM borrowings WHERE book_id = ANY($1) AND returned_at IS NULL ORDER BY borrowed_at, id",
+            &ids
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Some(group_by_book(rows, |l| l.book_id))
+    } else {
+        None
+    };
+    let mut holds = if relations.contains(&Relation::Holds) {
+        Some(group_by_book(holds::active_for_books(&mut *conn, &ids).await?, |h| h.book_id))
+    } else {
+        None
+    };
+    let mut toc = if relations.contains(&Relation::Toc) {
+        let entries = group_by_book(toc::for_books(&mut *conn, &ids).await?, |e| e.book_id);
+        Some(entries.into_iter().map(|(id, entries)| (id, entries.into_iter().map(|e| e.entry).collect())).collect())
+    } else {
+        None
+    };
+
+    Ok(books
+        .into_iter()
+        .map(|book| {
+            let id = book.id;
+            ExpandedBook {
+                copies: take_from(copies.as_mut(), id),
+                loans: take_from(loans.as_mut(), id),
+                holds: take_from(holds.as_mut(), id),
+                toc: take_from(toc.as_mut(), id),
+                book,
+            }
+        })
+        .collect())
+}
+
+fn take_from<T>(map: Option<&mut HashMap<i64, Vec<T>>>, book_id: i64) -> Option<Vec<T>> {
+    map.map(|m| m.remove(&book_id).unwrap_or_default())
+}
+
+fn group_by_book<T>(items: Vec<T>, book_id: impl Fn(&T) -> i64) -> HashMap<i64, Vec<T>> {
+    let mut grouped: HashMap<i64, Vec<T>> = HashMap::new();
+    for item in items {
+        grouped.entry(book_id(&item)).or_default().push(item);
+    }
+    grouped
+}
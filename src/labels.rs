@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::State,
+    http::header,
+    response::IntoResponse,
+};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{AppError, barcode};
+
+/// Sheets are US Letter with 3 × 10 labels of 2⅝" × 1" (Avery 5160 and
+/// compatibles). All measurements are in PDF points.
+const PAGE_WIDTH: f32 = 612.0;
+const PAGE_HEIGHT: f32 = 792.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 10;
+const LABELS_PER_SHEET: usize = COLUMNS * ROWS;
+const LEFT_MARGIN: f32 = 13.5;
+const TOP_MARGIN: f32 = 36.0;
+const LABEL_WIDTH: f32 = 189.0;
+const LABEL_HEIGHT: f32 = 72.0;
+const COLUMN_PITCH: f32 = 198.0;
+
+/// Call number block on the left of the label, barcode on the right.
+const CALL_NUMBER_X: f32 = 8.0;
+const CALL_NUMBER_LINES: usize = 4;
+const BARCODE_X: f32 = 60.0;
+const BARCODE_WIDTH: f32 = LABEL_WIDTH - BARCODE_X - CALL_NUMBER_X;
+const BARCODE_Y: f32 = 20.0;
+const BARCODE_HEIGHT: f32 = 28.0;
+/// Helvetica's digits and average Latin glyphs are roughly this wide per
+/// point of font size; good enough for centring and truncating.
+pub(crate) const GLYPH_WIDTH: f32 = 0.556;
+
+const MAX_LABELS: usize = 10 * LABELS_PER_SHEET;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+#[derive(Debug, Deserialize)]
+pub struct PrintLabels {
+    copy_ids: Vec<i64>,
+    /// Number of labels already used on the first sheet, so partly used
+    /// sheets can go back through the printer.
+    skip: Option<usize>,
+}
+
+struct LabelRow {
+    id: i64,
+    barcode: String,
+    call_number: Option<String>,
+    title: String,
+}
+
+/// Lays out spine labels for the given copies, in the order requested, and
+/// returns them as a PDF ready to print onto label sheets.
+pub async fn print_labels(
+    State(pool): State<PgPool>,
+    Json(input): Json<PrintLabels>,
+) -> Result<impl IntoResponse, AppError> {
+    if input.copy_ids.is_empty() || input.copy_ids.len() > MAX_LABELS {
+        return Err(AppError::InvalidInput(format!(
+            "copy_ids must contain between 1 and {} copies",
+            MAX_LABELS
+        )));
+    }
+    let skip = input.skip.unwrap_or(0);
+    if skip >= LABELS_PER_SHEET {
+        return Err(AppError::InvalidInput(format!(
+            "skip must be less than {}, the number of labels on a sheet",
+            LABELS_PER_SHEET
+        )));
+    }
+
+    let rows = sqlx::query_as!(
+        LabelRow,
+        "SELECT c.id, c.barcode, COALESCE(c.call_number, b.classification) AS call_number, b.title
+         FROM copies c JOIN books b ON b.id = c.book_id
+         WHERE c.id = ANY($1)",
+        &input.copy_ids,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let mut by_id: HashMap<i64, LabelRow> = rows.into_iter().map(|r| (r.id, r)).collect();
+    let labels = input
+        .copy_ids
+        .iter()
+        .map(|id| by_id.remove(id).ok_or(AppError::ResourceNotFound("Copy", *id)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let pdf = render_sheets(&labels, skip);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf"),
+            (header::CONTENT_DISPOSITION, "inline; filename=\"labels.pdf\""),
+        ],
+        pdf,
+    ))
+}
+
+fn render_sheets(labels: &[LabelRow], skip: usize) -> Vec<u8> {
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let regular_id = Ref::new(3);
+    let bold_id = Ref::new(4);
+
+    let sheets = (skip + labels.len()).div_ceil(LABELS_PER_SHEET);
+    let page_ids: Vec<Ref> = (0..sheets).map(|i| Ref::new(5 + 2 * i as i32)).collect();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().copied()).count(sheets as i32);
+    pdf.type1_font(regular_id)
+        .base_font(Name(b"Helvetica"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.type1_font(bold_id)
+        .base_font(Name(b"Helvetica-Bold"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+    for (sheet, page_id) in page_ids.iter().enumerate() {
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut page = pdf.page(*page_id);
+        page.parent(page_tree_id)
+            .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .contents(content_id);
+        page.resources().fonts().pair(REGULAR, regular_id).pair(BOLD, bold_id);
+        page.finish();
+
+        let mut content = Content::new();
+        let first_slot = sheet * LABELS_PER_SHEET;
+        for slot in first_slot.max(skip)..(first_slot + LABELS_PER_SHEET).min(skip + labels.len()) {
+            let position = slot - first_slot;
+            let x = LEFT_MARGIN + (position % COLUMNS) as f32 * COLUMN_PITCH;
+            let y = PAGE_HEIGHT - TOP_MARGIN - (position / COLUMNS + 1) as f32 * LABEL_HEIGHT;
+            draw_label(&mut content, &labels[slot - skip], x, y);
+        }
+        pdf.stream(content_id, &content.finish());
+    }
+
+    pdf.finish()
+}
+
+/// Draws one label with its bottom-left corner at (`x`, `y`).
+fn draw_label(content: &mut Content, label: &LabelRow, x: f32, y: f32) {
+    // Spine labels break the call number onto one line per part, e.g.
+    // "823.92 / CLA / 2020".
+    let call_number = label.call_number.as_deref().unwrap_or_default();
+    for (i, part) in call_number.split_whitespace().take(CALL_NUMBER_LINES).enumerate() {
+        text(content, BOLD, 9.0, x + CALL_NUMBER_X, y + LABEL_HEIGHT - 16.0 - 11.0 * i as f32, part);
+    }
+
+    let max_title_chars = (BARCODE_WIDTH / (7.0 * GLYPH_WIDTH)) as usize;
+    let title: String = if label.title.chars().count() > max_title_chars {
+        label.title.chars().take(max_title_chars - 3).chain("...".chars()).collect()
+    } else {
+        label.title.clone()
+    };
+    text(content, REGULAR, 7.0, x + BARCODE_X, y + LABEL_HEIGHT - 14.0, &title);
+
+    if let Some((bars, symbol_width)) = barcode::code39_bars(&label.barcode) {
+        let scale = BARCODE_WIDTH / symbol_width as f32;
+        for (offset, width) in bars {
+            content.rect(
+                x + BARCODE_X + offset as f32 * scale,
+                y + BARCODE_Y,
+                width as f32 * scale,
+                BARCODE_HEIGHT,
+            );
+        }
+        content.fill_nonzero();
+    }
+
+    let digits_width = label.barcode.chars().count() as f32 * 7.0 * GLYPH_WIDTH;
+    let digits_x = x + BARCODE_X + (BARCODE_WIDTH - digits_width).max(0.0) / 2.0;
+    text(content, REGULAR, 7.0, digits_x, y + 11.0, &label.barcode);
+}
+
+pub(crate) fn text(content: &mut Content, font: Name, size: f32, x: f32, y: f32, value: &str) {
+    content
+        .begin_text()
+        .set_font(font, size)
+        .next_line(x, y)
+        .show(Str(&win_ansi(value)))
+        .end_text();
+}
+
+/// The standard PDF fonts only cover WinAnsi, which matches Latin-1 for the
+/// accented letters; anything beyond that is printed as `?`.
+fn win_ansi(value: &str) -> Vec<u8> {
+    value
+        .chars()
+        .map(|c| {
+            u8::try_from(u32::from(c))
+                .ok()
+                .filter(|b| (0x20..0x7F).contains(b) || *b >= 0xA0)
+                .unwrap_or(b'?')
+        })
+        .collect()
+}
@@ -0,0 +1,183 @@
+//! Sign-in against an LDAP directory (OpenLDAP, Active Directory), for
+//! libraries whose staff and patrons already have institutional accounts.
+//! `POST /auth/login` with a `username` binds to the directory as that user,
+//! reads their name, email and groups, and signs them in as the member
+//! linked to that username, creating it on first login. The role follows
+//! the configured staff and admin groups on every login, so access is
+//! managed in the directory.
+//!
+//! The protocol itself is left to the `ldap3` crate. Passwords only travel
+//! encrypted, over `ldaps://` or StartTLS, unless plaintext is explicitly
+//! allowed.
+
+use std::time::Duration;
+
+use ldap3::{DerefAliases, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, SearchOptions, dn_escape, ldap_escape};
+
+use crate::{auth::ExternalAccount, members::MemberRole, tls};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const SUCCESS: u32 = 0;
+const SIZE_LIMIT_EXCEEDED: u32 = 4;
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// How the connection to the directory is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// `ldaps://`: TLS from the first byte.
+    Tls,
+    /// `ldap://` upgraded with StartTLS before binding, as Active Directory
+    /// on port 389 expects.
+    StartTls,
+    /// `ldap://` in the clear, passwords included. Only by explicit opt-in,
+    /// for a directory on the same host or a trusted link.
+    Plaintext,
+}
+
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    /// `ldaps://host[:port]` or `ldap://host[:port]`.
+    pub url: String,
+    pub transport: Transport,
+    /// The name to bind as, with `{username}` where the login name goes:
+    /// `uid={username},ou=people,dc=example,dc=org`, or
+    /// `{username}@example.org` for Active Directory.
+    pub user_dn: String,
+    /// Where to look the user up after binding. Without it the bind DN is
+    /// read directly, which only works when `user_dn` is a real DN.
+    pub search_base: Option<String>,
+    /// Matched against the login name when searching under `search_base`,
+    /// e.g. `uid` or `sAMAccountName`.
+    pub login_attribute: String,
+    pub name_attribute: String,
+    pub email_attribute: String,
+    /// Lists the DNs of the user's groups, e.g. `memberOf`.
+    pub group_attribute: String,
+    pub staff_group: Option<String>,
+    pub admin_group: Option<String>,
+}
+
+impl LdapConfig {
+    /// Whether `url` is an `ldaps://` URL (`Some(true)`) or an `ldap://`
+    /// one (`Some(false)`), naming a host and nothing after it.
+    pub fn is_tls_url(url: &str) -> Option<bool> {
+        let (tls, rest) = match url.trim().split_once("://")? {
+            (scheme, rest) if scheme.eq_ignore_ascii_case("ldap") => (false, rest),
+            (scheme, rest) if scheme.eq_ignore_ascii_case("ldaps") => (true, rest),
+            _ => return None,
+        };
+        let authority = rest.trim_end_matches('/');
+        let host = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                port.parse::<u16>().ok()?;
+                host
+            }
+            _ => authority,
+        };
+        if host.trim_start_matches('[').trim_end_matches(']').is_empty() || authority.contains('/') {
+            return None;
+        }
+        Some(tls)
+    }
+}
+
+#[derive(Debug)]
+pub enum LdapError {
+    /// The directory refused the bind, or the login name can't be one.
+    InvalidCredentials,
+    /// The directory couldn't be reached or answered with something other
+    /// than a yes or no.
+    Unavailable(String),
+}
+
+impl From<ldap3::LdapError> for LdapError {
+    fn from(e: ldap3::LdapError) -> Self {
+        LdapError::Unavailable(e.to_string())
+    }
+}
+
+/// Binds as `username` and reads their entry: name, email, and the role
+/// their groups map to.
+pub async fn authenticate(config: &LdapConfig, username: &str, password: &str) -> Result<ExternalAccount, LdapError> {
+    // An empty password is an "unauthenticated bind", which many servers
+    // accept for any name.
+    if password.is_empty() || username.trim().is_empty() || username.chars().any(char::is_control) {
+        return Err(LdapError::InvalidCredentials);
+    }
+    match tokio::time::timeout(TIMEOUT, session(config, username.trim(), password)).await {
+        Ok(result) => result,
+        Err(_) => Err(LdapError::Unavailable(format!("no answer from {} within {:?}", config.url, TIMEOUT))),
+    }
+}
+
+async fn session(config: &LdapConfig, username: &str, password: &str) -> Result<ExternalAccount, LdapError> {
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(TIMEOUT)
+        .set_config(tls::client_config())
+        .set_starttls(config.transport == Transport::StartTls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url).await?;
+    ldap3::drive!(conn);
+
+    let bind_dn = config.user_dn.replace("{username}", &dn_escape(username));
+    match ldap.simple_bind(&bind_dn, password).await?.rc {
+        SUCCESS => {}
+        INVALID_CREDENTIALS => return Err(LdapError::InvalidCredentials),
+        other => return Err(LdapError::Unavailable(format!("bind failed with result code {}", other))),
+    }
+
+    let filter = format!("({}={})", config.login_attribute, ldap_escape(username));
+    let (base, scope, filter) = match &config.search_base {
+        Some(base) => (base.as_str(), Scope::Subtree, filter.as_str()),
+        None => (bind_dn.as_str(), Scope::Base, "(objectClass=*)"),
+    };
+    let attributes = [&config.name_attribute, &config.email_attribute, &config.group_attribute];
+    // Two entries are enough to tell that a login name is ambiguous.
+    let result = ldap
+        .with_search_options(SearchOptions::new().deref(DerefAliases::Never).sizelimit(2))
+        .search(base, scope, filter, attributes)
+        .await?;
+    // Unbinding is a courtesy; the connection closes either way.
+    let _ = ldap.unbind().await;
+
+    let entries = match result.1.rc {
+        SUCCESS => result.0,
+        SIZE_LIMIT_EXCEEDED => {
+            return Err(LdapError::Unavailable(format!("more than one directory entry for {:?}", username)));
+        }
+        other => return Err(LdapError::Unavailable(format!("search failed with result code {}", other))),
+    };
+    let entry = match <[_; 1]>::try_from(entries) {
+        Ok([entry]) => SearchEntry::construct(entry),
+        Err(entries) if entries.is_empty() => {
+            return Err(LdapError::Unavailable(format!("no directory entry for {:?}", username)));
+        }
+        Err(_) => return Err(LdapError::Unavailable(format!("more than one directory entry for {:?}", username))),
+    };
+    let values = |name: &str| -> Vec<&String> {
+        entry.attrs.iter().filter(|(attr, _)| attr.eq_ignore_ascii_case(name)).flat_map(|(_, v)| v).collect()
+    };
+    let in_group = |group: &Option<String>| {
+        group.as_ref().is_some_and(|group| values(&config.group_attribute).iter().any(|g| same_dn(g, group)))
+    };
+    let role = if in_group(&config.admin_group) {
+        MemberRole::Admin
+    } else if in_group(&config.staff_group) {
+        MemberRole::Staff
+    } else {
+        MemberRole::Patron
+    };
+
+    Ok(ExternalAccount {
+        name: values(&config.name_attribute).first().map(|v| v.to_string()),
+        email: values(&config.email_attribute).first().map(|v| v.to_string()),
+        role,
+    })
+}
+
+/// Group DNs as the directory returns them may differ in case and in
+/// spacing after commas.
+fn same_dn(a: &str, b: &str) -> bool {
+    let normalize = |dn: &str| dn.split(',').map(|part| part.trim().to_lowercase()).collect::<Vec<_>>();
+    normalize(a) == normalize(b)
+}
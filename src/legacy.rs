@@ -0,0 +1,61 @@
+//! The API is served twice: under `/v1`, and at the original unversioned
+//! paths for clients written before versioning. Responses on the old paths
+//! carry machine-readable warnings (RFC 9745 `Deprecation`, RFC 8594
+//! `Sunset`) and a `Link` to the same resource under `/v1`.
+
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use chrono::NaiveDate;
+
+use crate::CatalogConfig;
+
+pub const PREFIX: &str = "/v1";
+
+/// When the unversioned routes were or will be deprecated and switched
+/// off. Without dates the responses only get the successor link.
+#[derive(Debug, Clone, Default)]
+pub struct Deprecation {
+    pub deprecated_on: Option<NaiveDate>,
+    pub sunset_on: Option<NaiveDate>,
+    /// Migration notes, linked with `rel="deprecation"`.
+    pub docs_url: Option<String>,
+}
+
+pub async fn mark_legacy(State(catalog): State<CatalogConfig>, request: Request, next: Next) -> Response {
+    // Under a host application's `nest`, the link keeps the host's prefix.
+    let mount = request
+        .extensions()
+        .get::<OriginalUri>()
+        .and_then(|original| original.path().strip_suffix(request.uri().path()).map(str::to_string))
+        .unwrap_or_default();
+    let successor = match request.uri().path_and_query() {
+        Some(path) => format!("{}{}{}", mount, PREFIX, path),
+        None => format!("{}{}", mount, PREFIX),
+    };
+    let mut response = next.run(request).await;
+    let deprecation = &catalog.deprecation;
+    let headers = response.headers_mut();
+
+    let mut links = vec![format!("<{}>; rel=\"successor-version\"", successor)];
+    if let Some(url) = &deprecation.docs_url {
+        links.push(format!("<{}>; rel=\"deprecation\"", url));
+    }
+    for link in links {
+        if let Ok(value) = HeaderValue::from_str(&link) {
+            headers.append("link", value);
+        }
+    }
+    if let Some(date) = deprecation.deprecated_on {
+        let timestamp = date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        headers.insert("deprecation", HeaderValue::from_str(&format!("@{}", timestamp)).unwrap());
+    }
+    if let Some(date) = deprecation.sunset_on {
+        let http_date = date.and_hms_opt(0, 0, 0).unwrap().and_utc().format("%a, %d %b %Y %H:%M:%S GMT");
+        headers.insert("sunset", HeaderValue::from_str(&http_date.to_string()).unwrap());
+    }
+    response
+}
@@ -0,0 +1,285 @@
+use std::{net::SocketAddr, sync::Arc};
+
+mod acquisitions;
+mod age_rating;
+mod admin_ui;
+mod api_keys;
+mod audit;
+mod auth;
+mod backup;
+mod barcode;
+mod batch;
+mod books;
+mod borrowings;
+mod budgets;
+mod card;
+mod challenges;
+mod chat;
+mod classification;
+mod circulation;
+mod conditional;
+pub mod config;
+mod content_type;
+mod copies;
+mod demo;
+mod description;
+mod donations;
+mod duplicates;
+mod ebooks;
+mod error;
+mod excerpts;
+mod features;
+mod facets;
+mod filter;
+mod formats;
+mod fines;
+mod holds;
+pub mod http_client;
+mod ill;
+mod include;
+mod labels;
+mod ldap;
+mod legacy;
+mod mailer;
+mod maintenance;
+mod me;
+mod members;
+mod notifications;
+mod pagination;
+mod privacy;
+mod push;
+mod query;
+mod query_cache;
+mod queue;
+mod receipts;
+mod router;
+mod saml;
+mod search;
+mod seed;
+mod shelf;
+mod sip2;
+mod slug;
+mod sms;
+mod sort;
+mod sru;
+mod strict_json;
+mod templates;
+mod terms;
+mod throttle;
+mod tls;
+mod toc;
+mod totp;
+mod translations;
+mod two_factor;
+mod validation;
+mod vendors;
+mod warehouse;
+mod weeding;
+mod xml;
+
+pub use router::{AppState, LibraryApi, LibraryApiBuilder, build_router};
+
+use config::{CatalogConfig, Config};
+use error::{AppError, FieldError};
+
+/// What the binary was asked to do, from its arguments.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Migrate the database, then serve the API. The default.
+    Serve,
+    /// Apply pending migrations and exit, for deploy pipelines that migrate
+    /// before rolling out new servers.
+    Migrate,
+    /// Load the demo fixtures and exit.
+    Seed,
+    /// Write a warehouse snapshot to the directory and exit.
+    Export(String),
+    /// Give the member with this card number the admin role and exit.
+    MakeAdmin(String),
+}
+
+const USAGE: &str = "Usage: book-library-api [serve | migrate | seed | export <dir> | make-admin <card-number>]";
+
+impl Command {
+    fn parse(args: &[String]) -> Result<Command, String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [] | ["serve"] => Ok(Command::Serve),
+            ["migrate"] => Ok(Command::Migrate),
+            ["migrate", "--revert"] => Err(
+                "Migrations are forward-only and can't be reverted; restore a database backup instead".to_string(),
+            ),
+            ["seed"] => Ok(Command::Seed),
+            ["export", dir] => Ok(Command::Export(dir.to_string())),
+            ["make-admin", card_number] => Ok(Command::MakeAdmin(card_number.to_string())),
+            _ => Err(USAGE.to_string()),
+        }
+    }
+}
+
+/// Runs the `book-library-api` binary: the server, or one of the
+/// maintenance commands in `Command`.
+pub async fn run() {
+    dotenvy::dotenv().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = Command::parse(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+
+    let config = Config::from_env().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    });
+
+    let pool = config.database.connect().await.unwrap_or_else(|e| {
+        eprintln!(
+            "Could not connect to the database at {}: {}",
+            config.database.redacted_url(),
+            e
+        );
+        std::process::exit(1);
+    });
+
+    let migrator = sqlx::migrate!("./migrations");
+    if let Err(e) = migrator.run(&pool).await {
+        eprintln!("Failed to run database migrations: {}", e);
+        std::process::exit(1);
+    }
+
+    if command == Command::Migrate {
+        println!("Database schema is up to date ({} migrations)", migrator.iter().count());
+        return;
+    }
+
+    if let Command::Export(dir) = &command {
+        let Some(key) = &config.warehouse_key else {
+            eprintln!("Export failed: WAREHOUSE_MEMBER_KEY must be set");
+            std::process::exit(1);
+        };
+        match warehouse::export(&pool, std::path::Path::new(dir), chrono::Utc::now().date_naive(), key).await {
+            Ok(summary) => println!(
+                "Exported {} books, {} loans, and {} events to {}",
+                summary.books, summary.loans, summary.events, dir
+            ),
+            Err(e) => {
+                eprintln!("Export failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Command::MakeAdmin(card_number) = &command {
+        match members::make_admin(&pool, card_number).await {
+            Ok(member) => println!("{} (card {}) is now an admin", member.name, member.card_number),
+            Err(e) => {
+                let message = match e {
+                    AppError::Database(e) => e.to_string(),
+                    _ => format!("no member has card number {}", card_number),
+                };
+                eprintln!("Could not make an admin: {}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if command == Command::Seed {
+        match seed::load_fixtures(&pool).await {
+            Ok(summary) => println!(
+                "Loaded {} books, {} members, and {} loans",
+                summary.books_created, summary.members_created, summary.borrowings_created
+            ),
+            Err(e) => {
+                let message = match e {
+                    AppError::Conflict(message) => message,
+                    AppError::Database(e) => e.to_string(),
+                    _ => "the fixtures are invalid".to_string(),
+                };
+                eprintln!("Could not load fixtures: {}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if config.catalog.demo_mode {
+        match seed::load_fixtures(&pool).await {
+            Ok(summary) => println!("Demo mode: loaded {} sample books", summary.books_created),
+            // The catalog already has data, e.g. from an earlier start.
+            Err(AppError::Conflict(_)) => {}
+            Err(_) => {
+                eprintln!("Demo mode: could not load the sample catalog");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match config.catalog.check(&pool).await {
+        Ok(true) => {}
+        Ok(false) => {
+            eprintln!(
+                "CATALOG_LOCALE {:?} has no ICU collation on the database server",
+                config.catalog.locale
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Could not check the catalog collation: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(sip2) = config.sip2 {
+        let listener = tokio::net::TcpListener::bind(sip2.addr).await.unwrap_or_else(|e| {
+            eprintln!("Could not listen for SIP2 on {}: {}", sip2.addr, e);
+            std::process::exit(1);
+        });
+        println!(" SIP2 listening on {}", sip2.addr);
+        tokio::spawn(sip2::serve(listener, pool.clone(), Arc::new(sip2)));
+    }
+
+    if let Some(smtp) = config.smtp {
+        println!(" Sending email through {}:{}", smtp.host, smtp.port);
+        tokio::spawn(mailer::run(pool.clone(), Arc::new(smtp)));
+    }
+
+    // Shared by the integrations that talk to HTTP APIs.
+    let http: Arc<http_client::LiveClient> = Arc::new(http_client::LiveClient::new(std::time::Duration::from_secs(15)));
+
+    if let Some(sms) = config.sms {
+        println!(" Sending SMS through {}", sms.api_url);
+        tokio::spawn(sms::run(pool.clone(), Arc::new(sms::HttpSmsSender::new(sms, http.clone()))));
+    }
+
+    if let Some(push) = config.push {
+        println!(" Sending push notifications through{}{}", if push.fcm.is_some() { " FCM" } else { "" }, if push.apns.is_some() { " APNs" } else { "" });
+        tokio::spawn(push::run(pool.clone(), Arc::new(push::PushSender::new(push, http.clone()))));
+    }
+
+    // Chat webhooks are configured at runtime, so the worker always runs.
+    tokio::spawn(chat::run(pool.clone(), http));
+
+    tokio::spawn(holds::run(pool.clone()));
+
+    let app = build_router(AppState { pool, auth: config.auth, catalog: config.catalog, cache: Default::default() });
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+        .await
+        .unwrap();
+
+    println!("\n Server running on http://localhost:3000");
+
+    // Client addresses feed login throttling.
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+#[cfg(test)]
+mod tests;
\ No newline at end of file
@@ -0,0 +1,251 @@
+//! The email worker. With `SMTP_HOST` set, the server drains the email
+//! messages in the notification outbox itself: it claims them through
+//! `queue` like any outside worker, sends each through lettre's SMTP
+//! transport (STARTTLS or implicit TLS, `AUTH PLAIN`), and reports the outcome,
+//! which lands in the delivery log. Temporary failures (4xx replies,
+//! network trouble) are retried on the queue's schedule; a 5xx reply such
+//! as an unknown mailbox gives up on the message at once.
+
+use std::{error::Error as _, fmt, sync::Arc, time::Duration};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Utc};
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+    address::Envelope,
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        client::{Tls, TlsParameters},
+        extension::ClientId,
+    },
+};
+use rand::RngCore;
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    notifications::{Notification, NotificationChannel},
+    queue,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+const BATCH: i64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Connect in plain text and upgrade with STARTTLS (port 587).
+    StartTls,
+    /// TLS from the first byte (port 465).
+    Tls,
+    /// No encryption, for a relay on the same host or network.
+    None,
+}
+
+/// Set from `SMTP_HOST` and friends; without `SMTP_HOST` email stays in
+/// the outbox for an outside worker.
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The envelope sender and `From` address.
+    pub from: String,
+    /// Display name in `From`, and the signature under each message.
+    pub from_name: String,
+}
+
+impl fmt::Debug for SmtpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("security", &self.security)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("from", &self.from)
+            .field("from_name", &self.from_name)
+            .finish()
+    }
+}
+
+/// Why a message wasn't accepted.
+#[derive(Debug)]
+pub struct SmtpError {
+    /// The server refused it for good (a 5xx reply), or it can't be sent
+    /// as addressed.
+    pub permanent: bool,
+    pub message: String,
+}
+
+impl SmtpError {
+    fn transient(message: impl Into<String>) -> Self {
+        SmtpError { permanent: false, message: message.into() }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SmtpError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        // A refusal is logged as the server's reply, e.g. "550 5.1.1 No
+        // such mailbox"; anything else as lettre describes it.
+        let message = match (e.status(), e.source()) {
+            (Some(code), Some(reply)) => format!("{} {}", code, reply),
+            _ => e.to_string(),
+        };
+        SmtpError { permanent: e.is_permanent(), message }
+    }
+}
+
+/// Sends due email every `POLL_INTERVAL`, forever.
+pub async fn run(pool: PgPool, config: Arc<SmtpConfig>) {
+    loop {
+        match deliver_due(&pool, &config).await {
+            Ok(_) => {}
+            Err(AppError::Database(e)) => eprintln!("Mailer: {}", e),
+            Err(_) => eprintln!("Mailer: could not record a delivery"),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Claims the email messages that are due, sends them, and reports each
+/// outcome. Returns how many were delivered.
+pub async fn deliver_due(pool: &PgPool, config: &SmtpConfig) -> Result<usize, AppError> {
+    let mut delivered = 0;
+    loop {
+        let mut conn = pool.acquire().await?;
+        let batch = queue::claim_due(&mut conn, Some(NotificationChannel::Email), BATCH).await?;
+        drop(conn);
+        if batch.is_empty() {
+            return Ok(delivered);
+        }
+        for notification in batch {
+            let outcome = match tokio::time::timeout(SEND_TIMEOUT, send(config, &notification)).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(SmtpError::transient(format!("no answer from {} within {:?}", config.host, SEND_TIMEOUT))),
+            };
+            let mut tx = pool.begin().await?;
+            match outcome {
+                Ok(()) => {
+                    queue::record_delivered(&mut tx, notification.id).await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    queue::record_failure(&mut tx, notification.id, &e.message, e.permanent).await?;
+                }
+            }
+            tx.commit().await?;
+        }
+    }
+}
+
+/// The message as sent: headers, the queued body in quoted-printable, and
+/// the library's signature. Category notices (not account messages) also
+/// say where to change notification preferences.
+pub fn compose(config: &SmtpConfig, notification: &Notification, date: DateTime<Utc>) -> String {
+    let mut unique = [0u8; 8];
+    rand::rng().fill_bytes(&mut unique);
+    let domain = config.from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+
+    let mut text = notification.body.trim_end().to_string();
+    text.push_str("\n\n-- \n");
+    text.push_str(&config.from_name);
+    if notification.category.is_some() {
+        text.push_str("\nChoose which notices you get and how under notification preferences in your account.");
+    }
+    text.push('\n');
+
+    let headers = [
+        ("From", format!("{} <{}>", encode_header(&config.from_name), config.from)),
+        ("To", format!("<{}>", notification.recipient)),
+        ("Subject", encode_header(&notification.subject)),
+        ("Date", date.to_rfc2822()),
+        ("Message-ID", format!("<notification-{}.{}@{}>", notification.id, hex::encode(unique), domain)),
+        ("MIME-Version", "1.0".to_string()),
+        ("Content-Type", "text/plain; charset=utf-8".to_string()),
+        ("Content-Transfer-Encoding", "quoted-printable".to_string()),
+    ];
+    let mut message: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    message.push_str("\r\n");
+    message.push_str(&quoted_printable(&text));
+    message
+}
+
+async fn send(config: &SmtpConfig, notification: &Notification) -> Result<(), SmtpError> {
+    if !is_plain_address(&notification.recipient) {
+        return Err(SmtpError { permanent: true, message: format!("{:?} is not an email address", notification.recipient) });
+    }
+    let envelope = match (config.from.parse(), notification.recipient.parse()) {
+        (Ok(from), Ok(to)) => Envelope::new(Some(from), vec![to]).map_err(|e| SmtpError::transient(e.to_string()))?,
+        (Err(e), _) => return Err(SmtpError::transient(format!("SMTP_FROM: {}", e))),
+        (_, Err(e)) => return Err(SmtpError { permanent: true, message: format!("{:?}: {}", notification.recipient, e) }),
+    };
+    let message = compose(config, notification, Utc::now());
+    transport(config)?.send_raw(&envelope, message.as_bytes()).await?;
+    Ok(())
+}
+
+/// A transport for one message; without the `pool` feature every send
+/// opens its own connection.
+fn transport(config: &SmtpConfig) -> Result<AsyncSmtpTransport<Tokio1Executor>, SmtpError> {
+    let tls = match config.security {
+        SmtpSecurity::None => Tls::None,
+        SmtpSecurity::StartTls => Tls::Required(TlsParameters::new(config.host.clone())?),
+        SmtpSecurity::Tls => Tls::Wrapper(TlsParameters::new(config.host.clone())?),
+    };
+    let domain = config.from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+    let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+        .port(config.port)
+        .tls(tls)
+        .hello_name(ClientId::Domain(domain.to_string()))
+        .authentication(vec![Mechanism::Plain])
+        .timeout(Some(SEND_TIMEOUT));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
+
+/// An address that can go between `<` and `>` in an SMTP command and a
+/// header as is.
+fn is_plain_address(address: &str) -> bool {
+    address.split_once('@').is_some_and(|(local, domain)| !local.is_empty() && !domain.is_empty())
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '"' | ','))
+}
+
+/// A header value, as an RFC 2047 encoded word when it isn't plain ASCII.
+fn encode_header(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", BASE64.encode(value.replace(['\r', '\n'], " ")))
+    }
+}
+
+/// Quoted-printable (RFC 2045) with CRLF line endings and lines of at most
+/// 76 characters.
+fn quoted_printable(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / 8);
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let mut width = 0;
+        let bytes = line.as_bytes();
+        for (i, &byte) in bytes.iter().enumerate() {
+            let last = i + 1 == bytes.len();
+            let literal = matches!(byte, b'!'..=b'<' | b'>'..=b'~') || (matches!(byte, b' ' | b'\t') && !last);
+            let encoded = if literal { (byte as char).to_string() } else { format!("={:02X}", byte) };
+            if width + encoded.len() > 75 {
+                out.push_str("=\r\n");
+                width = 0;
+            }
+            width += encoded.len();
+            out.push_str(&encoded);
+        }
+        out.push_str("\r\n");
+    }
+    // `split` leaves an empty last piece after the final newline.
+    out.truncate(out.len() - 2);
+    out
+}
@@ -0,0 +1,103 @@
+//! Maintenance mode: while data migrations or restores run, everything but
+//! the admin routes and `/health` answers 503 with a `Retry-After`. The
+//! switch is a database row, so it covers every instance at once.
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{AppError, api_keys::AdminAccess};
+
+const DEFAULT_MESSAGE: &str = "The library is down for maintenance";
+const DEFAULT_RETRY_AFTER_SECS: i32 = 300;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Maintenance {
+    pub message: String,
+    pub retry_after_secs: i32,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenanceStatus {
+    pub active: bool,
+    #[serde(flatten)]
+    pub maintenance: Option<Maintenance>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StartMaintenance {
+    pub message: Option<String>,
+    pub retry_after_secs: Option<i32>,
+}
+
+async fn current(pool: &PgPool) -> Result<Option<Maintenance>, sqlx::Error> {
+    sqlx::query_as!(Maintenance, "SELECT message, retry_after_secs, started_at FROM maintenance")
+        .fetch_optional(pool)
+        .await
+}
+
+fn exempt(path: &str) -> bool {
+    path == "/health" || path == "/admin" || path.starts_with("/admin/")
+}
+
+pub async fn gate(State(pool): State<PgPool>, request: Request, next: Next) -> Response {
+    if !exempt(request.uri().path()) {
+        match current(&pool).await {
+            Ok(None) => {}
+            Ok(Some(maintenance)) => {
+                return AppError::Unavailable(maintenance.message, maintenance.retry_after_secs.into()).into_response();
+            }
+            Err(e) => return AppError::Database(e).into_response(),
+        }
+    }
+    next.run(request).await
+}
+
+pub async fn get_maintenance(State(pool): State<PgPool>, _access: AdminAccess) -> Result<Json<MaintenanceStatus>, AppError> {
+    let maintenance = current(&pool).await?;
+    Ok(Json(MaintenanceStatus { active: maintenance.is_some(), maintenance }))
+}
+
+/// Turns maintenance mode on, or updates the message of the running window
+/// without moving its start.
+pub async fn start_maintenance(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    input: Option<Json<StartMaintenance>>,
+) -> Result<Json<MaintenanceStatus>, AppError> {
+    let input = input.map(|Json(input)| input).unwrap_or_default();
+    let message = input.message.map(|m| m.trim().to_string()).unwrap_or_else(|| DEFAULT_MESSAGE.to_string());
+    if message.is_empty() {
+        return Err(AppError::InvalidInput("message must not be empty".to_string()));
+    }
+    let retry_after_secs = input.retry_after_secs.unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+    if retry_after_secs < 1 {
+        return Err(AppError::InvalidInput("retry_after_secs must be a positive number of seconds".to_string()));
+    }
+
+    let maintenance = sqlx::query_as!(
+        Maintenance,
+        "INSERT INTO maintenance (message, retry_after_secs) VALUES ($1, $2)
+         ON CONFLICT (singleton) DO UPDATE SET message = EXCLUDED.message, retry_after_secs = EXCLUDED.retry_after_secs
+         RETURNING message, retry_after_secs, started_at",
+        message,
+        retry_after_secs,
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(MaintenanceStatus { active: true, maintenance: Some(maintenance) }))
+}
+
+pub async fn end_maintenance(State(pool): State<PgPool>, _access: AdminAccess) -> Result<StatusCode, AppError> {
+    sqlx::query!("DELETE FROM maintenance").execute(&pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
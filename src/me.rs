@@ -0,0 +1,104 @@
+//! Patron self-service: everything under `/me` is scoped to the member the
+//! bearer token belongs to.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    auth::{self, AuthMember},
+    fines::{self, FineSummary},
+    holds::{self, Hold},
+    query::Query,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LoanParams {
+    /// Include returned loans as well as current ones.
+    history: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberLoan {
+    pub id: i64,
+    pub book_id: i64,
+    pub copy_id: Option<i64>,
+    pub title: String,
+    pub author: String,
+    pub borrowed_at: DateTime<Utc>,
+    pub due_date: DateTime<Utc>,
+    pub returned_at: Option<DateTime<Utc>>,
+    pub overdue: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaceHold {
+    book_id: i64,
+}
+
+pub async fn my_loans(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Query(params): Query<LoanParams>,
+) -> Result<Json<Vec<MemberLoan>>, AppError> {
+    let loans = sqlx::query_as!(
+        MemberLoan,
+        r#"SELECT br.id, br.book_id, br.copy_id, b.title, b.author,
+                  br.borrowed_at, br.due_date, br.returned_at,
+                  (br.returned_at IS NULL AND br.due_date < $3) AS "overdue!"
+           FROM borrowings br JOIN books b ON b.id = br.book_id
+           WHERE br.member_id = $1 AND ($2 OR br.returned_at IS NULL)
+           ORDER BY br.borrowed_at DESC, br.id DESC"#,
+        member.id,
+        params.history.unwrap_or(false),
+        Utc::now(),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(loans))
+}
+
+pub async fn my_holds(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+) -> Result<Json<Vec<Hold>>, AppError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(holds::for_member(&mut conn, member.id).await?))
+}
+
+pub async fn place_hold(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Json(input): Json<PlaceHold>,
+) -> Result<(StatusCode, Json<Hold>), AppError> {
+    auth::require_verified_email(&member)?;
+    let mut conn = pool.acquire().await?;
+    let hold = holds::place(&mut conn, member.id, input.book_id).await?;
+    Ok((StatusCode::CREATED, Json(hold)))
+}
+
+pub async fn cancel_hold(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<Json<Hold>, AppError> {
+    let mut tx = pool.begin().await?;
+    let hold = holds::cancel(&mut tx, member.id, id).await?;
+    tx.commit().await?;
+    Ok(Json(hold))
+}
+
+pub async fn my_fines(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+) -> Result<Json<FineSummary>, AppError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(fines::summary_for_member(&mut conn, member.id).await?))
+}
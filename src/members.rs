@@ -0,0 +1,280 @@
+use std::{fmt, str::FromStr};
+
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError, age_rating,
+    api_keys::AdminAccess,
+    audit::{self, AuditEvent},
+    auth::{self, AuthMember, ClientIp},
+};
+
+/// Patrons borrow; staff and admins also run the library. Roles gate
+/// staff-only features such as two-factor enrollment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberRole {
+    Patron,
+    Staff,
+    Admin,
+}
+
+impl MemberRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MemberRole::Patron => "patron",
+            MemberRole::Staff => "staff",
+            MemberRole::Admin => "admin",
+        }
+    }
+
+    pub fn is_staff(self) -> bool {
+        matches!(self, MemberRole::Staff | MemberRole::Admin)
+    }
+}
+
+impl fmt::Display for MemberRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MemberRole {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "patron" => Ok(MemberRole::Patron),
+            "staff" => Ok(MemberRole::Staff),
+            "admin" => Ok(MemberRole::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A registered library patron. `card_number` is allocated on registration
+/// and printed on the library card the circulation desk scans.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Member {
+    pub id: i64,
+    pub card_number: String,
+    pub name: String,
+    pub email: Option<String>,
+    /// Set once the member confirms `email` with the token mailed to them.
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub role: MemberRole,
+    /// Checked against books' age ratings at checkout.
+    pub birthdate: Option<NaiveDate>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct MemberRow {
+    pub id: i64,
+    pub card_number: String,
+    pub name: String,
+    pub email: Option<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub role: String,
+    pub birthdate: Option<NaiveDate>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<MemberRow> for Member {
+    fn from(r: MemberRow) -> Self {
+        Member {
+            id: r.id,
+            card_number: r.card_number,
+            name: r.name,
+            email: r.email,
+            email_verified_at: r.email_verified_at,
+            role: r.role.parse().unwrap_or(MemberRole::Patron),
+            birthdate: r.birthdate,
+            created_at: r.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMember {
+    name: String,
+    email: Option<String>,
+    /// Lets the member sign in to the self-service endpoints.
+    password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRole {
+    role: MemberRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetBirthdate {
+    birthdate: Option<NaiveDate>,
+}
+
+pub async fn add_member(
+    State(pool): State<PgPool>,
+    Json(input): Json<AddMember>,
+) -> Result<(StatusCode, Json<Member>), AppError> {
+    if input.name.trim().is_empty() {
+        return Err(AppError::InvalidInput("Member name must not be empty".to_string()));
+    }
+    if input.email.as_deref().is_some_and(|e| !e.contains('@')) {
+        return Err(AppError::InvalidInput("Member email must be a valid email address".to_string()));
+    }
+    let password_hash = input.password.as_deref().map(auth::hash_password).transpose()?;
+
+    let mut tx = pool.begin().await?;
+    let member: Member = sqlx::query_as!(
+        MemberRow,
+        "INSERT INTO members (name, email, password_hash, created_at) VALUES ($1, $2, $3, $4)
+         ON CONFLICT ((LOWER(email))) DO NOTHING
+         RETURNING id, card_number, name, email, email_verified_at, role, birthdate, created_at",
+        input.name,
+        input.email,
+        password_hash,
+        Utc::now(),
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::Conflict("A member with this email address already exists".to_string()))?
+    .into();
+
+    if member.email.is_some() {
+        auth::send_verification(&mut tx, &member).await?;
+    }
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(member)))
+}
+
+pub async fn list_members(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+) -> Result<Json<Vec<Member>>, AppError> {
+    let members = sqlx::query_as!(
+        MemberRow,
+        "SELECT id, card_number, name, email, email_verified_at, role, birthdate, created_at FROM members ORDER BY id"
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(members.into_iter().map(Member::from).collect()))
+}
+
+/// For the member themselves (signed in) or an admin.
+pub async fn get_member(
+    State(pool): State<PgPool>,
+    member: Result<AuthMember, AppError>,
+    admin: Result<AdminAccess, AppError>,
+    Path(id): Path<i64>,
+) -> Result<Json<Member>, AppError> {
+    match (member, admin) {
+        (_, Ok(_)) => {}
+        (Ok(AuthMember(member)), Err(_)) if member.id == id => {}
+        (Ok(_), Err(_)) => return Err(AppError::Forbidden("Members can only see their own record".to_string())),
+        (Err(_), Err(e)) => return Err(e),
+    }
+    let member = sqlx::query_as!(
+        MemberRow,
+        "SELECT id, card_number, name, email, email_verified_at, role, birthdate, created_at FROM members WHERE id = $1",
+        id
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Member", id))?;
+
+    Ok(Json(member.into()))
+}
+
+/// Admins only, since an admin role opens every admin endpoint.
+pub async fn set_member_role(
+    State(pool): State<PgPool>,
+    access: AdminAccess,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+    Json(input): Json<SetRole>,
+) -> Result<Json<Member>, AppError> {
+    let mut tx = pool.begin().await?;
+    let member: Member = sqlx::query_as!(
+        MemberRow,
+        "UPDATE members SET role = $1 WHERE id = $2
+         RETURNING id, card_number, name, email, email_verified_at, role, birthdate, created_at",
+        input.role.as_str(),
+        id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Member", id))?
+    .into();
+
+    let detail = format!("role set to {} by {}", member.role, access.describe());
+    audit::record(&mut tx, AuditEvent::RoleChanged, Some(id), ip.as_deref(), &detail).await?;
+    tx.commit().await?;
+
+    Ok(Json(member))
+}
+
+/// Makes the member with this card an admin, from the command line, so a
+/// new installation can get its first admin.
+pub async fn make_admin(pool: &PgPool, card_number: &str) -> Result<Member, AppError> {
+    let mut tx = pool.begin().await?;
+    let member: Member = sqlx::query_as!(
+        MemberRow,
+        "UPDATE members SET role = $1 WHERE card_number = $2
+         RETURNING id, card_number, name, email, email_verified_at, role, birthdate, created_at",
+        MemberRole::Admin.as_str(),
+        card_number,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::ResourceNotFoundBy("Member", "card number", card_number.to_string()))?
+    .into();
+
+    let detail = format!("role set to {} from the command line", member.role);
+    audit::record(&mut tx, AuditEvent::RoleChanged, Some(member.id), None, &detail).await?;
+    tx.commit().await?;
+
+    Ok(member)
+}
+
+/// Records or corrects a member's birthdate; `null` removes it. Staff only,
+/// since the birthdate decides which age-rated titles a member may borrow.
+pub async fn set_member_birthdate(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<SetBirthdate>,
+) -> Result<Json<Member>, AppError> {
+    if !staff.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can set a member's birthdate".to_string()));
+    }
+    age_rating::validate_birthdate(input.birthdate)?;
+    let member = sqlx::query_as!(
+        MemberRow,
+        "UPDATE members SET birthdate = $1 WHERE id = $2
+         RETURNING id, card_number, name, email, email_verified_at, role, birthdate, created_at",
+        input.birthdate,
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Member", id))?;
+
+    Ok(Json(member.into()))
+}
+
+pub async fn find_by_card(conn: &mut PgConnection, card_number: &str) -> Result<Member, AppError> {
+    sqlx::query_as!(
+        MemberRow,
+        "SELECT id, card_number, name, email, email_verified_at, role, birthdate, created_at FROM members WHERE card_number = $1",
+        card_number
+    )
+        .fetch_optional(conn)
+        .await?
+        .map(Member::from)
+        .ok_or_else(|| AppError::ResourceNotFoundBy("Member", "card number", card_number.to_string()))
+}
@@ -0,0 +1,433 @@
+//! Outgoing messages to members. Notifications are written to an outbox
+//! table in the same transaction as the change that triggers them; a
+//! delivery worker sends pending rows over their channel and reports back
+//! through `queue`, which stamps `sent_at` or schedules a retry.
+//!
+//! Account messages (verification, password reset) always go by email.
+//! Everything else belongs to a category and follows the member's
+//! preferences.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    api_keys::AdminAccess,
+    auth::AuthMember,
+    templates::{self, Template},
+};
+
+/// Loans due within this window get a "due soon" reminder.
+const DUE_SOON_DAYS: i64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    Email,
+    Sms,
+    Webhook,
+    /// The library's mobile app, on every device the member registered.
+    Push,
+    None,
+}
+
+impl NotificationChannel {
+    fn as_str(self) -> &'static str {
+        match self {
+            NotificationChannel::Email => "email",
+            NotificationChannel::Sms => "sms",
+            NotificationChannel::Webhook => "webhook",
+            NotificationChannel::Push => "push",
+            NotificationChannel::None => "none",
+        }
+    }
+}
+
+impl fmt::Display for NotificationChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NotificationChannel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(NotificationChannel::Email),
+            "sms" => Ok(NotificationChannel::Sms),
+            "webhook" => Ok(NotificationChannel::Webhook),
+            "push" => Ok(NotificationChannel::Push),
+            "none" => Ok(NotificationChannel::None),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationCategory {
+    DueSoon,
+    Overdue,
+    HoldReady,
+    NewArrivals,
+}
+
+impl NotificationCategory {
+    const ALL: [NotificationCategory; 4] = [
+        NotificationCategory::DueSoon,
+        NotificationCategory::Overdue,
+        NotificationCategory::HoldReady,
+        NotificationCategory::NewArrivals,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            NotificationCategory::DueSoon => "due_soon",
+            NotificationCategory::Overdue => "overdue",
+            NotificationCategory::HoldReady => "hold_ready",
+            NotificationCategory::NewArrivals => "new_arrivals",
+        }
+    }
+}
+
+impl fmt::Display for NotificationCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NotificationCategory {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "due_soon" => Ok(NotificationCategory::DueSoon),
+            "overdue" => Ok(NotificationCategory::Overdue),
+            "hold_ready" => Ok(NotificationCategory::HoldReady),
+            "new_arrivals" => Ok(NotificationCategory::NewArrivals),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: i64,
+    pub member_id: i64,
+    pub channel: String,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    /// `None` for account messages.
+    pub category: Option<String>,
+    pub borrowing_id: Option<i64>,
+    /// Failed delivery attempts so far, and the latest one's error.
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// Set when delivery was given up after too many attempts.
+    pub failed_at: Option<DateTime<Utc>>,
+}
+
+impl Notification {
+    /// Account messages carry a one-time code; only the delivery worker
+    /// needs it, so it is blanked before a message is shown anywhere else.
+    pub fn without_code(mut self) -> Self {
+        if self.category.is_none() {
+            self.body = templates::hide_code(&self.body);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub channel: NotificationChannel,
+    pub categories: Vec<NotificationCategory>,
+    pub phone: Option<String>,
+    pub webhook_url: Option<String>,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            channel: NotificationChannel::Email,
+            categories: NotificationCategory::ALL.to_vec(),
+            phone: None,
+            webhook_url: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReminderRun {
+    pub due_soon: usize,
+    pub overdue: usize,
+}
+
+struct PreferenceRow {
+    email: Option<String>,
+    channel: Option<String>,
+    categories: Option<Vec<String>>,
+    phone: Option<String>,
+    webhook_url: Option<String>,
+}
+
+impl PreferenceRow {
+    fn preferences(&self) -> NotificationPreferences {
+        let (Some(channel), Some(categories)) = (&self.channel, &self.categories) else {
+            return NotificationPreferences::default();
+        };
+        NotificationPreferences {
+            channel: channel.parse().unwrap_or(NotificationChannel::Email),
+            categories: categories.iter().filter_map(|c| c.parse().ok()).collect(),
+            phone: self.phone.clone(),
+            webhook_url: self.webhook_url.clone(),
+        }
+    }
+}
+
+async fn preference_row(conn: &mut PgConnection, member_id: i64) -> Result<PreferenceRow, AppError> {
+    sqlx::query_as!(
+        PreferenceRow,
+        r#"SELECT m.email, p.channel AS "channel?", p.categories AS "categories?", p.phone, p.webhook_url
+           FROM members m LEFT JOIN notification_preferences p ON p.member_id = m.id
+           WHERE m.id = $1"#,
+        member_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Member", member_id))
+}
+
+pub async fn preferences_for_member(
+    conn: &mut PgConnection,
+    member_id: i64,
+) -> Result<NotificationPreferences, AppError> {
+    Ok(preference_row(conn, member_id).await?.preferences())
+}
+
+pub async fn enqueue_email(
+    conn: &mut PgConnection,
+    member_id: i64,
+    recipient: &str,
+    subject: &str,
+    body: &str,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        "INSERT INTO notifications (member_id, channel, recipient, subject, body, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        member_id,
+        NotificationChannel::Email.as_str(),
+        recipient,
+        subject,
+        body,
+        Utc::now(),
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Queues a category notification over the member's chosen channel (once
+/// per registered device for push). Returns `false` if their preferences
+/// opt out of it or the channel has nowhere to deliver to.
+pub async fn notify(
+    conn: &mut PgConnection,
+    member_id: i64,
+    category: NotificationCategory,
+    borrowing_id: Option<i64>,
+    subject: &str,
+    body: &str,
+) -> Result<bool, AppError> {
+    let row = preference_row(&mut *conn, member_id).await?;
+    let preferences = row.preferences();
+    if !preferences.categories.contains(&category) {
+        return Ok(false);
+    }
+    let recipients: Vec<String> = match preferences.channel {
+        NotificationChannel::Email => row.email.into_iter().collect(),
+        NotificationChannel::Sms => preferences.phone.into_iter().collect(),
+        NotificationChannel::Webhook => preferences.webhook_url.into_iter().collect(),
+        NotificationChannel::Push => {
+            sqlx::query_scalar!("SELECT token FROM push_devices WHERE member_id = $1 ORDER BY id", member_id)
+                .fetch_all(&mut *conn)
+                .await?
+        }
+        NotificationChannel::None => Vec::new(),
+    };
+    if recipients.is_empty() {
+        return Ok(false);
+    }
+
+    for recipient in recipients {
+        sqlx::query!(
+            "INSERT INTO notifications (member_id, channel, recipient, subject, body, created_at, category, borrowing_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            member_id,
+            preferences.channel.as_str(),
+            recipient,
+            subject,
+            body,
+            Utc::now(),
+            category.as_str(),
+            borrowing_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(true)
+}
+
+/// Everything sent, or waiting to be sent, to a member, for the member
+/// themselves (signed in) or an admin. Codes in account messages are hidden.
+pub async fn list_member_notifications(
+    State(pool): State<PgPool>,
+    member: Result<AuthMember, AppError>,
+    admin: Result<AdminAccess, AppError>,
+    Path(member_id): Path<i64>,
+) -> Result<Json<Vec<Notification>>, AppError> {
+    match (member, admin) {
+        (_, Ok(_)) => {}
+        (Ok(AuthMember(member)), Err(_)) if member.id == member_id => {}
+        (Ok(_), Err(_)) => {
+            return Err(AppError::Forbidden("Members can only see their own notifications".to_string()));
+        }
+        (Err(_), Err(e)) => return Err(e),
+    }
+    let notifications = sqlx::query_as!(
+        Notification,
+        "SELECT * FROM notifications WHERE member_id = $1 ORDER BY created_at DESC, id DESC",
+        member_id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(notifications.into_iter().map(Notification::without_code).collect()))
+}
+
+pub async fn get_my_preferences(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+) -> Result<Json<NotificationPreferences>, AppError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(preferences_for_member(&mut conn, member.id).await?))
+}
+
+pub async fn update_my_preferences(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Json(mut input): Json<NotificationPreferences>,
+) -> Result<Json<NotificationPreferences>, AppError> {
+    match input.channel {
+        NotificationChannel::Email if member.email.is_none() => {
+            return Err(AppError::InvalidInput("Add an email address before choosing email notifications".to_string()));
+        }
+        NotificationChannel::Sms if input.phone.as_deref().is_none_or(|p| p.trim().is_empty()) => {
+            return Err(AppError::InvalidInput("phone is required for SMS notifications".to_string()));
+        }
+        NotificationChannel::Webhook
+            if !input
+                .webhook_url
+                .as_deref()
+                .is_some_and(|u| u.starts_with("https://") || u.starts_with("http://")) =>
+        {
+            return Err(AppError::InvalidInput("webhook_url must be an http(s) URL for webhook notifications".to_string()));
+        }
+        NotificationChannel::Push => {
+            let devices = sqlx::query_scalar!("SELECT count(*) FROM push_devices WHERE member_id = $1", member.id)
+                .fetch_one(&pool)
+                .await?;
+            if devices == Some(0) {
+                return Err(AppError::InvalidInput("Register a device under /me/devices before choosing push notifications".to_string()));
+            }
+        }
+        _ => {}
+    }
+    input.categories.sort_by_key(|c| c.as_str());
+    input.categories.dedup();
+    let categories: Vec<String> = input.categories.iter().map(|c| c.as_str().to_string()).collect();
+
+    sqlx::query!(
+        "INSERT INTO notification_preferences (member_id, channel, categories, phone, webhook_url, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (member_id) DO UPDATE SET
+             channel = EXCLUDED.channel, categories = EXCLUDED.categories, phone = EXCLUDED.phone,
+             webhook_url = EXCLUDED.webhook_url, updated_at = EXCLUDED.updated_at",
+        member.id,
+        input.channel.as_str(),
+        &categories,
+        input.phone,
+        input.webhook_url,
+        Utc::now(),
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(Json(input))
+}
+
+struct ReminderCandidate {
+    id: i64,
+    member_id: i64,
+    title: String,
+    due_date: DateTime<Utc>,
+}
+
+/// Queues "due soon" and "overdue" reminders for open loans that haven't
+/// had one yet. Meant to be called periodically by a scheduler, with an
+/// admin API key.
+pub async fn send_reminders(State(pool): State<PgPool>, _access: AdminAccess) -> Result<Json<ReminderRun>, AppError> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now();
+
+    let mut run = ReminderRun { due_soon: 0, overdue: 0 };
+    for category in [NotificationCategory::DueSoon, NotificationCategory::Overdue] {
+        let (from, to) = match category {
+            NotificationCategory::DueSoon => (Some(now), now + Duration::days(DUE_SOON_DAYS)),
+            _ => (None, now),
+        };
+        let loans = sqlx::query_as!(
+            ReminderCandidate,
+            r#"SELECT br.id, br.member_id AS "member_id!", b.title, br.due_date
+               FROM borrowings br JOIN books b ON b.id = br.book_id
+               WHERE br.returned_at IS NULL AND br.member_id IS NOT NULL
+                 AND ($1::timestamptz IS NULL OR br.due_date >= $1) AND br.due_date < $2
+                 AND NOT EXISTS (
+                     SELECT 1 FROM notifications n WHERE n.borrowing_id = br.id AND n.category = $3
+                 )
+               ORDER BY br.due_date, br.id"#,
+            from,
+            to,
+            category.as_str(),
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for loan in loans {
+            let message = match category {
+                NotificationCategory::DueSoon => Template::DueSoon { title: &loan.title, due_date: loan.due_date },
+                _ => Template::Overdue { title: &loan.title, due_date: loan.due_date },
+            };
+            if notify(&mut tx, loan.member_id, category, Some(loan.id), message.subject(), &message.body()).await? {
+                match category {
+                    NotificationCategory::DueSoon => run.due_soon += 1,
+                    _ => run.overdue += 1,
+                }
+            }
+        }
+    }
+    tx.commit().await?;
+
+    Ok(Json(run))
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginatedResponse<T> {
+    pub data: Vec<T>,
+    pub pagination: PaginationMeta,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginationMeta {
+    pub page: usize,
+    pub limit: usize,
+    pub total_items: usize,
+    pub total_pages: usize,
+}
+
+/// Page size when a list request doesn't give one.
+pub const DEFAULT_PAGE_LIMIT: usize = 10;
+
+/// Checks `page` and `limit`, applying the defaults, so a page always has
+/// a positive size and a representable offset.
+pub fn page_bounds(page: Option<usize>, limit: Option<usize>, max_limit: usize) -> Result<(usize, usize), AppError> {
+    let page = page.unwrap_or(1);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT.min(max_limit));
+    if page < 1 {
+        return Err(AppError::InvalidInput(format!("page must be at least 1, got {}", page)));
+    }
+    if !(1..=max_limit).contains(&limit) {
+        return Err(AppError::InvalidInput(format!("limit must be between 1 and {}, got {}", max_limit, limit)));
+    }
+    if (page - 1).checked_mul(limit).is_none_or(|offset| offset > i64::MAX as usize) {
+        return Err(AppError::InvalidInput(format!("page {} is out of range", page)));
+    }
+    Ok((page, limit))
+}
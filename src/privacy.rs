@@ -0,0 +1,256 @@
+//! Data-protection requests: subject-access exports of everything held about
+//! a member, and erasure of a member's personal data.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    api_keys::AdminAccess,
+    borrowings::Borrowing,
+    challenges,
+    audit::{self, AuditEntry, AuditEvent},
+    auth::{AuthMember, ClientIp},
+    fines::{self, Fine},
+    holds::{self, Hold},
+    ill::{self, IllRequest},
+    members::{Member, MemberRow},
+    notifications::{self, Notification, NotificationPreferences},
+    push::PushDevice,
+    terms::{self, TermsAcceptance},
+    throttle,
+};
+
+/// Name left on loans, requests, and donations after the member is erased.
+const ERASED_BORROWER: &str = "Erased member";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErasureSummary {
+    pub member_id: i64,
+    pub loans_anonymized: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Everything stored about one member. Secrets (password and token hashes,
+/// codes in account messages) are left out; sessions are listed by their
+/// dates only.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberExport {
+    pub exported_at: DateTime<Utc>,
+    pub member: Member,
+    pub loans: Vec<Borrowing>,
+    pub holds: Vec<Hold>,
+    pub fines: Vec<Fine>,
+    pub ill_requests: Vec<IllRequest>,
+    pub notifications: Vec<Notification>,
+    pub notification_preferences: NotificationPreferences,
+    pub push_devices: Vec<PushDevice>,
+    pub reading_goals: Vec<challenges::ReadingGoal>,
+    pub challenges: Vec<challenges::ChallengeProgress>,
+    pub sessions: Vec<SessionRecord>,
+    pub terms_accepted: Vec<TermsAcceptance>,
+    pub audit_log: Vec<AuditEntry>,
+}
+
+/// For the member themselves (signed in) or an admin.
+pub async fn export_member(
+    State(pool): State<PgPool>,
+    member: Result<AuthMember, AppError>,
+    admin: Result<AdminAccess, AppError>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    match (member, admin) {
+        (_, Ok(_)) => {}
+        (Ok(AuthMember(member)), Err(_)) if member.id == id => {}
+        (Ok(_), Err(_)) => return Err(AppError::Forbidden("Members can only export their own data".to_string())),
+        (Err(_), Err(e)) => return Err(e),
+    }
+    let mut tx = pool.begin().await?;
+
+    let member: Member = sqlx::query_as!(
+        MemberRow,
+        "SELECT id, card_number, name, email, email_verified_at, role, birthdate, created_at FROM members WHERE id = $1",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Member", id))?
+    .into();
+
+    let loans = sqlx::query_as!(
+        Borrowing,
+        "SELECT id, book_id, copy_id, member_id, borrower_name, borrowed_at, due_date, returned_at
+         FROM borrowings WHERE member_id = $1 ORDER BY borrowed_at, id",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let holds = holds::for_member(&mut tx, id).await?;
+    let fines = fines::summary_for_member(&mut tx, id).await?.fines;
+    let ill_requests = ill::for_member(&mut tx, id).await?;
+
+    let notifications = sqlx::query_as!(
+        Notification,
+        "SELECT * FROM notifications WHERE member_id = $1 ORDER BY created_at, id",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(Notification::without_code)
+    .collect();
+
+    let notification_preferences = notifications::preferences_for_member(&mut tx, id).await?;
+
+    let push_devices = sqlx::query_as!(
+        PushDevice,
+        "SELECT id, platform, token, created_at FROM push_devices WHERE member_id = $1 ORDER BY id",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let reading_goals = challenges::goals_for_member(&mut tx, id).await?;
+    let challenges = challenges::for_member(&mut tx, id).await?;
+
+    let sessions = sqlx::query_as!(
+        SessionRecord,
+        "SELECT created_at, expires_at FROM sessions WHERE member_id = $1 ORDER BY created_at",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let terms_accepted = terms::acceptances_for_member(&mut tx, id).await?;
+    let audit_log = audit::for_member(&mut tx, id).await?;
+
+    tx.commit().await?;
+
+    let export = MemberExport {
+        exported_at: Utc::now(),
+        member,
+        loans,
+        holds,
+        fines,
+        ill_requests,
+        notifications,
+        notification_preferences,
+        push_devices,
+        reading_goals,
+        challenges,
+        sessions,
+        terms_accepted,
+        audit_log,
+    };
+    let disposition = format!("attachment; filename=\"member-{}-export.json\"", id);
+
+    Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(export)))
+}
+
+/// Deletes a member and everything personal attached to them. Past loans,
+/// ILL requests, purchase suggestions, and donations are kept, detached
+/// from the member and without their name or email, so statistics still
+/// add up. Refused while the member has items out or fines to pay. Admins only;
+/// the audit entry is filed under the admin who did it.
+pub async fn erase_member(
+    State(pool): State<PgPool>,
+    access: AdminAccess,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+) -> Result<Json<ErasureSummary>, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let email = sqlx::query_scalar!("SELECT email FROM members WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Member", id))?;
+
+    let active_loans = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM borrowings WHERE member_id = $1 AND returned_at IS NULL"#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if active_loans > 0 {
+        return Err(AppError::Conflict(format!(
+            "Member {} still has {} item(s) on loan; check them in before erasing",
+            id, active_loans
+        )));
+    }
+
+    let outstanding = fines::summary_for_member(&mut tx, id).await?.outstanding_cents;
+    if outstanding > 0 {
+        return Err(AppError::Conflict(format!(
+            "Member {} has {} cents in unpaid fines; settle or waive them before erasing",
+            id, outstanding
+        )));
+    }
+
+    let loans_anonymized = sqlx::query!(
+        "UPDATE borrowings SET member_id = NULL, borrower_name = $1 WHERE member_id = $2",
+        ERASED_BORROWER,
+        id,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        "UPDATE ill_requests SET member_id = NULL, patron_name = $1 WHERE member_id = $2",
+        ERASED_BORROWER,
+        id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE acquisition_requests SET member_id = NULL, requested_by = $1 WHERE member_id = $2",
+        ERASED_BORROWER,
+        id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Donations recorded without the card are matched by email address.
+    sqlx::query!(
+        "UPDATE donations SET member_id = NULL, donor_name = $1, donor_email = NULL
+         WHERE member_id = $2 OR LOWER(donor_email) = LOWER($3)",
+        ERASED_BORROWER,
+        id,
+        email,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    throttle::clear(&mut tx, &throttle::member_key(id)).await?;
+
+    // The audit trail keeps the events but not who or where they came from.
+    sqlx::query!("UPDATE audit_log SET ip = NULL WHERE member_id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Sessions, tokens, holds, fines, and notifications cascade.
+    sqlx::query!("DELETE FROM members WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    let detail = format!("member {} erased by {}, {} loan(s) anonymized", id, access.describe(), loans_anonymized);
+    audit::record(&mut tx, AuditEvent::MemberErased, access.member_id(), ip.as_deref(), &detail).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(ErasureSummary { member_id: id, loans_anonymized }))
+}
@@ -0,0 +1,464 @@
+//! Push notifications to the library's mobile app. The app registers each
+//! install's device token under `/me/devices`; members who pick the `push`
+//! channel then get one queued message per device, which the push worker
+//! sends through Firebase Cloud Messaging (HTTP v1 API) or Apple Push
+//! Notification service, depending on the device. Both providers take a
+//! short-lived signed token instead of a fixed key: an OAuth access token
+//! for FCM, exchanged for a JWT signed with the service account's key, and
+//! an ES256 provider JWT for APNs. Devices a provider reports as gone are
+//! forgotten.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL},
+};
+use chrono::{DateTime, Utc};
+use ring::{
+    rand::SystemRandom,
+    signature::{self, EcdsaKeyPair, RsaKeyPair},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    auth::AuthMember,
+    http_client::{HttpClient, HttpRequest},
+    notifications::{Notification, NotificationChannel},
+    queue,
+};
+
+/// Push is meant to be immediate, so it is polled more often than email.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH: i64 = 50;
+const MAX_TOKEN_LEN: usize = 4096;
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+/// APNs accepts a provider token for an hour; renew a little sooner.
+const APNS_TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    Fcm,
+    Apns,
+}
+
+impl Platform {
+    fn as_str(self) -> &'static str {
+        match self {
+            Platform::Fcm => "fcm",
+            Platform::Apns => "apns",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushDevice {
+    pub id: i64,
+    pub platform: String,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterDevice {
+    platform: Platform,
+    token: String,
+}
+
+/// Registers this install of the app for push. A token already registered
+/// (say, to whoever used the phone before) moves to the caller.
+pub async fn register_device(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Json(input): Json<RegisterDevice>,
+) -> Result<(StatusCode, Json<PushDevice>), AppError> {
+    let token = input.token.trim();
+    if token.is_empty() || token.len() > MAX_TOKEN_LEN || token.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(AppError::InvalidInput("token must be the device token the push provider issued".to_string()));
+    }
+    let device = sqlx::query_as!(
+        PushDevice,
+        "INSERT INTO push_devices (member_id, platform, token, created_at) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (token) DO UPDATE SET
+             member_id = EXCLUDED.member_id, platform = EXCLUDED.platform, created_at = EXCLUDED.created_at
+         RETURNING id, platform, token, created_at",
+        member.id,
+        input.platform.as_str(),
+        token,
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(device)))
+}
+
+pub async fn my_devices(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+) -> Result<Json<Vec<PushDevice>>, AppError> {
+    let devices = sqlx::query_as!(
+        PushDevice,
+        "SELECT id, platform, token, created_at FROM push_devices WHERE member_id = $1 ORDER BY id",
+        member.id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(devices))
+}
+
+/// Unregisters a device, e.g. when the member signs out of the app.
+pub async fn remove_device(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let removed = sqlx::query!("DELETE FROM push_devices WHERE id = $1 AND member_id = $2", id, member.id)
+        .execute(&pool)
+        .await?;
+    if removed.rows_affected() == 0 {
+        return Err(AppError::ResourceNotFound("Device", id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set from `FCM_SERVICE_ACCOUNT` and `APNS_*`; either provider may be
+/// missing, and messages for its devices then fail.
+#[derive(Debug, Clone, Default)]
+pub struct PushConfig {
+    pub fcm: Option<FcmConfig>,
+    pub apns: Option<ApnsConfig>,
+}
+
+#[derive(Clone)]
+pub struct FcmConfig {
+    pub project_id: String,
+    pub client_email: String,
+    /// The service account's RSA key, PKCS#8 DER.
+    pub private_key: Vec<u8>,
+    pub token_uri: String,
+}
+
+impl fmt::Debug for FcmConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FcmConfig")
+            .field("project_id", &self.project_id)
+            .field("client_email", &self.client_email)
+            .field("private_key", &"***")
+            .field("token_uri", &self.token_uri)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: Option<String>,
+}
+
+impl FcmConfig {
+    /// Reads the JSON key file Firebase hands out for a service account.
+    pub fn from_service_account(json: &str) -> Option<FcmConfig> {
+        let account: ServiceAccount = serde_json::from_str(json).ok()?;
+        let private_key = pem_body(&account.private_key)?;
+        RsaKeyPair::from_pkcs8(&private_key).ok()?;
+        Some(FcmConfig {
+            project_id: account.project_id,
+            client_email: account.client_email,
+            private_key,
+            token_uri: account.token_uri.unwrap_or_else(|| "https://oauth2.googleapis.com/token".to_string()),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ApnsConfig {
+    pub key_id: String,
+    pub team_id: String,
+    /// The app's bundle ID.
+    pub topic: String,
+    /// The `.p8` signing key, PKCS#8 DER.
+    pub private_key: Vec<u8>,
+    /// Use Apple's development environment, for debug builds of the app.
+    pub sandbox: bool,
+}
+
+impl fmt::Debug for ApnsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApnsConfig")
+            .field("key_id", &self.key_id)
+            .field("team_id", &self.team_id)
+            .field("topic", &self.topic)
+            .field("private_key", &"***")
+            .field("sandbox", &self.sandbox)
+            .finish()
+    }
+}
+
+impl ApnsConfig {
+    /// Checks a `.p8` key's contents, returning the DER.
+    pub fn parse_key(pem: &str) -> Option<Vec<u8>> {
+        let der = pem_body(pem)?;
+        EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &der, &SystemRandom::new()).ok()?;
+        Some(der)
+    }
+}
+
+fn pem_body(pem: &str) -> Option<Vec<u8>> {
+    // Service account files carry the key with `\n` escapes already undone
+    // by the JSON parser, but env files often keep them literally.
+    let pem = pem.replace("\\n", "\n");
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("-----"))
+        .flat_map(|line| line.chars().filter(|c| !c.is_whitespace()))
+        .collect();
+    BASE64.decode(body).ok()
+}
+
+/// Why a push wasn't accepted.
+#[derive(Debug)]
+pub struct PushError {
+    pub permanent: bool,
+    /// The provider says the token is no longer valid.
+    pub unregistered: bool,
+    pub message: String,
+}
+
+impl PushError {
+    fn transient(message: impl Into<String>) -> Self {
+        PushError { permanent: false, unregistered: false, message: message.into() }
+    }
+
+    fn permanent(message: impl Into<String>) -> Self {
+        PushError { permanent: true, unregistered: false, message: message.into() }
+    }
+
+    fn unregistered(message: impl Into<String>) -> Self {
+        PushError { permanent: true, unregistered: true, message: message.into() }
+    }
+}
+
+/// Sends pushes through both providers, keeping their tokens between sends.
+pub struct PushSender {
+    config: PushConfig,
+    client: Arc<dyn HttpClient>,
+    rng: SystemRandom,
+    fcm_token: Mutex<Option<(String, Instant)>>,
+    apns_token: Mutex<Option<(String, Instant)>>,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl PushSender {
+    pub fn new(config: PushConfig, client: Arc<dyn HttpClient>) -> Self {
+        PushSender {
+            config,
+            client,
+            rng: SystemRandom::new(),
+            fcm_token: Mutex::new(None),
+            apns_token: Mutex::new(None),
+        }
+    }
+
+    pub async fn send(&self, platform: Platform, token: &str, title: &str, body: &str) -> Result<(), PushError> {
+        match platform {
+            Platform::Fcm => self.send_fcm(token, title, body).await,
+            Platform::Apns => self.send_apns(token, title, body).await,
+        }
+    }
+
+    fn jwt(&self, header: serde_json::Value, claims: serde_json::Value, sign: impl FnOnce(&[u8]) -> Option<Vec<u8>>) -> Result<String, PushError> {
+        let input = format!("{}.{}", BASE64_URL.encode(header.to_string()), BASE64_URL.encode(claims.to_string()));
+        let signature = sign(input.as_bytes()).ok_or_else(|| PushError::transient("could not sign the provider token"))?;
+        Ok(format!("{}.{}", input, BASE64_URL.encode(signature)))
+    }
+
+    async fn fcm_access_token(&self, config: &FcmConfig) -> Result<String, PushError> {
+        if let Some((token, expires)) = &*self.fcm_token.lock().unwrap()
+            && *expires > Instant::now()
+        {
+            return Ok(token.clone());
+        }
+        let now = Utc::now().timestamp();
+        let assertion = self.jwt(
+            json!({ "alg": "RS256", "typ": "JWT" }),
+            json!({ "iss": config.client_email, "scope": FCM_SCOPE, "aud": config.token_uri, "iat": now, "exp": now + 3600 }),
+            |input| {
+                let key = RsaKeyPair::from_pkcs8(&config.private_key).ok()?;
+                let mut signature = vec![0; key.public().modulus_len()];
+                key.sign(&signature::RSA_PKCS1_SHA256, &self.rng, input, &mut signature).ok()?;
+                Some(signature)
+            },
+        )?;
+        let request = HttpRequest::post_form(
+            &config.token_uri,
+            &[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)],
+        );
+        let response = self.client.send(request).await.map_err(|e| PushError::transient(e.to_string()))?;
+        let token: AccessToken = match response.is_success() {
+            true => serde_json::from_str(&response.body).map_err(|e| PushError::transient(format!("token response: {}", e)))?,
+            false => return Err(PushError::transient(format!("FCM sign-in failed with HTTP {}", response.status))),
+        };
+        let expires = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        *self.fcm_token.lock().unwrap() = Some((token.access_token.clone(), expires));
+        Ok(token.access_token)
+    }
+
+    async fn send_fcm(&self, token: &str, title: &str, body: &str) -> Result<(), PushError> {
+        let config = self.config.fcm.as_ref().ok_or_else(|| PushError::transient("FCM is not configured"))?;
+        let access_token = self.fcm_access_token(config).await?;
+        let url = format!("https://fcm.googleapis.com/v1/projects/{}/messages:send", config.project_id);
+        let message = json!({ "message": { "token": token, "notification": { "title": title, "body": body } } });
+        let request = HttpRequest::post_json(url, &message).header("authorization", format!("Bearer {}", access_token));
+        let response = self.client.send(request).await.map_err(|e| PushError::transient(e.to_string()))?;
+        if response.is_success() {
+            return Ok(());
+        }
+
+        let error: serde_json::Value = serde_json::from_str(&response.body).unwrap_or_default();
+        let error_code = error["error"]["details"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find_map(|detail| detail["errorCode"].as_str())
+            .unwrap_or_default();
+        let message = format!("FCM HTTP {}: {}", response.status, error["error"]["message"].as_str().unwrap_or(error_code));
+        match (response.status, error_code) {
+            (404, _) | (_, "UNREGISTERED") => Err(PushError::unregistered(message)),
+            (400, _) => Err(PushError::permanent(message)),
+            (401, _) => {
+                *self.fcm_token.lock().unwrap() = None;
+                Err(PushError::transient(message))
+            }
+            _ => Err(PushError::transient(message)),
+        }
+    }
+
+    fn apns_provider_token(&self, config: &ApnsConfig) -> Result<String, PushError> {
+        let mut cached = self.apns_token.lock().unwrap();
+        if let Some((token, expires)) = &*cached
+            && *expires > Instant::now()
+        {
+            return Ok(token.clone());
+        }
+        let token = self.jwt(
+            json!({ "alg": "ES256", "kid": config.key_id }),
+            json!({ "iss": config.team_id, "iat": Utc::now().timestamp() }),
+            |input| {
+                let key = EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &config.private_key, &self.rng).ok()?;
+                Some(key.sign(&self.rng, input).ok()?.as_ref().to_vec())
+            },
+        )?;
+        *cached = Some((token.clone(), Instant::now() + APNS_TOKEN_LIFETIME));
+        Ok(token)
+    }
+
+    async fn send_apns(&self, token: &str, title: &str, body: &str) -> Result<(), PushError> {
+        let config = self.config.apns.as_ref().ok_or_else(|| PushError::transient("APNs is not configured"))?;
+        if !token.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PushError::unregistered("APNs device tokens are hexadecimal"));
+        }
+        let provider_token = self.apns_provider_token(config)?;
+        let host = if config.sandbox { "api.sandbox.push.apple.com" } else { "api.push.apple.com" };
+        let payload = json!({ "aps": { "alert": { "title": title, "body": body }, "sound": "default" } });
+        let request = HttpRequest::post_json(format!("https://{}/3/device/{}", host, token), &payload)
+            .header("authorization", format!("bearer {}", provider_token))
+            .header("apns-topic", config.topic.clone())
+            .header("apns-push-type", "alert")
+            .header("apns-priority", "10");
+        let response = self.client.send(request).await.map_err(|e| PushError::transient(e.to_string()))?;
+        if response.is_success() {
+            return Ok(());
+        }
+
+        let reason: serde_json::Value = serde_json::from_str(&response.body).unwrap_or_default();
+        let reason = reason["reason"].as_str().unwrap_or_default();
+        let message = format!("APNs HTTP {}: {}", response.status, reason);
+        match (response.status, reason) {
+            (410, _) | (400, "BadDeviceToken") | (400, "DeviceTokenNotForTopic") => Err(PushError::unregistered(message)),
+            (400, _) => Err(PushError::permanent(message)),
+            (403, "ExpiredProviderToken") => {
+                *self.apns_token.lock().unwrap() = None;
+                Err(PushError::transient(message))
+            }
+            _ => Err(PushError::transient(message)),
+        }
+    }
+}
+
+/// Sends due pushes every `POLL_INTERVAL`, forever.
+pub async fn run(pool: PgPool, sender: Arc<PushSender>) {
+    loop {
+        match deliver_due(&pool, &sender).await {
+            Ok(_) => {}
+            Err(AppError::Database(e)) => eprintln!("Push: {}", e),
+            Err(_) => eprintln!("Push: could not record a delivery"),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Claims the push messages that are due, sends each to its device, and
+/// reports the outcome. Returns how many were delivered.
+pub async fn deliver_due(pool: &PgPool, sender: &PushSender) -> Result<usize, AppError> {
+    let mut delivered = 0;
+    loop {
+        let mut conn = pool.acquire().await?;
+        let batch = queue::claim_due(&mut conn, Some(NotificationChannel::Push), BATCH).await?;
+        drop(conn);
+        if batch.is_empty() {
+            return Ok(delivered);
+        }
+        for notification in batch {
+            let outcome = send_one(pool, sender, &notification).await?;
+            let mut tx = pool.begin().await?;
+            match outcome {
+                Ok(()) => {
+                    queue::record_delivered(&mut tx, notification.id).await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    if e.unregistered {
+                        sqlx::query!("DELETE FROM push_devices WHERE token = $1", notification.recipient)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                    queue::record_failure(&mut tx, notification.id, &e.message, e.permanent).await?;
+                }
+            }
+            tx.commit().await?;
+        }
+    }
+}
+
+async fn send_one(pool: &PgPool, sender: &PushSender, notification: &Notification) -> Result<Result<(), PushError>, AppError> {
+    let platform = sqlx::query_scalar!("SELECT platform FROM push_devices WHERE token = $1", notification.recipient)
+        .fetch_optional(pool)
+        .await?;
+    Ok(match platform.as_deref() {
+        Some("fcm") => sender.send(Platform::Fcm, &notification.recipient, &notification.subject, &notification.body).await,
+        Some("apns") => sender.send(Platform::Apns, &notification.recipient, &notification.subject, &notification.body).await,
+        _ => Err(PushError::permanent("the device is no longer registered")),
+    })
+}
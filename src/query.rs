@@ -0,0 +1,56 @@
+//! Query strings. Axum's `Query` answers a bad value with a terse plain-text
+//! 400; this one names the parameter, echoes the value, and says what would
+//! have been accepted.
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::AppError;
+
+/// Drop-in for `axum::extract::Query`.
+pub(crate) struct Query<T>(pub T);
+
+#[derive(Debug, Serialize)]
+pub struct ParameterError {
+    pub parameter: String,
+    pub value: Option<String>,
+    /// What the parameter takes, e.g. `a whole number` or `one of print,
+    /// ebook, audiobook`.
+    pub expected: String,
+}
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer).map(Query).map_err(|e| {
+            let parameter = e.path().to_string();
+            let value = form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| *key == parameter)
+                .map(|(_, value)| value.into_owned());
+            AppError::InvalidQuery(ParameterError { parameter, value, expected: expected(&e.into_inner().to_string()) })
+        })
+    }
+}
+
+/// Rewords serde's parse errors as the format the parameter takes.
+fn expected(error: &str) -> String {
+    if let Some((_, variants)) = error.split_once("expected one of ") {
+        return format!("one of {}", variants.replace('`', "").replace(", ", ","));
+    }
+    match error {
+        "invalid digit found in string" | "cannot parse integer from empty string" => "a whole number".to_string(),
+        "number too large to fit in target type" | "number too small to fit in target type" => {
+            "a whole number in range".to_string()
+        }
+        "invalid float literal" => "a number".to_string(),
+        "provided string was not `true` or `false`" => "true or false".to_string(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,145 @@
+//! Recent `GET /books` pages, kept in memory for public catalog pages that
+//! ask for the same lists over and over. Entries are tagged with the
+//! catalog generation, a sequence the database bumps on every change to the
+//! books, so a write from any instance invalidates them all.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{Json, body::Bytes, extract::State};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, PgConnection};
+
+use crate::{AppError, api_keys::AdminAccess};
+
+/// Distinct queries kept per generation. Past this, new queries are served
+/// uncached until the next change clears the cache.
+const MAX_ENTRIES: usize = 1000;
+
+/// Shared by every request the server handles; cloning shares it.
+#[derive(Clone)]
+pub struct QueryCache {
+    /// `None` for a cache that keeps nothing; see [`QueryCache::disabled`].
+    inner: Option<Arc<Inner>>,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        QueryCache { inner: Some(Arc::default()) }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    generation: i64,
+    bodies: HashMap<String, Bytes>,
+}
+
+impl Entries {
+    /// Drops everything cached before `generation`. Returns whether the
+    /// entries are for `generation`, i.e. whether the caller's view is not
+    /// older than the cache's.
+    fn advance(&mut self, generation: i64) -> bool {
+        if generation > self.generation {
+            self.generation = generation;
+            self.bodies.clear();
+        }
+        generation == self.generation
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+pub struct Generation {
+    pub number: i64,
+    /// False while a change that already bumped the generation has yet to
+    /// commit: a result read now may miss it, so it mustn't be cached.
+    pub cacheable: bool,
+}
+
+/// The catalog generation as of now. Read it before running the query
+/// whose result gets cached, so the result is never older than its tag.
+pub async fn generation(conn: &mut PgConnection) -> Result<Generation, AppError> {
+    let number = sqlx::query_scalar!(
+        r#"SELECT CASE WHEN is_called THEN last_value ELSE 0 END AS "generation!" FROM catalog_generation"#
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    // Writers hold the lock shared until they commit; see the migration.
+    let mut tx = conn.begin().await?;
+    let cacheable = sqlx::query_scalar!("SELECT pg_try_advisory_xact_lock(hashtext('catalog_generation'))")
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap_or(false);
+    tx.commit().await?;
+    Ok(Generation { number, cacheable })
+}
+
+impl QueryCache {
+    /// A cache that never stores or serves anything, for requests whose
+    /// reads may not be committed, such as those in an atomic batch.
+    pub fn disabled() -> Self {
+        QueryCache { inner: None }
+    }
+
+    /// Whether results should be looked up and stored at all. Callers skip
+    /// reading the generation when not, since that takes a lock.
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn get(&self, generation: i64, key: &str) -> Option<Bytes> {
+        let inner = self.inner.as_ref()?;
+        let body = {
+            let mut entries = inner.entries.lock().unwrap_or_else(PoisonError::into_inner);
+            if entries.advance(generation) { entries.bodies.get(key).cloned() } else { None }
+        };
+        let counter = if body.is_some() { &inner.hits } else { &inner.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        body
+    }
+
+    pub fn insert(&self, generation: i64, key: String, body: Bytes) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let mut entries = inner.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.advance(generation) && (entries.bodies.len() < MAX_ENTRIES || entries.bodies.contains_key(&key)) {
+            entries.bodies.insert(key, body);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let Some(inner) = &self.inner else {
+            return CacheStats { hits: 0, misses: 0, entries: 0 };
+        };
+        let entries = inner.entries.lock().unwrap_or_else(PoisonError::into_inner).bodies.len();
+        CacheStats {
+            hits: inner.hits.load(Ordering::Relaxed),
+            misses: inner.misses.load(Ordering::Relaxed),
+            entries,
+        }
+    }
+}
+
+/// Hit and miss counts since the server started, for `GET /admin/cache`.
+pub async fn cache_stats(State(cache): State<QueryCache>, _access: AdminAccess) -> Json<CacheStats> {
+    Json(cache.stats())
+}
@@ -0,0 +1,304 @@
+//! The delivery side of the notification outbox. Workers that talk to the
+//! outside world (the mailer, the SMS gateway, webhook senders) claim due
+//! messages here and report each outcome. Failures are retried with
+//! exponential backoff; since the schedule lives in the table, it survives
+//! restarts of both the API and the workers. Every report is also kept in
+//! a delivery log. With SMTP configured, the server's own `mailer` is the
+//! email worker.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    api_keys::AdminAccess,
+    notifications::{Notification, NotificationChannel},
+    query::Query,
+};
+
+/// Attempts before a message is marked failed and left for staff.
+pub const MAX_ATTEMPTS: i32 = 8;
+/// How long a claimed message is reserved for the worker that claimed it.
+/// A worker that dies mid-send releases it when this runs out.
+const CLAIM_LEASE_MINUTES: i64 = 5;
+const DEFAULT_CLAIM: i64 = 50;
+const MAX_ITEMS: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueStatus {
+    /// Waiting for a first attempt or a retry.
+    Pending,
+    /// Given up after `MAX_ATTEMPTS`.
+    Failed,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueueParams {
+    status: Option<QueueStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimParams {
+    limit: Option<i64>,
+    /// Only messages for this channel, e.g. `sms` for the SMS gateway.
+    channel: Option<NotificationChannel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryFailure {
+    error: String,
+}
+
+/// One delivery attempt, from the worker's report.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeliveryAttempt {
+    pub attempted_at: DateTime<Utc>,
+    pub delivered: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueueOverview {
+    pub pending: i64,
+    pub failed: i64,
+    /// Oldest first, at most 500.
+    pub items: Vec<Notification>,
+}
+
+/// Wait before the next attempt after `attempts` failures: one minute,
+/// doubling each time, never more than six hours.
+pub fn retry_delay(attempts: i32) -> Duration {
+    let doublings = attempts.clamp(1, 20) - 1;
+    (Duration::minutes(1) * 2i32.pow(doublings as u32)).min(Duration::hours(6))
+}
+
+/// Undelivered messages, both still retrying and given up, or just one
+/// kind with `?status=`.
+pub async fn list_queue(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Query(params): Query<QueueParams>,
+) -> Result<Json<QueueOverview>, AppError> {
+    let counts = sqlx::query!(
+        r#"SELECT COUNT(*) FILTER (WHERE failed_at IS NULL) AS "pending!",
+                  COUNT(*) FILTER (WHERE failed_at IS NOT NULL) AS "failed!"
+           FROM notifications WHERE sent_at IS NULL"#
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let failed = params.status.map(|s| s == QueueStatus::Failed);
+    let items = sqlx::query_as!(
+        Notification,
+        "SELECT * FROM notifications
+         WHERE sent_at IS NULL AND ($1::boolean IS NULL OR (failed_at IS NOT NULL) = $1)
+         ORDER BY next_attempt_at, id
+         LIMIT $2",
+        failed,
+        MAX_ITEMS,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(QueueOverview { pending: counts.pending, failed: counts.failed, items }))
+}
+
+/// Hands a worker the messages that are due, reserving them so other
+/// workers polling at the same time get different ones.
+pub async fn claim(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Query(params): Query<ClaimParams>,
+) -> Result<Json<Vec<Notification>>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_CLAIM);
+    if !(1..=MAX_ITEMS).contains(&limit) {
+        return Err(AppError::InvalidInput(format!("limit must be between 1 and {}", MAX_ITEMS)));
+    }
+    let mut conn = pool.acquire().await?;
+    Ok(Json(claim_due(&mut conn, params.channel, limit).await?))
+}
+
+pub async fn claim_due(
+    conn: &mut PgConnection,
+    channel: Option<NotificationChannel>,
+    limit: i64,
+) -> Result<Vec<Notification>, AppError> {
+    let now = Utc::now();
+    let claimed = sqlx::query_as!(
+        Notification,
+        "UPDATE notifications SET next_attempt_at = $2
+         WHERE id IN (
+             SELECT id FROM notifications
+             WHERE sent_at IS NULL AND failed_at IS NULL AND next_attempt_at <= $1
+               AND ($4::text IS NULL OR channel = $4)
+             ORDER BY next_attempt_at, id
+             LIMIT $3
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *",
+        now,
+        now + Duration::minutes(CLAIM_LEASE_MINUTES),
+        limit,
+        channel.map(|c| c.to_string()),
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(claimed)
+}
+
+pub async fn mark_delivered(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<Json<Notification>, AppError> {
+    let mut tx = pool.begin().await?;
+    let notification = record_delivered(&mut tx, id).await?;
+    tx.commit().await?;
+
+    Ok(Json(notification))
+}
+
+pub async fn record_delivered(conn: &mut PgConnection, id: i64) -> Result<Notification, AppError> {
+    undelivered(&mut *conn, id).await?;
+    let now = Utc::now();
+    log_attempt(&mut *conn, id, None).await?;
+    let notification = sqlx::query_as!(
+        Notification,
+        "UPDATE notifications SET sent_at = $2, failed_at = NULL WHERE id = $1 RETURNING *",
+        id,
+        now,
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(notification)
+}
+
+/// Records a failed attempt and schedules the next one, or gives up after
+/// `MAX_ATTEMPTS`.
+pub async fn mark_failed(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+    Json(input): Json<DeliveryFailure>,
+) -> Result<Json<Notification>, AppError> {
+    let error = input.error.trim();
+    if error.is_empty() {
+        return Err(AppError::InvalidInput("error must not be empty".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+    let notification = record_failure(&mut tx, id, error, false).await?;
+    tx.commit().await?;
+
+    Ok(Json(notification))
+}
+
+/// Like `mark_failed`; a `permanent` failure (a mailbox that doesn't
+/// exist) gives up at once instead of retrying.
+pub async fn record_failure(
+    conn: &mut PgConnection,
+    id: i64,
+    error: &str,
+    permanent: bool,
+) -> Result<Notification, AppError> {
+    let current = undelivered(&mut *conn, id).await?;
+    if current.failed_at.is_some() {
+        return Err(AppError::Conflict(format!("Notification {} has already been given up on", id)));
+    }
+    log_attempt(&mut *conn, id, Some(error)).await?;
+    let attempts = current.attempts + 1;
+    let now = Utc::now();
+    let failed_at = (permanent || attempts >= MAX_ATTEMPTS).then_some(now);
+    let notification = sqlx::query_as!(
+        Notification,
+        "UPDATE notifications SET attempts = $2, last_error = $3, next_attempt_at = $4, failed_at = $5
+         WHERE id = $1 RETURNING *",
+        id,
+        attempts,
+        error,
+        now + retry_delay(attempts),
+        failed_at,
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(notification)
+}
+
+async fn log_attempt(conn: &mut PgConnection, id: i64, error: Option<&str>) -> Result<(), AppError> {
+    sqlx::query!(
+        "INSERT INTO notification_deliveries (notification_id, attempted_at, delivered, error) VALUES ($1, $2, $3, $4)",
+        id,
+        Utc::now(),
+        error.is_none(),
+        error,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Every reported attempt at a message, oldest first, with the error each
+/// failure gave.
+pub async fn delivery_log(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<DeliveryAttempt>>, AppError> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query_scalar!("SELECT id FROM notifications WHERE id = $1", id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Notification", id))?;
+    let attempts = sqlx::query_as!(
+        DeliveryAttempt,
+        "SELECT attempted_at, delivered, error FROM notification_deliveries WHERE notification_id = $1 ORDER BY id",
+        id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Json(attempts))
+}
+
+/// Puts a message that was given up on back in the queue with a fresh set
+/// of attempts, e.g. once a member has fixed their webhook.
+pub async fn retry(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Path(id): Path<i64>,
+) -> Result<Json<Notification>, AppError> {
+    let mut tx = pool.begin().await?;
+    let current = undelivered(&mut tx, id).await?;
+    if current.failed_at.is_none() {
+        return Err(AppError::Conflict(format!("Notification {} is still being retried", id)));
+    }
+    let notification = sqlx::query_as!(
+        Notification,
+        "UPDATE notifications SET attempts = 0, next_attempt_at = $2, failed_at = NULL WHERE id = $1 RETURNING *",
+        id,
+        Utc::now(),
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(notification))
+}
+
+/// Locks the message for an update, refusing ones already delivered.
+async fn undelivered(conn: &mut PgConnection, id: i64) -> Result<Notification, AppError> {
+    let notification = sqlx::query_as!(Notification, "SELECT * FROM notifications WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Notification", id))?;
+    if notification.sent_at.is_some() {
+        return Err(AppError::Conflict(format!("Notification {} has already been delivered", id)));
+    }
+    Ok(notification)
+}
@@ -0,0 +1,267 @@
+//! Loan slips for the circulation desk's receipt printer: one roll-width
+//! page, as long as the list of items needs.
+
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    api_keys::CirculationAccess,
+    auth::AuthMember,
+    config::{BranchInfo, CatalogConfig},
+    labels::{GLYPH_WIDTH, text},
+};
+
+/// 80 mm thermal paper. Measurements are in PDF points.
+const PAGE_WIDTH: f32 = 226.8;
+const MARGIN: f32 = 12.0;
+const TEXT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+/// Line height as a multiple of the font size.
+const LEADING: f32 = 1.35;
+
+const MAX_LOANS: usize = 50;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+#[derive(Debug, Deserialize)]
+pub struct SessionReceipt {
+    /// Loans made together at the desk, in the order they were scanned.
+    loan_ids: Vec<i64>,
+}
+
+struct ReceiptLoan {
+    id: i64,
+    title: String,
+    author: String,
+    barcode: Option<String>,
+    member_id: Option<i64>,
+    borrower_name: String,
+    card_number: Option<String>,
+    borrowed_at: DateTime<Utc>,
+    due_date: DateTime<Utc>,
+    returned_at: Option<DateTime<Utc>>,
+}
+
+/// Receipts are for the desk (staff, or a key with the `circulation`
+/// scope) or for the borrower reprinting their own; the member to hold the
+/// loans to, if any.
+fn receipt_reader(
+    member: Result<AuthMember, AppError>,
+    desk: Result<CirculationAccess, AppError>,
+) -> Result<Option<i64>, AppError> {
+    match (member, desk) {
+        (_, Ok(CirculationAccess)) => Ok(None),
+        (Ok(AuthMember(member)), Err(_)) => Ok(Some(member.id)),
+        (Err(_), Err(e)) => Err(e),
+    }
+}
+
+fn require_own(borrower: Option<i64>, loans: &[ReceiptLoan]) -> Result<(), AppError> {
+    match borrower {
+        Some(id) if loans.iter().any(|l| l.member_id != Some(id)) => {
+            Err(AppError::Forbidden("Members can only print receipts for their own loans".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A slip for one loan.
+pub async fn loan_receipt(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    member: Result<AuthMember, AppError>,
+    desk: Result<CirculationAccess, AppError>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let borrower = receipt_reader(member, desk)?;
+    let loans = fetch_loans(&pool, &[id]).await?;
+    require_own(borrower, &loans)?;
+    Ok(pdf_response(render(&catalog.branch, &loans), format!("loan-{}-receipt.pdf", id)))
+}
+
+/// One slip listing every item checked out in a desk session. The loans
+/// must all be to the same borrower.
+pub async fn session_receipt(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    member: Result<AuthMember, AppError>,
+    desk: Result<CirculationAccess, AppError>,
+    Json(input): Json<SessionReceipt>,
+) -> Result<impl IntoResponse, AppError> {
+    let borrower = receipt_reader(member, desk)?;
+    if input.loan_ids.is_empty() || input.loan_ids.len() > MAX_LOANS {
+        return Err(AppError::InvalidInput(format!("loan_ids must contain between 1 and {} loans", MAX_LOANS)));
+    }
+    let loans = fetch_loans(&pool, &input.loan_ids).await?;
+    require_own(borrower, &loans)?;
+    if loans.iter().any(|l| l.borrower_name != loans[0].borrower_name || l.card_number != loans[0].card_number) {
+        return Err(AppError::InvalidInput("loan_ids must all be loans to the same borrower".to_string()));
+    }
+    Ok(pdf_response(render(&catalog.branch, &loans), "checkout-receipt.pdf".to_string()))
+}
+
+async fn fetch_loans(pool: &PgPool, ids: &[i64]) -> Result<Vec<ReceiptLoan>, AppError> {
+    let rows = sqlx::query_as!(
+        ReceiptLoan,
+        r#"SELECT br.id, b.title, b.author, c.barcode AS "barcode?", br.member_id, br.borrower_name,
+                  m.card_number AS "card_number?", br.borrowed_at, br.due_date, br.returned_at
+           FROM borrowings br
+           JOIN books b ON b.id = br.book_id
+           LEFT JOIN copies c ON c.id = br.copy_id
+           LEFT JOIN members m ON m.id = br.member_id
+           WHERE br.id = ANY($1)"#,
+        ids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_id: HashMap<i64, ReceiptLoan> = rows.into_iter().map(|r| (r.id, r)).collect();
+    let mut loans = Vec::with_capacity(ids.len());
+    for id in ids {
+        // A loan listed twice is printed once.
+        if loans.iter().any(|l: &ReceiptLoan| l.id == *id) {
+            continue;
+        }
+        loans.push(by_id.remove(id).ok_or(AppError::ResourceNotFound("Loan", *id))?);
+    }
+    Ok(loans)
+}
+
+fn pdf_response(pdf: Vec<u8>, filename: String) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", filename)),
+        ],
+        pdf,
+    )
+}
+
+/// Cards are printed with all but the last four digits hidden, since
+/// receipts get left behind.
+fn masked_card(card_number: &str) -> String {
+    let visible = card_number.len().saturating_sub(4);
+    format!("{}{}", "*".repeat(visible.min(4)), &card_number[visible..])
+}
+
+/// Splits `value` into lines that fit the roll at `size`.
+fn wrap(value: &str, size: f32) -> Vec<String> {
+    let max_chars = (TEXT_WIDTH / (size * GLYPH_WIDTH)) as usize;
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in value.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Lines of text top to bottom, each with its font and size.
+#[derive(Default)]
+struct Slip(Vec<(Name<'static>, f32, String)>);
+
+impl Slip {
+    fn line(&mut self, font: Name<'static>, size: f32, value: &str) {
+        for line in wrap(value, size) {
+            self.0.push((font, size, line));
+        }
+    }
+
+    fn gap(&mut self) {
+        self.0.push((REGULAR, 6.0, String::new()));
+    }
+}
+
+fn render(branch: &BranchInfo, loans: &[ReceiptLoan]) -> Vec<u8> {
+    let mut slip = Slip::default();
+    slip.line(BOLD, 11.0, &branch.name);
+    for line in &branch.address {
+        slip.line(REGULAR, 8.0, line);
+    }
+    if let Some(phone) = &branch.phone {
+        slip.line(REGULAR, 8.0, &format!("Tel. {}", phone));
+    }
+    slip.gap();
+
+    let borrowed_at = loans.iter().map(|l| l.borrowed_at).min().unwrap_or_else(Utc::now);
+    let borrower = match &loans[0].card_number {
+        Some(card) => format!("{} (card {})", loans[0].borrower_name, masked_card(card)),
+        None => loans[0].borrower_name.clone(),
+    };
+    slip.line(BOLD, 10.0, "Checkout receipt");
+    slip.line(REGULAR, 8.0, &borrowed_at.format("%e %b %Y %H:%M UTC").to_string());
+    slip.line(REGULAR, 8.0, &borrower);
+    for loan in loans {
+        slip.gap();
+        slip.line(BOLD, 9.0, &loan.title);
+        slip.line(REGULAR, 8.0, &loan.author);
+        if let Some(barcode) = &loan.barcode {
+            slip.line(REGULAR, 8.0, &format!("Item {}", barcode));
+        }
+        match loan.returned_at {
+            Some(returned_at) => slip.line(BOLD, 9.0, &format!("Returned {}", returned_at.format("%a %e %b %Y"))),
+            None => slip.line(BOLD, 9.0, &format!("Due {}", loan.due_date.format("%a %e %b %Y"))),
+        }
+    }
+    slip.gap();
+    let count = if loans.len() == 1 { "1 item".to_string() } else { format!("{} items", loans.len()) };
+    slip.line(REGULAR, 8.0, &count);
+    let lines = slip.0;
+
+    // Blank lines still take up their height.
+    let height: f32 = 2.0 * MARGIN + lines.iter().map(|(_, size, _)| size * LEADING).sum::<f32>();
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let regular_id = Ref::new(3);
+    let bold_id = Ref::new(4);
+    let page_id = Ref::new(5);
+    let content_id = Ref::new(6);
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids([page_id]).count(1);
+    pdf.type1_font(regular_id)
+        .base_font(Name(b"Helvetica"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.type1_font(bold_id)
+        .base_font(Name(b"Helvetica-Bold"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    let mut page = pdf.page(page_id);
+    page.parent(page_tree_id)
+        .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, height))
+        .contents(content_id);
+    page.resources().fonts().pair(REGULAR, regular_id).pair(BOLD, bold_id);
+    page.finish();
+
+    let mut content = Content::new();
+    let mut y = height - MARGIN;
+    for (font, size, line) in &lines {
+        y -= size * LEADING;
+        if !line.is_empty() {
+            text(&mut content, *font, *size, MARGIN, y + size * (LEADING - 1.0), line);
+        }
+    }
+    pdf.stream(content_id, &content.finish());
+
+    pdf.finish()
+}
@@ -0,0 +1,296 @@
+use std::convert::Infallible;
+
+use axum::{
+    Router,
+    extract::{DefaultBodyLimit, FromRef, Request},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{Route, delete, get, post, put},
+};
+use sqlx::PgPool;
+use tower::{Layer, Service};
+use tower_http::catch_panic::CatchPanicLayer;
+
+use crate::{
+    acquisitions, admin_ui, api_keys, audit, auth, backup, batch, books, borrowings, budgets, card, challenges, chat, circulation, content_type,
+    copies, demo, description, donations, ebooks, excerpts, facets, features, ill, labels, legacy, maintenance, me,
+    members, notifications, privacy, push, query_cache, queue, receipts, saml, seed, shelf, sru, terms, toc, translations, two_factor, vendors, weeding,
+    config::{AuthConfig, CatalogConfig, Config},
+    query_cache::QueryCache,
+};
+
+/// Shared state for every handler. Handlers that only need the database can
+/// keep extracting `State<PgPool>`.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub auth: AuthConfig,
+    pub catalog: CatalogConfig,
+    /// Recent book list pages; see `query_cache`.
+    pub cache: QueryCache,
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for AuthConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.auth.clone()
+    }
+}
+
+impl FromRef<AppState> for CatalogConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.catalog.clone()
+    }
+}
+
+impl FromRef<AppState> for QueryCache {
+    fn from_ref(state: &AppState) -> Self {
+        state.cache.clone()
+    }
+}
+
+/// The API under `/v1`, and again at the unversioned paths with
+/// deprecation headers.
+pub fn build_router(state: AppState) -> Router {
+    let api = routes(state.clone());
+    Router::new()
+        .nest(legacy::PREFIX, api.clone())
+        .merge(api.layer(middleware::from_fn_with_state(state, legacy::mark_legacy)))
+}
+
+/// Entry point for applications that host the API inside their own server:
+///
+/// ```ignore
+/// let library = LibraryApi::builder()
+///     .config(Config::from_env()?)
+///     .layer(TraceLayer::new_for_http())
+///     .build(pool);
+/// let app = Router::new().nest("/library", library).merge(host_routes);
+/// ```
+pub struct LibraryApi;
+
+impl LibraryApi {
+    pub fn builder() -> LibraryApiBuilder {
+        LibraryApiBuilder { auth: AuthConfig::default(), catalog: CatalogConfig::default(), layers: Vec::new() }
+    }
+}
+
+type RouterLayer = Box<dyn FnOnce(Router) -> Router + Send>;
+
+pub struct LibraryApiBuilder {
+    auth: AuthConfig,
+    catalog: CatalogConfig,
+    layers: Vec<RouterLayer>,
+}
+
+impl LibraryApiBuilder {
+    /// Takes the auth and catalog settings from a loaded config. The
+    /// database settings are the host's business; it passes the pool to
+    /// `build`.
+    pub fn config(self, config: Config) -> Self {
+        LibraryApiBuilder { auth: config.auth, catalog: config.catalog, ..self }
+    }
+
+    pub fn auth(self, auth: AuthConfig) -> Self {
+        LibraryApiBuilder { auth, ..self }
+    }
+
+    pub fn catalog(self, catalog: CatalogConfig) -> Self {
+        LibraryApiBuilder { catalog, ..self }
+    }
+
+    /// Wraps every route, versioned and not, in a tower layer. Layers added
+    /// later run first, as with `Router::layer`.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers.push(Box::new(move |router: Router| router.layer(layer)));
+        self
+    }
+
+    /// Storage isn't pluggable: handlers query Postgres through `pool`
+    /// directly, so the host supplies a pool on a database migrated with
+    /// this crate's migrations.
+    pub fn build(self, pool: PgPool) -> Router {
+        let router = build_router(AppState { pool, auth: self.auth, catalog: self.catalog, cache: QueryCache::default() });
+        self.layers.into_iter().fold(router, |router, layer| layer(router))
+    }
+}
+
+fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/batch", post(batch::run_batch))
+        .route("/books", get(books::list_books).post(books::add_book))
+        .route("/books/count", get(books::books_count))
+        .route("/books/facets", get(facets::book_facets))
+        .route("/books/random", get(books::random_book))
+        .route("/books/stream", get(books::stream_books))
+        .route("/books/by-slug/{slug}", get(books::get_book_by_slug))
+        .route("/books/isbn/{isbn}", get(books::get_book_by_isbn))
+        .route("/books/{id}", get(books::get_book).put(books::update_book).delete(books::delete_book))
+        .route("/books/{id}/restore", post(books::restore_book))
+        .route("/books/{id}/card", get(card::book_card))
+        .route("/books/{id}/description.html", get(description::description_html))
+        .route("/books/{id}/toc", get(toc::get_toc).put(toc::put_toc))
+        .route("/files/{id}", delete(ebooks::delete_file))
+        .route("/files/{id}/link", post(ebooks::create_download_link))
+        .route("/files/{id}/download", get(ebooks::download_file))
+        .route(
+            "/books/{id}/excerpt",
+            get(excerpts::get_excerpt).put(excerpts::put_excerpt).delete(excerpts::delete_excerpt),
+        )
+        .route("/books/{id}/translations", get(translations::list_translations))
+        .route(
+            "/books/{id}/translations/{language}",
+            put(translations::put_translation).delete(translations::delete_translation),
+        )
+        .route("/books/{id}/borrow", post(borrowings::borrow_book))
+        .route("/books/{id}/return", post(borrowings::return_book))
+        .route("/borrowings/overdue", get(borrowings::list_overdue))
+        .route("/loans/{id}/receipt.pdf", get(receipts::loan_receipt))
+        .route("/loans/receipt.pdf", post(receipts::session_receipt))
+        .route("/sru", get(sru::sru))
+        .route("/books/{id}/copies", get(copies::list_book_copies).post(copies::add_book_copies))
+        .route("/copies/{id}", get(copies::get_copy).put(copies::update_copy))
+        .route("/copies/{id}/condition-history", get(copies::condition_history))
+        .route("/copies/{id}/barcode.png", get(copies::copy_barcode_png))
+        .route("/copies/{id}/qr.png", get(copies::copy_qr_png))
+        .route("/labels/print", post(labels::print_labels))
+        .route("/shelf-order", get(shelf::shelf_order))
+        .route("/ill", get(ill::list_ill_requests).post(ill::create_ill_request))
+        .route("/ill/{id}", get(ill::get_ill_request).put(ill::update_ill_request))
+        .route("/acquisitions/requests", get(acquisitions::list_acquisitions).post(acquisitions::suggest_purchase))
+        .route("/acquisitions/requests/{id}", get(acquisitions::get_acquisition).put(acquisitions::update_acquisition))
+        .route("/donations", get(donations::list_donations).post(donations::record_donation))
+        .route("/donations/acknowledgments", get(donations::donor_acknowledgments))
+        .route("/donations/{id}", get(donations::get_donation))
+        .route("/donations/{id}/accept", post(donations::accept_donation))
+        .route("/donations/{id}/decline", post(donations::decline_donation))
+        .route("/vendors", get(vendors::list_vendors).post(vendors::add_vendor))
+        .route("/vendors/{id}", get(vendors::get_vendor).put(vendors::update_vendor))
+        .route("/vendors/{id}/orders", get(vendors::list_vendor_orders))
+        .route("/members", get(members::list_members).post(members::add_member))
+        .route("/members/{id}", get(members::get_member))
+        .route("/members/{id}/role", put(members::set_member_role))
+        .route("/members/{id}/birthdate", put(members::set_member_birthdate))
+        .route("/members/{id}/notifications", get(notifications::list_member_notifications))
+        .route("/notifications/reminders", post(notifications::send_reminders))
+        .route("/members/{id}/export", get(privacy::export_member))
+        .route("/members/{id}/erase", post(privacy::erase_member))
+        .route("/circulation/scan", post(circulation::scan))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/logout", post(auth::logout))
+        .route("/auth/saml/metadata", get(saml::metadata))
+        .route("/auth/saml/login", get(saml::start_login))
+        .route("/auth/verify", post(auth::verify_email))
+        .route("/auth/verify/resend", post(auth::resend_verification))
+        .route("/auth/forgot-password", post(auth::forgot_password))
+        .route("/auth/reset-password", post(auth::reset_password))
+        .route("/me/loans", get(me::my_loans))
+        .route("/me/holds", get(me::my_holds).post(me::place_hold))
+        .route("/me/holds/{id}/cancel", post(me::cancel_hold))
+        .route("/me/fines", get(me::my_fines))
+        .route("/me/devices", get(push::my_devices).post(push::register_device))
+        .route("/me/devices/{id}", delete(push::remove_device))
+        .route("/me/reading-goal", get(challenges::my_reading_goal).put(challenges::set_my_reading_goal))
+        .route("/me/challenges", get(challenges::my_challenges))
+        .route("/me/challenges/{id}", put(challenges::join_challenge).delete(challenges::leave_challenge))
+        .route("/me/terms", get(terms::my_terms))
+        .route("/me/2fa/enroll", post(two_factor::enroll))
+        .route("/me/2fa/qr.png", get(two_factor::enrollment_qr))
+        .route("/me/2fa/confirm", post(two_factor::confirm_enrollment))
+        .route(
+            "/me/notification-preferences",
+            get(notifications::get_my_preferences).put(notifications::update_my_preferences),
+        )
+        .route("/me/terms/accept", post(terms::accept_terms))
+        .route("/challenges", get(challenges::list_challenges).post(challenges::create_challenge))
+        .route("/challenges/{id}", get(challenges::get_challenge))
+        .route("/challenges/{id}/leaderboard", get(challenges::challenge_leaderboard))
+        .route("/terms", post(terms::publish_terms))
+        .route("/terms/current", get(terms::get_current_terms))
+        .route("/budgets", get(budgets::list_budgets).post(budgets::add_budget))
+        .route("/budgets/{id}", get(budgets::get_budget).put(budgets::update_budget))
+        .route("/weeding/scan", post(weeding::scan_for_candidates))
+        .route("/weeding/candidates", get(weeding::list_candidates))
+        .route("/weeding/candidates/{id}", get(weeding::get_candidate).put(weeding::review_candidate))
+        .route("/weeding/candidates/{id}/discard", post(weeding::discard_candidate))
+        .route("/weeding/report", get(weeding::weeding_report))
+        .route("/admin/api-keys", get(api_keys::list_api_keys).post(api_keys::create_api_key))
+        .route("/admin/api-keys/{id}", put(api_keys::update_api_key))
+        .route("/admin/api-keys/{id}/rotate", post(api_keys::rotate_api_key))
+        .route("/admin/api-keys/{id}/revoke", post(api_keys::revoke_api_key))
+        .route("/admin/audit", get(audit::list_audit_log))
+        .route("/admin/chat-webhooks", get(chat::list_chat_webhooks).post(chat::create_chat_webhook))
+        .route("/admin/chat-webhooks/{id}", put(chat::update_chat_webhook).delete(chat::delete_chat_webhook))
+        .route("/admin/chat-webhooks/{id}/messages", get(chat::list_chat_messages))
+        .route("/admin/cache", get(query_cache::cache_stats))
+        .route("/admin/features", get(features::list_features))
+        .route(
+            "/admin/maintenance",
+            get(maintenance::get_maintenance)
+                .post(maintenance::start_maintenance)
+                .delete(maintenance::end_maintenance),
+        )
+        .route("/admin/features/{name}", put(features::set_feature).delete(features::clear_feature))
+        .route("/admin/ui", get(admin_ui::index))
+        .route("/admin/ui/", get(admin_ui::index))
+        .route("/admin/ui/{*path}", get(admin_ui::asset))
+        .route("/admin/queue", get(queue::list_queue))
+        .route("/admin/queue/claim", post(queue::claim))
+        .route("/admin/queue/{id}/delivered", post(queue::mark_delivered))
+        .route("/admin/queue/{id}/failed", post(queue::mark_failed))
+        .route("/admin/queue/{id}/log", get(queue::delivery_log))
+        .route("/admin/queue/{id}/retry", post(queue::retry))
+        .route("/admin/seed", post(seed::seed_data))
+        .route("/admin/seed/fixtures", post(seed::seed_fixtures))
+        .route("/admin/backup", post(backup::backup_catalog))
+        .route(
+            "/admin/restore",
+            post(backup::restore_catalog).layer(DefaultBodyLimit::max(backup::MAX_BACKUP_BYTES)),
+        )
+        .route_layer(middleware::from_fn(content_type::require_json))
+        // The identity provider posts a form.
+        .route("/auth/saml/acs", post(saml::consume_assertion))
+        // Uploads carry the file itself and check their own Content-Type.
+        .route(
+            "/books/{id}/files",
+            get(ebooks::list_book_files)
+                .post(ebooks::upload_file)
+                .layer(DefaultBodyLimit::max(ebooks::MAX_FILE_BYTES)),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), books::hide_numeric_ids))
+        .route_layer(middleware::from_fn_with_state(state.clone(), demo::reject_mutations))
+        .route_layer(middleware::from_fn_with_state(state.clone(), features::gate))
+        .route_layer(middleware::from_fn_with_state(state.clone(), maintenance::gate))
+        .with_state(state)
+        .layer(CatchPanicLayer::custom(panic_response))
+}
+
+/// Turns a panicking handler into a plain 500 for that one request. Nothing
+/// is shared between requests but the pool, so the server keeps serving.
+pub fn panic_response(panic: Box<dyn std::any::Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    eprintln!("Handler panicked: {}", message);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
+
+pub async fn health_check() -> &'static str {
+    "OK"
+}
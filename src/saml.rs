@@ -0,0 +1,419 @@
+//! SAML 2.0 single sign-on, as a service provider, for institutions whose
+//! identity provider speaks nothing else. `GET /auth/saml/metadata`
+//! describes this service for the identity provider's administrator,
+//! `GET /auth/saml/login` sends the browser to the identity provider, and
+//! the signed response it posts back to `POST /auth/saml/acs` is exchanged
+//! for a session, like `POST /auth/login`.
+//!
+//! Responses are trusted only when the response or its assertion carries
+//! an enveloped RSA-SHA256 signature, made with the configured certificate,
+//! over exactly the element that is then read. Encrypted assertions are not
+//! supported.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use flate2::{Compression, write::DeflateEncoder};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::io::Write;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+
+use crate::{
+    AppError,
+    audit::{self, AuditEvent},
+    auth::{self, ClientIp, ExternalAccount, ExternalId, Session},
+    card::escape_html as escape,
+    config::AuthConfig,
+    content_type,
+    members::MemberRole,
+    xml::{self, Element, Scope},
+};
+
+const PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const HTTP_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+
+/// How far the identity provider's clock may be from ours.
+const CLOCK_SKEW_SECS: i64 = 120;
+
+pub const FORM: &[&str] = &["application/x-www-form-urlencoded"];
+
+#[derive(Debug, Clone)]
+pub struct SamlConfig {
+    /// This service's entity ID, which assertions must be addressed to.
+    pub entity_id: String,
+    /// The absolute URL of `POST /auth/saml/acs` as the identity provider
+    /// sees it.
+    pub acs_url: String,
+    /// The identity provider's entity ID, expected as the assertion issuer.
+    pub idp_entity_id: String,
+    /// Where `GET /auth/saml/login` sends the browser (HTTP-Redirect binding).
+    pub idp_sso_url: String,
+    /// The identity provider's signing certificate, DER-encoded.
+    pub idp_certificate: Vec<u8>,
+    pub name_attribute: String,
+    pub email_attribute: String,
+    /// Attribute whose values decide the role, e.g. groups or affiliations.
+    pub role_attribute: String,
+    pub staff_values: Vec<String>,
+    pub admin_values: Vec<String>,
+}
+
+impl SamlConfig {
+    /// Reads a certificate given as PEM or as the bare base64 found in
+    /// identity provider metadata.
+    pub fn parse_certificate(value: &str) -> Option<Vec<u8>> {
+        let body: String = value
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("-----"))
+            .flat_map(|line| line.chars().filter(|c| !c.is_whitespace()))
+            .collect();
+        let der = BASE64.decode(body).ok()?;
+        webpki::EndEntityCert::try_from(&CertificateDer::from(der.as_slice())).ok()?;
+        Some(der)
+    }
+}
+
+/// A verified assertion, reduced to what sign-in needs.
+#[derive(Debug)]
+pub struct Assertion {
+    pub id: String,
+    /// The `NameID`.
+    pub subject: String,
+    pub attributes: Vec<(String, Vec<String>)>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Assertion {
+    fn values(&self, name: &str) -> impl Iterator<Item = &String> {
+        self.attributes.iter().filter(move |(attr, _)| attr == name).flat_map(|(_, values)| values)
+    }
+
+    fn account(&self, config: &SamlConfig) -> ExternalAccount {
+        let has_value = |wanted: &[String]| self.values(&config.role_attribute).any(|v| wanted.iter().any(|w| w.eq_ignore_ascii_case(v)));
+        let role = if has_value(&config.admin_values) {
+            MemberRole::Admin
+        } else if has_value(&config.staff_values) {
+            MemberRole::Staff
+        } else {
+            MemberRole::Patron
+        };
+        ExternalAccount {
+            name: self.values(&config.name_attribute).next().cloned(),
+            email: self.values(&config.email_attribute).next().cloned(),
+            role,
+        }
+    }
+}
+
+fn configured(auth: &AuthConfig) -> Result<&SamlConfig, AppError> {
+    auth.saml.as_ref().ok_or_else(|| AppError::InvalidInput("SAML sign-in is not configured".to_string()))
+}
+
+pub async fn metadata(State(auth): State<AuthConfig>) -> Result<Response, AppError> {
+    let config = configured(&auth)?;
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{}">
+  <md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}">
+    <md:NameIDFormat>urn:oasis:names:tc:SAML:2.0:nameid-format:persistent</md:NameIDFormat>
+    <md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>
+  </md:SPSSODescriptor>
+</md:EntityDescriptor>
+"#,
+        escape(&config.entity_id),
+        PROTOCOL,
+        HTTP_POST,
+        escape(&config.acs_url),
+    );
+    Ok(([(header::CONTENT_TYPE, "application/samlmetadata+xml")], body).into_response())
+}
+
+/// Redirects to the identity provider with an `AuthnRequest`.
+pub async fn start_login(State(auth): State<AuthConfig>) -> Result<Response, AppError> {
+    let config = configured(&auth)?;
+    let mut id = [0u8; 16];
+    rand::rng().fill_bytes(&mut id);
+    let request = format!(
+        r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="_{}" Version="2.0" IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" ProtocolBinding="{}"><saml:Issuer>{}</saml:Issuer></samlp:AuthnRequest>"#,
+        PROTOCOL,
+        ASSERTION,
+        hex::encode(id),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        escape(&config.idp_sso_url),
+        escape(&config.acs_url),
+        HTTP_POST,
+        escape(&config.entity_id),
+    );
+    let mut deflater = DeflateEncoder::new(Vec::new(), Compression::default());
+    deflater.write_all(request.as_bytes()).and_then(|_| deflater.flush()).expect("writing to a Vec can't fail");
+    let deflated = deflater.finish().expect("writing to a Vec can't fail");
+    let encoded: String = form_urlencoded::byte_serialize(BASE64.encode(deflated).as_bytes()).collect();
+    let separator = if config.idp_sso_url.contains('?') { '&' } else { '?' };
+    let location = format!("{}{}SAMLRequest={}", config.idp_sso_url, separator, encoded);
+    Ok((StatusCode::SEE_OTHER, [(header::LOCATION, location)]).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcsForm {
+    #[serde(rename = "SAMLResponse")]
+    saml_response: String,
+}
+
+/// The assertion consumer service: checks the posted response and signs
+/// the member in, creating them on first sign-in.
+pub async fn consume_assertion(
+    State(pool): State<PgPool>,
+    State(auth): State<AuthConfig>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Session>, AppError> {
+    let config = configured(&auth)?;
+    if content_type::media_type(&headers).is_none_or(|t| !FORM.contains(&t.as_str())) {
+        return Err(content_type::unsupported(&headers, FORM));
+    }
+    let form: AcsForm = serde_urlencoded::from_bytes(&body)
+        .map_err(|_| AppError::InvalidInput("SAMLResponse is required".to_string()))?;
+    let decoded = BASE64
+        .decode(form.saml_response.split_whitespace().collect::<String>())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| AppError::InvalidInput("SAMLResponse is not base64-encoded XML".to_string()))?;
+
+    let mut tx = pool.begin().await?;
+    let assertion = match verify_response(config, &decoded, Utc::now()) {
+        Ok(assertion) => assertion,
+        Err(reason) => {
+            audit::record(&mut tx, AuditEvent::LoginFailed, None, ip.as_deref(), &format!("SAML: {}", reason)).await?;
+            tx.commit().await?;
+            return Err(AppError::Unauthorized(format!("The SAML response was rejected: {}", reason)));
+        }
+    };
+
+    sqlx::query!("DELETE FROM saml_assertions WHERE expires_at < $1", Utc::now()).execute(&mut *tx).await?;
+    let fresh = sqlx::query!(
+        "INSERT INTO saml_assertions (id, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        assertion.id,
+        assertion.expires_at,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        == 1;
+    if !fresh {
+        return Err(AppError::Unauthorized("The SAML response was rejected: the assertion was already used".to_string()));
+    }
+
+    let external_id = ExternalId::Saml(&assertion.subject);
+    let member_id = auth::find_external_member(&mut tx, external_id).await?;
+    let member_id = auth::sync_external_member(&mut tx, external_id, member_id, assertion.account(config)).await?;
+    audit::record(&mut tx, AuditEvent::LoginSucceeded, Some(member_id), ip.as_deref(), "SAML").await?;
+    let session = auth::start_session(&mut tx, member_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(session))
+}
+
+/// Checks a decoded `samlp:Response` against the configuration and returns
+/// its assertion. The error says why a response was refused.
+pub fn verify_response(config: &SamlConfig, document: &str, now: DateTime<Utc>) -> Result<Assertion, String> {
+    let response = xml::parse(document).map_err(|e| format!("malformed XML: {}", e))?;
+    let scope = response.scope(&Scope::new());
+    if !response.is(&scope, PROTOCOL, "Response") {
+        return Err("not a SAML response".to_string());
+    }
+    // Two elements with one ID would let a signature over one vouch for
+    // the other.
+    let mut ids: Vec<&str> = response.descendants().iter().filter_map(|e| e.attr("ID")).collect();
+    ids.sort_unstable();
+    if ids.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err("duplicate IDs".to_string());
+    }
+
+    let status = response
+        .child(&scope, PROTOCOL, "Status")
+        .and_then(|(status, scope)| status.child(&scope, PROTOCOL, "StatusCode").and_then(|(code, _)| code.attr("Value").map(str::to_string)))
+        .unwrap_or_default();
+    if status != STATUS_SUCCESS {
+        return Err(format!("the identity provider reported {:?}", status));
+    }
+    if response.attr("Destination").is_some_and(|d| d != config.acs_url) {
+        return Err("the response is addressed to another service".to_string());
+    }
+    if response.child(&scope, ASSERTION, "EncryptedAssertion").is_some() {
+        return Err("encrypted assertions are not supported".to_string());
+    }
+    let assertions: Vec<_> = response.children_named(&scope, ASSERTION, "Assertion").collect();
+    let [(assertion, assertion_scope)] = assertions.as_slice() else {
+        return Err("the response must contain exactly one assertion".to_string());
+    };
+
+    let response_signed = verify_signature(config, &response, &Scope::new())?;
+    let assertion_signed = verify_signature(config, assertion, &scope)?;
+    if !response_signed && !assertion_signed {
+        return Err("the response is not signed".to_string());
+    }
+
+    let text = |element: &Element, scope: &Scope, name: &str| {
+        element.child(scope, ASSERTION, name).map(|(child, _)| child.text().trim().to_string())
+    };
+    if text(assertion, assertion_scope, "Issuer").as_deref() != Some(config.idp_entity_id.as_str()) {
+        return Err("the assertion was issued by another identity provider".to_string());
+    }
+    let id = assertion.attr("ID").unwrap_or_default().to_string();
+
+    let skew = Duration::seconds(CLOCK_SKEW_SECS);
+    let time = |element: &Element, name: &str| -> Result<Option<DateTime<Utc>>, String> {
+        element
+            .attr(name)
+            .map(|value| DateTime::parse_from_rfc3339(value).map(|t| t.with_timezone(&Utc)).map_err(|_| format!("invalid {}", name)))
+            .transpose()
+    };
+
+    let (subject, subject_scope) = assertion.child(assertion_scope, ASSERTION, "Subject").ok_or("the assertion has no subject")?;
+    let name_id = text(subject, &subject_scope, "NameID").filter(|n| !n.is_empty()).ok_or("the subject has no NameID")?;
+    let mut confirmed_until = None;
+    for (confirmation, scope) in subject.children_named(&subject_scope, ASSERTION, "SubjectConfirmation") {
+        let Some((data, _)) = confirmation.child(&scope, ASSERTION, "SubjectConfirmationData") else { continue };
+        let until = time(data, "NotOnOrAfter")?;
+        if confirmation.attr("Method") == Some(BEARER)
+            && data.attr("Recipient") == Some(config.acs_url.as_str())
+            && let Some(until) = until
+            && now - skew < until
+        {
+            confirmed_until = Some(until);
+        }
+    }
+    let confirmed_until = confirmed_until.ok_or("no current bearer confirmation for this service")?;
+
+    let (conditions, conditions_scope) =
+        assertion.child(assertion_scope, ASSERTION, "Conditions").ok_or("the assertion has no conditions")?;
+    if time(conditions, "NotBefore")?.is_some_and(|not_before| now + skew < not_before) {
+        return Err("the assertion is not valid yet".to_string());
+    }
+    let valid_until = time(conditions, "NotOnOrAfter")?;
+    if valid_until.is_some_and(|until| until <= now - skew) {
+        return Err("the assertion has expired".to_string());
+    }
+    let restrictions: Vec<_> = conditions.children_named(&conditions_scope, ASSERTION, "AudienceRestriction").collect();
+    let for_us = |(restriction, scope): &(&Element, Scope)| {
+        restriction.children_named(scope, ASSERTION, "Audience").any(|(audience, _)| audience.text().trim() == config.entity_id)
+    };
+    if restrictions.is_empty() || !restrictions.iter().all(for_us) {
+        return Err("the assertion is meant for another service".to_string());
+    }
+
+    let mut attributes = Vec::new();
+    for (statement, scope) in assertion.children_named(assertion_scope, ASSERTION, "AttributeStatement") {
+        for (attribute, scope) in statement.children_named(&scope, ASSERTION, "Attribute") {
+            let values = attribute.children_named(&scope, ASSERTION, "AttributeValue").map(|(v, _)| v.text().trim().to_string()).collect();
+            attributes.push((attribute.attr("Name").unwrap_or_default().to_string(), values));
+        }
+    }
+
+    Ok(Assertion {
+        id,
+        subject: name_id,
+        attributes,
+        expires_at: valid_until.map_or(confirmed_until, |until| until.min(confirmed_until)),
+    })
+}
+
+/// Checks the enveloped signature directly inside `element`, if there is
+/// one. `Ok(false)` means unsigned; a signature that doesn't check out is
+/// an error, never a fallback to the other element.
+fn verify_signature(config: &SamlConfig, element: &Element, outer: &Scope) -> Result<bool, String> {
+    let scope = element.scope(outer);
+    let Some((signature, signature_scope)) = element.child(&scope, DSIG, "Signature") else {
+        return Ok(false);
+    };
+    let algorithm = |parent: &Element, scope: &Scope, name: &str| {
+        parent.child(scope, DSIG, name).and_then(|(child, _)| child.attr("Algorithm").map(str::to_string))
+    };
+    let (signed_info, signed_info_scope) =
+        signature.child(&signature_scope, DSIG, "SignedInfo").ok_or("the signature has no SignedInfo")?;
+    let (canonicalization, canonicalization_scope) = signed_info
+        .child(&signed_info_scope, DSIG, "CanonicalizationMethod")
+        .ok_or("the signature has no canonicalization method")?;
+    if canonicalization.attr("Algorithm") != Some(EXC_C14N) {
+        return Err("only exclusive canonicalization is supported".to_string());
+    }
+    if algorithm(signed_info, &signed_info_scope, "SignatureMethod").as_deref() != Some(RSA_SHA256) {
+        return Err("only RSA-SHA256 signatures are supported".to_string());
+    }
+
+    let references: Vec<_> = signed_info.children_named(&signed_info_scope, DSIG, "Reference").collect();
+    let [(reference, reference_scope)] = references.as_slice() else {
+        return Err("the signature must have exactly one reference".to_string());
+    };
+    let id = element.attr("ID").ok_or("the signed element has no ID")?;
+    if reference.attr("URI") != Some(&format!("#{}", id)) {
+        return Err("the signature covers a different element".to_string());
+    }
+    let mut enveloped = false;
+    let mut prefixes = None;
+    if let Some((transforms, scope)) = reference.child(reference_scope, DSIG, "Transforms") {
+        for (transform, scope) in transforms.children_named(&scope, DSIG, "Transform") {
+            match transform.attr("Algorithm") {
+                Some(ENVELOPED_SIGNATURE) => enveloped = true,
+                Some(EXC_C14N) => prefixes = Some(inclusive_prefixes(transform, &scope)),
+                _ => return Err("unsupported signature transform".to_string()),
+            }
+        }
+    }
+    let (true, Some(prefixes)) = (enveloped, prefixes) else {
+        return Err("the signature must be enveloped and exclusively canonicalized".to_string());
+    };
+    if algorithm(reference, reference_scope, "DigestMethod").as_deref() != Some(SHA256) {
+        return Err("only SHA-256 digests are supported".to_string());
+    }
+    let expected_digest = reference
+        .child(reference_scope, DSIG, "DigestValue")
+        .and_then(|(value, _)| BASE64.decode(value.text().split_whitespace().collect::<String>()).ok())
+        .ok_or("the signature has no digest")?;
+    let canonical = xml::canonicalize(element, outer, &prefixes, Some(signature));
+    if Sha256::digest(canonical.as_bytes()).as_slice() != expected_digest.as_slice() {
+        return Err("the signed content was changed".to_string());
+    }
+
+    let signature_value = signature
+        .child(&signature_scope, DSIG, "SignatureValue")
+        .and_then(|(value, _)| BASE64.decode(value.text().split_whitespace().collect::<String>()).ok())
+        .ok_or("the signature has no value")?;
+    let signed_info_prefixes = inclusive_prefixes(canonicalization, &canonicalization_scope);
+    let canonical_signed_info = xml::canonicalize(signed_info, &signature_scope, &signed_info_prefixes, None);
+    let certificate = CertificateDer::from(config.idp_certificate.as_slice());
+    let certificate = webpki::EndEntityCert::try_from(&certificate).map_err(|_| "the configured certificate is invalid")?;
+    certificate
+        .verify_signature(webpki::ring::RSA_PKCS1_2048_8192_SHA256, canonical_signed_info.as_bytes(), &signature_value)
+        .map_err(|_| "the signature does not match the identity provider's certificate".to_string())?;
+    Ok(true)
+}
+
+/// The `PrefixList` of an exclusive canonicalization's `InclusiveNamespaces`.
+fn inclusive_prefixes(method: &Element, scope: &Scope) -> Vec<String> {
+    method
+        .child(scope, EXC_C14N, "InclusiveNamespaces")
+        .and_then(|(namespaces, _)| namespaces.attr("PrefixList").map(str::to_string))
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|prefix| if prefix == "#default" { String::new() } else { prefix.to_string() })
+        .collect()
+}
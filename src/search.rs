@@ -0,0 +1,18 @@
+//! The `q` parameter on the book list: full-text search over each book's
+//! title, author, description, and table of contents.
+
+use sqlx::{Postgres, QueryBuilder};
+
+/// Appends a condition matching books that contain every word of `text`.
+/// The query uses web-search syntax, so `"exact phrase"`, `or`, and `-word`
+/// work. Words aren't stemmed and ignore case and accents.
+pub fn push_match(query: &mut QueryBuilder<'_, Postgres>, text: &str) {
+    query
+        .push(
+            "to_tsvector('simple', fold_text(concat_ws(' ', title, author, description, \
+             (SELECT string_agg(t.title, ' ') FROM book_toc_entries t WHERE t.book_id = books.id)))) \
+             @@ websearch_to_tsquery('simple', fold_text(",
+        )
+        .push_bind(text.to_string())
+        .push("))");
+}
@@ -0,0 +1,369 @@
+//! Sample data. `POST /admin/seed` generates any number of random books for
+//! load testing; the fixtures (`POST /admin/seed/fixtures`, or the `seed`
+//! command) are a small curated set for demos and local development.
+
+use std::collections::HashMap;
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Duration, Utc};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    api_keys::AdminAccess,
+    borrowings::DEFAULT_LOAN_DAYS,
+    classification::ClassificationScheme,
+    members::MemberRole,
+    query::Query,
+    slug,
+    validation::classification_key,
+};
+
+const MAX_SEED_COUNT: usize = 1_000_000;
+const INSERT_CHUNK: usize = 10_000;
+
+const ADJECTIVES: &[&str] = &[
+    "Silent", "Hidden", "Broken", "Golden", "Last", "Forgotten", "Crimson", "Distant",
+    "Secret", "Burning", "Frozen", "Wandering", "Quiet", "Endless", "Lost", "Bright",
+];
+const NOUNS: &[&str] = &[
+    "River", "Garden", "Empire", "Letter", "Mountain", "Harbor", "Library", "Winter",
+    "Kingdom", "Orchard", "Voyage", "Lantern", "Archive", "Shadow", "Island", "Machine",
+];
+const FIRST_NAMES: &[&str] = &[
+    "Alice", "Bruno", "Chiara", "Daniel", "Elena", "Farid", "Grace", "Hiro",
+    "Ines", "Jonas", "Kavya", "Liam", "Maya", "Nikolai", "Olivia", "Pablo",
+];
+const LAST_NAMES: &[&str] = &[
+    "Anderson", "Bianchi", "Chen", "Dubois", "Eriksen", "Fischer", "Garcia", "Haddad",
+    "Ivanova", "Jensen", "Kowalski", "Lopez", "Moreau", "Nakamura", "Okafor", "Petrov",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct SeedParams {
+    count: Option<usize>,
+    loans: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedSummary {
+    pub books_created: usize,
+    pub borrowings_created: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FixtureSummary {
+    pub books_created: usize,
+    pub members_created: usize,
+    pub borrowings_created: usize,
+}
+
+/// Classics with descriptions and Dewey numbers, a few members, and their
+/// loans: some current, some overdue, some returned.
+const FIXTURES: &str = include_str!("../fixtures/demo.json");
+
+#[derive(Deserialize)]
+struct Fixtures {
+    books: Vec<FixtureBook>,
+    members: Vec<FixtureMember>,
+    loans: Vec<FixtureLoan>,
+}
+
+#[derive(Deserialize)]
+struct FixtureBook {
+    title: String,
+    author: String,
+    year: i64,
+    isbn: String,
+    /// Dewey class number.
+    classification: String,
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct FixtureMember {
+    name: String,
+    email: String,
+}
+
+/// Loan dates are relative to when the fixtures are loaded, so overdue
+/// loans stay overdue however old the fixture file is.
+#[derive(Deserialize)]
+struct FixtureLoan {
+    isbn: String,
+    /// The borrowing member's email.
+    member: String,
+    borrowed_days_ago: i64,
+    returned_days_ago: Option<i64>,
+}
+
+struct GeneratedBook {
+    title: String,
+    author: String,
+    year: i64,
+    isbn: String,
+    loan: Option<GeneratedLoan>,
+}
+
+struct GeneratedLoan {
+    borrower_name: String,
+    borrowed_at: DateTime<Utc>,
+    due_date: DateTime<Utc>,
+    returned_at: Option<DateTime<Utc>>,
+}
+
+/// Generates `count` random books and loans them out to `loans` of them, for
+/// evaluating pagination and search at scale. Everything is inserted in one
+/// transaction so an aborted seed leaves no partial data behind.
+pub async fn seed_data(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Query(params): Query<SeedParams>,
+) -> Result<(StatusCode, Json<SeedSummary>), AppError> {
+    let count = params.count.unwrap_or(1_000);
+    let loans = params.loans.unwrap_or(count / 10);
+    if count == 0 || count > MAX_SEED_COUNT || loans > count {
+        return Err(AppError::InvalidInput(format!(
+            "count must be between 1 and {}, and loans must not exceed count",
+            MAX_SEED_COUNT
+        )));
+    }
+
+    let books = generate_books(count, loans, Utc::now());
+
+    let mut tx = pool.begin().await?;
+    let mut borrowings_created = 0;
+
+    for chunk in books.chunks(INSERT_CHUNK) {
+        let titles: Vec<&str> = chunk.iter().map(|b| b.title.as_str()).collect();
+        let authors: Vec<&str> = chunk.iter().map(|b| b.author.as_str()).collect();
+        let years: Vec<i64> = chunk.iter().map(|b| b.year).collect();
+        let isbns: Vec<&str> = chunk.iter().map(|b| b.isbn.as_str()).collect();
+        let available: Vec<bool> = chunk
+            .iter()
+            .map(|b| b.loan.as_ref().is_none_or(|l| l.returned_at.is_some()))
+            .collect();
+
+        let ids: Vec<i64> = sqlx::query_scalar!(
+            "INSERT INTO books (title, author, year, isbn, available)
+             SELECT * FROM UNNEST($1::text[], $2::text[], $3::bigint[], $4::text[], $5::boolean[])
+             RETURNING id",
+            &titles as &[&str],
+            &authors as &[&str],
+            &years,
+            &isbns as &[&str],
+            &available,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // Numbered by id rather than one lookup per book; any slug that
+        // happens to be taken already is left unset.
+        let slugs: Vec<String> = ids
+            .iter()
+            .zip(chunk)
+            .map(|(id, book)| format!("{}-{}", slug::slugify(&book.title, book.year), id))
+            .collect();
+        sqlx::query!(
+            "UPDATE books SET slug = s.slug
+             FROM UNNEST($1::bigint[], $2::text[]) AS s(id, slug)
+             WHERE books.id = s.id AND NOT EXISTS (SELECT 1 FROM books taken WHERE taken.slug = s.slug)",
+            &ids,
+            &slugs,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let mut book_ids = Vec::new();
+        let mut borrower_names = Vec::new();
+        let mut borrowed_ats = Vec::new();
+        let mut due_dates = Vec::new();
+        let mut returned_ats = Vec::new();
+        for (id, book) in ids.iter().zip(chunk) {
+            if let Some(loan) = &book.loan {
+                book_ids.push(*id);
+                borrower_names.push(loan.borrower_name.as_str());
+                borrowed_ats.push(loan.borrowed_at);
+                due_dates.push(loan.due_date);
+                returned_ats.push(loan.returned_at);
+            }
+        }
+
+        sqlx::query!(
+            "INSERT INTO borrowings (book_id, borrower_name, borrowed_at, due_date, returned_at)
+             SELECT * FROM UNNEST($1::bigint[], $2::text[], $3::timestamptz[], $4::timestamptz[], $5::timestamptz[])",
+            &book_ids,
+            &borrower_names as &[&str],
+            &borrowed_ats,
+            &due_dates,
+            &returned_ats as &[Option<DateTime<Utc>>],
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        borrowings_created += book_ids.len();
+    }
+
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(SeedSummary {
+        books_created: books.len(),
+        borrowings_created,
+    })))
+}
+
+pub async fn seed_fixtures(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+) -> Result<(StatusCode, Json<FixtureSummary>), AppError> {
+    Ok((StatusCode::CREATED, Json(load_fixtures(&pool).await?)))
+}
+
+/// Adds the fixtures to an empty catalog, in one transaction. Members come
+/// with their email already verified so they can borrow straight away.
+/// Staff accounts don't count against an empty catalog: over HTTP, an admin
+/// has to exist to load them.
+pub async fn load_fixtures(pool: &PgPool) -> Result<FixtureSummary, AppError> {
+    let mut tx = pool.begin().await?;
+    let summary = insert_fixtures(&mut tx).await?;
+    tx.commit().await?;
+    Ok(summary)
+}
+
+async fn insert_fixtures(conn: &mut PgConnection) -> Result<FixtureSummary, AppError> {
+    let fixtures: Fixtures = serde_json::from_str(FIXTURES).expect("fixtures/demo.json matches the fixture format");
+
+    let in_use = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM books) OR EXISTS(SELECT 1 FROM members WHERE role = $1)",
+        MemberRole::Patron.as_str(),
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .unwrap_or(false);
+    if in_use {
+        return Err(AppError::Conflict("Fixtures can only be loaded into an empty catalog".to_string()));
+    }
+
+    let now = Utc::now();
+    let mut book_ids = HashMap::new();
+    for book in &fixtures.books {
+        let key = classification_key(Some(ClassificationScheme::Dewey), Some(&book.classification))?;
+        let id = sqlx::query_scalar!(
+            "INSERT INTO books (title, author, year, isbn, available, classification_scheme, classification, classification_key, description)
+             VALUES ($1, $2, $3, $4, true, $5, $6, $7, $8)
+             RETURNING id",
+            book.title,
+            book.author,
+            book.year,
+            book.isbn,
+            ClassificationScheme::Dewey.as_str(),
+            book.classification,
+            key,
+            book.description,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        slug::assign(&mut *conn, id, &book.title, book.year).await?;
+        book_ids.insert(book.isbn.as_str(), id);
+    }
+
+    let mut members = HashMap::new();
+    for member in &fixtures.members {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO members (name, email, email_verified_at, created_at) VALUES ($1, $2, $3, $3) RETURNING id",
+            member.name,
+            member.email,
+            now,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        members.insert(member.email.as_str(), (id, member.name.as_str()));
+    }
+
+    for loan in &fixtures.loans {
+        let book_id = book_ids[loan.isbn.as_str()];
+        let (member_id, name) = members[loan.member.as_str()];
+        let borrowed_at = now - Duration::days(loan.borrowed_days_ago);
+        sqlx::query!(
+            "INSERT INTO borrowings (book_id, member_id, borrower_name, borrowed_at, due_date, returned_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            book_id,
+            member_id,
+            name,
+            borrowed_at,
+            borrowed_at + Duration::days(DEFAULT_LOAN_DAYS),
+            loan.returned_days_ago.map(|days| now - Duration::days(days)),
+        )
+        .execute(&mut *conn)
+        .await?;
+        if loan.returned_days_ago.is_none() {
+            sqlx::query!("UPDATE books SET available = false WHERE id = $1", book_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+
+    Ok(FixtureSummary {
+        books_created: fixtures.books.len(),
+        members_created: fixtures.members.len(),
+        borrowings_created: fixtures.loans.len(),
+    })
+}
+
+fn generate_books(count: usize, loans: usize, now: DateTime<Utc>) -> Vec<GeneratedBook> {
+    let mut rng = StdRng::from_os_rng();
+
+    let mut books: Vec<GeneratedBook> = (0..count)
+        .map(|_| GeneratedBook {
+            title: format!(
+                "The {} {}",
+                ADJECTIVES.choose(&mut rng).unwrap(),
+                NOUNS.choose(&mut rng).unwrap()
+            ),
+            author: random_name(&mut rng),
+            year: rng.random_range(1900..=2024),
+            isbn: random_isbn(&mut rng),
+            loan: None,
+        })
+        .collect();
+
+    for book in books.iter_mut().take(loans) {
+        let borrowed_at = now - Duration::days(rng.random_range(0..60));
+        let due_date = borrowed_at + Duration::days(14);
+        let returned_at = rng
+            .random_bool(0.5)
+            .then(|| borrowed_at + Duration::days(rng.random_range(1..14)))
+            .filter(|returned| *returned < now);
+        book.loan = Some(GeneratedLoan {
+            borrower_name: random_name(&mut rng),
+            borrowed_at,
+            due_date,
+            returned_at,
+        });
+    }
+
+    books
+}
+
+fn random_name(rng: &mut impl Rng) -> String {
+    format!(
+        "{} {}",
+        FIRST_NAMES.choose(rng).unwrap(),
+        LAST_NAMES.choose(rng).unwrap()
+    )
+}
+
+/// A random ISBN-13 in the 978 prefix with a correct check digit.
+fn random_isbn(rng: &mut impl Rng) -> String {
+    let mut digits = vec![9, 7, 8];
+    digits.extend((0..9).map(|_| rng.random_range(0..10u32)));
+    let sum: u32 = digits
+        .iter()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 })
+        .sum();
+    digits.push((10 - sum % 10) % 10);
+    digits.iter().map(|d| char::from_digit(*d, 10).unwrap()).collect()
+}
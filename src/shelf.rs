@@ -0,0 +1,95 @@
+//! Shelf-reading: the copies that should be on a stretch of shelving, in the
+//! order they should stand, for staff checking the shelves for misfiled items.
+
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{AppError, api_keys::CatalogRead, classification::ClassificationScheme, copies::CopyStatus, query::Query};
+
+#[derive(Debug, Deserialize)]
+pub struct ShelfParams {
+    /// Class numbers to read, e.g. `500-519` or `QA1-QA99`. Each end matches
+    /// every class number it is a prefix of.
+    range: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShelfItem {
+    /// 1 for the first copy on the shelf.
+    pub position: i64,
+    pub copy_id: i64,
+    pub barcode: String,
+    pub call_number: Option<String>,
+    pub book_id: i64,
+    pub title: String,
+    pub author: String,
+    pub classification: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShelfOrder {
+    pub scheme: ClassificationScheme,
+    pub from: String,
+    pub to: String,
+    pub items: Vec<ShelfItem>,
+}
+
+/// Copies on the shelf (not on loan or discarded) whose books are classed
+/// within `range`, in shelf order: class number, then call number, then
+/// barcode for copies shelved together.
+pub async fn shelf_order(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    Query(params): Query<ShelfParams>,
+) -> Result<Json<ShelfOrder>, AppError> {
+    let (scheme, from, to) = parse_range(&params.range)?;
+    let key = |value: &str| {
+        scheme
+            .sort_key(value)
+            .ok_or_else(|| AppError::InvalidInput(format!("{} is not a valid {} class number", value, scheme)))
+    };
+    let (from_key, to_key) = (key(from)?, key(to)?);
+    if from_key > to_key && !from_key.starts_with(&to_key) {
+        return Err(AppError::InvalidInput(format!("range {} ends before it starts", params.range)));
+    }
+
+    let items = sqlx::query_as!(
+        ShelfItem,
+        r#"SELECT ROW_NUMBER() OVER (ORDER BY b.classification_key, c.call_number, c.barcode) AS "position!",
+                  c.id AS copy_id, c.barcode, c.call_number, b.id AS book_id, b.title, b.author, b.classification
+           FROM copies c
+           JOIN books b ON b.id = c.book_id
+           WHERE c.status = $1
+             AND b.deleted_at IS NULL
+             AND b.classification_scheme = $2
+             AND b.classification_key >= $3
+             AND (b.classification_key <= $4 OR starts_with(b.classification_key, $4))
+           ORDER BY 1"#,
+        CopyStatus::Available.as_str(),
+        scheme.as_str(),
+        from_key,
+        to_key,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(ShelfOrder { scheme, from: from.to_string(), to: to.to_string(), items }))
+}
+
+/// Splits `500-519` into its ends. Class numbers starting with a letter are
+/// Library of Congress, otherwise Dewey.
+fn parse_range(range: &str) -> Result<(ClassificationScheme, &str, &str), AppError> {
+    let (from, to) = range
+        .split_once('-')
+        .map(|(from, to)| (from.trim(), to.trim()))
+        .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+        .ok_or_else(|| AppError::InvalidInput("range must be two class numbers joined by '-', e.g. 500-519".to_string()))?;
+    let scheme = |value: &str| {
+        if value.starts_with(|c: char| c.is_ascii_alphabetic()) { ClassificationScheme::Lcc } else { ClassificationScheme::Dewey }
+    };
+    if scheme(from) != scheme(to) {
+        return Err(AppError::InvalidInput("range must not mix Dewey and Library of Congress numbers".to_string()));
+    }
+    Ok((scheme(from), from, to))
+}
@@ -0,0 +1,432 @@
+//! SIP2 (3M Standard Interchange Protocol, version 2.00) for self-check
+//! kiosks and security gates, on its own TCP port next to the HTTP API.
+//! Checkouts and check-ins go through the same code as the circulation desk
+//! scan, so they follow the same rules for holds, fines, and terms.
+//!
+//! Supported messages: login (93), SC status (99), checkout (11), check-in
+//! (09), item information (17), patron status (23), end session (35), and
+//! resend (97). Messages are answered with error detection (`AY`/`AZ`)
+//! when the request uses it.
+
+use std::{collections::HashMap, fmt, net::SocketAddr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    AppError, auth,
+    age_rating::AgePolicy,
+    borrowings::DEFAULT_LOAN_DAYS,
+    circulation,
+    copies::{BookCopy, CopyStatus},
+    members, terms,
+};
+
+/// Longest message accepted; kiosks send a few hundred bytes at most.
+const MAX_MESSAGE_BYTES: u64 = 4096;
+
+/// Which of the 16 messages in the `BX` field of an ACS status are
+/// supported: patron status, checkout, check-in, SC status, resend, login,
+/// end session, and item information.
+const SUPPORTED_MESSAGES: &str = "YYYNYYYNYNYNNNNN";
+
+/// Set from `SIP2_ADDR` and friends; without `SIP2_ADDR` there is no
+/// listener.
+#[derive(Clone)]
+pub struct Sip2Config {
+    pub addr: SocketAddr,
+    /// Credentials kiosks send in their login message.
+    pub username: String,
+    pub password: String,
+    /// Institution id (`AO`) used when a request doesn't send one.
+    pub institution: String,
+    /// Same as the desk's, from `AGE_RESTRICTIONS`.
+    pub age_policy: AgePolicy,
+}
+
+impl fmt::Debug for Sip2Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sip2Config")
+            .field("addr", &self.addr)
+            .field("username", &self.username)
+            .field("password", &"***")
+            .field("institution", &self.institution)
+            .field("age_policy", &self.age_policy)
+            .finish()
+    }
+}
+
+/// Accepts kiosk connections until the listener fails.
+pub async fn serve(listener: TcpListener, pool: PgPool, config: Arc<Sip2Config>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, pool.clone(), config.clone()));
+            }
+            Err(e) => eprintln!("SIP2: could not accept a connection: {}", e),
+        }
+    }
+}
+
+/// One kiosk's connection: it logs in, then sends one message at a time,
+/// each ended by a carriage return.
+async fn handle_connection(stream: TcpStream, pool: PgPool, config: Arc<Sip2Config>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut session = Session::default();
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        match (&mut reader).take(MAX_MESSAGE_BYTES).read_until(b'\r', &mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) if !buffer.ends_with(b"\r") && buffer.len() as u64 == MAX_MESSAGE_BYTES => return,
+            Ok(_) => {}
+        }
+        let text = String::from_utf8_lossy(&buffer);
+        let message = text.trim_matches(['\r', '\n']);
+        if message.is_empty() {
+            continue;
+        }
+        let response = respond(&pool, &config, &mut session, message).await;
+        if writer.write_all(format!("{}\r", response).as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Session {
+    logged_in: bool,
+    /// Sent again for a resend request.
+    last_response: Option<String>,
+}
+
+/// The response to one message, without the trailing carriage return.
+pub async fn respond(pool: &PgPool, config: &Sip2Config, session: &mut Session, message: &str) -> String {
+    let Some((body, sequence)) = split_error_detection(message) else {
+        // The checksum didn't match: ask the kiosk to send it again.
+        return "96".to_string();
+    };
+    if body.starts_with("97") {
+        return session.last_response.clone().unwrap_or_else(|| "96".to_string());
+    }
+
+    let response = match body.get(..2) {
+        Some("93") => login(config, session, body),
+        Some("99") => Some(acs_status(config)),
+        Some("11") => checkout(pool, config, session, body).await,
+        Some("09") => checkin(pool, config, session, body).await,
+        Some("17") => item_information(pool, config, session, body).await,
+        Some("23") => patron_status(pool, config, session, body).await,
+        Some("35") => end_session(config, session, body),
+        _ => None,
+    };
+    let Some(mut response) = response else {
+        return "96".to_string();
+    };
+    if let Some(sequence) = sequence {
+        response = format!("{}AY{}AZ", response, sequence);
+        let checksum = checksum(&response);
+        response.push_str(&checksum);
+    }
+    session.last_response = Some(response.clone());
+    response
+}
+
+/// Strips an `AY<sequence>AZ<checksum>` trailer, checking the checksum.
+/// `None` if it doesn't match.
+fn split_error_detection(message: &str) -> Option<(&str, Option<char>)> {
+    let bytes = message.as_bytes();
+    let len = bytes.len();
+    if len < 9 || &bytes[len - 9..len - 7] != b"AY" || &bytes[len - 6..len - 4] != b"AZ" {
+        return Some((message, None));
+    }
+    let sequence = bytes[len - 7] as char;
+    let sent = &message[len - 4..];
+    checksum(&message[..len - 4]).eq_ignore_ascii_case(sent).then_some((&message[..len - 9], Some(sequence)))
+}
+
+/// The two's complement of the byte sum, as four hex digits.
+pub fn checksum(text: &str) -> String {
+    let sum = text.bytes().fold(0u16, |sum, byte| sum.wrapping_add(byte.into()));
+    format!("{:04X}", sum.wrapping_neg())
+}
+
+/// `YYYYMMDDZZZZHHMMSS`, with the zone as `   Z` for UTC.
+pub fn sip_date(time: DateTime<Utc>) -> String {
+    format!("{}   Z{}", time.format("%Y%m%d"), time.format("%H%M%S"))
+}
+
+/// The `XXvalue|` fields after a message's fixed-length part. `None` if
+/// the message is shorter than that part.
+fn fields(message: &str, fixed_length: usize) -> Option<HashMap<&str, &str>> {
+    let rest = message.get(fixed_length..)?;
+    let mut fields = HashMap::new();
+    for field in rest.split('|').filter(|f| f.len() >= 2 && f.is_char_boundary(2)) {
+        let (code, value) = field.split_at(2);
+        fields.entry(code).or_insert(value);
+    }
+    Some(fields)
+}
+
+/// Builds a response: fixed-length part first, then fields.
+struct Response(String);
+
+impl Response {
+    fn new(fixed: &str) -> Self {
+        Response(fixed.to_string())
+    }
+
+    fn field(mut self, code: &str, value: &str) -> Self {
+        // A `|` in a title would end the field early.
+        self.0.push_str(code);
+        self.0.push_str(&value.replace('|', "/"));
+        self.0.push('|');
+        self
+    }
+
+    fn screen_message(self, error: Option<&AppError>) -> Self {
+        match error {
+            Some(error) => self.field("AF", &screen_message(error)),
+            None => self,
+        }
+    }
+}
+
+fn yes_no(value: bool) -> char {
+    if value { 'Y' } else { 'N' }
+}
+
+fn screen_message(error: &AppError) -> String {
+    match error {
+        AppError::ResourceNotFoundBy(kind, field, value) => format!("{} with {} {} not found", kind, field, value),
+        AppError::Conflict(message)
+        | AppError::Forbidden(message)
+        | AppError::Unauthorized(message)
+        | AppError::InvalidInput(message) => message.clone(),
+        AppError::Database(e) => {
+            eprintln!("SIP2: database error: {}", e);
+            "The library system is unavailable; please see staff".to_string()
+        }
+        _ => "This request could not be completed; please see staff".to_string(),
+    }
+}
+
+fn require_login(session: &Session) -> Result<(), AppError> {
+    if !session.logged_in {
+        return Err(AppError::Unauthorized("The kiosk is not logged in".to_string()));
+    }
+    Ok(())
+}
+
+fn institution<'a>(config: &'a Sip2Config, fields: &HashMap<&str, &'a str>) -> &'a str {
+    fields.get("AO").copied().unwrap_or(&config.institution)
+}
+
+async fn book_title(conn: &mut PgConnection, book_id: i64) -> Result<String, AppError> {
+    Ok(sqlx::query_scalar!("SELECT title FROM books WHERE id = $1", book_id).fetch_one(conn).await?)
+}
+
+/// 93: `93` + UID algorithm + PWD algorithm, then `CN` user and `CO`
+/// password.
+fn login(config: &Sip2Config, session: &mut Session, message: &str) -> Option<String> {
+    let fields = fields(message, 4)?;
+    session.logged_in =
+        fields.get("CN") == Some(&config.username.as_str()) && fields.get("CO") == Some(&config.password.as_str());
+    Some(format!("94{}", if session.logged_in { '1' } else { '0' }))
+}
+
+/// 98: what the ACS supports, sent in answer to an SC status (99).
+fn acs_status(config: &Sip2Config) -> String {
+    // Online, check-in and checkout allowed, no renewals, no status updates,
+    // no offline mode, default timeout, unlimited retries.
+    let fixed = format!("98YYYNNN000999{}2.00", sip_date(Utc::now()));
+    Response::new(&fixed)
+        .field("AO", &config.institution)
+        .field("BX", SUPPORTED_MESSAGES)
+        .0
+}
+
+/// 11: `11` + renewal policy + no block + transaction date + no-block due
+/// date, then `AO`, `AA` patron, `AB` item, and `AC` terminal password.
+async fn checkout(pool: &PgPool, config: &Sip2Config, session: &Session, message: &str) -> Option<String> {
+    let fields = fields(message, 40)?;
+    let patron = fields.get("AA").copied().unwrap_or_default();
+    let item = fields.get("AB").copied().unwrap_or_default();
+    let result: Result<_, AppError> = async {
+        require_login(session)?;
+        let mut tx = pool.begin().await?;
+        let copy = circulation::lock_copy(&mut tx, item).await?;
+        let scan = match copy.status {
+            CopyStatus::Available => circulation::check_out(&mut tx, copy, patron, DEFAULT_LOAN_DAYS, config.age_policy).await?,
+            CopyStatus::OnLoan => {
+                return Err(AppError::Conflict(format!("Copy {} is already checked out", copy.barcode)));
+            }
+            CopyStatus::Discarded => return Err(circulation::discarded(&copy)),
+        };
+        let title = book_title(&mut tx, scan.copy.book_id).await?;
+        tx.commit().await?;
+        Ok((scan.borrowing.due_date, title))
+    }
+    .await;
+
+    let now = sip_date(Utc::now());
+    let response = match &result {
+        // Checked out, no renewal, no magnetic media, desensitize.
+        Ok(_) => Response::new(&format!("121NNY{}", now)),
+        Err(_) => Response::new(&format!("120NUN{}", now)),
+    };
+    let (due_date, title) = result.as_ref().map(|(due, title)| (sip_date(*due), title.as_str())).unwrap_or_default();
+    Some(
+        response
+            .field("AO", institution(config, &fields))
+            .field("AA", patron)
+            .field("AB", item)
+            .field("AJ", title)
+            .field("AH", &due_date)
+            .screen_message(result.as_ref().err())
+            .0,
+    )
+}
+
+/// 09: `09` + no block + transaction date + return date, then `AP`
+/// location, `AO`, `AB` item, and `AC` terminal password.
+async fn checkin(pool: &PgPool, config: &Sip2Config, session: &Session, message: &str) -> Option<String> {
+    let fields = fields(message, 39)?;
+    let item = fields.get("AB").copied().unwrap_or_default();
+    let result: Result<_, AppError> = async {
+        require_login(session)?;
+        let mut tx = pool.begin().await?;
+        let copy = circulation::lock_copy(&mut tx, item).await?;
+        if copy.status != CopyStatus::OnLoan {
+            return Err(AppError::Conflict(format!("Copy {} is not checked out", copy.barcode)));
+        }
+        // Kiosks can't grade a copy; it keeps the one it went out with.
+        let condition = copy.condition;
+        let scan = circulation::check_in(&mut tx, copy, condition, None).await?;
+        let title = book_title(&mut tx, scan.copy.book_id).await?;
+        tx.commit().await?;
+        Ok((scan.copy, title))
+    }
+    .await;
+
+    let now = sip_date(Utc::now());
+    let response = match &result {
+        // Checked in, resensitize, no magnetic media, no alert.
+        Ok(_) => Response::new(&format!("101YNN{}", now)),
+        Err(_) => Response::new(&format!("100NUN{}", now)),
+    };
+    let (location, title) = result
+        .as_ref()
+        .map(|(copy, title)| (copy.call_number.as_deref().unwrap_or_default(), title.as_str()))
+        .unwrap_or_default();
+    Some(
+        response
+            .field("AO", institution(config, &fields))
+            .field("AB", item)
+            .field("AQ", location)
+            .field("AJ", title)
+            .screen_message(result.as_ref().err())
+            .0,
+    )
+}
+
+/// 17: `17` + transaction date, then `AO`, `AB` item, and `AC`.
+async fn item_information(pool: &PgPool, config: &Sip2Config, session: &Session, message: &str) -> Option<String> {
+    let fields = fields(message, 20)?;
+    let item = fields.get("AB").copied().unwrap_or_default();
+    let result: Result<(BookCopy, String, Option<DateTime<Utc>>), AppError> = async {
+        require_login(session)?;
+        let mut tx = pool.begin().await?;
+        let copy = circulation::lock_copy(&mut tx, item).await?;
+        let title = book_title(&mut tx, copy.book_id).await?;
+        let due_date = sqlx::query_scalar!(
+            "SELECT due_date FROM borrowings WHERE copy_id = $1 AND returned_at IS NULL",
+            copy.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok((copy, title, due_date))
+    }
+    .await;
+
+    // Circulation status: 03 available, 04 charged, 01 other.
+    let status = match &result {
+        Ok((copy, ..)) if copy.status == CopyStatus::Available => "03",
+        Ok((copy, ..)) if copy.status == CopyStatus::OnLoan => "04",
+        _ => "01",
+    };
+    // Security marker "other", fee type "other".
+    let mut response = Response::new(&format!("18{}0001{}", status, sip_date(Utc::now())));
+    if let Ok((_, _, Some(due_date))) = &result {
+        response = response.field("AH", &sip_date(*due_date));
+    }
+    let (title, location) = result
+        .as_ref()
+        .map(|(copy, title, _)| (title.as_str(), copy.call_number.as_deref().unwrap_or_default()))
+        .unwrap_or_default();
+    Some(
+        response
+            .field("AO", institution(config, &fields))
+            .field("AB", item)
+            .field("AJ", title)
+            .field("AQ", location)
+            .screen_message(result.as_ref().err())
+            .0,
+    )
+}
+
+/// 23: `23` + language + transaction date, then `AO`, `AA` patron, `AC`,
+/// and `AD` patron password. Patron passwords aren't checked: as at the
+/// desk, the card identifies the member.
+async fn patron_status(pool: &PgPool, config: &Sip2Config, session: &Session, message: &str) -> Option<String> {
+    let fields = fields(message, 23)?;
+    let patron = fields.get("AA").copied().unwrap_or_default();
+    let result: Result<_, AppError> = async {
+        require_login(session)?;
+        let mut conn = pool.acquire().await?;
+        let member = members::find_by_card(&mut conn, patron.trim()).await?;
+        let accepted_terms = match terms::require_current_accepted(&mut conn, member.id).await {
+            Ok(()) => true,
+            Err(AppError::Forbidden(_)) => false,
+            Err(e) => return Err(e),
+        };
+        let may_borrow = accepted_terms && auth::require_verified_email(&member).is_ok();
+        Ok((member.name, may_borrow))
+    }
+    .await;
+
+    // The first of the 14 status flags is "charge privileges denied".
+    let (name, valid, flags) = match &result {
+        Ok((name, may_borrow)) => (name.as_str(), true, format!("{:<14}", if *may_borrow { "" } else { "Y" })),
+        Err(_) => ("", false, format!("{:<14}", "Y")),
+    };
+    Some(
+        Response::new(&format!("24{}000{}", flags, sip_date(Utc::now())))
+            .field("AO", institution(config, &fields))
+            .field("AA", patron)
+            .field("AE", name)
+            .field("BL", &yes_no(valid).to_string())
+            .screen_message(result.as_ref().err())
+            .0,
+    )
+}
+
+/// 35: `35` + transaction date, then `AO`, `AA` patron. Nothing is held
+/// per patron, so this only acknowledges.
+fn end_session(config: &Sip2Config, session: &Session, message: &str) -> Option<String> {
+    let fields = fields(message, 20)?;
+    let ended = require_login(session);
+    Some(
+        Response::new(&format!("36{}{}", yes_no(ended.is_ok()), sip_date(Utc::now())))
+            .field("AO", institution(config, &fields))
+            .field("AA", fields.get("AA").copied().unwrap_or_default())
+            .screen_message(ended.as_ref().err())
+            .0,
+    )
+}
@@ -0,0 +1,85 @@
+//! URL slugs for books, e.g. `the-rust-programming-language-2018`. A book
+//! keeps its slug when edited, so published links keep working.
+
+use sqlx::PgConnection;
+
+use crate::AppError;
+
+/// Longest title part of a slug, in characters.
+const MAX_TITLE_LEN: usize = 80;
+
+/// `title` lowercased with accents folded, runs of anything else turned
+/// into single hyphens, followed by the year.
+pub fn slugify(title: &str, year: i64) -> String {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(fold) {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() == MAX_TITLE_LEN {
+                break;
+            }
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    let slug = if slug.is_empty() { "book" } else { slug };
+    format!("{}-{}", slug, year)
+}
+
+/// Common Latin letters with diacritics and their plain spellings.
+fn fold(c: char) -> impl Iterator<Item = char> {
+    let folded: &str = match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' | 'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' => "a",
+        'æ' | 'Æ' => "ae",
+        'ç' | 'ć' | 'č' | 'Ç' | 'Ć' | 'Č' => "c",
+        'ď' | 'đ' | 'ð' | 'Ď' | 'Đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' | 'È' | 'É' | 'Ê' | 'Ë' | 'Ě' => "e",
+        'ğ' | 'Ğ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' | 'Ì' | 'Í' | 'Î' | 'Ï' | 'İ' => "i",
+        'ł' | 'ľ' | 'Ł' | 'Ľ' => "l",
+        'ñ' | 'ń' | 'ň' | 'Ñ' | 'Ń' | 'Ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' | 'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ő' => "o",
+        'œ' | 'Œ' => "oe",
+        'ř' | 'Ř' => "r",
+        'ś' | 'š' | 'ş' | 'Ś' | 'Š' | 'Ş' => "s",
+        'ß' => "ss",
+        'ť' | 'ţ' | 'Ť' | 'Ţ' => "t",
+        'þ' | 'Þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ů' | 'Ű' => "u",
+        'ý' | 'ÿ' | 'Ý' => "y",
+        'ź' | 'ż' | 'ž' | 'Ź' | 'Ż' | 'Ž' => "z",
+        _ => "",
+    };
+    let plain = if folded.is_empty() { Some(c) } else { None };
+    folded.chars().chain(plain)
+}
+
+/// Gives a new book its slug, numbering it (`-2`, `-3`, ...) when another
+/// book already has the plain one.
+///
+/// Run inside the transaction that creates the book: concurrent creates
+/// of the same title wait for each other here until commit, so they can't
+/// both pick the same free number.
+pub async fn assign(conn: &mut PgConnection, book_id: i64, title: &str, year: i64) -> Result<String, AppError> {
+    let base = slugify(title, year);
+    sqlx::query!("SELECT pg_advisory_xact_lock(hashtext($1))", base)
+        .execute(&mut *conn)
+        .await?;
+    let taken: Vec<String> = sqlx::query_scalar!(
+        "SELECT slug AS \"slug!\" FROM books WHERE slug = $1 OR starts_with(slug, $1 || '-')",
+        base
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let slug = std::iter::once(base.clone())
+        .chain((2..).map(|n| format!("{}-{}", base, n)))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("the candidates never run out");
+
+    sqlx::query!("UPDATE books SET slug = $1 WHERE id = $2", slug, book_id)
+        .execute(conn)
+        .await?;
+    Ok(slug)
+}
@@ -0,0 +1,146 @@
+//! The text-message worker. With `SMS_ACCOUNT_SID` set, the server sends
+//! the SMS messages in the notification outbox itself, the way `mailer`
+//! sends email. Providers sit behind [`SmsSender`]; the one built in speaks
+//! Twilio's Messages API, which several other gateways also accept.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    AppError,
+    http_client::{BoxFuture, HttpClient, HttpRequest},
+    notifications::NotificationChannel,
+    queue,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const BATCH: i64 = 20;
+
+/// Why a text wasn't accepted.
+#[derive(Debug)]
+pub struct SmsError {
+    /// The provider refused it for good, e.g. the number doesn't exist.
+    pub permanent: bool,
+    pub message: String,
+}
+
+/// Sends one text message to a phone number.
+pub trait SmsSender: Send + Sync {
+    fn send<'a>(&'a self, to: &'a str, body: &'a str) -> BoxFuture<'a, Result<(), SmsError>>;
+}
+
+/// Set from `SMS_ACCOUNT_SID` and friends.
+#[derive(Clone)]
+pub struct SmsConfig {
+    /// `https://api.twilio.com`, or a compatible gateway.
+    pub api_url: String,
+    pub account_sid: String,
+    pub auth_token: String,
+    /// The sending number, or a messaging service SID (`MG...`).
+    pub from: String,
+}
+
+impl fmt::Debug for SmsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmsConfig")
+            .field("api_url", &self.api_url)
+            .field("account_sid", &self.account_sid)
+            .field("auth_token", &"***")
+            .field("from", &self.from)
+            .finish()
+    }
+}
+
+/// A provider with Twilio's Messages API.
+pub struct HttpSmsSender {
+    config: SmsConfig,
+    client: Arc<dyn HttpClient>,
+}
+
+impl HttpSmsSender {
+    pub fn new(config: SmsConfig, client: Arc<dyn HttpClient>) -> Self {
+        HttpSmsSender { config, client }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProviderError {
+    code: Option<i64>,
+    message: Option<String>,
+}
+
+impl SmsSender for HttpSmsSender {
+    fn send<'a>(&'a self, to: &'a str, body: &'a str) -> BoxFuture<'a, Result<(), SmsError>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/2010-04-01/Accounts/{}/Messages.json",
+                self.config.api_url.trim_end_matches('/'),
+                self.config.account_sid
+            );
+            let from = if self.config.from.starts_with("MG") { "MessagingServiceSid" } else { "From" };
+            let credentials = BASE64.encode(format!("{}:{}", self.config.account_sid, self.config.auth_token));
+            let request = HttpRequest::post_form(url, &[("To", to), (from, &self.config.from), ("Body", body)])
+                .header("authorization", format!("Basic {}", credentials));
+            let response = self
+                .client
+                .send(request)
+                .await
+                .map_err(|e| SmsError { permanent: false, message: e.to_string() })?;
+            if response.is_success() {
+                return Ok(());
+            }
+            let detail = match serde_json::from_str::<ProviderError>(&response.body) {
+                Ok(ProviderError { code: Some(code), message: Some(message) }) => format!("{} ({})", message, code),
+                Ok(ProviderError { message: Some(message), .. }) => message,
+                _ => response.body.chars().take(200).collect(),
+            };
+            // A 400 is about the message itself (an invalid or opted-out
+            // number); bad credentials and outages are worth retrying once
+            // they're fixed.
+            Err(SmsError { permanent: response.status == 400, message: format!("HTTP {}: {}", response.status, detail) })
+        })
+    }
+}
+
+/// Sends due texts every `POLL_INTERVAL`, forever.
+pub async fn run(pool: PgPool, sender: Arc<dyn SmsSender>) {
+    loop {
+        match deliver_due(&pool, sender.as_ref()).await {
+            Ok(_) => {}
+            Err(AppError::Database(e)) => eprintln!("SMS: {}", e),
+            Err(_) => eprintln!("SMS: could not record a delivery"),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Claims the SMS messages that are due, sends them, and reports each
+/// outcome. Returns how many were delivered.
+pub async fn deliver_due(pool: &PgPool, sender: &dyn SmsSender) -> Result<usize, AppError> {
+    let mut delivered = 0;
+    loop {
+        let mut conn = pool.acquire().await?;
+        let batch = queue::claim_due(&mut conn, Some(NotificationChannel::Sms), BATCH).await?;
+        drop(conn);
+        if batch.is_empty() {
+            return Ok(delivered);
+        }
+        for notification in batch {
+            let outcome = sender.send(&notification.recipient, &notification.body).await;
+            let mut tx = pool.begin().await?;
+            match outcome {
+                Ok(()) => {
+                    queue::record_delivered(&mut tx, notification.id).await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    queue::record_failure(&mut tx, notification.id, &e.message, e.permanent).await?;
+                }
+            }
+            tx.commit().await?;
+        }
+    }
+}
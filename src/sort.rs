@@ -0,0 +1,66 @@
+//! The `sort` query parameter on the book list, e.g. `sort=author,-year`:
+//! fields in priority order, `-` for descending.
+
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::AppError;
+
+/// Sortable fields and the expression each sorts by.
+const SORTABLE: &[(&str, &str)] = &[
+    ("id", "id"),
+    ("title", "title"),
+    ("author", "author"),
+    ("year", "year"),
+    ("isbn", "isbn"),
+    ("available", "available"),
+    // Shelf order rather than the call number's text.
+    ("classification", "classification_key"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    expression: &'static str,
+    descending: bool,
+}
+
+pub fn parse(value: Option<&str>) -> Result<Vec<SortKey>, AppError> {
+    let mut keys = Vec::new();
+    for term in value.unwrap_or_default().split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let (name, descending) = match term.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (term, false),
+        };
+        let expression = SORTABLE
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, expression)| *expression)
+            .ok_or_else(|| {
+                let fields: Vec<&str> = SORTABLE.iter().map(|(field, _)| *field).collect();
+                AppError::InvalidInput(format!("Cannot sort by '{}'; expected one of {}", name, fields.join(", ")))
+            })?;
+        if keys.iter().any(|k: &SortKey| k.expression == expression) {
+            return Err(AppError::InvalidInput(format!("'{}' is sorted on more than once", name)));
+        }
+        keys.push(SortKey { expression, descending });
+    }
+    Ok(keys)
+}
+
+/// Columns compared as human-readable text, using the catalog collation.
+const COLLATED: &[&str] = &["title", "author"];
+
+/// Appends ` ORDER BY ...`, ending with `id` so equal rows keep a stable
+/// order across pages. `collation` must name an existing collation; see
+/// `CatalogConfig::check`.
+pub fn push_order_by(query: &mut QueryBuilder<'_, Postgres>, keys: &[SortKey], collation: &str) {
+    query.push(" ORDER BY ");
+    for key in keys {
+        query.push(key.expression);
+        if COLLATED.contains(&key.expression) {
+            query.push(format!(" COLLATE \"{}\"", collation));
+        }
+        query.push(if key.descending { " DESC NULLS LAST, " } else { " ASC NULLS LAST, " });
+    }
+    let id_descending = keys.iter().any(|k| k.expression == "id" && k.descending);
+    query.push(if id_descending { "id DESC" } else { "id" });
+}
@@ -0,0 +1,455 @@
+//! SRU 1.2 (Search/Retrieve via URL) at `GET /sru`, for federated library
+//! search portals. Queries are a subset of CQL: clauses joined by `and` over
+//! keyword, title, author, ISBN, and date indexes. Records come back as
+//! MARCXML or Dublin Core. Bad requests get SRU diagnostics in a `200`
+//! response, as the protocol expects, rather than HTTP errors.
+
+use std::iter::Peekable;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sqlx::{PgPool, QueryBuilder};
+
+use crate::{
+    AppError,
+    api_keys::CatalogRead,
+    books::{Book, BookFilters, BookRow, count_books},
+    card::escape_html as escape,
+    config::CatalogConfig,
+    filter::{Condition, Field, Op},
+    formats::BookFormat,
+    query::Query,
+};
+
+const VERSION: &str = "1.2";
+const DEFAULT_MAXIMUM_RECORDS: usize = 10;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SruParams {
+    pub operation: Option<String>,
+    pub version: Option<String>,
+    pub query: Option<String>,
+    /// Numbers are taken as text so bad values get SRU diagnostics.
+    pub start_record: Option<String>,
+    pub maximum_records: Option<String>,
+    pub record_schema: Option<String>,
+}
+
+/// An SRU diagnostic: its number under `info:srw/diagnostic/1/`, the
+/// standard message, and what in the request caused it.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub code: u32,
+    pub message: &'static str,
+    pub details: String,
+}
+
+impl Diagnostic {
+    fn new(code: u32, message: &'static str, details: impl Into<String>) -> Self {
+        Diagnostic { code, message, details: details.into() }
+    }
+
+    fn syntax(details: impl Into<String>) -> Self {
+        Diagnostic::new(10, "Query syntax error", details)
+    }
+
+    fn unsupported_relation(relation: &str) -> Self {
+        Diagnostic::new(19, "Unsupported relation", relation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Schema {
+    MarcXml,
+    DublinCore,
+}
+
+impl Schema {
+    fn parse(name: Option<&str>) -> Result<Self, Diagnostic> {
+        match name.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("marcxml") | Some("info:srw/schema/1/marcxml-v1.1") => Ok(Schema::MarcXml),
+            Some("dc") | Some("info:srw/schema/1/dc-v1.1") => Ok(Schema::DublinCore),
+            Some(_) => Err(Diagnostic::new(66, "Unknown schema for retrieval", name.unwrap_or_default())),
+        }
+    }
+
+    fn uri(self) -> &'static str {
+        match self {
+            Schema::MarcXml => "info:srw/schema/1/marcxml-v1.1",
+            Schema::DublinCore => "info:srw/schema/1/dc-v1.1",
+        }
+    }
+}
+
+pub async fn sru(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogRead,
+    Query(params): Query<SruParams>,
+) -> Result<Response, AppError> {
+    let default_operation = if params.query.is_some() { "searchRetrieve" } else { "explain" };
+    let body = match params.version.as_deref() {
+        Some(version) if version != "1.1" && version != VERSION => {
+            diagnostic_response("searchRetrieveResponse", Diagnostic::new(5, "Unsupported version", VERSION))
+        }
+        _ => match params.operation.as_deref().unwrap_or(default_operation) {
+            "explain" => explain_response(),
+            "searchRetrieve" => search_retrieve(&pool, &catalog, &params).await?,
+            other => diagnostic_response("searchRetrieveResponse", Diagnostic::new(4, "Unsupported operation", other)),
+        },
+    };
+    Ok(([(header::CONTENT_TYPE, "application/xml; charset=utf-8")], body).into_response())
+}
+
+async fn search_retrieve(pool: &PgPool, catalog: &CatalogConfig, params: &SruParams) -> Result<String, AppError> {
+    let request = match SearchRequest::parse(params, catalog) {
+        Ok(request) => request,
+        Err(diagnostic) => return Ok(diagnostic_response("searchRetrieveResponse", diagnostic)),
+    };
+
+    let total = count_books(pool, &request.filters).await? as usize;
+    if request.start > total && total > 0 {
+        let diagnostic = Diagnostic::new(61, "First record position out of range", request.start.to_string());
+        return Ok(diagnostic_response("searchRetrieveResponse", diagnostic));
+    }
+
+    let mut select = QueryBuilder::new(
+        "SELECT id, title, author, year, isbn, available, temporary, classification_scheme, classification, slug, description, original_title, original_language, translator, age_rating,
+                format, narrator, duration_minutes, disc_count, file_count, deleted_at, version, uuid FROM books",
+    );
+    request.filters.push_where(&mut select);
+    select
+        .push(" ORDER BY id LIMIT ")
+        .push_bind(request.maximum as i64)
+        .push(" OFFSET ")
+        .push_bind((request.start - 1) as i64);
+    let rows: Vec<BookRow> = select.build_query_as().fetch_all(pool).await?;
+    let books: Vec<Book> = rows.into_iter().map(Book::from).collect();
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<searchRetrieveResponse xmlns=\"http://www.loc.gov/zing/srw/\">\
+         <version>{}</version><numberOfRecords>{}</numberOfRecords>",
+        VERSION, total
+    );
+    if !books.is_empty() {
+        xml.push_str("<records>");
+        for (i, book) in books.iter().enumerate() {
+            let data = match request.schema {
+                Schema::MarcXml => marcxml(book),
+                Schema::DublinCore => dublin_core(book),
+            };
+            xml.push_str(&format!(
+                "<record><recordSchema>{}</recordSchema><recordPacking>xml</recordPacking>\
+                 <recordData>{}</recordData><recordPosition>{}</recordPosition></record>",
+                request.schema.uri(),
+                data,
+                request.start + i
+            ));
+        }
+        xml.push_str("</records>");
+    }
+    let next = request.start + books.len();
+    if !books.is_empty() && next <= total {
+        xml.push_str(&format!("<nextRecordPosition>{}</nextRecordPosition>", next));
+    }
+    xml.push_str("</searchRetrieveResponse>");
+    Ok(xml)
+}
+
+struct SearchRequest {
+    filters: BookFilters,
+    /// 1-based, as in SRU.
+    start: usize,
+    maximum: usize,
+    schema: Schema,
+}
+
+impl SearchRequest {
+    fn parse(params: &SruParams, catalog: &CatalogConfig) -> Result<Self, Diagnostic> {
+        let query = params.query.as_deref().ok_or_else(|| Diagnostic::new(7, "Mandatory parameter not supplied", "query"))?;
+        let number = |value: &Option<String>, name: &str, default: usize| -> Result<usize, Diagnostic> {
+            value.as_deref().map_or(Ok(default), |v| {
+                v.trim().parse().map_err(|_| Diagnostic::new(6, "Unsupported parameter value", format!("{}={}", name, v)))
+            })
+        };
+        let start = number(&params.start_record, "startRecord", 1)?;
+        if start == 0 {
+            return Err(Diagnostic::new(6, "Unsupported parameter value", "startRecord=0"));
+        }
+        // Portals asking for more than a page get a page; SRU allows that.
+        let maximum = number(&params.maximum_records, "maximumRecords", DEFAULT_MAXIMUM_RECORDS)?.min(catalog.max_page_limit);
+        Ok(SearchRequest {
+            filters: parse_cql(query)?,
+            start,
+            maximum,
+            schema: Schema::parse(params.record_schema.as_deref())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Relation(String),
+    Open,
+    Close,
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(Diagnostic::syntax("unterminated quoted term")),
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            '<' | '>' | '=' => {
+                let mut relation = String::new();
+                while let Some(&c) = chars.peek().filter(|c| matches!(c, '<' | '>' | '=')) {
+                    relation.push(c);
+                    chars.next();
+                }
+                if !["=", "==", "<>", "<", ">", "<=", ">="].contains(&relation.as_str()) {
+                    return Err(Diagnostic::syntax(format!("unknown relation {}", relation)));
+                }
+                tokens.push(Token::Relation(relation));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace() && !"()\"<>=".contains(**c)) {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// CQL's named relations; a word in this position is a relation rather
+/// than the start of the next search term.
+const RELATION_WORDS: &[&str] = &["any", "all", "adj", "exact"];
+
+/// Turns a CQL query into book filters. Only `and` is supported between
+/// clauses, since the filters can't express alternatives.
+pub fn parse_cql(query: &str) -> Result<BookFilters, Diagnostic> {
+    let mut tokens = tokenize(query)?.into_iter().peekable();
+    let mut filters = BookFilters::default();
+    let mut keywords = Vec::new();
+    loop {
+        clause(&mut tokens, &mut filters, &mut keywords)?;
+        match tokens.next() {
+            None => break,
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("and") => {}
+            Some(Token::Word(word)) if ["or", "not", "prox"].contains(&word.to_ascii_lowercase().as_str()) => {
+                return Err(Diagnostic::new(37, "Unsupported boolean operator", word));
+            }
+            Some(other) => return Err(Diagnostic::syntax(format!("expected and, found {:?}", other))),
+        }
+    }
+    if !keywords.is_empty() {
+        filters.search = Some(keywords.join(" "));
+    }
+    Ok(filters)
+}
+
+fn term(token: Option<Token>) -> Result<String, Diagnostic> {
+    match token {
+        Some(Token::Word(text)) | Some(Token::Quoted(text)) => Ok(text),
+        Some(Token::Open) | Some(Token::Close) => Err(Diagnostic::syntax("parentheses are not supported")),
+        Some(Token::Relation(relation)) => Err(Diagnostic::syntax(format!("expected a term, found {}", relation))),
+        None => Err(Diagnostic::syntax("expected a term")),
+    }
+}
+
+fn clause(
+    tokens: &mut Peekable<impl Iterator<Item = Token>>,
+    filters: &mut BookFilters,
+    keywords: &mut Vec<String>,
+) -> Result<(), Diagnostic> {
+    let first = tokens.next();
+    let is_index = matches!(first, Some(Token::Word(_)))
+        && match tokens.peek() {
+            Some(Token::Relation(_)) => true,
+            Some(Token::Word(word)) => RELATION_WORDS.contains(&word.to_ascii_lowercase().as_str()),
+            _ => false,
+        };
+    if !is_index {
+        // A bare term searches everywhere.
+        return apply("cql.serverChoice", "=", term(first)?, filters, keywords);
+    }
+    let index = term(first)?;
+    let relation = match tokens.next() {
+        Some(Token::Relation(relation)) | Some(Token::Word(relation)) => relation.to_ascii_lowercase(),
+        _ => unreachable!("checked above"),
+    };
+    apply(&index, &relation, term(tokens.next())?, filters, keywords)
+}
+
+fn apply(
+    index: &str,
+    relation: &str,
+    term: String,
+    filters: &mut BookFilters,
+    keywords: &mut Vec<String>,
+) -> Result<(), Diagnostic> {
+    let name = index.to_ascii_lowercase();
+    let name = ["dc.", "bath.", "cql.", "rec."].iter().find_map(|prefix| name.strip_prefix(prefix)).unwrap_or(&name);
+    match name {
+        "serverchoice" | "anywhere" | "keywords" => keywords.push(match relation {
+            "=" | "all" => term,
+            "adj" | "==" | "exact" => format!("\"{}\"", term.replace('"', "")),
+            "any" => term.split_whitespace().collect::<Vec<_>>().join(" or "),
+            _ => return Err(Diagnostic::unsupported_relation(relation)),
+        }),
+        "title" | "creator" | "author" => {
+            let field = if name == "title" { Field::Title } else { Field::Author };
+            match relation {
+                "=" | "adj" => filters.conditions.push(Condition::contains(field, &term)),
+                "all" => filters.conditions.extend(term.split_whitespace().map(|word| Condition::contains(field, word))),
+                "==" | "exact" => filters.conditions.push(Condition::equals(field, &term)),
+                _ => return Err(Diagnostic::unsupported_relation(relation)),
+            }
+        }
+        "isbn" => match relation {
+            "=" | "==" | "exact" => filters.isbn = Some(term),
+            _ => return Err(Diagnostic::unsupported_relation(relation)),
+        },
+        "date" | "year" => {
+            let op = match relation {
+                "=" | "==" | "exact" => Op::Eq,
+                "<>" => Op::Ne,
+                "<" => Op::Lt,
+                ">" => Op::Gt,
+                "<=" => Op::Lte,
+                ">=" => Op::Gte,
+                _ => return Err(Diagnostic::unsupported_relation(relation)),
+            };
+            let year = term
+                .trim()
+                .parse()
+                .map_err(|_| Diagnostic::new(36, "Term in invalid format for index or relation", term.clone()))?;
+            filters.conditions.push(Condition::year(op, year));
+        }
+        _ => return Err(Diagnostic::new(16, "Unsupported index", index)),
+    }
+    Ok(())
+}
+
+fn diagnostic_response(root: &str, diagnostic: Diagnostic) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<{root} xmlns=\"http://www.loc.gov/zing/srw/\">\
+         <version>{}</version><numberOfRecords>0</numberOfRecords><diagnostics>\
+         <diagnostic xmlns=\"http://www.loc.gov/zing/srw/diagnostic/\"><uri>info:srw/diagnostic/1/{}</uri>\
+         <details>{}</details><message>{}</message></diagnostic></diagnostics></{root}>",
+        VERSION,
+        diagnostic.code,
+        escape(&diagnostic.details),
+        diagnostic.message,
+    )
+}
+
+fn explain_response() -> String {
+    let indexes = [
+        ("cql", "serverChoice", "Keywords in title, author, description, and contents"),
+        ("dc", "title", "Title"),
+        ("dc", "creator", "Author"),
+        ("dc", "date", "Year of publication"),
+        ("bath", "isbn", "ISBN"),
+    ]
+    .iter()
+    .map(|(set, name, title)| format!("<index><title>{}</title><map><name set=\"{}\">{}</name></map></index>", title, set, name))
+    .collect::<String>();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<explainResponse xmlns=\"http://www.loc.gov/zing/srw/\">\
+         <version>{VERSION}</version><record><recordSchema>http://explain.z3950.org/dtd/2.0/</recordSchema>\
+         <recordPacking>xml</recordPacking><recordData><explain xmlns=\"http://explain.z3950.org/dtd/2.0/\">\
+         <serverInfo protocol=\"SRU\" version=\"{VERSION}\"><database>books</database></serverInfo>\
+         <indexInfo><set name=\"cql\" identifier=\"info:srw/cql-context-set/1/cql-v1.2\"/>\
+         <set name=\"dc\" identifier=\"info:srw/cql-context-set/1/dc-v1.1\"/>\
+         <set name=\"bath\" identifier=\"http://zing.z3950.org/cql/bath/2.0/\"/>{indexes}</indexInfo>\
+         <schemaInfo><schema identifier=\"{}\" name=\"marcxml\"><title>MARCXML</title></schema>\
+         <schema identifier=\"{}\" name=\"dc\"><title>Dublin Core</title></schema></schemaInfo>\
+         <configInfo><default type=\"numberOfRecords\">{DEFAULT_MAXIMUM_RECORDS}</default></configInfo>\
+         </explain></recordData></record></explainResponse>",
+        Schema::MarcXml.uri(),
+        Schema::DublinCore.uri(),
+    )
+}
+
+fn datafield(tag: &str, indicators: (char, char), code: char, value: &str) -> String {
+    format!(
+        "<datafield tag=\"{}\" ind1=\"{}\" ind2=\"{}\"><subfield code=\"{}\">{}</subfield></datafield>",
+        tag,
+        indicators.0,
+        indicators.1,
+        code,
+        escape(value)
+    )
+}
+
+/// A minimal MARC 21 bibliographic record: ISBN (020), main entry (100),
+/// original title (240), title (245), date (264), and summary (520).
+pub fn marcxml(book: &Book) -> String {
+    // Leader position 6: language material, or a nonmusical sound recording
+    // for audiobooks.
+    let record_type = if book.format == BookFormat::Audiobook { 'i' } else { 'a' };
+    let mut xml = format!(
+        "<record xmlns=\"http://www.loc.gov/MARC21/slim\"><leader>00000n{}m a2200000 a 4500</leader>\
+         <controlfield tag=\"001\">{}</controlfield>",
+        record_type, book.id
+    );
+    xml.push_str(&datafield("020", (' ', ' '), 'a', &book.isbn));
+    xml.push_str(&datafield("100", ('1', ' '), 'a', &book.author));
+    if let Some(original_title) = &book.original_title {
+        xml.push_str(&datafield("240", ('1', '0'), 'a', original_title));
+    }
+    xml.push_str(&datafield("245", ('1', '0'), 'a', &book.title));
+    xml.push_str(&datafield("264", (' ', '1'), 'c', &book.year.to_string()));
+    if let Some(description) = &book.description {
+        xml.push_str(&datafield("520", (' ', ' '), 'a', description));
+    }
+    xml.push_str("</record>");
+    xml
+}
+
+pub fn dublin_core(book: &Book) -> String {
+    let mut xml = String::from(
+        "<srw_dc:dc xmlns:srw_dc=\"info:srw/schema/1/dc-schema\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">",
+    );
+    let mut element = |name: &str, value: &str| xml.push_str(&format!("<dc:{0}>{1}</dc:{0}>", name, escape(value)));
+    element("title", &book.title);
+    element("creator", &book.author);
+    element("date", &book.year.to_string());
+    element("identifier", &format!("urn:isbn:{}", book.isbn.replace('-', "")));
+    element("type", if book.format == BookFormat::Audiobook { "Sound" } else { "Text" });
+    if let Some(description) = &book.description {
+        element("description", description);
+    }
+    xml.push_str("</srw_dc:dc>");
+    xml
+}
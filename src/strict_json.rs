@@ -0,0 +1,70 @@
+//! Request bodies that can refuse unknown fields. With `STRICT_JSON` off
+//! this behaves exactly like axum's `Json`.
+
+use axum::{
+    Json,
+    extract::{FromRef, FromRequest, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+
+use crate::{AppError, CatalogConfig, FieldError};
+
+/// A JSON body that, in strict mode, fails with a field error for every
+/// key `T` would otherwise silently ignore, nested ones included (e.g.
+/// `audiobook.narator`).
+pub(crate) struct StrictJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for StrictJson<T>
+where
+    T: DeserializeOwned,
+    CatalogConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !CatalogConfig::from_ref(state).strict_json {
+            let Json(value) = Json::<T>::from_request(req, state).await.map_err(IntoResponse::into_response)?;
+            return Ok(StrictJson(value));
+        }
+
+        let Json(value) = Json::<serde_json::Value>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let mut unknown = Vec::new();
+        let parsed: Result<T, _> = serde_ignored::deserialize(value, |path| unknown.push(field_name(&path)));
+        if !unknown.is_empty() {
+            let errors = unknown
+                .into_iter()
+                .map(|field| FieldError { field, message: "is not a known field".to_string() })
+                .collect();
+            return Err(AppError::InvalidFields(errors).into_response());
+        }
+        let value = parsed.map_err(|e| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to deserialize the JSON body into the target type: {}", e),
+            )
+                .into_response()
+        })?;
+        Ok(StrictJson(value))
+    }
+}
+
+/// Dotted field name for an ignored key, e.g. `audiobook.narator`. Unlike
+/// `Path`'s own `Display`, this leaves out the `?` that `Option` adds.
+fn field_name(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => join(field_name(parent), &index.to_string()),
+        Path::Map { parent, key } => join(field_name(parent), key),
+        Path::Some { parent } | Path::NewtypeStruct { parent } | Path::NewtypeVariant { parent } => field_name(parent),
+    }
+}
+
+fn join(parent: String, key: &str) -> String {
+    if parent.is_empty() { key.to_string() } else { format!("{}.{}", parent, key) }
+}
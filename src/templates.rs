@@ -0,0 +1,70 @@
+//! The wording of every message the library sends members. Callers pick a
+//! template and queue the subject and body it renders; the channel adds
+//! its own framing when delivering (see `mailer` for email).
+//!
+//! Messages that carry a code end with it, on a line of its own, so it is
+//! easy to copy, and so `hide_code` can blank it wherever a stored message
+//! is shown back through the API.
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Template<'a> {
+    EmailVerification { name: &'a str, token: &'a str, hours: i64 },
+    PasswordReset { name: &'a str, token: &'a str, minutes: i64 },
+    DueSoon { title: &'a str, due_date: DateTime<Utc> },
+    Overdue { title: &'a str, due_date: DateTime<Utc> },
+    HoldReady { title: &'a str, pick_up_by: DateTime<Utc> },
+}
+
+impl Template<'_> {
+    pub fn subject(&self) -> &'static str {
+        match self {
+            Template::EmailVerification { .. } => "Confirm your email address",
+            Template::PasswordReset { .. } => "Reset your password",
+            Template::DueSoon { .. } => "A loan is due soon",
+            Template::Overdue { .. } => "A loan is overdue",
+            Template::HoldReady { .. } => "Your hold is ready",
+        }
+    }
+
+    pub fn body(&self) -> String {
+        match self {
+            Template::EmailVerification { name, token, hours } => format!(
+                "Hello {},\n\nPlease confirm your email address for your library account. \
+                 This code expires in {} hours:\n\n{}\n",
+                name, hours, token
+            ),
+            Template::PasswordReset { name, token, minutes } => format!(
+                "Hello {},\n\nSomeone asked to reset the password for your library account. \
+                 If it wasn't you, ignore this message. This code expires in {} minutes:\n\n{}\n",
+                name, minutes, token
+            ),
+            Template::DueSoon { title, due_date } => {
+                format!("\"{}\" is due back on {}.", title, due_date.format("%Y-%m-%d"))
+            }
+            Template::Overdue { title, due_date } => format!(
+                "\"{}\" was due back on {}. Please return it as soon as possible.",
+                title,
+                due_date.format("%Y-%m-%d")
+            ),
+            Template::HoldReady { title, pick_up_by } => format!(
+                "\"{}\" is waiting for you at the library. Please pick it up by {}.",
+                title,
+                pick_up_by.format("%Y-%m-%d")
+            ),
+        }
+    }
+}
+
+/// Shown instead of a code in stored messages.
+pub const HIDDEN_CODE: &str = "[code hidden]";
+
+/// Replaces the code on the last line of a message that carries one.
+pub fn hide_code(body: &str) -> String {
+    let trimmed = body.trim_end_matches('\n');
+    match trimmed.rsplit_once('\n') {
+        Some((text, _)) => format!("{}\n{}\n", text, HIDDEN_CODE),
+        None => HIDDEN_CODE.to_string(),
+    }
+}
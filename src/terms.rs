@@ -0,0 +1,180 @@
+//! Versions of the library's terms of use and which members accepted them.
+//! The most recently published version is the current one; members must
+//! accept it before borrowing.
+
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, api_keys::AdminAccess, auth::AuthMember};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermsVersion {
+    pub version: String,
+    pub body: String,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermsAcceptance {
+    pub member_id: i64,
+    pub version: String,
+    pub accepted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PublishTerms {
+    version: String,
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptTerms {
+    version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberTermsStatus {
+    pub current_version: Option<String>,
+    pub accepted: Vec<TermsAcceptance>,
+    /// Whether the member has accepted the current version (or none exists).
+    pub up_to_date: bool,
+}
+
+async fn current(conn: &mut PgConnection) -> Result<Option<TermsVersion>, AppError> {
+    let terms = sqlx::query_as!(
+        TermsVersion,
+        "SELECT version, body, published_at FROM terms_versions ORDER BY published_at DESC, id DESC LIMIT 1"
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(terms)
+}
+
+pub async fn acceptances_for_member(
+    conn: &mut PgConnection,
+    member_id: i64,
+) -> Result<Vec<TermsAcceptance>, AppError> {
+    let accepted = sqlx::query_as!(
+        TermsAcceptance,
+        "SELECT member_id, version, accepted_at FROM terms_acceptances
+         WHERE member_id = $1 ORDER BY accepted_at DESC",
+        member_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(accepted)
+}
+
+pub async fn get_current_terms(State(pool): State<PgPool>) -> Result<Json<TermsVersion>, AppError> {
+    let mut conn = pool.acquire().await?;
+    current(&mut conn)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::ResourceNotFoundBy("Terms", "status", "current".to_string()))
+}
+
+/// Publishes a new version, which becomes current immediately. Members who
+/// accepted an earlier version must accept this one before borrowing again.
+/// Admins only, since publishing blocks every member's borrowing.
+pub async fn publish_terms(
+    State(pool): State<PgPool>,
+    _access: AdminAccess,
+    Json(input): Json<PublishTerms>,
+) -> Result<(StatusCode, Json<TermsVersion>), AppError> {
+    let version = input.version.trim();
+    if version.is_empty() || input.body.trim().is_empty() {
+        return Err(AppError::InvalidInput("Terms version and body must not be empty".to_string()));
+    }
+
+    let terms = sqlx::query_as!(
+        TermsVersion,
+        "INSERT INTO terms_versions (version, body, published_at) VALUES ($1, $2, $3)
+         ON CONFLICT (version) DO NOTHING
+         RETURNING version, body, published_at",
+        version,
+        input.body,
+        Utc::now(),
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| AppError::Conflict(format!("Terms version {} has already been published", version)))?;
+
+    Ok((StatusCode::CREATED, Json(terms)))
+}
+
+pub async fn my_terms(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+) -> Result<Json<MemberTermsStatus>, AppError> {
+    let mut conn = pool.acquire().await?;
+    let current_version = current(&mut conn).await?.map(|t| t.version);
+    let accepted = acceptances_for_member(&mut conn, member.id).await?;
+
+    let up_to_date = current_version
+        .as_ref()
+        .is_none_or(|v| accepted.iter().any(|a| &a.version == v));
+    Ok(Json(MemberTermsStatus { current_version, accepted, up_to_date }))
+}
+
+/// Records the member's acceptance. Only the current version can be
+/// accepted, so a client can't agree to terms the member wasn't shown.
+pub async fn accept_terms(
+    State(pool): State<PgPool>,
+    AuthMember(member): AuthMember,
+    Json(input): Json<AcceptTerms>,
+) -> Result<Json<TermsAcceptance>, AppError> {
+    let mut conn = pool.acquire().await?;
+    let current = current(&mut conn)
+        .await?
+        .ok_or_else(|| AppError::ResourceNotFoundBy("Terms", "status", "current".to_string()))?;
+    if input.version.trim() != current.version {
+        return Err(AppError::Conflict(format!(
+            "Terms version {} is not current; the current version is {}",
+            input.version.trim(),
+            current.version
+        )));
+    }
+
+    let acceptance = sqlx::query_as!(
+        TermsAcceptance,
+        "INSERT INTO terms_acceptances (member_id, version, accepted_at) VALUES ($1, $2, $3)
+         ON CONFLICT (member_id, version) DO UPDATE SET accepted_at = terms_acceptances.accepted_at
+         RETURNING member_id, version, accepted_at",
+        member.id,
+        current.version,
+        Utc::now(),
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Json(acceptance))
+}
+
+/// Refuses to lend to a member who hasn't accepted the current terms.
+pub async fn require_current_accepted(conn: &mut PgConnection, member_id: i64) -> Result<(), AppError> {
+    let Some(current) = current(&mut *conn).await? else {
+        return Ok(());
+    };
+    let accepted = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM terms_acceptances WHERE member_id = $1 AND version = $2)",
+        member_id,
+        current.version,
+    )
+    .fetch_one(conn)
+    .await?
+    .unwrap_or(false);
+
+    if !accepted {
+        return Err(AppError::Forbidden(format!(
+            "The member must accept terms version {} before borrowing",
+            current.version
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Helpers for tests that drive the API end to end: this crate's own suite,
+//! and integration tests elsewhere with the `test-util` feature enabled.
+//! They need a scratch database in `TEST_DATABASE_URL`, which is wiped on
+//! every [`TestApp::new`].
+//!
+//! ```ignore
+//! let app = TestApp::new().await;
+//! let (status, _) = app.send(catalog_request("POST", "/books", r#"{"title": ...}"#)).await;
+//! assert_eq!(status, StatusCode::CREATED);
+//! ```
+
+use axum::{
+    Router,
+    body::Body,
+    http::{HeaderValue, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use tower::ServiceExt;
+
+use crate::{AppState, api_keys::API_KEY_HEADER, auth, build_router, config::{AuthConfig, CatalogConfig}, query_cache::QueryCache, seed};
+
+/// An `X-Api-Key` with the `write` scope, issued on every [`test_pool`],
+/// for tests that change the catalog.
+pub const CATALOG_KEY: &str = "lib_test-catalog-writer";
+
+/// A migrated, empty test database, apart from [`CATALOG_KEY`]. One
+/// connection, so a test sees its own writes in order.
+pub async fn test_pool() -> PgPool {
+    dotenvy::dotenv().ok();
+
+    let db_url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must be set");
+
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&db_url)
+        .await
+        .unwrap();
+
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .unwrap();
+
+    sqlx::query!("TRUNCATE TABLE reading_challenges, chat_messages, chat_webhooks, saml_assertions, maintenance, feature_flags, api_keys, recovery_codes, notification_preferences, terms_acceptances, terms_versions, audit_log, login_throttles, notifications, member_tokens, fines, holds, sessions, members, weeding_candidates, acquisition_requests, budgets, vendors, copies, ill_requests, borrowings, books RESTART IDENTITY CASCADE")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    sqlx::query!(
+        "INSERT INTO api_keys (label, prefix, key_hash, scopes, created_at) VALUES ('Tests', $1, $2, ARRAY['write'], NOW())",
+        &CATALOG_KEY[..12],
+        auth::hash_token(CATALOG_KEY),
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    pool
+}
+
+/// The router with default configuration.
+pub fn make_app(pool: PgPool) -> Router {
+    build_router(AppState { pool, auth: AuthConfig::default(), catalog: CatalogConfig::default(), cache: Default::default() })
+}
+
+/// Runs one request and returns the status and the whole body.
+pub async fn send(app: Router, req: Request<Body>) -> (StatusCode, Vec<u8>) {
+    let response = app.oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes().to_vec();
+    (status, body)
+}
+
+pub fn json_request(method: &str, uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Like [`json_request`], sent with [`CATALOG_KEY`].
+pub fn catalog_request(method: &str, uri: &str, body: &str) -> Request<Body> {
+    let mut request = json_request(method, uri, body);
+    request.headers_mut().insert(API_KEY_HEADER, HeaderValue::from_static(CATALOG_KEY));
+    request
+}
+
+/// The app over a fresh test database. Change `auth` or `catalog` before
+/// sending to test other configurations.
+pub struct TestApp {
+    pub pool: PgPool,
+    pub auth: AuthConfig,
+    pub catalog: CatalogConfig,
+    /// Shared by every request, like the server's.
+    pub cache: QueryCache,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        TestApp {
+            pool: test_pool().await,
+            auth: AuthConfig::default(),
+            catalog: CatalogConfig::default(),
+            cache: QueryCache::default(),
+        }
+    }
+
+    /// Starts from the demo fixtures instead of an empty catalog.
+    pub async fn with_fixtures() -> Self {
+        let test_app = TestApp::new().await;
+        assert!(seed::load_fixtures(&test_app.pool).await.is_ok(), "the fixtures failed to load");
+        test_app
+    }
+
+    pub fn router(&self) -> Router {
+        build_router(AppState { pool: self.pool.clone(), auth: self.auth.clone(), catalog: self.catalog.clone(), cache: self.cache.clone() })
+    }
+
+    pub async fn send(&self, req: Request<Body>) -> (StatusCode, Vec<u8>) {
+        send(self.router(), req).await
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Vec<u8>) {
+        self.send(Request::builder().uri(uri).body(Body::empty()).unwrap()).await
+    }
+}
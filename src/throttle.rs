@@ -0,0 +1,87 @@
+//! Login throttling. Failed attempts are counted per account and per client
+//! address; after a few free attempts each further failure doubles the wait
+//! before the next try, and a long run of failures locks the account out.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgConnection;
+
+use crate::AppError;
+
+/// Failures allowed before any delay applies.
+const FREE_ATTEMPTS: i32 = 3;
+/// Failures after which the key is locked out rather than delayed.
+pub const LOCKOUT_THRESHOLD: i32 = 10;
+const LOCKOUT_MINUTES: i64 = 15;
+/// A failure this long after the previous one starts the count again.
+const FAILURE_WINDOW_HOURS: i64 = 1;
+
+pub fn member_key(member_id: i64) -> String {
+    format!("member:{}", member_id)
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// How long to refuse logins after `failures` consecutive failures.
+fn backoff(failures: i32) -> Option<Duration> {
+    if failures >= LOCKOUT_THRESHOLD {
+        Some(Duration::minutes(LOCKOUT_MINUTES))
+    } else if failures >= FREE_ATTEMPTS {
+        Some(Duration::seconds(1 << (failures - FREE_ATTEMPTS)))
+    } else {
+        None
+    }
+}
+
+/// Rejects the attempt with 429 if any of `keys` is still blocked.
+pub async fn check(conn: &mut PgConnection, keys: &[String]) -> Result<(), AppError> {
+    let now = Utc::now();
+    let blocked_until: Option<DateTime<Utc>> = sqlx::query_scalar!(
+        "SELECT MAX(blocked_until) FROM login_throttles WHERE key = ANY($1) AND blocked_until > $2",
+        keys,
+        now,
+    )
+    .fetch_one(conn)
+    .await?;
+
+    match blocked_until {
+        Some(until) => Err(AppError::TooManyRequests((until - now).num_seconds() + 1)),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed attempt against `key` and returns the new failure count.
+pub async fn record_failure(conn: &mut PgConnection, key: &str) -> Result<i32, AppError> {
+    let now = Utc::now();
+    let failures = sqlx::query_scalar!(
+        "INSERT INTO login_throttles (key, failures, last_failed_at) VALUES ($1, 1, $2)
+         ON CONFLICT (key) DO UPDATE SET
+             failures = CASE WHEN login_throttles.last_failed_at < $3 THEN 1
+                             ELSE login_throttles.failures + 1 END,
+             last_failed_at = $2
+         RETURNING failures",
+        key,
+        now,
+        now - Duration::hours(FAILURE_WINDOW_HOURS),
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE login_throttles SET blocked_until = $1 WHERE key = $2",
+        backoff(failures).map(|wait| now + wait),
+        key,
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(failures)
+}
+
+pub async fn clear(conn: &mut PgConnection, key: &str) -> Result<(), AppError> {
+    sqlx::query!("DELETE FROM login_throttles WHERE key = $1", key)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,18 @@
+//! TLS settings for the integrations that open their own connections
+//! (LDAP). Servers are checked against the public root certificates.
+
+use std::sync::{Arc, OnceLock};
+
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+/// Client settings trusting the public roots, built once.
+pub fn client_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut roots = RootCertStore::empty();
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+        })
+        .clone()
+}
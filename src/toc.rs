@@ -0,0 +1,100 @@
+//! Tables of contents: the chapters of a book with the page each starts on.
+//! Chapter titles are also matched by the book list's `q` search.
+
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{AppError, api_keys::{CatalogRead, CatalogWrite}, books::BookId, conditional};
+
+/// Longest table of contents accepted in one request.
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub title: String,
+    pub page: Option<i64>,
+}
+
+/// A chapter tagged with its book, for loading several books' contents at
+/// once.
+pub struct BookTocEntry {
+    pub book_id: i64,
+    pub entry: TocEntry,
+}
+
+pub async fn get_toc(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    BookId(book_id): BookId,
+) -> Result<Json<Vec<TocEntry>>, AppError> {
+    let mut conn = pool.acquire().await?;
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)", book_id)
+        .fetch_one(&mut *conn)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(AppError::NotFound(book_id));
+    }
+
+    let entries = for_books(&mut conn, &[book_id]).await?;
+    Ok(Json(entries.into_iter().map(|e| e.entry).collect()))
+}
+
+/// Replaces the book's table of contents with `entries`, in the order
+/// given. An empty list removes it.
+pub async fn put_toc(
+    State(pool): State<PgPool>,
+    _scope: CatalogWrite,
+    BookId(book_id): BookId,
+    Json(entries): Json<Vec<TocEntry>>,
+) -> Result<Json<Vec<TocEntry>>, AppError> {
+    if entries.len() > MAX_ENTRIES {
+        return Err(AppError::InvalidInput(format!("A table of contents can have at most {} entries", MAX_ENTRIES)));
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.title.trim().is_empty() {
+            return Err(AppError::InvalidInput(format!("Entry {}: title must not be empty", i)));
+        }
+        if entry.page.is_some_and(|p| p < 1) {
+            return Err(AppError::InvalidInput(format!("Entry {}: page must be at least 1", i)));
+        }
+    }
+
+    let mut tx = pool.begin().await?;
+    conditional::touch_book(&mut tx, book_id).await?;
+    sqlx::query!("DELETE FROM book_toc_entries WHERE book_id = $1", book_id)
+        .execute(&mut *tx)
+        .await?;
+    let positions: Vec<i32> = (0..entries.len() as i32).collect();
+    let titles: Vec<String> = entries.iter().map(|e| e.title.clone()).collect();
+    let pages: Vec<Option<i64>> = entries.iter().map(|e| e.page).collect();
+    sqlx::query!(
+        "INSERT INTO book_toc_entries (book_id, position, title, page)
+         SELECT $1, * FROM UNNEST($2::int[], $3::text[], $4::bigint[])",
+        book_id,
+        &positions,
+        &titles,
+        &pages as &[Option<i64>],
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(entries))
+}
+
+/// The contents of all `book_ids`, in reading order within each book.
+pub async fn for_books(conn: &mut PgConnection, book_ids: &[i64]) -> Result<Vec<BookTocEntry>, AppError> {
+    let rows = sqlx::query!(
+        "SELECT book_id, title, page FROM book_toc_entries WHERE book_id = ANY($1) ORDER BY book_id, position",
+        book_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| BookTocEntry { book_id: r.book_id, entry: TocEntry { title: r.title, page: r.page } })
+        .collect())
+}
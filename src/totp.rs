@@ -0,0 +1,97 @@
+//! Time-based one-time passwords (RFC 6238) as produced by authenticator
+//! apps: HMAC-SHA1, six digits, 30-second steps.
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from one step either side are accepted to allow for clock drift.
+const DRIFT_STEPS: i64 = 1;
+const SECRET_BYTES: usize = 20;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::RngCore::fill_bytes(&mut rand::rng(), &mut secret);
+    base32_encode(&secret)
+}
+
+/// The `otpauth://` URI authenticator apps import, usually from a QR code.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = percent_encode(issuer),
+        account = percent_encode(account),
+    )
+}
+
+/// Checks `code` against the steps around `unix_time` and returns the step
+/// it matched, so the caller can refuse to accept that step again.
+pub fn verify(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let key = base32_decode(secret)?;
+    let code: u32 = code.trim().parse().ok().filter(|_| code.trim().len() == DIGITS as usize)?;
+    let current = unix_time.div_euclid(STEP_SECONDS);
+    (current - DRIFT_STEPS..=current + DRIFT_STEPS).find(|&step| code_at(&key, step) == code)
+}
+
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation: the low nibble of the last byte picks four bytes.
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    (value & 0x7fff_ffff) % 10u32.pow(DIGITS)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(value.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in value.bytes().filter(|b| *b != b'=') {
+        let index = BASE32_ALPHABET.iter().position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | index as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub fn code_for(secret: &str, unix_time: i64) -> String {
+    let key = base32_decode(secret).unwrap();
+    format!("{:0width$}", code_at(&key, unix_time.div_euclid(STEP_SECONDS)), width = DIGITS as usize)
+}
@@ -0,0 +1,214 @@
+//! Titles and descriptions in other languages, chosen per request from the
+//! client's `Accept-Language`.
+
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError, CatalogConfig,
+    api_keys::{CatalogRead, CatalogWrite},
+    books::{Book, BookId},
+    conditional, config,
+    validation::check_field_lengths,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookTranslation {
+    pub book_id: i64,
+    pub language: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutTranslation {
+    title: String,
+    description: Option<String>,
+}
+
+pub async fn list_translations(
+    State(pool): State<PgPool>,
+    _scope: CatalogRead,
+    BookId(book_id): BookId,
+) -> Result<Json<Vec<BookTranslation>>, AppError> {
+    ensure_book_exists(&pool, book_id).await?;
+    let translations = for_books(&mut *pool.acquire().await?, &[book_id]).await?;
+    Ok(Json(translations))
+}
+
+/// Adds or replaces the book's translation into `language`.
+pub async fn put_translation(
+    State(pool): State<PgPool>,
+    State(catalog): State<CatalogConfig>,
+    _scope: CatalogWrite,
+    BookId(book_id): BookId,
+    Path((_, language)): Path<(String, String)>,
+    Json(input): Json<PutTranslation>,
+) -> Result<Json<BookTranslation>, AppError> {
+    check_field_lengths(&catalog.field_limits, Some(&input.title), None, None, input.description.as_deref())?;
+    let language = normalize_language(&language)?;
+    if input.title.trim().is_empty() {
+        return Err(AppError::InvalidInput("Translated title must not be empty".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+    conditional::touch_book(&mut tx, book_id).await?;
+    let translation = sqlx::query_as!(
+        BookTranslation,
+        "INSERT INTO book_translations (book_id, language, title, description, updated_at)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (book_id, language)
+         DO UPDATE SET title = EXCLUDED.title, description = EXCLUDED.description, updated_at = EXCLUDED.updated_at
+         RETURNING book_id, language, title, description, updated_at",
+        book_id,
+        language,
+        input.title,
+        input.description,
+        Utc::now(),
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(translation))
+}
+
+pub async fn delete_translation(
+    State(pool): State<PgPool>,
+    _scope: CatalogWrite,
+    BookId(book_id): BookId,
+    Path((_, language)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let language = normalize_language(&language)?;
+
+    let mut tx = pool.begin().await?;
+    conditional::touch_book(&mut tx, book_id).await?;
+    let deleted = sqlx::query!(
+        "DELETE FROM book_translations WHERE book_id = $1 AND language = $2",
+        book_id,
+        language
+    )
+    .execute(&mut *tx)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::ResourceNotFoundBy("Translation", "language", language));
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replaces each book's title and description with the best translation
+/// for `preferred`, leaving books in the catalog's own language (or with no
+/// matching translation) untouched.
+pub async fn localize(
+    conn: &mut PgConnection,
+    books: &mut [Book],
+    preferred: &[String],
+    catalog_locale: &str,
+) -> Result<(), AppError> {
+    if preferred.is_empty() || books.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i64> = books.iter().map(|b| b.id).collect();
+    let mut by_book: HashMap<i64, Vec<BookTranslation>> = HashMap::new();
+    for translation in for_books(conn, &ids).await? {
+        by_book.entry(translation.book_id).or_default().push(translation);
+    }
+
+    let catalog_locale = catalog_locale.to_ascii_lowercase();
+    for book in books {
+        let Some(mut available) = by_book.remove(&book.id) else { continue };
+        // Moved rather than cloned: nothing else needs the fetched rows.
+        if let Some(i) = pick(preferred, &available, &catalog_locale) {
+            let translation = available.swap_remove(i);
+            book.title = translation.title;
+            book.description = translation.description;
+            book.language = Some(translation.language);
+        }
+    }
+    Ok(())
+}
+
+/// Languages from `Accept-Language`, most preferred first. Wildcards and
+/// languages refused with `q=0` are dropped.
+pub fn preferred_languages(headers: &HeaderMap) -> Vec<String> {
+    let Some(value) = headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()) else {
+        return Vec::new();
+    };
+
+    let mut ranked: Vec<(String, f32)> = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (q > 0.0 && tag != "*" && config::is_language_tag(&tag)).then_some((tag, q))
+        })
+        .collect();
+    // Stable, so equally weighted languages keep the client's order.
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// RFC 4647 lookup: each preferred tag is tried as is and then with
+/// subtags removed (`pt-br`, then `pt`). Reaching the catalog's own language
+/// first means the original record is preferred.
+/// Index in `available` of the translation to serve, if any. `catalog_locale`
+/// must be lowercase.
+fn pick(preferred: &[String], available: &[BookTranslation], catalog_locale: &str) -> Option<usize> {
+    for tag in preferred {
+        let mut candidate = tag.as_str();
+        loop {
+            if candidate == catalog_locale {
+                return None;
+            }
+            if let Some(i) = available.iter().position(|t| t.language == candidate) {
+                return Some(i);
+            }
+            match candidate.rfind('-') {
+                Some(i) => candidate = &candidate[..i],
+                None => break,
+            }
+        }
+    }
+    None
+}
+
+async fn for_books(conn: &mut PgConnection, book_ids: &[i64]) -> Result<Vec<BookTranslation>, AppError> {
+    Ok(sqlx::query_as!(
+        BookTranslation,
+        "SELECT book_id, language, title, description, updated_at
+         FROM book_translations WHERE book_id = ANY($1) ORDER BY book_id, language",
+        book_ids
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+fn normalize_language(language: &str) -> Result<String, AppError> {
+    let language = language.trim().to_ascii_lowercase();
+    if !config::is_language_tag(&language) {
+        return Err(AppError::InvalidInput(format!("{} is not a language tag such as fr or pt-BR", language)));
+    }
+    Ok(language)
+}
+
+async fn ensure_book_exists(pool: &PgPool, book_id: i64) -> Result<(), AppError> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)", book_id)
+        .fetch_one(pool)
+        .await?
+        .unwrap_or(false);
+    if exists { Ok(()) } else { Err(AppError::NotFound(book_id)) }
+}
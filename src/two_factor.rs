@@ -0,0 +1,214 @@
+//! Optional TOTP two-factor authentication for staff accounts: enrollment
+//! with a QR code for authenticator apps, and single-use recovery codes for
+//! when the device is lost.
+
+use axum::{
+    Json,
+    extract::State,
+    http::header,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppError,
+    audit::{self, AuditEvent},
+    auth::{self, ClientIp, SessionMember},
+    barcode, totp,
+};
+
+const ISSUER: &str = "Library";
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Enrollment {
+    /// Base32 secret, for apps that can't scan the QR code.
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEnrollment {
+    code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodes {
+    /// Shown once; only hashes are stored.
+    pub recovery_codes: Vec<String>,
+}
+
+/// The second-factor outcome of a login whose password was correct.
+pub enum LoginFactor {
+    NotEnrolled,
+    Missing,
+    Passed,
+    Failed,
+}
+
+struct TwoFactorRow {
+    totp_secret: Option<String>,
+    totp_enabled_at: Option<chrono::DateTime<Utc>>,
+    totp_last_step: Option<i64>,
+}
+
+async fn two_factor_row(conn: &mut PgConnection, member_id: i64) -> Result<TwoFactorRow, AppError> {
+    let row = sqlx::query_as!(
+        TwoFactorRow,
+        "SELECT totp_secret, totp_enabled_at, totp_last_step FROM members WHERE id = $1",
+        member_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(row)
+}
+
+pub async fn is_enabled(pool: &PgPool, member_id: i64) -> Result<bool, AppError> {
+    let mut conn = pool.acquire().await?;
+    Ok(two_factor_row(&mut conn, member_id).await?.totp_enabled_at.is_some())
+}
+
+/// Starts (or restarts) enrollment with a fresh secret. Two-factor isn't
+/// enforced until the member confirms a code from their authenticator.
+pub async fn enroll(
+    State(pool): State<PgPool>,
+    SessionMember(member): SessionMember,
+) -> Result<Json<Enrollment>, AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Two-factor authentication is available to staff accounts".to_string()));
+    }
+    let mut conn = pool.acquire().await?;
+    if two_factor_row(&mut conn, member.id).await?.totp_enabled_at.is_some() {
+        return Err(AppError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    sqlx::query!(
+        "UPDATE members SET totp_secret = $1, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = $2",
+        secret,
+        member.id,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let provisioning_uri = totp::provisioning_uri(&secret, ISSUER, &member.card_number);
+    Ok(Json(Enrollment { secret, provisioning_uri }))
+}
+
+/// The pending enrollment's provisioning URI as a QR code to scan.
+pub async fn enrollment_qr(
+    State(pool): State<PgPool>,
+    SessionMember(member): SessionMember,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = pool.acquire().await?;
+    let row = two_factor_row(&mut conn, member.id).await?;
+    let secret = match (row.totp_secret, row.totp_enabled_at) {
+        (Some(secret), None) => secret,
+        _ => return Err(AppError::Conflict("There is no pending two-factor enrollment".to_string())),
+    };
+
+    let uri = totp::provisioning_uri(&secret, ISSUER, &member.card_number);
+    let png = barcode::qr_png(&uri)
+        .ok_or_else(|| AppError::InvalidInput("Provisioning URI is too long for a QR code".to_string()))?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
+/// Completes enrollment once the member proves their authenticator works,
+/// and hands out a fresh set of recovery codes.
+pub async fn confirm_enrollment(
+    State(pool): State<PgPool>,
+    SessionMember(member): SessionMember,
+    ClientIp(ip): ClientIp,
+    Json(input): Json<ConfirmEnrollment>,
+) -> Result<Json<RecoveryCodes>, AppError> {
+    let mut tx = pool.begin().await?;
+    let row = two_factor_row(&mut tx, member.id).await?;
+    let secret = match (row.totp_secret, row.totp_enabled_at) {
+        (Some(secret), None) => secret,
+        (_, Some(_)) => return Err(AppError::Conflict("Two-factor authentication is already enabled".to_string())),
+        (None, None) => return Err(AppError::Conflict("Start enrollment first (POST /me/2fa/enroll)".to_string())),
+    };
+    let step = totp::verify(&secret, &input.code, Utc::now().timestamp())
+        .ok_or_else(|| AppError::InvalidInput("The code doesn't match; check the authenticator's clock".to_string()))?;
+
+    sqlx::query!(
+        "UPDATE members SET totp_enabled_at = $1, totp_last_step = $2 WHERE id = $3",
+        Utc::now(),
+        step,
+        member.id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE member_id = $1", member.id)
+        .execute(&mut *tx)
+        .await?;
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| new_recovery_code()).collect();
+    let hashes: Vec<String> = recovery_codes.iter().map(|c| auth::hash_token(&normalize_recovery_code(c))).collect();
+    sqlx::query!(
+        "INSERT INTO recovery_codes (member_id, code_hash) SELECT $1, UNNEST($2::text[])",
+        member.id,
+        &hashes,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    audit::record(&mut tx, AuditEvent::TwoFactorEnabled, Some(member.id), ip.as_deref(), "").await?;
+    tx.commit().await?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Checks the `otp` sent with a login: an authenticator code not used
+/// before, or an unused recovery code, which is then spent.
+pub async fn check_login(
+    conn: &mut PgConnection,
+    member_id: i64,
+    otp: Option<&str>,
+) -> Result<LoginFactor, AppError> {
+    let row = two_factor_row(&mut *conn, member_id).await?;
+    let (Some(secret), Some(_)) = (row.totp_secret, row.totp_enabled_at) else {
+        return Ok(LoginFactor::NotEnrolled);
+    };
+    let Some(otp) = otp.map(str::trim).filter(|o| !o.is_empty()) else {
+        return Ok(LoginFactor::Missing);
+    };
+
+    if let Some(step) = totp::verify(&secret, otp, Utc::now().timestamp())
+        && row.totp_last_step.is_none_or(|last| step > last)
+    {
+        sqlx::query!("UPDATE members SET totp_last_step = $1 WHERE id = $2", step, member_id)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(LoginFactor::Passed);
+    }
+
+    let spent = sqlx::query_scalar!(
+        "UPDATE recovery_codes SET used_at = $1
+         WHERE id = (
+             SELECT id FROM recovery_codes
+             WHERE member_id = $2 AND code_hash = $3 AND used_at IS NULL
+             LIMIT 1
+         )
+         RETURNING id",
+        Utc::now(),
+        member_id,
+        auth::hash_token(&normalize_recovery_code(otp)),
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(if spent.is_some() { LoginFactor::Passed } else { LoginFactor::Failed })
+}
+
+/// Ten hex digits grouped as `xxxxx-xxxxx`.
+fn new_recovery_code() -> String {
+    let token = auth::new_token();
+    format!("{}-{}", &token[..5], &token[5..10])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect()
+}
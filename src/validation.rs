@@ -0,0 +1,101 @@
+use chrono::Datelike;
+
+use crate::{AppError, FieldError, books::AddBook, classification::ClassificationScheme, config::{self, FieldLimits}};
+
+/// Rejects values longer than `limits`, listing every field that is too
+/// long. Lengths are counted in characters.
+pub fn check_field_lengths(
+    limits: &FieldLimits,
+    title: Option<&str>,
+    author: Option<&str>,
+    isbn: Option<&str>,
+    description: Option<&str>,
+) -> Result<(), AppError> {
+    let errors: Vec<FieldError> = [
+        ("title", title, limits.title),
+        ("author", author, limits.author),
+        ("isbn", isbn, limits.isbn),
+        ("description", description, limits.description),
+    ]
+    .into_iter()
+    .filter_map(|(field, value, max)| {
+        let length = value?.chars().count();
+        (length > max).then(|| FieldError {
+            field: field.to_string(),
+            message: format!("must be at most {} characters, got {}", max, length),
+        })
+    })
+    .collect();
+
+    if errors.is_empty() { Ok(()) } else { Err(AppError::InvalidFields(errors)) }
+}
+
+pub fn validate_book(book: &AddBook) -> bool {
+    !book.title.is_empty() &&
+    !book.author.is_empty() &&
+    is_valid_year(book.year) &&
+    is_valid_isbn(&book.isbn)
+}
+
+pub fn is_valid_year(year: i64) -> bool {
+    let current_year = chrono::Utc::now().year() as i64;
+    (1000..=current_year).contains(&year)
+}
+
+pub fn is_valid_isbn(isbn: &str) -> bool {
+    let cleaned = isbn.replace("-", "");
+    cleaned.len() == 13 && cleaned.chars().all(|c| c.is_numeric())
+}
+
+/// Validates a class number against its scheme and returns the shelf-order key
+/// to store alongside it. Scheme and class number must be given together.
+pub fn classification_key(
+    scheme: Option<ClassificationScheme>,
+    classification: Option<&str>,
+) -> Result<Option<String>, AppError> {
+    match (scheme, classification) {
+        (None, None) => Ok(None),
+        (Some(scheme), Some(value)) if scheme.is_valid(value) => Ok(scheme.sort_key(value)),
+        (Some(scheme), Some(value)) => Err(AppError::InvalidInput(format!(
+            "{} is not a valid {} class number",
+            value, scheme
+        ))),
+        _ => Err(AppError::InvalidInput(
+            "classification_scheme and classification must be given together".to_string(),
+        )),
+    }
+}
+
+/// Validates the bibliographic fields that workflows like ILL and acquisitions
+/// collect before a full catalog record exists.
+pub fn validate_optional_bibliographic(year: Option<i64>, isbn: Option<&str>) -> Result<(), AppError> {
+    if year.is_some_and(|y| !is_valid_year(y)) || isbn.is_some_and(|i| !is_valid_isbn(i)) {
+        return Err(AppError::InvalidInput(
+            "year must be between 1000 and the current year, and isbn must be a valid ISBN-13".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the translated-work fields and returns the original language in
+/// lowercase, the form it is stored and filtered in.
+pub fn validate_original_work(
+    original_title: &Option<String>,
+    original_language: &Option<String>,
+    translator: &Option<String>,
+) -> Result<Option<String>, AppError> {
+    if original_title.as_deref().is_some_and(|t| t.trim().is_empty()) || translator.as_deref().is_some_and(|t| t.trim().is_empty()) {
+        return Err(AppError::InvalidInput("original_title and translator must not be empty".to_string()));
+    }
+    original_language
+        .as_deref()
+        .map(|language| {
+            let language = language.trim();
+            if config::is_language_tag(language) {
+                Ok(language.to_ascii_lowercase())
+            } else {
+                Err(AppError::InvalidInput(format!("{} is not a valid language tag, e.g. ja or pt-br", language)))
+            }
+        })
+        .transpose()
+}
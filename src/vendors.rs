@@ -0,0 +1,154 @@
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{AppError, acquisitions, auth::AuthMember, members::Member};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vendor {
+    pub id: i64,
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub address: Option<String>,
+    /// Free-form purchasing terms, e.g. "Net 30, 15% library discount".
+    pub terms: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddVendor {
+    name: String,
+    contact_name: Option<String>,
+    email: Option<String>,
+    phone: Option<String>,
+    address: Option<String>,
+    terms: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateVendor {
+    name: Option<String>,
+    contact_name: Option<String>,
+    email: Option<String>,
+    phone: Option<String>,
+    address: Option<String>,
+    terms: Option<String>,
+}
+
+fn require_staff(member: &Member) -> Result<(), AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can manage vendors".to_string()));
+    }
+    Ok(())
+}
+
+pub async fn add_vendor(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Json(input): Json<AddVendor>,
+) -> Result<(StatusCode, Json<Vendor>), AppError> {
+    require_staff(&staff)?;
+    validate_vendor(Some(&input.name), input.email.as_deref())?;
+
+    let vendor = sqlx::query_as!(
+        Vendor,
+        "INSERT INTO vendors (name, contact_name, email, phone, address, terms, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         RETURNING *",
+        input.name,
+        input.contact_name,
+        input.email,
+        input.phone,
+        input.address,
+        input.terms,
+        Utc::now(),
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(vendor)))
+}
+
+pub async fn list_vendors(State(pool): State<PgPool>) -> Result<Json<Vec<Vendor>>, AppError> {
+    let vendors = sqlx::query_as!(Vendor, "SELECT * FROM vendors ORDER BY name, id")
+        .fetch_all(&pool)
+        .await?;
+
+    Ok(Json(vendors))
+}
+
+pub async fn get_vendor(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vendor>, AppError> {
+    let vendor = sqlx::query_as!(Vendor, "SELECT * FROM vendors WHERE id = $1", id)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Vendor", id))?;
+
+    Ok(Json(vendor))
+}
+
+pub async fn update_vendor(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateVendor>,
+) -> Result<Json<Vendor>, AppError> {
+    require_staff(&staff)?;
+    validate_vendor(input.name.as_deref(), input.email.as_deref())?;
+
+    let vendor = sqlx::query_as!(
+        Vendor,
+        "UPDATE vendors
+         SET name         = COALESCE($1, name),
+             contact_name = COALESCE($2, contact_name),
+             email        = COALESCE($3, email),
+             phone        = COALESCE($4, phone),
+             address      = COALESCE($5, address),
+             terms        = COALESCE($6, terms)
+         WHERE id = $7
+         RETURNING *",
+        input.name,
+        input.contact_name,
+        input.email,
+        input.phone,
+        input.address,
+        input.terms,
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Vendor", id))?;
+
+    Ok(Json(vendor))
+}
+
+/// Purchasing history: every acquisition order placed with this vendor.
+pub async fn list_vendor_orders(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<acquisitions::AcquisitionRequest>>, AppError> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM vendors WHERE id = $1)", id)
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(AppError::ResourceNotFound("Vendor", id));
+    }
+
+    Ok(Json(acquisitions::orders_for_vendor(&pool, id).await?))
+}
+
+fn validate_vendor(name: Option<&str>, email: Option<&str>) -> Result<(), AppError> {
+    if name.is_some_and(|n| n.trim().is_empty()) {
+        return Err(AppError::InvalidInput("Vendor name must not be empty".to_string()));
+    }
+    if email.is_some_and(|e| !e.contains('@')) {
+        return Err(AppError::InvalidInput("Vendor email must be a valid email address".to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Snapshots for the analytics warehouse: books, loans, and audit events as
+//! Parquet files, laid out as one partition per export date
+//! (`loans/export_date=2026-10-16/part-0.parquet`) so warehouse loaders can
+//! pick up each run without scraping the API. Run from a scheduler with
+//! `book-library-api export <dir>`.
+//!
+//! Members appear only as a keyed hash of their id, the same in every
+//! partition made with the same `WAREHOUSE_MEMBER_KEY`. Erasing a member
+//! doesn't rewrite partitions already written: their loans and events stay
+//! there under the pseudonym, which can't be traced back to the member
+//! without the key and the id. Deleted books are exported with `deleted_at`
+//! so their past loans still join.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use parquet::{
+    basic::Compression,
+    data_type::{BoolType, ByteArray, ByteArrayType, DataType, Int32Type, Int64Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::{SerializedColumnWriter, SerializedFileWriter}},
+    schema::parser::parse_message_type,
+};
+use sha2::Sha256;
+use sqlx::PgPool;
+
+use crate::config::SigningKey;
+
+#[derive(Debug)]
+pub struct ExportSummary {
+    pub books: usize,
+    pub loans: usize,
+    pub events: usize,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(sqlx::Error),
+    Io(std::io::Error),
+    Parquet(ParquetError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Database(e) => write!(f, "database error: {}", e),
+            ExportError::Io(e) => write!(f, "could not write the export: {}", e),
+            ExportError::Parquet(e) => write!(f, "could not encode Parquet: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(e: sqlx::Error) -> Self {
+        ExportError::Database(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<ParquetError> for ExportError {
+    fn from(e: ParquetError) -> Self {
+        ExportError::Parquet(e)
+    }
+}
+
+/// Writes the three tables under `dir`, replacing any export already made
+/// for `date`. Loans and events carry member pseudonyms but no ids, names,
+/// or addresses.
+pub async fn export(pool: &PgPool, dir: &Path, date: NaiveDate, key: &SigningKey) -> Result<ExportSummary, ExportError> {
+    let books = sqlx::query!(
+        "SELECT id, title, author, year, isbn, format, classification_scheme, classification,
+                original_language, age_rating, available, updated_at, deleted_at
+         FROM books ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+    let books = write_table(
+        &partition(dir, "books", date)?,
+        "books",
+        vec![
+            Column::required("id", Values::Int64(books.iter().map(|b| Some(b.id)).collect())),
+            Column::required("title", Values::Text(books.iter().map(|b| Some(b.title.clone())).collect())),
+            Column::required("author", Values::Text(books.iter().map(|b| Some(b.author.clone())).collect())),
+            Column::required("year", Values::Int64(books.iter().map(|b| Some(b.year)).collect())),
+            Column::required("isbn", Values::Text(books.iter().map(|b| Some(b.isbn.clone())).collect())),
+            Column::required("format", Values::Text(books.iter().map(|b| Some(b.format.clone())).collect())),
+            Column::optional(
+                "classification_scheme",
+                Values::Text(books.iter().map(|b| b.classification_scheme.clone()).collect()),
+            ),
+            Column::optional("classification", Values::Text(books.iter().map(|b| b.classification.clone()).collect())),
+            Column::optional(
+                "original_language",
+                Values::Text(books.iter().map(|b| b.original_language.clone()).collect()),
+            ),
+            Column::optional("age_rating", Values::Int32(books.iter().map(|b| b.age_rating).collect())),
+            Column::required("available", Values::Boolean(books.iter().map(|b| Some(b.available)).collect())),
+            Column::required("updated_at", Values::Timestamp(books.iter().map(|b| Some(b.updated_at)).collect())),
+            Column::optional("deleted_at", Values::Timestamp(books.iter().map(|b| b.deleted_at).collect())),
+        ],
+    )?;
+
+    let loans = sqlx::query!(
+        "SELECT id, book_id, copy_id, member_id, borrowed_at, due_date, returned_at FROM borrowings ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+    let loans = write_table(
+        &partition(dir, "loans", date)?,
+        "loans",
+        vec![
+            Column::required("id", Values::Int64(loans.iter().map(|l| Some(l.id)).collect())),
+            Column::required("book_id", Values::Int64(loans.iter().map(|l| Some(l.book_id)).collect())),
+            Column::optional("copy_id", Values::Int64(loans.iter().map(|l| l.copy_id).collect())),
+            Column::optional("member", Values::Text(loans.iter().map(|l| l.member_id.map(|id| pseudonym(key, id))).collect())),
+            Column::required("borrowed_at", Values::Timestamp(loans.iter().map(|l| Some(l.borrowed_at)).collect())),
+            Column::required("due_date", Values::Timestamp(loans.iter().map(|l| Some(l.due_date)).collect())),
+            Column::optional("returned_at", Values::Timestamp(loans.iter().map(|l| l.returned_at).collect())),
+        ],
+    )?;
+
+    // The audit detail and client IP stay out of the warehouse.
+    let events = sqlx::query!("SELECT id, event, member_id, created_at FROM audit_log ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    let events = write_table(
+        &partition(dir, "events", date)?,
+        "events",
+        vec![
+            Column::required("id", Values::Int64(events.iter().map(|e| Some(e.id)).collect())),
+            Column::required("event", Values::Text(events.iter().map(|e| Some(e.event.clone())).collect())),
+            Column::optional("member", Values::Text(events.iter().map(|e| e.member_id.map(|id| pseudonym(key, id))).collect())),
+            Column::required("created_at", Values::Timestamp(events.iter().map(|e| Some(e.created_at)).collect())),
+        ],
+    )?;
+
+    Ok(ExportSummary { books, loans, events })
+}
+
+/// Stands in for a member id: stable across exports, but not reversible
+/// without the key.
+pub fn pseudonym(key: &SigningKey, member_id: i64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("member:{}", member_id).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The file for one table's export on `date`, creating its directory.
+fn partition(dir: &Path, table: &str, date: NaiveDate) -> Result<PathBuf, ExportError> {
+    let partition = dir.join(table).join(format!("export_date={}", date));
+    fs::create_dir_all(&partition)?;
+    Ok(partition.join("part-0.parquet"))
+}
+
+enum Values {
+    Boolean(Vec<Option<bool>>),
+    Int32(Vec<Option<i32>>),
+    Int64(Vec<Option<i64>>),
+    Text(Vec<Option<String>>),
+    /// Stored as UTC milliseconds.
+    Timestamp(Vec<Option<DateTime<Utc>>>),
+}
+
+struct Column {
+    name: &'static str,
+    required: bool,
+    values: Values,
+}
+
+impl Column {
+    fn required(name: &'static str, values: Values) -> Self {
+        Column { name, required: true, values }
+    }
+
+    fn optional(name: &'static str, values: Values) -> Self {
+        Column { name, required: false, values }
+    }
+
+    fn len(&self) -> usize {
+        match &self.values {
+            Values::Boolean(v) => v.len(),
+            Values::Int32(v) => v.len(),
+            Values::Int64(v) => v.len(),
+            Values::Text(v) => v.len(),
+            Values::Timestamp(v) => v.len(),
+        }
+    }
+
+    /// The column's line in the Parquet message type.
+    fn schema(&self) -> String {
+        let repetition = if self.required { "REQUIRED" } else { "OPTIONAL" };
+        let (physical, logical) = match self.values {
+            Values::Boolean(_) => ("BOOLEAN", ""),
+            Values::Int32(_) => ("INT32", ""),
+            Values::Int64(_) => ("INT64", ""),
+            Values::Text(_) => ("BYTE_ARRAY", " (STRING)"),
+            Values::Timestamp(_) => ("INT64", " (TIMESTAMP(MILLIS,true))"),
+        };
+        format!("{} {} {}{};", repetition, physical, self.name, logical)
+    }
+
+    fn write(self, writer: &mut SerializedColumnWriter<'_>) -> Result<(), ParquetError> {
+        let required = self.required;
+        match self.values {
+            Values::Boolean(v) => write_values::<BoolType>(writer, required, v),
+            Values::Int32(v) => write_values::<Int32Type>(writer, required, v),
+            Values::Int64(v) => write_values::<Int64Type>(writer, required, v),
+            Values::Text(v) => write_values::<ByteArrayType>(
+                writer,
+                required,
+                v.into_iter().map(|s| s.map(|s| ByteArray::from(s.into_bytes()))).collect(),
+            ),
+            Values::Timestamp(v) => write_values::<Int64Type>(
+                writer,
+                required,
+                v.into_iter().map(|t| t.map(|t| t.timestamp_millis())).collect(),
+            ),
+        }
+    }
+}
+
+fn write_values<T: DataType>(
+    writer: &mut SerializedColumnWriter<'_>,
+    required: bool,
+    values: Vec<Option<T::T>>,
+) -> Result<(), ParquetError> {
+    // Definition level 1 marks a present value in an optional column.
+    let levels: Vec<i16> = values.iter().map(|v| i16::from(v.is_some())).collect();
+    let present: Vec<T::T> = values.into_iter().flatten().collect();
+    writer.typed::<T>().write_batch(&present, (!required).then_some(&levels[..]), None)?;
+    Ok(())
+}
+
+/// Writes one row group to `path`. The file appears under its final name
+/// only once it is complete, so a loader never reads half an export.
+fn write_table(path: &Path, table: &str, columns: Vec<Column>) -> Result<usize, ExportError> {
+    let rows = columns.first().map_or(0, Column::len);
+    let fields: Vec<String> = columns.iter().map(Column::schema).collect();
+    let schema = Arc::new(parse_message_type(&format!("message {} {{ {} }}", table, fields.join(" ")))?);
+    let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+
+    let partial = path.with_extension("parquet.partial");
+    let mut writer = SerializedFileWriter::new(File::create(&partial)?, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    for column in columns {
+        let mut column_writer = row_group
+            .next_column()?
+            .ok_or_else(|| ParquetError::General(format!("{} has more columns than its schema", table)))?;
+        column.write(&mut column_writer)?;
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    fs::rename(&partial, path)?;
+    Ok(rows)
+}
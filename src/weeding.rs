@@ -0,0 +1,419 @@
+use std::{fmt, str::FromStr};
+
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{AppError, auth::AuthMember, chat, copies::CopyStatus, members::Member, query::Query};
+
+/// Copies that haven't circulated for this long are flagged by a scan unless
+/// the caller asks for a different window.
+const DEFAULT_IDLE_DAYS: i64 = 730;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeedingReason {
+    LowCirculation,
+    PoorCondition,
+}
+
+impl WeedingReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            WeedingReason::LowCirculation => "low_circulation",
+            WeedingReason::PoorCondition => "poor_condition",
+        }
+    }
+}
+
+impl FromStr for WeedingReason {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low_circulation" => Ok(WeedingReason::LowCirculation),
+            "poor_condition" => Ok(WeedingReason::PoorCondition),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A flagged copy waits for staff review. Approved candidates can then be
+/// discarded; retained ones stay in the collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeedingStatus {
+    Flagged,
+    Approved,
+    Retained,
+    Discarded,
+}
+
+impl WeedingStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            WeedingStatus::Flagged => "flagged",
+            WeedingStatus::Approved => "approved",
+            WeedingStatus::Retained => "retained",
+            WeedingStatus::Discarded => "discarded",
+        }
+    }
+
+    fn can_become(self, next: WeedingStatus) -> bool {
+        use WeedingStatus::*;
+        matches!(
+            (self, next),
+            (Flagged, Approved) | (Flagged, Retained) | (Approved, Retained) | (Approved, Discarded)
+        )
+    }
+}
+
+impl fmt::Display for WeedingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WeedingStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flagged" => Ok(WeedingStatus::Flagged),
+            "approved" => Ok(WeedingStatus::Approved),
+            "retained" => Ok(WeedingStatus::Retained),
+            "discarded" => Ok(WeedingStatus::Discarded),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeedingCandidate {
+    pub id: i64,
+    pub copy_id: i64,
+    pub reason: WeedingReason,
+    pub status: WeedingStatus,
+    /// Most recent loan of the copy's title when it was flagged.
+    pub last_loaned_at: Option<DateTime<Utc>>,
+    /// The staff member who last reviewed the candidate.
+    pub reviewed_by: Option<i64>,
+    /// Reviewer as typed in, for reviews from before they were tied to an
+    /// account.
+    pub reviewer_name: Option<String>,
+    pub review_notes: Option<String>,
+    pub discard_reason: Option<String>,
+    pub discarded_by: Option<i64>,
+    pub flagged_at: DateTime<Utc>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub discarded_at: Option<DateTime<Utc>>,
+}
+
+struct CandidateRow {
+    id: i64,
+    copy_id: i64,
+    reason: String,
+    status: String,
+    last_loaned_at: Option<DateTime<Utc>>,
+    reviewed_by: Option<i64>,
+    reviewer_name: Option<String>,
+    review_notes: Option<String>,
+    discard_reason: Option<String>,
+    discarded_by: Option<i64>,
+    flagged_at: DateTime<Utc>,
+    reviewed_at: Option<DateTime<Utc>>,
+    discarded_at: Option<DateTime<Utc>>,
+}
+
+impl From<CandidateRow> for WeedingCandidate {
+    fn from(r: CandidateRow) -> Self {
+        WeedingCandidate {
+            id: r.id,
+            copy_id: r.copy_id,
+            reason: r.reason.parse().unwrap_or(WeedingReason::LowCirculation),
+            status: r.status.parse().unwrap_or(WeedingStatus::Flagged),
+            last_loaned_at: r.last_loaned_at,
+            reviewed_by: r.reviewed_by,
+            reviewer_name: r.reviewer_name,
+            review_notes: r.review_notes,
+            discard_reason: r.discard_reason,
+            discarded_by: r.discarded_by,
+            flagged_at: r.flagged_at,
+            reviewed_at: r.reviewed_at,
+            discarded_at: r.discarded_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScanParams {
+    idle_days: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CandidateParams {
+    status: Option<WeedingStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewCandidate {
+    status: WeedingStatus,
+    review_notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiscardCopy {
+    reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeedingReport {
+    pub flagged: i64,
+    pub approved: i64,
+    pub retained: i64,
+    pub discarded: i64,
+    pub discarded_low_circulation: i64,
+    pub discarded_poor_condition: i64,
+}
+
+/// Flags every copy in poor or damaged condition, plus copies older than the
+/// idle window whose title hasn't been borrowed within it. Copies already
+/// under review or discarded are skipped. Returns the newly flagged candidates.
+pub async fn scan_for_candidates(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Query(params): Query<ScanParams>,
+) -> Result<(StatusCode, Json<Vec<WeedingCandidate>>), AppError> {
+    require_staff(&staff)?;
+    let idle_days = params.idle_days.unwrap_or(DEFAULT_IDLE_DAYS);
+    if idle_days < 1 {
+        return Err(AppError::InvalidInput("idle_days must be at least 1".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+    let now = Utc::now();
+    let rows = sqlx::query_as!(
+        CandidateRow,
+        "INSERT INTO weeding_candidates (copy_id, reason, status, last_loaned_at, flagged_at)
+         SELECT c.id,
+                CASE WHEN c.condition IN ('poor', 'damaged') THEN $1 ELSE $2 END,
+                $3, l.last_loaned_at, $4
+         FROM copies c
+         LEFT JOIN LATERAL (
+             SELECT MAX(b.borrowed_at) AS last_loaned_at FROM borrowings b WHERE b.book_id = c.book_id
+         ) l ON true
+         WHERE c.status <> $5
+           AND NOT EXISTS (
+               SELECT 1 FROM weeding_candidates w
+               WHERE w.copy_id = c.id AND w.status IN ('flagged', 'approved')
+           )
+           AND (c.condition IN ('poor', 'damaged')
+                OR (c.created_at < $6 AND (l.last_loaned_at IS NULL OR l.last_loaned_at < $6)))
+         ORDER BY c.id
+         RETURNING *",
+        WeedingReason::PoorCondition.as_str(),
+        WeedingReason::LowCirculation.as_str(),
+        WeedingStatus::Flagged.as_str(),
+        now,
+        CopyStatus::Discarded.as_str(),
+        now - Duration::days(idle_days),
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let poor_condition = rows.iter().filter(|r| r.reason == WeedingReason::PoorCondition.as_str()).count();
+    let summary = format!(
+        "{} copies flagged for review ({} in poor condition, {} unused for {} days)",
+        rows.len(),
+        poor_condition,
+        rows.len() - poor_condition,
+        idle_days
+    );
+    chat::announce(&mut tx, chat::Announcement::ReportCompleted { report: "Weeding scan", summary }).await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(rows.into_iter().map(WeedingCandidate::from).collect())))
+}
+
+pub async fn list_candidates(
+    State(pool): State<PgPool>,
+    Query(params): Query<CandidateParams>,
+) -> Result<Json<Vec<WeedingCandidate>>, AppError> {
+    let rows = sqlx::query_as!(
+        CandidateRow,
+        "SELECT * FROM weeding_candidates
+         WHERE ($1::text IS NULL OR status = $1)
+         ORDER BY id",
+        params.status.map(WeedingStatus::as_str),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(WeedingCandidate::from).collect()))
+}
+
+pub async fn get_candidate(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<WeedingCandidate>, AppError> {
+    let row = sqlx::query_as!(CandidateRow, "SELECT * FROM weeding_candidates WHERE id = $1", id)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or(AppError::ResourceNotFound("Weeding candidate", id))?;
+
+    Ok(Json(row.into()))
+}
+
+/// Staff review: approve a candidate for discard or retain the copy.
+pub async fn review_candidate(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<ReviewCandidate>,
+) -> Result<Json<WeedingCandidate>, AppError> {
+    require_staff(&staff)?;
+    if input.status == WeedingStatus::Discarded {
+        return Err(AppError::InvalidInput(format!(
+            "Use POST /weeding/candidates/{}/discard to discard a copy",
+            id
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let current: WeedingCandidate = sqlx::query_as!(
+        CandidateRow,
+        "SELECT * FROM weeding_candidates WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Weeding candidate", id))?
+    .into();
+
+    if !current.status.can_become(input.status) {
+        return Err(AppError::Conflict(format!(
+            "Weeding candidate with ID {} cannot move from {} to {}",
+            id, current.status, input.status
+        )));
+    }
+
+    let row = sqlx::query_as!(
+        CandidateRow,
+        "UPDATE weeding_candidates
+         SET status       = $1,
+             reviewed_by  = $2,
+             review_notes = COALESCE($3, review_notes),
+             reviewed_at  = $4
+         WHERE id = $5
+         RETURNING *",
+        input.status.as_str(),
+        staff.id,
+        input.review_notes,
+        Utc::now(),
+        id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(row.into()))
+}
+
+/// Withdraws an approved candidate's copy from the collection, recording why.
+pub async fn discard_candidate(
+    State(pool): State<PgPool>,
+    AuthMember(staff): AuthMember,
+    Path(id): Path<i64>,
+    Json(input): Json<DiscardCopy>,
+) -> Result<Json<WeedingCandidate>, AppError> {
+    require_staff(&staff)?;
+    if input.reason.trim().is_empty() {
+        return Err(AppError::InvalidInput("reason must not be empty".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let current: WeedingCandidate = sqlx::query_as!(
+        CandidateRow,
+        "SELECT * FROM weeding_candidates WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::ResourceNotFound("Weeding candidate", id))?
+    .into();
+
+    if !current.status.can_become(WeedingStatus::Discarded) {
+        return Err(AppError::Conflict(format!(
+            "Weeding candidate with ID {} must be approved before it is discarded (currently {})",
+            id, current.status
+        )));
+    }
+
+    let updated = sqlx::query!(
+        "UPDATE copies SET status = $1 WHERE id = $2 AND status <> $3",
+        CopyStatus::Discarded.as_str(),
+        current.copy_id,
+        CopyStatus::OnLoan.as_str(),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(AppError::Conflict(format!(
+            "Copy with ID {} is on loan and must be returned first",
+            current.copy_id
+        )));
+    }
+
+    let now = Utc::now();
+    let row = sqlx::query_as!(
+        CandidateRow,
+        "UPDATE weeding_candidates
+         SET status         = $1,
+             discard_reason = $2,
+             discarded_by   = $3,
+             discarded_at   = $4
+         WHERE id = $5
+         RETURNING *",
+        WeedingStatus::Discarded.as_str(),
+        input.reason,
+        staff.id,
+        now,
+        id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(row.into()))
+}
+
+pub async fn weeding_report(State(pool): State<PgPool>) -> Result<Json<WeedingReport>, AppError> {
+    let report = sqlx::query_as!(
+        WeedingReport,
+        r#"SELECT
+               COUNT(*) FILTER (WHERE status = 'flagged')   AS "flagged!",
+               COUNT(*) FILTER (WHERE status = 'approved')  AS "approved!",
+               COUNT(*) FILTER (WHERE status = 'retained')  AS "retained!",
+               COUNT(*) FILTER (WHERE status = 'discarded') AS "discarded!",
+               COUNT(*) FILTER (WHERE status = 'discarded' AND reason = 'low_circulation') AS "discarded_low_circulation!",
+               COUNT(*) FILTER (WHERE status = 'discarded' AND reason = 'poor_condition')  AS "discarded_poor_condition!"
+           FROM weeding_candidates"#
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(report))
+}
+
+fn require_staff(member: &Member) -> Result<(), AppError> {
+    if !member.role.is_staff() {
+        return Err(AppError::Forbidden("Only staff can weed the collection".to_string()));
+    }
+    Ok(())
+}
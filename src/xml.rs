@@ -0,0 +1,425 @@
+//! Just enough XML for verifying signed SAML responses: a namespace-aware
+//! parser into a small tree, and Exclusive XML Canonicalization (exc-c14n,
+//! without comments) to reproduce the bytes the signer hashed.
+//!
+//! Document type declarations are refused outright, so there are no
+//! external or expanding entities to worry about.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+/// Deep enough for any SAML response; stops stack exhaustion on hostile input.
+const MAX_DEPTH: usize = 64;
+
+/// Prefix (`""` for the default namespace) to namespace URI.
+pub type Scope = BTreeMap<String, String>;
+
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub prefix: Option<String>,
+    pub name: String,
+    /// The `xmlns` and `xmlns:*` attributes, as prefix (`""` for the
+    /// default) and URI.
+    pub namespaces: Vec<(String, String)>,
+    pub attributes: Vec<Attribute>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub prefix: Option<String>,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    /// The namespaces in scope inside this element, given those in scope
+    /// around it.
+    pub fn scope(&self, outer: &Scope) -> Scope {
+        let mut scope = outer.clone();
+        for (prefix, uri) in &self.namespaces {
+            scope.insert(prefix.clone(), uri.clone());
+        }
+        scope
+    }
+
+    /// Whether this element is `{namespace}name`, with `scope` from
+    /// [`Element::scope`].
+    pub fn is(&self, scope: &Scope, namespace: &str, name: &str) -> bool {
+        self.name == name && resolve(scope, self.prefix.as_deref().unwrap_or("")) == Some(namespace)
+    }
+
+    /// An unprefixed attribute.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.prefix.is_none() && a.name == name).map(|a| a.value.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// The first child element `{namespace}name`, and its scope.
+    pub fn child(&self, scope: &Scope, namespace: &str, name: &str) -> Option<(&Element, Scope)> {
+        self.elements().map(|e| (e, e.scope(scope))).find(|(e, s)| e.is(s, namespace, name))
+    }
+
+    /// Every child element `{namespace}name`, with their scopes.
+    pub fn children_named<'a>(
+        &'a self,
+        scope: &'a Scope,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = (&'a Element, Scope)> + 'a {
+        self.elements().map(|e| (e, e.scope(scope))).filter(move |(e, s)| e.is(s, namespace, name))
+    }
+
+    /// The text directly inside this element.
+    pub fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Text(text) => Some(text.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+
+    /// Every element in the subtree, this one included, in document order.
+    pub fn descendants(&self) -> Vec<&Element> {
+        let mut all = vec![self];
+        for child in self.elements() {
+            all.extend(child.descendants());
+        }
+        all
+    }
+}
+
+fn resolve<'a>(scope: &'a Scope, prefix: &str) -> Option<&'a str> {
+    match prefix {
+        "xml" => Some(XML_NAMESPACE),
+        "" => scope.get("").map(String::as_str).filter(|uri| !uri.is_empty()),
+        prefix => scope.get(prefix).map(String::as_str),
+    }
+}
+
+/// Parses a whole document and returns its root element.
+pub fn parse(input: &str) -> Result<Element, String> {
+    let normalized = input.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut parser = Parser { input: &normalized, pos: 0 };
+    parser.skip_misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err("expected a root element".to_string());
+    }
+    let root = parser.element(&Scope::new(), 0)?;
+    parser.skip_misc()?;
+    if !parser.rest().is_empty() {
+        return Err("content after the root element".to_string());
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n']).len();
+    }
+
+    /// Skips to just past `end`.
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, String> {
+        let rest = self.rest();
+        let at = rest.find(end).ok_or_else(|| format!("missing {:?}", end))?;
+        self.pos += at + end.len();
+        Ok(&rest[..at])
+    }
+
+    /// Whitespace, comments, and processing instructions (including the
+    /// XML declaration) outside the root element.
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                return Err("document type declarations are not accepted".to_string());
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<(Option<String>, String), String> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=')).unwrap_or(rest.len());
+        let qname = &rest[..len];
+        self.pos += len;
+        let (prefix, name) = match qname.split_once(':') {
+            Some((prefix, name)) => (Some(prefix.to_string()), name),
+            None => (None, qname),
+        };
+        if name.is_empty() || name.contains(':') || prefix.as_deref() == Some("") {
+            return Err(format!("invalid name {:?}", qname));
+        }
+        Ok((prefix, name.to_string()))
+    }
+
+    fn element(&mut self, outer: &Scope, depth: usize) -> Result<Element, String> {
+        if depth > MAX_DEPTH {
+            return Err("elements are nested too deeply".to_string());
+        }
+        self.pos += 1;
+        let (prefix, name) = self.name()?;
+        let mut element = Element { prefix, name, namespaces: Vec::new(), attributes: Vec::new(), children: Vec::new() };
+
+        let empty = loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                break true;
+            }
+            if rest.starts_with('>') {
+                self.pos += 1;
+                break false;
+            }
+            let (attr_prefix, attr_name) = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(format!("attribute {} has no value", attr_name));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let quote = self.rest().chars().next().filter(|c| matches!(c, '"' | '\'')).ok_or("unquoted attribute")?;
+            self.pos += 1;
+            let raw = self.skip_past(&quote.to_string())?;
+            if raw.contains('<') {
+                return Err("'<' in an attribute value".to_string());
+            }
+            // Attribute value normalization: literal whitespace becomes
+            // spaces; character references keep what they say.
+            let value = decode(&raw.replace(['\t', '\n'], " "))?;
+            match (attr_prefix.as_deref(), attr_name.as_str()) {
+                (None, "xmlns") => element.namespaces.push((String::new(), value)),
+                (Some("xmlns"), prefix) => element.namespaces.push((prefix.to_string(), value)),
+                _ => element.attributes.push(Attribute { prefix: attr_prefix, name: attr_name, value }),
+            }
+        };
+
+        let scope = element.scope(outer);
+        let unbound = |prefix: &Option<String>| prefix.as_deref().is_some_and(|p| resolve(&scope, p).is_none());
+        if unbound(&element.prefix) || element.attributes.iter().any(|a| unbound(&a.prefix)) {
+            return Err(format!("undeclared namespace prefix in {}", element.name));
+        }
+        let mut seen = BTreeSet::new();
+        for attribute in &element.attributes {
+            let namespace = attribute.prefix.as_deref().and_then(|p| resolve(&scope, p)).unwrap_or("");
+            if !seen.insert((namespace, attribute.name.as_str())) {
+                return Err(format!("duplicate attribute {}", attribute.name));
+            }
+        }
+        if empty {
+            return Ok(element);
+        }
+
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                let end = self.name()?;
+                self.skip_whitespace();
+                if end != (element.prefix.clone(), element.name.clone()) || !self.rest().starts_with('>') {
+                    return Err(format!("mismatched end tag for {}", element.name));
+                }
+                self.pos += 1;
+                return Ok(element);
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.pos += 9;
+                let text = self.skip_past("]]>")?;
+                push_text(&mut element.children, text);
+            } else if rest.starts_with("<?") {
+                return Err("processing instructions are not accepted inside elements".to_string());
+            } else if rest.starts_with("<!") {
+                return Err("document type declarations are not accepted".to_string());
+            } else if rest.starts_with('<') {
+                let child = self.element(&scope, depth + 1)?;
+                element.children.push(Node::Element(child));
+            } else if rest.is_empty() {
+                return Err(format!("unclosed element {}", element.name));
+            } else {
+                let len = rest.find('<').unwrap_or(rest.len());
+                self.pos += len;
+                push_text(&mut element.children, &decode(&rest[..len])?);
+            }
+        }
+    }
+}
+
+fn push_text(children: &mut Vec<Node>, text: &str) {
+    match children.last_mut() {
+        Some(Node::Text(previous)) => previous.push_str(text),
+        _ => children.push(Node::Text(text.to_string())),
+    }
+}
+
+/// Replaces the predefined entities and character references.
+fn decode(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        let end = rest[at..].find(';').ok_or("unterminated entity reference")? + at;
+        let entity = &rest[at + 1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32).ok_or_else(|| format!("unknown entity &{};", entity))?
+            }
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Exclusive canonical form of `element`, leaving out `omit` (the
+/// enveloped signature) wherever it appears. `outer` is the scope around
+/// `element` in its document; `inclusive` lists the prefixes of the
+/// transform's `InclusiveNamespaces` (`""` for `#default`).
+pub fn canonicalize(element: &Element, outer: &Scope, inclusive: &[String], omit: Option<&Element>) -> String {
+    let mut out = String::new();
+    write_canonical(&mut out, element, outer, &Scope::new(), inclusive, omit);
+    out
+}
+
+fn write_canonical(
+    out: &mut String,
+    element: &Element,
+    outer: &Scope,
+    rendered: &Scope,
+    inclusive: &[String],
+    omit: Option<&Element>,
+) {
+    let scope = element.scope(outer);
+    let mut utilized: BTreeSet<&str> = BTreeSet::new();
+    utilized.insert(element.prefix.as_deref().unwrap_or(""));
+    for attribute in &element.attributes {
+        if let Some(prefix) = attribute.prefix.as_deref().filter(|p| *p != "xml") {
+            utilized.insert(prefix);
+        }
+    }
+    for prefix in inclusive {
+        if scope.contains_key(prefix) {
+            utilized.insert(prefix);
+        }
+    }
+
+    let qname = |prefix: &Option<String>, name: &str| match prefix {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_string(),
+    };
+    out.push('<');
+    out.push_str(&qname(&element.prefix, &element.name));
+
+    let mut now_rendered = rendered.clone();
+    for prefix in utilized {
+        let uri = scope.get(prefix).map(String::as_str).unwrap_or("");
+        // Already declared the same way by an output ancestor; an absent
+        // default namespace counts as declared empty.
+        if rendered.get(prefix).map(String::as_str).unwrap_or("") == uri {
+            continue;
+        }
+        if prefix.is_empty() {
+            out.push_str(" xmlns=\"");
+        } else {
+            out.push_str(" xmlns:");
+            out.push_str(prefix);
+            out.push_str("=\"");
+        }
+        escape_attribute(out, uri);
+        out.push('"');
+        now_rendered.insert(prefix.to_string(), uri.to_string());
+    }
+
+    let mut attributes: Vec<(&str, &Attribute)> = element
+        .attributes
+        .iter()
+        .map(|a| (a.prefix.as_deref().and_then(|p| resolve(&scope, p)).unwrap_or(""), a))
+        .collect();
+    attributes.sort_by(|(ns_a, a), (ns_b, b)| (ns_a, &a.name).cmp(&(ns_b, &b.name)));
+    for (_, attribute) in attributes {
+        out.push(' ');
+        out.push_str(&qname(&attribute.prefix, &attribute.name));
+        out.push_str("=\"");
+        escape_attribute(out, &attribute.value);
+        out.push('"');
+    }
+    out.push('>');
+
+    for child in &element.children {
+        match child {
+            Node::Text(text) => escape_text(out, text),
+            Node::Element(child) if omit.is_some_and(|omit| std::ptr::eq(omit, child)) => {}
+            Node::Element(child) => write_canonical(out, child, &scope, &now_rendered, inclusive, omit),
+        }
+    }
+    out.push_str("</");
+    out.push_str(&qname(&element.prefix, &element.name));
+    out.push('>');
+}
+
+fn escape_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}